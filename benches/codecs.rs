@@ -0,0 +1,185 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Speed of the codecs and of reading and writing frames, on synthetic videos of several
+//! sizes that are held in memory so that the results do not depend on the disk. This is
+//! meant for measuring performance work on the decoders, such as SIMD, rayon or the GPU,
+//! and for catching regressions: criterion compares each run with the previous one and
+//! reports changes that are outside the noise.
+//!
+//! Run with `cargo bench --bench codecs`. Pass a filter to only run the benchmarks whose
+//! names match it, such as `cargo bench --bench codecs -- 4K` or `-- debayer`.
+//!
+//! The codecs convert output rows in parallel with rayon, so run again with
+//! `RAYON_NUM_THREADS=1` to measure the speedup from using every core.
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use astro_video_player::codecs::{DebayerCodec, MonoCodec, RgbCodec};
+use astro_video_player::ser::{SerBytes, SerFormat, SerWriter};
+use astro_video_player::video_format::{Bayer, Endianness, OpenMode};
+use astro_video_player::{ImageCodec, Video};
+
+/// Frame sizes, from a planetary region of interest up to a large deep sky sensor
+const SIZES: &[(&str, u32, u32)] = &[
+    ("VGA", 640, 480),
+    ("1080p", 1920, 1080),
+    ("4K", 3840, 2160),
+    ("24 MP", 6000, 4000),
+];
+
+/// Frames in each synthetic video
+const FRAME_COUNT: usize = 4;
+
+/// Samples taken of each benchmark. The largest frames take tens of milliseconds to decode,
+/// so criterion's default of 100 would make a full run take many minutes.
+const SAMPLE_SIZE: usize = 20;
+
+fn ser_format(width: u32, height: u32, bayer: Bayer, pixel_depth_bits: u32) -> SerFormat {
+    SerFormat {
+        width,
+        height,
+        bayer,
+        pixel_depth_bits,
+        endianness: Endianness::LittleEndian,
+    }
+}
+
+/// Pseudo-random samples with `pixel_depth_bits` significant bits, so that table reads and
+/// branches do not all take the same path
+fn frame_bytes(format: &SerFormat, seed: u32) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32 ^ seed;
+    let count = format.width as usize * format.height as usize * format.bayer.planes();
+    let mut bytes = Vec::with_capacity(count * 2);
+    for _ in 0..count {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let sample = (state >> (32 - format.pixel_depth_bits)) as u16;
+        match (format.pixel_depth_bits, format.endianness) {
+            (1..=8, _) => bytes.push(sample as u8),
+            (_, Endianness::LittleEndian) => bytes.extend_from_slice(&sample.to_le_bytes()),
+            (_, Endianness::BigEndian) => bytes.extend_from_slice(&sample.to_be_bytes()),
+        }
+    }
+    bytes
+}
+
+/// A synthetic SER file held in memory
+fn ser_video(format: &SerFormat) -> SerBytes {
+    let mut writer = SerWriter::new(Cursor::new(vec![]), format).unwrap();
+    for i in 0..FRAME_COUNT {
+        writer.write_frame(&frame_bytes(format, i as u32)).unwrap();
+    }
+    let bytes = writer.finish().unwrap().into_inner();
+    SerBytes::new(bytes, OpenMode::default()).unwrap()
+}
+
+/// Benchmarks named `group` that decode every size of video in each format with the codec
+/// for that format, both at full resolution and as the previews shown while seeking
+fn bench_codec(c: &mut Criterion, group: &str, formats: &[(Bayer, u32, &dyn ImageCodec)]) {
+    let mut group = c.benchmark_group(group);
+    group.sample_size(SAMPLE_SIZE);
+    for &(size, width, height) in SIZES {
+        group.throughput(Throughput::Elements(width as u64 * height as u64));
+        for &(bayer, pixel_depth_bits, codec) in formats {
+            let video = ser_video(&ser_format(width, height, bayer, pixel_depth_bits));
+            let parameter = format!("{:?} {}-bit {}", bayer, pixel_depth_bits, size);
+            let mut out = vec![];
+            let mut index = 0;
+            group.bench_function(BenchmarkId::new("decode", &parameter), |b| {
+                b.iter(|| {
+                    index = (index + 1) % FRAME_COUNT;
+                    codec.decode_into(&video, index, &mut out).unwrap()
+                })
+            });
+            group.bench_function(BenchmarkId::new("preview", &parameter), |b| {
+                b.iter(|| {
+                    index = (index + 1) % FRAME_COUNT;
+                    codec.decode_scaled(&video, index, 4).unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn debayer(c: &mut Criterion) {
+    let formats: [(Bayer, u32, &dyn ImageCodec); 2] = [
+        (Bayer::RGGB, 8, &DebayerCodec {}),
+        (Bayer::RGGB, 12, &DebayerCodec {}),
+    ];
+    bench_codec(c, "debayer", &formats);
+}
+
+fn rgb(c: &mut Criterion) {
+    let formats: [(Bayer, u32, &dyn ImageCodec); 2] = [
+        (Bayer::BGR, 8, &RgbCodec::new(Bayer::BGR)),
+        (Bayer::RGB, 16, &RgbCodec::new(Bayer::RGB)),
+    ];
+    bench_codec(c, "rgb", &formats);
+}
+
+fn mono(c: &mut Criterion) {
+    bench_codec(c, "mono", &[(Bayer::Mono, 16, &MonoCodec {})]);
+}
+
+/// Writing a SER file into memory, and reading raw and native samples
+fn frame_io(c: &mut Criterion) {
+    let mut group = c.benchmark_group("io");
+    group.sample_size(SAMPLE_SIZE);
+    for &(size, width, height) in SIZES {
+        group.throughput(Throughput::Elements(width as u64 * height as u64));
+        let format = ser_format(width, height, Bayer::RGGB, 12);
+        let parameter = format!("RGGB 12-bit {}", size);
+        let frame = frame_bytes(&format, 0);
+        let video = ser_video(&format);
+        group.bench_function(BenchmarkId::new("write", &parameter), |b| {
+            b.iter(|| {
+                let buffer = Cursor::new(Vec::with_capacity(frame.len()));
+                let mut writer = SerWriter::new(buffer, &format).unwrap();
+                writer.write_frame(&frame).unwrap();
+                writer.finish().unwrap()
+            })
+        });
+        let mut index = 0;
+        // frames of in-memory videos are borrowed, so this copies them like reading from disk
+        group.bench_function(BenchmarkId::new("get_frame", &parameter), |b| {
+            b.iter(|| {
+                index = (index + 1) % FRAME_COUNT;
+                video.read_frame(index).unwrap().into_owned()
+            })
+        });
+        group.bench_function(BenchmarkId::new("get_frame_u16", &parameter), |b| {
+            b.iter(|| {
+                index = (index + 1) % FRAME_COUNT;
+                video.get_frame_u16(index).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, debayer, rgb, mono, frame_io);
+criterion_main!(benches);
@@ -0,0 +1,101 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Speed of converting 16-bit raw samples to the 8-bit display range, comparing the
+//! per-sample arithmetic that the codecs used to do with the `DisplayLut` lookup table.
+//!
+//! Run with `cargo bench --bench scaling`.
+
+use std::time::{Duration, Instant};
+
+use astro_video_player::codec::DisplayLut;
+
+/// Samples in a 4K RAW16 frame
+const SAMPLE_COUNT: usize = 3840 * 2160;
+const PIXEL_DEPTH_BITS: u32 = 12;
+const ITERATIONS: u32 = 20;
+
+/// Run `convert` over the samples `ITERATIONS` times, returning the fastest run and a checksum
+/// of the output so that the work cannot be optimized away
+fn time(samples: &[u16], convert: impl Fn(&[u16]) -> Vec<u8>) -> (Duration, u64) {
+    let mut fastest = Duration::from_secs(u64::MAX);
+    let mut checksum = 0;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        let out = convert(samples);
+        fastest = fastest.min(start.elapsed());
+        checksum = out.iter().map(|value| *value as u64).sum();
+    }
+    (fastest, checksum)
+}
+
+fn main() {
+    // a pseudo-random frame, so that table reads are not all cache hits on the same entry
+    let mut state = 0x2545_f491_u32;
+    let samples = (0..SAMPLE_COUNT)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 20) as u16
+        })
+        .collect::<Vec<_>>();
+
+    let max_value = (1_u32 << PIXEL_DEPTH_BITS) as f32;
+    let (float, float_sum) = time(&samples, |samples| {
+        samples
+            .iter()
+            .map(|value| ((*value as f32 / max_value) * 255.0) as u8)
+            .collect()
+    });
+    let max_value = (1_u32 << PIXEL_DEPTH_BITS) - 1;
+    let (divide, divide_sum) = time(&samples, |samples| {
+        samples
+            .iter()
+            .map(|value| ((*value as u32).min(max_value) * 255 / max_value) as u8)
+            .collect()
+    });
+    let (lut, lut_sum) = time(&samples, |samples| {
+        let lut = DisplayLut::for_depth(PIXEL_DEPTH_BITS);
+        samples.iter().map(|value| lut.get(*value)).collect()
+    });
+
+    println!(
+        "Converting {} {}-bit samples (fastest of {} runs)",
+        SAMPLE_COUNT, PIXEL_DEPTH_BITS, ITERATIONS
+    );
+    for (name, elapsed, checksum) in [
+        ("f32 division", float, float_sum),
+        ("integer division", divide, divide_sum),
+        ("lookup table", lut, lut_sum),
+    ]
+    .iter()
+    {
+        println!(
+            "{:>16}: {:>8.2} ms, {:.2}x the f32 speed (checksum {})",
+            name,
+            elapsed.as_secs_f64() * 1000.0,
+            float.as_secs_f64() / elapsed.as_secs_f64(),
+            checksum
+        );
+    }
+}
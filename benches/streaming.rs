@@ -0,0 +1,135 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Memory used while decoding every frame of a large SER file with each `ReadStrategy`.
+//!
+//! Run with `cargo bench --bench streaming`. The size of the generated file defaults to
+//! 512 MB and can be changed with the `AVP_BENCH_MB` environment variable. Resident memory
+//! is sampled from `/proc/self/status` after every frame, so figures are only reported on
+//! Linux. With `read` the peak should stay close to the starting value regardless of the
+//! file size, while with `mmap` it grows with the number of pages that have been touched.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
+
+use astro_video_player::codecs::codec_named;
+use astro_video_player::ser::{SerFormat, SerWriter};
+use astro_video_player::video_format::{Bayer, Endianness, ReadStrategy};
+use astro_video_player::{open_video_with, OpenOptions, Result};
+
+const WIDTH: u32 = 2048;
+const HEIGHT: u32 = 2048;
+
+/// Resident memory of this process in kB, as `(total, anonymous, file-backed)`
+fn resident_kb() -> Option<(u64, u64, u64)> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let field = |name: &str| {
+        status
+            .lines()
+            .find(|line| line.starts_with(name))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|value| value.parse::<u64>().ok())
+    };
+    Some((field("VmRSS:")?, field("RssAnon:")?, field("RssFile:")?))
+}
+
+fn write_ser(path: &Path, frame_count: usize) -> Result<()> {
+    let format = SerFormat {
+        width: WIDTH,
+        height: HEIGHT,
+        bayer: Bayer::Mono,
+        pixel_depth_bits: 8,
+        endianness: Endianness::LittleEndian,
+    };
+    let mut writer = SerWriter::new(BufWriter::new(File::create(path)?), &format)?;
+    let mut frame = vec![0_u8; (WIDTH * HEIGHT) as usize];
+    for i in 0..frame_count {
+        frame.iter_mut().for_each(|sample| *sample = i as u8);
+        writer.write_frame(&frame)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn decode_all(path: &Path, read: ReadStrategy) -> Result<()> {
+    let options = OpenOptions {
+        read,
+        ..OpenOptions::default()
+    };
+    let start = resident_kb();
+    let started = Instant::now();
+    let video = open_video_with(&path.to_string_lossy(), &options)?;
+    let codec = codec_named("auto", video.as_ref())?;
+    let mut peak = start;
+    for index in 0..video.frame_count() {
+        codec.decode(video.as_ref(), index)?;
+        if let (Some(peak), Some(now)) = (peak.as_mut(), resident_kb()) {
+            peak.0 = peak.0.max(now.0);
+            peak.1 = peak.1.max(now.1);
+            peak.2 = peak.2.max(now.2);
+        }
+    }
+    let elapsed = started.elapsed();
+    print!(
+        "{:>5}: {} frames in {:.2}s ({:.1} fps)",
+        format!("{:?}", read).to_lowercase(),
+        video.frame_count(),
+        elapsed.as_secs_f64(),
+        video.frame_count() as f64 / elapsed.as_secs_f64()
+    );
+    match (start, peak) {
+        (Some(start), Some(peak)) => println!(
+            ", peak resident {} MB (+{} MB: anonymous {} MB, file-backed {} MB)",
+            peak.0 / 1024,
+            peak.0.saturating_sub(start.0) / 1024,
+            peak.1.saturating_sub(start.1) / 1024,
+            peak.2.saturating_sub(start.2) / 1024
+        ),
+        _ => println!(", resident memory is not available on this platform"),
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let size_mb = env::var("AVP_BENCH_MB")
+        .ok()
+        .and_then(|mb| mb.parse::<usize>().ok())
+        .unwrap_or(512);
+    let frame_count = (size_mb << 20) / (WIDTH * HEIGHT) as usize;
+    let path = env::temp_dir().join("astro-video-player-streaming-bench.ser");
+    println!(
+        "Writing {} frames of {}x{} to {}",
+        frame_count,
+        WIDTH,
+        HEIGHT,
+        path.display()
+    );
+    write_ser(&path, frame_count)?;
+    // stream first so that pages of the file mapped by the other strategy are not counted
+    let result =
+        decode_all(&path, ReadStrategy::Read).and_then(|_| decode_all(&path, ReadStrategy::Mmap));
+    fs::remove_file(&path)?;
+    result
+}
@@ -0,0 +1,43 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+#![no_main]
+
+use astro_video_player::codecs::codec_for;
+use astro_video_player::ser::SerBytes;
+use astro_video_player::video_format::OpenMode;
+use astro_video_player::Video;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for mode in [OpenMode::Lenient, OpenMode::Strict] {
+        if let Ok(video) = SerBytes::new(data.to_vec(), mode) {
+            // keep the decoded frames small so that each input runs quickly
+            let pixels = video.image_width() as u64 * video.image_height() as u64;
+            if video.frame_count() > 0 && pixels <= 1 << 16 {
+                if let Ok(codec) = codec_for(&video) {
+                    let _ = codec.decode(&video, 0);
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,72 @@
+/*
+ * C interface to the astro-video-player decoders. Build the library with
+ * `cargo build --release --features ffi` and link against libastro_video_player.
+ *
+ * This header mirrors src/ffi.rs and can be regenerated with
+ * `cbindgen --config cbindgen.toml --output include/astro_video_player.h`.
+ */
+
+#ifndef ASTRO_VIDEO_PLAYER_H
+#define ASTRO_VIDEO_PLAYER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define AVP_OK 0
+#define AVP_ERROR -1
+#define AVP_INVALID_ARGUMENT -2
+#define AVP_BUFFER_TOO_SMALL -3
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An open video, owned by the caller until it is passed to avp_close */
+typedef struct AvpVideo AvpVideo;
+
+/* Header values of an open video */
+typedef struct AvpMetadata {
+    uint32_t width;
+    uint32_t height;
+    uint64_t frame_count;
+    uint32_t bytes_per_pixel;
+    uint32_t bit_depth;
+    /* SER color ID, such as 0 for mono or 8 for RGGB */
+    uint32_t color_id;
+    /* Non-zero if 16-bit samples are big endian */
+    uint32_t big_endian;
+} AvpMetadata;
+
+/* Open a SER or AVI file. Returns NULL on failure. */
+AvpVideo *avp_open(const char *path);
+
+/* Fill in metadata with the header values of the video */
+int avp_get_metadata(const AvpVideo *video, AvpMetadata *metadata);
+
+/*
+ * Decode a frame into buffer as 8-bit BGRA pixels and store the dimensions of the decoded
+ * image in width and height. If the buffer is too small, nothing is written to it,
+ * AVP_BUFFER_TOO_SMALL is returned and the dimensions are still stored so that the caller
+ * can allocate a large enough buffer.
+ */
+int avp_decode_frame_into_buffer(const AvpVideo *video,
+                                 uint64_t index,
+                                 uint8_t *buffer,
+                                 size_t buffer_len,
+                                 uint32_t *width,
+                                 uint32_t *height);
+
+/* Close a video that was returned by avp_open. Passing NULL does nothing. */
+void avp_close(AvpVideo *video);
+
+/*
+ * The reason for the most recent failure on this thread, or NULL if nothing has failed.
+ * The string is owned by the library and is valid until the next call on this thread.
+ */
+const char *avp_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* ASTRO_VIDEO_PLAYER_H */
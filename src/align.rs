@@ -0,0 +1,307 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Whole-frame translation alignment for stacking. Each frame is compared with a reference
+//! frame by cross-correlating their brightness profiles (the mean brightness of each column
+//! and of each row), which is fast and works well for a planet or the Moon drifting across
+//! the frame.
+//!
+//! Decoded images can also be centered on the brightness centroid of their target, which
+//! keeps a drifting planet still during playback.
+
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use crate::error::Result;
+use crate::quality::brightness;
+use crate::video_format::{check_uncompressed, Bayer, Video};
+
+/// Fraction of the width and height of a frame that a frame can be shifted by
+const MAX_SHIFT_FRACTION: usize = 4;
+
+/// Translation that moves a frame onto the reference frame, in pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Shift {
+    pub dx: i32,
+    pub dy: i32,
+}
+
+/// Shifts that align each of the frames with the given indices to the `reference` frame.
+/// Raw Bayer frames are only shifted by even numbers of pixels, so that the color filter
+/// pattern still lines up.
+pub fn frame_shifts(video: &dyn Video, indices: &[usize], reference: usize) -> Result<Vec<Shift>> {
+    check_uncompressed(video)?;
+    let (columns, rows) = profiles(video, reference)?;
+    indices
+        .par_iter()
+        .map(|&index| {
+            let (frame_columns, frame_rows) = profiles(video, index)?;
+            let scale = pattern_period(video) as i32;
+            Ok(Shift {
+                dx: -best_offset(&columns, &frame_columns) * scale,
+                dy: -best_offset(&rows, &frame_rows) * scale,
+            })
+        })
+        .collect()
+}
+
+/// Distance between pixels with the same color filter, which shifts must be a multiple of
+pub(crate) fn pattern_period(video: &dyn Video) -> usize {
+    match video.bayer() {
+        Bayer::Mono | Bayer::RGB | Bayer::BGR => 1,
+        _ => 2,
+    }
+}
+
+/// Mean brightness of each column and of each row of a frame, with the mean of the whole
+/// frame subtracted
+fn profiles(video: &dyn Video, index: usize) -> Result<(Vec<f32>, Vec<f32>)> {
+    let (width, height, values) = brightness(video, &video.read_frame(index)?);
+    let mut columns = vec![0.0; width];
+    let mut rows = vec![0.0; height];
+    for (y, row) in values.chunks_exact(width.max(1)).take(height).enumerate() {
+        for (x, value) in row.iter().enumerate() {
+            columns[x] += value;
+            rows[y] += value;
+        }
+    }
+    let centre = |profile: &mut Vec<f32>, count: usize| {
+        profile
+            .iter_mut()
+            .for_each(|sum| *sum /= count.max(1) as f32);
+        let mean = profile.iter().sum::<f32>() / profile.len().max(1) as f32;
+        profile.iter_mut().for_each(|value| *value -= mean);
+    };
+    centre(&mut columns, height);
+    centre(&mut rows, width);
+    Ok((columns, rows))
+}
+
+/// Offset `s` at which `profile[i + s]` best matches `reference[i]`, as measured by the
+/// mean product of the overlapping values
+fn best_offset(reference: &[f32], profile: &[f32]) -> i32 {
+    let len = reference.len().min(profile.len());
+    let max_shift = (len / MAX_SHIFT_FRACTION) as i32;
+    let mut best = (0, f32::MIN);
+    for offset in -max_shift..=max_shift {
+        let (start, end) = (0.max(-offset), (len as i32).min(len as i32 - offset));
+        let correlation = (start..end)
+            .map(|i| reference[i as usize] * profile[(i + offset) as usize])
+            .sum::<f32>()
+            / (end - start).max(1) as f32;
+        if correlation > best.1 {
+            best = (offset, correlation);
+        }
+    }
+    best.0
+}
+
+/// Position along an axis of `len` pixels that a translated pixel is read from: `position`
+/// if it is inside, and otherwise the nearest position in `0..len` with the same remainder
+/// when divided by `period`, so that uncovered areas repeat edge pixels of the same color
+pub(crate) fn source_position(position: i64, len: usize, period: usize) -> usize {
+    let (len, period) = (len as i64, period.min(len.max(1)) as i64);
+    let position = if position < 0 {
+        position.rem_euclid(period)
+    } else if position >= len {
+        len - period + (position - (len - period)).rem_euclid(period)
+    } else {
+        position
+    };
+    position as usize
+}
+
+/// Rows of a frame that `shift_samples` reads to produce the translated `rows`
+pub(crate) fn source_rows(
+    rows: Range<usize>,
+    height: usize,
+    period: usize,
+    shift: Shift,
+) -> Range<usize> {
+    let sources = rows.map(|y| source_position(y as i64 - shift.dy as i64, height, period));
+    let (first, last) = sources.fold((usize::MAX, 0), |(first, last), y| {
+        (first.min(y), last.max(y))
+    });
+    first..last + 1
+}
+
+/// Translate the samples of a frame by `shift`. Areas that the translation uncovers are
+/// filled from the nearest edge pixel with the same color filter.
+pub(crate) fn shift_samples(
+    values: &[u16],
+    width: usize,
+    height: usize,
+    channels: usize,
+    period: usize,
+    shift: Shift,
+) -> Vec<u16> {
+    let mut shifted = Vec::with_capacity(values.len());
+    for y in 0..height {
+        let source_y = source_position(y as i64 - shift.dy as i64, height, period);
+        for x in 0..width {
+            let source_x = source_position(x as i64 - shift.dx as i64, width, period);
+            let i = (source_y * width + source_x) * channels;
+            shifted.extend_from_slice(&values[i..i + channels]);
+        }
+    }
+    // any samples after the image are kept
+    shifted.extend_from_slice(&values[width * height * channels..]);
+    shifted
+}
+
+/// Brightness centroid of the target in a BGRA image, as `(x, y)` in pixels. Only pixels
+/// brighter than halfway between the mean and the maximum brightness are counted, so that
+/// the background does not pull the centroid away from a planet. Returns `None` if the image
+/// has no brighter target.
+pub fn centroid(width: u32, height: u32, pixels: &[u8]) -> Option<(f64, f64)> {
+    let (width, height) = (width as usize, height as usize);
+    let brightness = pixels
+        .chunks_exact(4)
+        .take(width * height)
+        .map(|pixel| pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32)
+        .collect::<Vec<_>>();
+    let max = *brightness.iter().max()?;
+    let mean = brightness.iter().map(|b| *b as u64).sum::<u64>() / brightness.len() as u64;
+    let threshold = (mean as u32 + max) / 2;
+    let (mut sum_x, mut sum_y, mut total) = (0.0, 0.0, 0.0);
+    for (i, value) in brightness.iter().enumerate() {
+        if *value > threshold {
+            let weight = (*value - threshold) as f64;
+            sum_x += weight * (i % width.max(1)) as f64;
+            sum_y += weight * (i / width.max(1)) as f64;
+            total += weight;
+        }
+    }
+    if total > 0.0 {
+        Some((sum_x / total, sum_y / total))
+    } else {
+        None
+    }
+}
+
+/// Shift that moves the brightness centroid of a BGRA image to the center of the image
+pub fn centering_shift(width: u32, height: u32, pixels: &[u8]) -> Shift {
+    match centroid(width, height, pixels) {
+        Some((x, y)) => Shift {
+            dx: ((width as f64 - 1.0) / 2.0 - x).round() as i32,
+            dy: ((height as f64 - 1.0) / 2.0 - y).round() as i32,
+        },
+        None => Shift::default(),
+    }
+}
+
+/// Translate a BGRA image by `shift`, filling the area that the translation uncovers with
+/// black
+pub fn shift_bgra(width: u32, height: u32, pixels: &[u8], shift: Shift) -> Vec<u8> {
+    let (width, height) = (width as i64, height as i64);
+    let mut shifted = vec![0; pixels.len()];
+    for pixel in shifted.chunks_exact_mut(4) {
+        pixel[3] = 255;
+    }
+    for y in 0..height {
+        let source_y = y - shift.dy as i64;
+        if source_y < 0 || source_y >= height {
+            continue;
+        }
+        // the part of the row that comes from inside the source image
+        let start = (shift.dx as i64).clamp(0, width);
+        let end = (width + shift.dx as i64).clamp(0, width);
+        if start >= end {
+            continue;
+        }
+        let to = ((y * width + start) * 4) as usize;
+        let from = ((source_y * width + start - shift.dx as i64) * 4) as usize;
+        let len = ((end - start) * 4) as usize;
+        shifted[to..to + len].copy_from_slice(&pixels[from..from + len]);
+    }
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{ser_format, ser_video};
+
+    /// A bright square on a dark background with its top left corner at (x, y)
+    fn square(x: usize, y: usize) -> Vec<u8> {
+        (0..32 * 32)
+            .map(|i| {
+                let (px, py) = (i % 32, i / 32);
+                if (x..x + 6).contains(&px) && (y..y + 6).contains(&py) {
+                    200
+                } else {
+                    10
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_frame_shifts() {
+        let frames = vec![square(10, 12), square(14, 10), square(8, 12)];
+        let video = ser_video(&ser_format(32, 32, Bayer::Mono, 8), &frames);
+        let shifts = frame_shifts(&video, &[0, 1, 2], 0).unwrap();
+        assert_eq!(
+            vec![
+                Shift::default(),
+                Shift { dx: -4, dy: 2 },
+                Shift { dx: 2, dy: 0 }
+            ],
+            shifts
+        );
+        // the shifted frame lines up with the reference
+        let values = frames[1].iter().map(|v| *v as u16).collect::<Vec<_>>();
+        let aligned = shift_samples(&values, 32, 32, 1, 1, shifts[1]);
+        let reference = frames[0].iter().map(|v| *v as u16).collect::<Vec<_>>();
+        assert_eq!(reference, aligned);
+    }
+
+    #[test]
+    fn test_centering() {
+        // a bright 2x2 planet in the top left of a dim 8x6 frame
+        let mut pixels = [20, 20, 20, 255].repeat(8 * 6);
+        for (x, y) in [(1, 1), (2, 1), (1, 2), (2, 2)] {
+            pixels[(y * 8 + x) * 4..][..3].copy_from_slice(&[200, 200, 200]);
+        }
+        assert_eq!(Some((1.5, 1.5)), centroid(8, 6, &pixels));
+        let shift = centering_shift(8, 6, &pixels);
+        assert_eq!(Shift { dx: 2, dy: 1 }, shift);
+        let centered = shift_bgra(8, 6, &pixels, shift);
+        assert_eq!(&[200, 200, 200, 255], &centered[(2 * 8 + 3) * 4..][..4]);
+        assert_eq!(&[0, 0, 0, 255], &centered[0..4]);
+        assert_eq!(Some((3.5, 2.5)), centroid(8, 6, &centered));
+
+        // nothing stands out in a uniform frame
+        assert_eq!(None, centroid(2, 2, &[50; 16]));
+    }
+
+    #[test]
+    fn test_shift_keeps_bayer_pattern() {
+        // a 4x2 RGGB frame where every sample names its color: 1 = R, 2 = G, 3 = B
+        let values = vec![1, 2, 1, 2, 2, 3, 2, 3];
+        let shifted = shift_samples(&values, 4, 2, 1, 2, Shift { dx: 2, dy: 0 });
+        assert_eq!(values, shifted);
+        let shifted = shift_samples(&values, 4, 2, 1, 2, Shift { dx: -2, dy: 2 });
+        assert_eq!(values, shifted);
+    }
+}
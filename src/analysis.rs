@@ -0,0 +1,257 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use rayon::prelude::*;
+
+use crate::error::Result;
+use crate::video_format::{check_uncompressed, samples, stored_row, Bayer, Video};
+
+/// How much more alike the two green samples of each 2x2 cell must be than the other two
+/// samples for a frame to be judged to be a Bayer mosaic
+const BAYER_CONTRAST: f64 = 1.25;
+
+/// Basic statistics of the raw sample values in a frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameStats {
+    pub min: u16,
+    pub max: u16,
+    pub mean: f64,
+}
+
+/// Calculate statistics for the raw (undecoded) sample values of a frame
+pub fn frame_stats(video: &dyn Video, index: usize) -> Result<FrameStats> {
+    check_uncompressed(video)?;
+    Ok(stats(video, &video.read_frame(index)?))
+}
+
+/// Calculate statistics for every frame in a video, in parallel
+pub fn sequence_stats(video: &dyn Video) -> Result<Vec<FrameStats>> {
+    check_uncompressed(video)?;
+    video.par_map_frames(|_, frame| stats(video, frame.bytes))
+}
+
+fn stats(video: &dyn Video, bytes: &[u8]) -> FrameStats {
+    let mut min = u16::MAX;
+    let mut max = 0;
+    let mut sum = 0_u64;
+    let mut count = 0_u64;
+    for value in samples(video, bytes) {
+        min = min.min(value);
+        max = max.max(value);
+        sum += value as u64;
+        count += 1;
+    }
+    if count == 0 {
+        min = 0;
+    }
+    FrameStats {
+        min,
+        max,
+        mean: if count == 0 {
+            0.0
+        } else {
+            sum as f64 / count as f64
+        },
+    }
+}
+
+/// Most likely Bayer pattern of the raw frames of a video, for files that do not record it
+/// such as raw AVI files. Each 2x2 cell of a mosaic holds two green samples on one diagonal,
+/// which are much more alike than the red and blue samples on the other diagonal. The
+/// brighter of red and blue is taken to be red, since blue is usually the least sensitive
+/// channel of color astronomy cameras. Returns `None` if the frames look monochrome.
+pub fn detect_bayer(video: &dyn Video) -> Result<Option<Bayer>> {
+    check_uncompressed(video)?;
+    let (width, height) = (video.image_width() as usize, video.image_height() as usize);
+    let count = video.frame_count();
+    let bytes_per_row = width * video.bytes_per_pixel() as usize;
+    // sums of the samples at each position of the cells, in the order top left, top right,
+    // bottom left and bottom right, and of the differences along each diagonal
+    let mut sums = [0_f64; 4];
+    let (mut main_diagonal, mut anti_diagonal) = (0_f64, 0_f64);
+    let mut indices = vec![0, count / 2, count.saturating_sub(1)];
+    indices.dedup();
+    for index in indices.into_iter().filter(|index| *index < count) {
+        let frame = video.read_frame(index)?;
+        let row = |y: usize| {
+            let start = stored_row(video, y as u32) as usize * bytes_per_row;
+            frame
+                .get(start..start + bytes_per_row)
+                .map(|row| samples(video, row).collect::<Vec<_>>())
+        };
+        for y in (0..height.saturating_sub(1)).step_by(2) {
+            let (top, bottom) = match (row(y), row(y + 1)) {
+                (Some(top), Some(bottom)) => (top, bottom),
+                _ => break,
+            };
+            for (top, bottom) in top.chunks_exact(2).zip(bottom.chunks_exact(2)) {
+                let cell = [top[0], top[1], bottom[0], bottom[1]].map(|value| value as f64);
+                for (sum, value) in sums.iter_mut().zip(&cell) {
+                    *sum += value;
+                }
+                main_diagonal += (cell[0] - cell[3]).abs();
+                anti_diagonal += (cell[1] - cell[2]).abs();
+            }
+        }
+    }
+    let is_red = |position: usize, other: usize| sums[position] >= sums[other];
+    Ok(if anti_diagonal * BAYER_CONTRAST < main_diagonal {
+        // green at the top right and bottom left
+        Some(if is_red(0, 3) {
+            Bayer::RGGB
+        } else {
+            Bayer::BGGR
+        })
+    } else if main_diagonal * BAYER_CONTRAST < anti_diagonal {
+        // green at the top left and bottom right
+        Some(if is_red(1, 2) {
+            Bayer::GRBG
+        } else {
+            Bayer::GBRG
+        })
+    } else {
+        None
+    })
+}
+
+/// Number of pixels at each 8-bit level of each channel of a decoded BGRA image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub red: [u32; 256],
+    pub green: [u32; 256],
+    pub blue: [u32; 256],
+    /// Rec. 709 luminance
+    pub luminance: [u32; 256],
+    pub pixel_count: u32,
+}
+
+impl Histogram {
+    fn empty() -> Self {
+        Self {
+            red: [0; 256],
+            green: [0; 256],
+            blue: [0; 256],
+            luminance: [0; 256],
+            pixel_count: 0,
+        }
+    }
+
+    /// Count the levels of a BGRA image, in parallel
+    pub fn from_bgra(pixels: &[u8]) -> Self {
+        pixels
+            .par_chunks(4 * 4096)
+            .fold(Self::empty, |mut histogram, chunk| {
+                for pixel in chunk.chunks_exact(4) {
+                    let (b, g, r) = (pixel[0], pixel[1], pixel[2]);
+                    histogram.blue[b as usize] += 1;
+                    histogram.green[g as usize] += 1;
+                    histogram.red[r as usize] += 1;
+                    // weights in 1/1024ths, which add up to 1024
+                    let luminance = (218 * r as u32 + 732 * g as u32 + 74 * b as u32) >> 10;
+                    histogram.luminance[luminance as usize] += 1;
+                    histogram.pixel_count += 1;
+                }
+                histogram
+            })
+            .reduce(Self::empty, |mut a, b| {
+                for i in 0..256 {
+                    a.red[i] += b.red[i];
+                    a.green[i] += b.green[i];
+                    a.blue[i] += b.blue[i];
+                    a.luminance[i] += b.luminance[i];
+                }
+                a.pixel_count += b.pixel_count;
+                a
+            })
+    }
+
+    /// Fraction of pixels at the maximum level in the red, green and blue channels, which
+    /// indicates overexposure
+    pub fn clipped(&self) -> [f64; 3] {
+        let fraction = |counts: &[u32; 256]| counts[255] as f64 / self.pixel_count.max(1) as f64;
+        [
+            fraction(&self.red),
+            fraction(&self.green),
+            fraction(&self.blue),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{ser_format, ser_video};
+
+    #[test]
+    fn test_detect_bayer() {
+        // an orange planet on a dark noisy background, as captured through each pattern
+        let (width, height) = (32, 24);
+        let color = |x: usize, y: usize| {
+            let noise = ((x * 73_856_093) ^ (y * 19_349_663)) as u32 % 7;
+            let (dx, dy) = (x as i32 - 16, y as i32 - 12);
+            if dx * dx + dy * dy < 64 {
+                [180 + noise, 120 + noise, 40 + noise]
+            } else {
+                [10 + noise, 12 + noise, 8 + noise]
+            }
+        };
+        for &bayer in &[Bayer::RGGB, Bayer::BGGR, Bayer::GRBG, Bayer::GBRG] {
+            let pattern = format!("{:?}", bayer);
+            let frame = (0..width * height)
+                .map(|i| {
+                    let (x, y) = (i % width, i / width);
+                    let channel = match &pattern[2 * (y % 2) + x % 2..][..1] {
+                        "R" => 0,
+                        "G" => 1,
+                        _ => 2,
+                    };
+                    color(x, y)[channel] as u8
+                })
+                .collect::<Vec<_>>();
+            let video = ser_video(&ser_format(32, 24, Bayer::Mono, 8), &[frame]);
+            assert_eq!(Some(bayer), detect_bayer(&video).unwrap());
+        }
+
+        let gray = (0..width * height)
+            .map(|i| color(i % width, i / width)[1] as u8)
+            .collect::<Vec<_>>();
+        let video = ser_video(&ser_format(32, 24, Bayer::Mono, 8), &[gray]);
+        assert_eq!(None, detect_bayer(&video).unwrap());
+    }
+
+    #[test]
+    fn test_histogram() {
+        let pixels = [[0, 0, 255, 255], [255, 255, 255, 255], [10, 20, 30, 255]].concat();
+        let histogram = Histogram::from_bgra(&pixels);
+        assert_eq!(3, histogram.pixel_count);
+        assert_eq!(1, histogram.red[30]);
+        assert_eq!(2, histogram.red[255]);
+        assert_eq!(1, histogram.green[20]);
+        assert_eq!(1, histogram.blue[10]);
+        assert_eq!(1, histogram.luminance[255]);
+        assert_eq!(1, histogram.luminance[54]);
+        let clipped = histogram
+            .clipped()
+            .map(|fraction| (fraction * 3.0).round() as u32);
+        assert_eq!([2, 1, 1], clipped);
+    }
+}
@@ -0,0 +1,300 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Export of a range of frames as an animation, for sharing short clips of the seeing. The
+//! frames are decoded with the chosen codec and shown with the same white balance, stretch
+//! and color map as in the player. Animated GIFs are always available, and MP4 videos are written by piping
+//! the frames to `ffmpeg`, which requires the `ffmpeg` feature.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+
+use crate::codec::ImageCodec;
+use crate::error::{image_error, PlayerError, Result};
+use crate::processing::{ColorMap, Stretch, WhiteBalance};
+use crate::sidecar::sidecar_path;
+use crate::video_format::Video;
+
+/// Speed of the GIF color quantizer, from 1 (best quality) to 30 (fastest)
+const GIF_SPEED: i32 = 10;
+
+/// Formats that a range of frames can be exported to as an animation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    /// Animated GIF, which loops forever
+    Gif,
+    /// H.264 video encoded by ffmpeg
+    Mp4,
+}
+
+/// Names of the animation formats, as accepted by `parse_animation_format`
+pub const ANIMATION_FORMAT_NAMES: &[&str] = &["gif", "mp4"];
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Mp4 => "mp4",
+        }
+    }
+}
+
+pub fn parse_animation_format(name: &str) -> Result<AnimationFormat> {
+    match name.to_ascii_lowercase().as_str() {
+        "gif" => Ok(AnimationFormat::Gif),
+        "mp4" => Ok(AnimationFormat::Mp4),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown animation format '{}', expected one of {}",
+            name,
+            ANIMATION_FORMAT_NAMES.join(", ")
+        ))),
+    }
+}
+
+/// How the frames of an animation are rendered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimationOptions {
+    /// Frames per second
+    pub fps: f64,
+    /// Only every `scale`th pixel is kept in each direction, which keeps files small
+    pub scale: u32,
+    pub white_balance: WhiteBalance,
+    pub stretch: Stretch,
+    pub color_map: ColorMap,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            fps: 10.0,
+            scale: 1,
+            white_balance: WhiteBalance::default(),
+            stretch: Stretch::default(),
+            color_map: ColorMap::default(),
+        }
+    }
+}
+
+/// Render frames of a video into an animation
+pub fn export_animation(
+    video: &dyn Video,
+    codec: &dyn ImageCodec,
+    indices: &[usize],
+    format: AnimationFormat,
+    options: &AnimationOptions,
+    path: &Path,
+) -> Result<()> {
+    if indices.is_empty() {
+        return Err(PlayerError::InvalidArgument(
+            "There are no frames to export".to_string(),
+        ));
+    }
+    if options.fps.is_nan() || options.fps <= 0.0 {
+        return Err(PlayerError::InvalidArgument(format!(
+            "Frame rate must be positive, not {}",
+            options.fps
+        )));
+    }
+    match format {
+        AnimationFormat::Gif => {
+            let out = BufWriter::new(File::create(path)?);
+            write_gif(video, codec, indices, options, out)
+        }
+        AnimationFormat::Mp4 => write_mp4(video, codec, indices, options, path),
+    }
+}
+
+/// Default name for an animation of a video, such as `jupiter_animation.gif`, in the same
+/// directory as the video
+pub fn animation_path(video_path: Option<&Path>, format: AnimationFormat) -> PathBuf {
+    sidecar_path(video_path, &format!("_animation.{}", format.extension()))
+}
+
+/// Decode a frame into `buffer` as BGRA pixels and apply the white balance, stretch and
+/// color map
+fn render(
+    video: &dyn Video,
+    codec: &dyn ImageCodec,
+    index: usize,
+    options: &AnimationOptions,
+    buffer: &mut Vec<u8>,
+) -> Result<(u32, u32)> {
+    let size = codec.decode_scaled_into(video, index, options.scale.max(1), buffer)?;
+    options.white_balance.apply(buffer);
+    options.stretch.apply(buffer);
+    options.color_map.apply(buffer);
+    Ok(size)
+}
+
+/// Make sure every frame of an animation has the same size as the first
+fn check_size(index: usize, size: (u32, u32), expected: (u32, u32)) -> Result<()> {
+    if size == expected {
+        Ok(())
+    } else {
+        Err(PlayerError::Decode(format!(
+            "Frame {} is {}x{} but the animation is {}x{}",
+            index, size.0, size.1, expected.0, expected.1
+        )))
+    }
+}
+
+fn write_gif(
+    video: &dyn Video,
+    codec: &dyn ImageCodec,
+    indices: &[usize],
+    options: &AnimationOptions,
+    out: impl Write,
+) -> Result<()> {
+    let mut encoder = GifEncoder::new_with_speed(out, GIF_SPEED);
+    encoder.set_repeat(Repeat::Infinite).map_err(image_error)?;
+    // GIF delays are stored in hundredths of a second
+    let delay = Delay::from_numer_denom_ms(100_000, (options.fps * 100.0).round() as u32);
+    let mut buffer = vec![];
+    let mut expected = None;
+    for &index in indices {
+        let size = render(video, codec, index, options, &mut buffer)?;
+        check_size(index, size, *expected.get_or_insert(size))?;
+        for pixel in buffer.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        let image = RgbaImage::from_raw(size.0, size.1, buffer.clone()).ok_or_else(|| {
+            PlayerError::Decode(format!("Frame {} has the wrong number of pixels", index))
+        })?;
+        encoder
+            .encode_frame(Frame::from_parts(image, 0, 0, delay))
+            .map_err(image_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "ffmpeg")]
+fn write_mp4(
+    video: &dyn Video,
+    codec: &dyn ImageCodec,
+    indices: &[usize],
+    options: &AnimationOptions,
+    path: &Path,
+) -> Result<()> {
+    use std::io::Error;
+    use std::process::{Command, Stdio};
+
+    let mut buffer = vec![];
+    let size = render(video, codec, indices[0], options, &mut buffer)?;
+    let mut ffmpeg = Command::new("ffmpeg")
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "bgra",
+        ])
+        .arg("-s")
+        .arg(format!("{}x{}", size.0, size.1))
+        .arg("-r")
+        .arg(options.fps.to_string())
+        .args(["-i", "-", "-c:v", "libx264", "-pix_fmt", "yuv420p"])
+        // H.264 needs even dimensions
+        .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::new(e.kind(), format!("Could not run ffmpeg: {}", e)))?;
+    let mut stdin = ffmpeg.stdin.take().expect("stdin is piped");
+    let mut write_frames = || -> Result<()> {
+        stdin.write_all(&buffer)?;
+        for &index in &indices[1..] {
+            check_size(
+                index,
+                render(video, codec, index, options, &mut buffer)?,
+                size,
+            )?;
+            stdin.write_all(&buffer)?;
+        }
+        Ok(())
+    };
+    let written = write_frames();
+    // closing stdin tells ffmpeg that there are no more frames
+    drop(stdin);
+    let status = ffmpeg.wait()?;
+    written?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!("ffmpeg failed with {}", status)).into())
+    }
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+fn write_mp4(
+    _video: &dyn Video,
+    _codec: &dyn ImageCodec,
+    _indices: &[usize],
+    _options: &AnimationOptions,
+    _path: &Path,
+) -> Result<()> {
+    crate::capabilities::Capability::FfmpegExport.require()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::DebayerCodec;
+    use crate::testutil::{gradient_frame, ser_format, ser_video};
+    use crate::video_format::Bayer;
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+
+    #[test]
+    fn test_gif() {
+        let format = ser_format(16, 12, Bayer::RGGB, 8);
+        let frames = (0..3)
+            .map(|i| gradient_frame(&format, i))
+            .collect::<Vec<_>>();
+        let video = ser_video(&format, &frames);
+        let options = AnimationOptions {
+            fps: 20.0,
+            ..AnimationOptions::default()
+        };
+        let mut gif = vec![];
+        write_gif(&video, &DebayerCodec {}, &[0, 2], &options, &mut gif).unwrap();
+        let frames = GifDecoder::new(gif.as_slice())
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(2, frames.len());
+        assert_eq!((8, 6), frames[0].buffer().dimensions());
+        assert_eq!(Delay::from_numer_denom_ms(50, 1), frames[0].delay());
+    }
+
+    #[test]
+    fn test_parse_animation_format() {
+        assert_eq!(AnimationFormat::Mp4, parse_animation_format("MP4").unwrap());
+        assert!(parse_animation_format("avi").is_err());
+    }
+}
@@ -0,0 +1,361 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Arrows and text labels placed on frames to point out detections, which are kept in a JSON
+//! file next to the video and can be burned into exported PNG frames
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PlayerError, Result};
+use crate::sidecar::sidecar_path;
+
+/// Height of the glyphs of the built-in font, in font pixels. Each glyph is 5 font pixels
+/// wide with a gap of one between glyphs.
+const GLYPH_HEIGHT: usize = 7;
+
+/// Glyphs of a 5x7 font, one byte per row with the leftmost pixel in bit 4. Lower case
+/// letters are drawn as upper case and other missing characters as `?`.
+const GLYPHS: &[(char, [u8; GLYPH_HEIGHT])] = &[
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    ('A', [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    (';', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08]),
+    ('!', [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04]),
+    ('?', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+    ('=', [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('(', [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02]),
+    (')', [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08]),
+    ('<', [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02]),
+    ('>', [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08]),
+    ('\'', [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00]),
+    ('"', [0x0A, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00]),
+    ('#', [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('*', [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]),
+    ('°', [0x0C, 0x12, 0x12, 0x0C, 0x00, 0x00, 0x00]),
+];
+
+/// An arrow or text label on one frame, with positions in frame pixels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    /// Frame that the annotation is shown on
+    pub frame: usize,
+    /// Position of the label, which is also the tail of the arrow
+    pub x: f32,
+    pub y: f32,
+    /// Point that the arrow points at, if this is an arrow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<(f32, f32)>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub text: String,
+}
+
+impl Annotation {
+    /// Center of the label, which is moved back from the tail of an arrow by `distance` so
+    /// that the label does not cover the arrow
+    pub fn label_center(&self, distance: f32) -> (f32, f32) {
+        match self.to {
+            Some((to_x, to_y)) => {
+                let (dx, dy) = (to_x - self.x, to_y - self.y);
+                let length = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+                (
+                    self.x - dx / length * distance,
+                    self.y - dy / length * distance,
+                )
+            }
+            None => (self.x, self.y),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AnnotationFile {
+    annotations: Vec<Annotation>,
+}
+
+/// File that the annotations of a video are kept in, which is named after the video and
+/// written next to it
+pub fn annotations_path(video_path: Option<&Path>) -> PathBuf {
+    sidecar_path(video_path, "_annotations.json")
+}
+
+/// Load annotations, which are empty if the file does not exist
+pub fn load_annotations(path: &Path) -> Result<Vec<Annotation>> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str::<AnnotationFile>(&text)
+            .map(|file| file.annotations)
+            .map_err(|e| PlayerError::Format(format!("{}: {}", path.display(), e))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Save annotations, removing the file instead when there are none
+pub fn save_annotations(path: &Path, annotations: &[Annotation]) -> Result<()> {
+    if annotations.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    let file = AnnotationFile {
+        annotations: annotations.to_vec(),
+    };
+    let text =
+        serde_json::to_string_pretty(&file).map_err(|e| PlayerError::Format(e.to_string()))?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Draw the annotations of a frame into its decoded BGRA pixels in white with a dark
+/// outline, so that they can be seen on both the sky and bright targets. `origin` is the
+/// frame pixel at the top left of the image, for images of a region of interest. Lines and
+/// text are scaled with the image so that they stay legible on large frames.
+pub fn burn_annotations(
+    pixels: &mut [u8],
+    width: u32,
+    height: u32,
+    annotations: &[Annotation],
+    origin: (u32, u32),
+) {
+    let mut canvas = Canvas {
+        pixels,
+        width: width as i64,
+        height: height as i64,
+    };
+    let scale = (width.max(height) / 320).max(1) as f32;
+    let (ox, oy) = (origin.0 as f32, origin.1 as f32);
+    // the outline is drawn first so that it does not cover neighbouring strokes
+    for (color, grow) in [([0, 0, 0, 255], scale), ([255, 255, 255, 255], 0.0)] {
+        for annotation in annotations {
+            let from = (annotation.x - ox, annotation.y - oy);
+            if let Some((to_x, to_y)) = annotation.to {
+                let to = (to_x - ox, to_y - oy);
+                canvas.line(from, to, scale + 2.0 * grow, color);
+                let (dx, dy) = (from.0 - to.0, from.1 - to.1);
+                let length = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+                let (dx, dy) = (dx / length * 6.0 * scale, dy / length * 6.0 * scale);
+                for side in [-1.0, 1.0] {
+                    let head = (
+                        to.0 + dx * 0.8 - side * dy * 0.5,
+                        to.1 + dy * 0.8 + side * dx * 0.5,
+                    );
+                    canvas.line(to, head, scale + 2.0 * grow, color);
+                }
+            }
+            if !annotation.text.is_empty() {
+                let distance = (GLYPH_HEIGHT as f32 + 2.0) * scale;
+                let (x, y) = annotation.label_center(distance);
+                canvas.text(
+                    (x - ox, y - oy),
+                    &annotation.text,
+                    scale,
+                    grow / scale,
+                    color,
+                );
+            }
+        }
+    }
+}
+
+/// BGRA pixels to draw on, clipping everything to the image
+struct Canvas<'a> {
+    pixels: &'a mut [u8],
+    width: i64,
+    height: i64,
+}
+
+impl Canvas<'_> {
+    /// Fill a square of pixels centered on a point
+    fn dot(&mut self, (x, y): (f32, f32), size: f32, color: [u8; 4]) {
+        let half = size / 2.0;
+        let (left, top) = ((x - half).round() as i64, (y - half).round() as i64);
+        let (right, bottom) = ((x + half).round() as i64, (y + half).round() as i64);
+        for py in top.max(0)..bottom.max(top + 1).min(self.height) {
+            for px in left.max(0)..right.max(left + 1).min(self.width) {
+                let i = ((py * self.width + px) * 4) as usize;
+                self.pixels[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), width: f32, color: [u8; 4]) {
+        let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+        let steps = (length * 2.0).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let point = (from.0 + t * (to.0 - from.0), from.1 + t * (to.1 - from.1));
+            self.dot(point, width, color);
+        }
+    }
+
+    /// Draw text centered on a point, with each font pixel drawn `scale` image pixels wide
+    /// and grown by `grow` font pixels on each side for the outline
+    fn text(&mut self, center: (f32, f32), text: &str, scale: f32, grow: f32, color: [u8; 4]) {
+        let count = text.chars().count() as f32;
+        let left = center.0 - (count * 6.0 - 1.0) * scale / 2.0;
+        let top = center.1 - GLYPH_HEIGHT as f32 * scale / 2.0;
+        for (i, c) in text.chars().enumerate() {
+            let rows = glyph(c);
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..5 {
+                    if bits & (0x10 >> column) != 0 {
+                        let x = left + (i as f32 * 6.0 + column as f32 + 0.5) * scale;
+                        let y = top + (row as f32 + 0.5) * scale;
+                        self.dot((x, y), scale * (1.0 + 2.0 * grow), color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .find(|(glyph, _)| *glyph == c)
+        .or_else(|| GLYPHS.iter().find(|(glyph, _)| *glyph == '?'))
+        .map(|(_, rows)| *rows)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!(
+            "astro_video_player_annotations_{}.json",
+            std::process::id()
+        ));
+        let annotations = vec![
+            Annotation {
+                frame: 3,
+                x: 10.0,
+                y: 20.0,
+                to: Some((30.0, 40.0)),
+                text: "Satellite".to_string(),
+            },
+            Annotation {
+                frame: 7,
+                x: 5.5,
+                y: 6.5,
+                to: None,
+                text: "Flash".to_string(),
+            },
+        ];
+        save_annotations(&path, &annotations).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("\"to\": null"));
+        assert_eq!(annotations, load_annotations(&path).unwrap());
+
+        // saving no annotations removes the file
+        save_annotations(&path, &[]).unwrap();
+        assert!(!path.exists());
+        assert!(load_annotations(&path).unwrap().is_empty());
+        save_annotations(&path, &[]).unwrap();
+
+        assert_eq!(
+            Path::new("/data/jupiter_annotations.json"),
+            annotations_path(Some(Path::new("/data/jupiter.ser")))
+        );
+    }
+
+    #[test]
+    fn test_burn_annotations() {
+        let (width, height) = (40, 30);
+        let mut pixels = vec![100; width * height * 4];
+        let pixel = |pixels: &[u8], x: usize, y: usize| {
+            let i = (y * width + x) * 4;
+            [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+        };
+        let annotations = vec![Annotation {
+            frame: 0,
+            x: 12.0,
+            y: 15.0,
+            to: Some((32.0, 15.0)),
+            text: "I".to_string(),
+        }];
+        // the image is a region whose top left is frame pixel (2, 0)
+        burn_annotations(
+            &mut pixels,
+            width as u32,
+            height as u32,
+            &annotations,
+            (2, 0),
+        );
+        assert_eq!([255; 4], pixel(&pixels, 20, 15));
+        // outlined in black
+        assert_eq!([0, 0, 0, 255], pixel(&pixels, 20, 16));
+        // the label is moved back from the tail of the arrow, and the middle column of "I"
+        // is drawn in white
+        assert_eq!([255; 4], pixel(&pixels, 1, 15));
+        assert_eq!([100; 4], pixel(&pixels, 20, 5));
+    }
+}
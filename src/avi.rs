@@ -20,15 +20,22 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::fmt::Display;
-use std::io::{Error, ErrorKind, Result};
+use std::fs::File;
+use std::io::Read;
 use std::str;
-use std::{error, fmt};
 
-use riff_io::{ChunkMeta, Entry, FourCC, ListMeta, RiffFile};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use memmap::Mmap;
+use riff_io::{ChunkMeta, Entry, FourCC, ListMeta};
+use tracing::{debug, instrument};
+
+use crate::error::{PlayerError, Result};
+use crate::video_format::OpenMode;
 
 // use https://www.rapidtables.com/convert/number/ascii-to-hex.html
 
+const FOURCC_RIFF: FourCC = [0x52, 0x49, 0x46, 0x46];
+const FOURCC_LIST: FourCC = [0x4c, 0x49, 0x53, 0x54];
 const FOURCC_AVIH: FourCC = [0x61, 0x76, 0x69, 0x68];
 const FOURCC_HDRL: FourCC = [0x68, 0x64, 0x72, 0x6c];
 const FOURCC_STRH: FourCC = [0x73, 0x74, 0x72, 0x68];
@@ -38,20 +45,136 @@ const FOURCC_STRL: FourCC = [0x73, 0x74, 0x72, 0x6c];
 const FOURCC_MOVI: FourCC = [0x6d, 0x6f, 0x76, 0x69];
 const FOURCC_VIDS: FourCC = [0x76, 0x69, 0x64, 0x73];
 const FOURCC_DIB_: FourCC = [0x44, 0x49, 0x42, 0x20];
+const FOURCC_00DB: FourCC = [0x30, 0x30, 0x64, 0x62];
+const FOURCC_00DC: FourCC = [0x30, 0x30, 0x64, 0x63];
+const FOURCC_MJPG: FourCC = [0x4d, 0x4a, 0x50, 0x47];
+const FOURCC_Y16_: FourCC = [0x59, 0x31, 0x36, 0x20];
+
+/// `biCompression` value for uncompressed pixels described by color masks
+const BI_BITFIELDS: u32 = 3;
+
+/// Sizes of the header structures
+const MAIN_HEADER_SIZE: usize = 44;
+const STREAM_HEADER_SIZE: usize = 56;
+const BITMAP_INFO_HEADER_SIZE: usize = 40;
 
+/// Maximum nesting of lists, so that a malicious file cannot exhaust the stack
+const MAX_LIST_DEPTH: usize = 16;
+
+/// A memory-mapped AVI file containing a single stream of uncompressed or MJPEG frames. This
+/// provides access to the headers and raw frame data; use `AviVideo` to decode the frames.
 pub struct AviFile {
-    riff: RiffFile,
-    main_header: AviMainHeader,
-    stream_header: AviStreamHeader,
-    stream_format: BitMapInfo,
-    /// chunk meta for the image frames
-    frames: Vec<ChunkMeta>,
+    mmap: Mmap,
+    layout: AviLayout,
 }
 
 impl AviFile {
+    /// Open a file, reading as many frames as possible from files that are damaged
     pub fn open(filename: &str) -> Result<Self> {
-        let riff = RiffFile::open(filename)?;
-        let entries = riff.read_entries()?;
+        Self::open_with_mode(filename, OpenMode::Lenient)
+    }
+
+    /// Open a file using the given level of validation
+    #[instrument]
+    pub fn open_with_mode(filename: &str, mode: OpenMode) -> Result<Self> {
+        let file = File::open(filename)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let layout = AviLayout::parse(&mmap, mode)?;
+        debug!(
+            frames = layout.frames.len(),
+            width = layout.main_header.width,
+            height = layout.main_header.height,
+            "parsed AVI headers"
+        );
+        Ok(Self { mmap, layout })
+    }
+
+    pub fn main_header(&self) -> &AviMainHeader {
+        &self.layout.main_header
+    }
+
+    pub fn stream_header(&self) -> &AviStreamHeader {
+        &self.layout.stream_header
+    }
+
+    pub fn stream_format(&self) -> &BitMapInfo {
+        &self.layout.stream_format
+    }
+
+    /// Values from the main header, stream header and stream format, as `(name, value)`
+    /// pairs for `Video::header_fields`
+    pub fn header_fields(&self) -> Vec<(String, String)> {
+        let main = self.main_header();
+        let stream = self.stream_header();
+        let format = &self.stream_format().header;
+        let fields = [
+            (
+                "Microseconds per frame",
+                main.micro_sec_per_frame.to_string(),
+            ),
+            ("Max bytes per second", main.max_bytes_per_sec.to_string()),
+            ("Main header flags", format!("{:#x}", main.flags)),
+            ("Main header frames", main.total_frames.to_string()),
+            ("Streams", main.streams.to_string()),
+            (
+                "Suggested buffer size",
+                main.suggested_buffer_size.to_string(),
+            ),
+            ("Stream handler", format_fourcc(stream.fcc_handler)),
+            ("Stream rate", format!("{}/{}", stream.rate, stream.scale)),
+            ("Stream start", stream.start.to_string()),
+            ("Stream length", stream.length.to_string()),
+            ("Stream quality", stream.quality.to_string()),
+            (
+                "DIB compression",
+                match format.compression {
+                    0 => "RGB".to_string(),
+                    BI_BITFIELDS => "bit fields".to_string(),
+                    fourcc => format_fourcc(fourcc.to_le_bytes()),
+                },
+            ),
+            ("DIB bit count", format.bit_count.to_string()),
+            ("DIB height", format.height.to_string()),
+            ("DIB image size", format.size_image.to_string()),
+            (
+                "Pixels per meter",
+                format!("{}x{}", format.x_pels_per_meter, format.y_pels_per_meter),
+            ),
+            ("Colors used", format.clr_used.to_string()),
+        ];
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    /// Location of each frame within the file
+    pub fn frames(&self) -> &[ChunkMeta] {
+        &self.layout.frames
+    }
+
+    /// Raw bytes of a frame
+    pub fn read_bytes(&self, chunk_meta: &ChunkMeta) -> &[u8] {
+        // the parser only returns chunks that are within the bounds of the file
+        &self.mmap[chunk_meta.data_offset..chunk_meta.data_offset + chunk_meta.data_size]
+    }
+}
+
+/// The headers of an AVI file and the location of each frame
+#[derive(Debug)]
+pub struct AviLayout {
+    pub main_header: AviMainHeader,
+    pub stream_header: AviStreamHeader,
+    pub stream_format: BitMapInfo,
+    /// chunk meta for the image frames
+    pub frames: Vec<ChunkMeta>,
+}
+
+impl AviLayout {
+    /// Parse the contents of an AVI file. This never panics, regardless of the input, and
+    /// every frame chunk that is returned is within the bounds of `bytes`.
+    pub fn parse(bytes: &[u8], mode: OpenMode) -> Result<Self> {
+        let entries = parse_riff(bytes, mode)?;
 
         /*
                 LIST 'hdrl'
@@ -76,36 +199,68 @@ impl AviFile {
         // main header
         let hdrl = find_mandatory_list(&entries, FOURCC_HDRL)?;
         let chunk = find_mandatory_chunk(hdrl, FOURCC_AVIH)?;
-        let main_header = parse_main_header(&riff, chunk)?;
+        let main_header = parse_main_header(bytes, chunk)?;
 
         // get first stream header and format (only one stream is currently supported)
         let strl = find_mandatory_list_in_list(hdrl, FOURCC_STRL)?;
         let strh = find_mandatory_chunk(strl, FOURCC_STRH)?;
-        let stream_header = parse_stream_header(&riff, strh)?;
+        let stream_header = parse_stream_header(bytes, strh)?;
         // only video streams are supported (no audio)
         if stream_header.fcc_type != FOURCC_VIDS {
-            return Err(Error::new(
-                ErrorKind::Other,
-                AviError::new(format!(
-                    "Unsupported stream format {}",
-                    format_fourcc(stream_header.fcc_type)
-                )),
-            ));
+            return Err(PlayerError::Unsupported(format!(
+                "Unsupported stream format {}",
+                format_fourcc(stream_header.fcc_type)
+            )));
         }
-        // only DIBS is supported. No H264 support.
-        if stream_header.fcc_handler != FOURCC_DIB_ {
-            return Err(Error::new(
-                ErrorKind::Other,
-                AviError::new(format!(
-                    "Unsupported stream codec {}",
-                    format_fourcc(stream_header.fcc_handler)
-                )),
-            ));
+        // only DIBS and MJPEG are supported. No H264 support. Some writers leave the handler
+        // empty and only set the compression of the stream format.
+        let mut handler = stream_header.fcc_handler;
+        handler.make_ascii_uppercase();
+        if ![FOURCC_DIB_, FOURCC_MJPG, FOURCC_Y16_, [0; 4]].contains(&handler) {
+            return Err(PlayerError::Unsupported(format!(
+                "Unsupported stream codec {}",
+                format_fourcc(stream_header.fcc_handler)
+            )));
         }
 
         // parse stream format
         let strf = find_mandatory_chunk(strl, FOURCC_STRF)?;
-        let stream_format = parse_stream_format(&riff, strf)?;
+        let stream_format = parse_stream_format(bytes, strf)?;
+        // frames are sized from the main header and decoded using the stream format, so a
+        // file where they disagree cannot be read safely
+        let format = &stream_format.header;
+        if main_header.width != format.width as u32
+            || main_header.height != format.height.unsigned_abs()
+        {
+            return Err(PlayerError::Format(format!(
+                "AVI main header is {}x{} but the stream format is {}x{}",
+                main_header.width,
+                main_header.height,
+                format.width,
+                format.height.unsigned_abs()
+            )));
+        }
+        if main_header.width == 0 || main_header.height == 0 {
+            return Err(PlayerError::Format(format!(
+                "Invalid AVI image size {}x{}",
+                main_header.width, main_header.height
+            )));
+        }
+        // uncompressed frames are read using the size and depth from the header, which must
+        // describe frames that fit in the file. Compressed frames vary in size.
+        let frame_size = match stream_format.color_coding {
+            ColorCoding::MJPEG => 0,
+            _ => main_header.width as u64 * format.bit_count as u64 / 8 * main_header.height as u64,
+        };
+        if frame_size > bytes.len() as u64 {
+            return Err(PlayerError::Format(format!(
+                "AVI frames are {}x{} with {} bits per pixel, which is larger than the {} byte file",
+                main_header.width,
+                main_header.height,
+                format.bit_count,
+                bytes.len()
+            )));
+        }
 
         let _indx = find_mandatory_chunk(strl, FOURCC_INDX)?;
 
@@ -116,86 +271,262 @@ impl AviFile {
             .children
             .iter()
             .filter_map(|e| match e {
-                Entry::Chunk(chunk) if chunk.chunk_id == [0x30, 0x30, 0x64, 0x62] => {
+                // uncompressed ('db') or compressed ('dc') frames of the first stream
+                Entry::Chunk(chunk)
+                    if chunk.chunk_id == FOURCC_00DB || chunk.chunk_id == FOURCC_00DC =>
+                {
                     Some(chunk.clone())
                 }
                 _ => None,
             })
-            .collect();
+            .collect::<Vec<_>>();
+
+        if mode == OpenMode::Strict {
+            if frames.len() < main_header.total_frames as usize {
+                return Err(PlayerError::Format(format!(
+                    "AVI header declares {} frames but the file only contains {}",
+                    main_header.total_frames,
+                    frames.len()
+                )));
+            }
+            // some writers leave the image size empty for uncompressed frames
+            let frame_size = match stream_format.color_coding {
+                ColorCoding::MJPEG => 0,
+                _ => (stream_format.header.size_image as usize).max(frame_size as usize),
+            };
+            if let Some(index) = frames.iter().position(|f| f.data_size < frame_size) {
+                return Err(PlayerError::Format(format!(
+                    "Frame {} is {} bytes but the stream format declares {} bytes",
+                    index, frames[index].data_size, frame_size
+                )));
+            }
+        }
 
         Ok(Self {
-            riff,
             main_header,
             stream_header,
             stream_format,
             frames,
         })
     }
+}
 
-    pub fn main_header(&self) -> &AviMainHeader {
-        &self.main_header
+/// Read the entries of a RIFF file, checking every offset against the bounds of the file.
+///
+/// A chunk that extends past the end of the list that contains it either overlaps the
+/// entries that follow the list or is truncated. In lenient mode, such chunks are skipped
+/// along with the rest of the list and lists are cut off at the end of their parent, so that
+/// frames can still be read from a capture that was interrupted. In strict mode this is an
+/// error.
+pub fn parse_riff(bytes: &[u8], mode: OpenMode) -> Result<Vec<Entry>> {
+    if bytes.len() < 12 || bytes[0..4] != FOURCC_RIFF {
+        return Err(PlayerError::Format("Not a RIFF file".to_string()));
     }
+    let declared = LittleEndian::read_u32(&bytes[4..8]) as usize;
+    // files larger than 1 GB contain further RIFF lists after the declared size
+    let end = match declared.checked_add(8) {
+        Some(end) if end <= bytes.len() => end,
+        _ if mode == OpenMode::Strict => {
+            return Err(PlayerError::Format(format!(
+                "RIFF header declares {} bytes but the file is {} bytes",
+                declared,
+                bytes.len()
+            )))
+        }
+        _ => bytes.len(),
+    };
+    parse_entries(bytes, 12, end, 0, mode)
+}
 
-    pub fn stream_header(&self) -> &AviStreamHeader {
-        &self.stream_header
+fn parse_entries(
+    bytes: &[u8],
+    start: usize,
+    end: usize,
+    depth: usize,
+    mode: OpenMode,
+) -> Result<Vec<Entry>> {
+    let mut entries = vec![];
+    let mut offset = start;
+    while offset < end {
+        let invalid = |message: String| {
+            if mode == OpenMode::Strict {
+                Err(PlayerError::Format(message))
+            } else {
+                debug!(offset, "{}", message);
+                Ok(())
+            }
+        };
+        if end - offset < 8 {
+            invalid(format!("Truncated chunk header at offset {}", offset))?;
+            break;
+        }
+        let mut id: FourCC = [0; 4];
+        id.copy_from_slice(&bytes[offset..offset + 4]);
+        let data_offset = offset + 8;
+        let mut size = LittleEndian::read_u32(&bytes[offset + 4..offset + 8]) as usize;
+        if size > end - data_offset {
+            invalid(format!(
+                "'{}' at offset {} is {} bytes, which extends past the end of its list at \
+                 offset {}",
+                format_fourcc(id),
+                offset,
+                size,
+                end
+            ))?;
+            if id != FOURCC_LIST {
+                break;
+            }
+            // keep reading the children of a truncated list, such as the 'movi' list of an
+            // interrupted capture
+            size = end - data_offset;
+        }
+        if id == FOURCC_LIST {
+            if size < 4 {
+                invalid(format!("List at offset {} is too small ({})", offset, size))?;
+                break;
+            }
+            if depth == MAX_LIST_DEPTH {
+                return Err(PlayerError::Format(format!(
+                    "Lists are nested more than {} levels deep",
+                    MAX_LIST_DEPTH
+                )));
+            }
+            let mut list_type: FourCC = [0; 4];
+            list_type.copy_from_slice(&bytes[data_offset..data_offset + 4]);
+            let children =
+                parse_entries(bytes, data_offset + 4, data_offset + size, depth + 1, mode)?;
+            entries.push(Entry::List(ListMeta {
+                list_type,
+                data_offset: data_offset + 4,
+                data_size: size - 4,
+                children,
+            }));
+        } else {
+            entries.push(Entry::Chunk(ChunkMeta {
+                chunk_id: id,
+                data_offset,
+                chunk_size: size,
+                data_size: size,
+            }));
+        }
+        // chunks are padded to an even number of bytes
+        offset = data_offset + size + size % 2;
     }
+    Ok(entries)
+}
 
-    pub fn stream_format(&self) -> &BitMapInfo {
-        &self.stream_format
+/// The first `size` bytes of a chunk, or an error naming the structure if the chunk is too
+/// small to contain it
+fn chunk_fields<'a>(
+    bytes: &'a [u8],
+    chunk: &ChunkMeta,
+    size: usize,
+    name: &str,
+) -> Result<&'a [u8]> {
+    if chunk.data_size < size {
+        return Err(PlayerError::Format(format!(
+            "'{}' chunk at offset {} is {} bytes, which is too small for the {} ({} bytes)",
+            format_fourcc(chunk.chunk_id),
+            chunk.data_offset,
+            chunk.data_size,
+            name,
+            size
+        )));
     }
+    // the parser only returns chunks that are within the bounds of the file
+    Ok(&bytes[chunk.data_offset..chunk.data_offset + size])
+}
 
-    pub fn frames(&self) -> &[ChunkMeta] {
-        &self.frames
-    }
+fn read_fourcc(fields: &mut &[u8]) -> Result<FourCC> {
+    let mut fourcc: FourCC = [0; 4];
+    fields.read_exact(&mut fourcc)?;
+    Ok(fourcc)
+}
 
-    pub fn read_bytes(&self, chunk_meta: &ChunkMeta) -> &[u8] {
-        self.riff
-            .read_bytes(chunk_meta.data_offset..chunk_meta.data_offset + chunk_meta.data_size)
-    }
+fn parse_main_header(bytes: &[u8], chunk: &ChunkMeta) -> Result<AviMainHeader> {
+    let mut fields = chunk_fields(bytes, chunk, MAIN_HEADER_SIZE, "main header")?;
+    Ok(AviMainHeader {
+        micro_sec_per_frame: fields.read_u32::<LittleEndian>()?,
+        max_bytes_per_sec: fields.read_u32::<LittleEndian>()?,
+        padding_granularity: fields.read_u32::<LittleEndian>()?,
+        flags: fields.read_u32::<LittleEndian>()?,
+        total_frames: fields.read_u32::<LittleEndian>()?,
+        initial_frames: fields.read_u32::<LittleEndian>()?,
+        streams: fields.read_u32::<LittleEndian>()?,
+        suggested_buffer_size: fields.read_u32::<LittleEndian>()?,
+        width: fields.read_u32::<LittleEndian>()?,
+        height: fields.read_u32::<LittleEndian>()?,
+        reserved: read_fourcc(&mut fields)?,
+    })
 }
 
-fn parse_main_header(riff: &RiffFile, chunk: &ChunkMeta) -> Result<AviMainHeader> {
-    assert!(chunk.data_size >= 44);
-    let bytes = riff.read_bytes(chunk.data_offset..chunk.data_offset + 44);
-    Ok(unsafe { std::ptr::read(bytes.as_ptr() as *const _) })
+fn parse_stream_header(bytes: &[u8], chunk: &ChunkMeta) -> Result<AviStreamHeader> {
+    let mut fields = chunk_fields(bytes, chunk, STREAM_HEADER_SIZE, "stream header")?;
+    Ok(AviStreamHeader {
+        fcc_type: read_fourcc(&mut fields)?,
+        fcc_handler: read_fourcc(&mut fields)?,
+        flags: fields.read_u32::<LittleEndian>()?,
+        priority: fields.read_u16::<LittleEndian>()?,
+        language: fields.read_u16::<LittleEndian>()?,
+        initial_frames: fields.read_u32::<LittleEndian>()?,
+        scale: fields.read_u32::<LittleEndian>()?,
+        rate: fields.read_u32::<LittleEndian>()?,
+        start: fields.read_u32::<LittleEndian>()?,
+        length: fields.read_u32::<LittleEndian>()?,
+        suggested_buffer_size: fields.read_u32::<LittleEndian>()?,
+        quality: fields.read_u32::<LittleEndian>()?,
+        sample_size: fields.read_u32::<LittleEndian>()?,
+        left: fields.read_u16::<LittleEndian>()?,
+        top: fields.read_u16::<LittleEndian>()?,
+        right: fields.read_u16::<LittleEndian>()?,
+        bottom: fields.read_u16::<LittleEndian>()?,
+    })
 }
 
-fn parse_stream_header(riff: &RiffFile, chunk: &ChunkMeta) -> Result<AviStreamHeader> {
-    if chunk.data_size >= 56 {
-        let bytes = riff.read_bytes(chunk.data_offset..chunk.data_offset + 56);
-        Ok(unsafe { std::ptr::read(bytes.as_ptr() as *const _) })
-    } else {
-        Err(Error::new(
-            ErrorKind::Other,
-            AviError::new(format!(
-                "Not enough bytes for stream header ({})",
-                chunk.data_size
-            )),
-        ))
+fn parse_bitmap_info_header(bytes: &[u8], chunk: &ChunkMeta) -> Result<BitMapInfoHeader> {
+    let mut fields = chunk_fields(bytes, chunk, BITMAP_INFO_HEADER_SIZE, "stream format")?;
+    let header = BitMapInfoHeader {
+        size: fields.read_u32::<LittleEndian>()?,
+        width: fields.read_i32::<LittleEndian>()?,
+        height: fields.read_i32::<LittleEndian>()?,
+        planes: fields.read_u16::<LittleEndian>()?,
+        bit_count: fields.read_u16::<LittleEndian>()?,
+        compression: fields.read_u32::<LittleEndian>()?,
+        size_image: fields.read_u32::<LittleEndian>()?,
+        x_pels_per_meter: fields.read_i32::<LittleEndian>()?,
+        y_pels_per_meter: fields.read_i32::<LittleEndian>()?,
+        clr_used: fields.read_u32::<LittleEndian>()?,
+        clr_important: fields.read_u32::<LittleEndian>()?,
+    };
+    if header.width <= 0 || header.height == 0 || header.height == i32::MIN {
+        return Err(PlayerError::Format(format!(
+            "Invalid stream format image size {}x{}",
+            header.width, header.height
+        )));
     }
+    Ok(header)
 }
 
-fn parse_stream_format(riff: &RiffFile, chunk: &ChunkMeta) -> Result<BitMapInfo> {
-    if chunk.data_size < 48 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            AviError::new(format!(
-                "Not enough bytes for stream format ({})",
-                chunk.data_size
-            )),
-        ));
-    }
+fn parse_stream_format(bytes: &[u8], chunk: &ChunkMeta) -> Result<BitMapInfo> {
+    let header = parse_bitmap_info_header(bytes, chunk)?;
 
-    let bytes = riff.read_bytes(chunk.data_offset..chunk.data_offset + 48);
-    let header: BitMapInfoHeader = unsafe { std::ptr::read(bytes.as_ptr() as *const _) };
+    let mut compression = header.compression.to_le_bytes();
+    compression.make_ascii_uppercase();
+    if compression == FOURCC_MJPG {
+        // the bit count is the depth of the decoded image
+        return Ok(BitMapInfo {
+            header,
+            color_coding: ColorCoding::MJPEG,
+            rgb: vec![],
+        });
+    }
 
     // https://docs.microsoft.com/en-us/previous-versions/dd183376(v=vs.85)
     match header.bit_count {
         0 => {
             // The number of bits-per-pixel is specified or is implied by the JPEG or PNG format.
-            Err(Error::new(
-                ErrorKind::Other,
-                AviError::new("JPG and PNG encodings are not supported".to_string()),
+            Err(PlayerError::Unsupported(
+                "JPG and PNG encodings are not supported".to_string(),
             ))
         }
         1 => {
@@ -203,9 +534,8 @@ fn parse_stream_format(riff: &RiffFile, chunk: &ChunkMeta) -> Result<BitMapInfo>
             // entries. Each bit in the bitmap array represents a pixel. If the bit is clear,
             // the pixel is displayed with the color of the first entry in the bmiColors table;
             // if the bit is set, the pixel has the color of the second entry in the table.
-            Err(Error::new(
-                ErrorKind::Other,
-                AviError::new("Monochrome images are not supported".to_string()),
+            Err(PlayerError::Unsupported(
+                "Monochrome images are not supported".to_string(),
             ))
         }
         4 => {
@@ -214,19 +544,31 @@ fn parse_stream_format(riff: &RiffFile, chunk: &ChunkMeta) -> Result<BitMapInfo>
             // index into the color table. For example, if the first byte in the bitmap is 0x1F,
             // the byte represents two pixels. The first pixel contains the color in the second
             // table entry, and the second pixel contains the color in the sixteenth table entry.
-            Err(Error::new(
-                ErrorKind::Other,
-                AviError::new("Unsupported bit_count (4)".to_string()),
+            Err(PlayerError::Unsupported(
+                "Unsupported bit_count (4)".to_string(),
             ))
         }
         8 => {
             // The bitmap has a maximum of 256 colors, and the bmiColors member of BITMAPINFO
             // contains up to 256 entries. In this case, each byte in the array represents a
             // single pixel.
-            Err(Error::new(
-                ErrorKind::Other,
-                AviError::new("Unsupported bit_count (8)".to_string()),
-            ))
+            //
+            // Planetary cameras use this to record raw 8-bit samples, such as Bayer data,
+            // with a grayscale palette. Palettes with colors are not supported.
+            let rgb = parse_palette(bytes, chunk, &header);
+            if rgb
+                .iter()
+                .any(|quad| quad.red != quad.green || quad.green != quad.blue)
+            {
+                return Err(PlayerError::Unsupported(
+                    "8-bit images with a color palette are not supported".to_string(),
+                ));
+            }
+            Ok(BitMapInfo {
+                header,
+                color_coding: ColorCoding::Y8,
+                rgb,
+            })
         }
         16 => {
             // The bitmap has a maximum of 2^16 colors. If the biCompression member of the
@@ -247,10 +589,32 @@ fn parse_stream_format(riff: &RiffFile, chunk: &ChunkMeta) -> Result<BitMapInfo>
             // When the biCompression member is BI_BITFIELDS, bits set in each DWORD mask
             // must be contiguous and should not overlap the bits of another mask. All the
             // bits in the pixel do not have to be used.
-            Err(Error::new(
-                ErrorKind::Other,
-                AviError::new("Unsupported bit_count (16)".to_string()),
-            ))
+            //
+            // Capture software records 16-bit grayscale or raw Bayer samples either with the
+            // 'Y16 ' compression or with the same mask for all three colors, where the mask
+            // gives the number of significant bits. 16-bit RGB is not supported.
+            let significant_bits = if compression == FOURCC_Y16_ {
+                Some(16)
+            } else if header.compression == BI_BITFIELDS {
+                match parse_bit_masks(bytes, chunk)? {
+                    [red, green, blue] if red == green && green == blue && red & 0xffff != 0 => {
+                        Some(32 - (red & 0xffff).leading_zeros())
+                    }
+                    _ => None,
+                }
+            } else {
+                None
+            };
+            match significant_bits {
+                Some(significant_bits) => Ok(BitMapInfo {
+                    header,
+                    color_coding: ColorCoding::Y16 { significant_bits },
+                    rgb: vec![],
+                }),
+                None => Err(PlayerError::Unsupported(
+                    "16-bit RGB images are not supported".to_string(),
+                )),
+            }
         }
         24 => {
             // The bitmap has a maximum of 2^24 colors, and the bmiColors member of BITMAPINFO
@@ -282,90 +646,91 @@ fn parse_stream_format(riff: &RiffFile, chunk: &ChunkMeta) -> Result<BitMapInfo>
             // When the biCompression member is BI_BITFIELDS, bits set in each DWORD mask must
             // be contiguous and should not overlap the bits of another mask. All the bits in
             // the pixel do not need to be used.
-            Err(Error::new(
-                ErrorKind::Other,
-                AviError::new("Unsupported bit_count (32)".to_string()),
+            Err(PlayerError::Unsupported(
+                "Unsupported bit_count (32)".to_string(),
             ))
         }
-        other => Err(Error::new(
-            ErrorKind::Other,
-            AviError::new(format!("Invalid bit_count ({})", other)),
-        )),
+        other => Err(PlayerError::Format(format!(
+            "Invalid bit_count ({})",
+            other
+        ))),
     }
 }
 
-fn find_mandatory_list<'a>(entries: &'a [Entry], list_type: FourCC) -> Result<&'a ListMeta> {
+/// Read the red, green and blue masks that follow the header of a stream format that uses
+/// BI_BITFIELDS. Later versions of the header contain the masks at the same offset.
+fn parse_bit_masks(bytes: &[u8], chunk: &ChunkMeta) -> Result<[u32; 3]> {
+    if chunk.data_size < 52 {
+        return Err(PlayerError::Format(format!(
+            "Not enough bytes for stream format color masks ({})",
+            chunk.data_size
+        )));
+    }
+    let masks = &bytes[chunk.data_offset + 40..chunk.data_offset + 52];
+    Ok([
+        LittleEndian::read_u32(&masks[0..4]),
+        LittleEndian::read_u32(&masks[4..8]),
+        LittleEndian::read_u32(&masks[8..12]),
+    ])
+}
+
+/// Read the color table that follows the header of an 8-bit stream format, which has at most
+/// 256 entries
+fn parse_palette(bytes: &[u8], chunk: &ChunkMeta, header: &BitMapInfoHeader) -> Vec<RgbQuad> {
+    let start = (header.size as usize).min(chunk.data_size);
+    bytes[chunk.data_offset + start..chunk.data_offset + chunk.data_size]
+        .chunks_exact(4)
+        .take(256)
+        .map(|quad| RgbQuad {
+            blue: quad[0],
+            green: quad[1],
+            red: quad[2],
+            reserved: quad[3],
+        })
+        .collect()
+}
+
+fn find_mandatory_list(entries: &[Entry], list_type: FourCC) -> Result<&ListMeta> {
     let list = entries.iter().find_map(|e| match e {
         Entry::List(meta) if meta.list_type == list_type => Some(meta),
         _ => None,
     });
     list.ok_or_else(|| {
-        Error::new(
-            ErrorKind::Other,
-            AviError::new(format!(
-                "AVI file is missing mandatory list '{}'",
-                format_fourcc(list_type)
-            )),
-        )
+        PlayerError::Format(format!(
+            "AVI file is missing mandatory list '{}'",
+            format_fourcc(list_type)
+        ))
     })
 }
 
-fn find_mandatory_list_in_list<'a>(
-    parent: &'a ListMeta,
-    list_type: FourCC,
-) -> Result<&'a ListMeta> {
+fn find_mandatory_list_in_list(parent: &ListMeta, list_type: FourCC) -> Result<&ListMeta> {
     let list = parent.children.iter().find_map(|e| match e {
         Entry::List(meta) if meta.list_type == list_type => Some(meta),
         _ => None,
     });
     list.ok_or_else(|| {
-        Error::new(
-            ErrorKind::Other,
-            AviError::new(format!(
-                "List '{}' is missing mandatory list '{}'",
-                format_fourcc(parent.list_type),
-                format_fourcc(list_type),
-            )),
-        )
+        PlayerError::Format(format!(
+            "List '{}' is missing mandatory list '{}'",
+            format_fourcc(parent.list_type),
+            format_fourcc(list_type)
+        ))
     })
 }
 
-fn find_mandatory_chunk<'a>(meta: &'a ListMeta, chunk_id: FourCC) -> Result<&'a ChunkMeta> {
+fn find_mandatory_chunk(meta: &ListMeta, chunk_id: FourCC) -> Result<&ChunkMeta> {
     let chunk = meta.children.iter().find_map(|e| match e {
         Entry::Chunk(chunk) if chunk.chunk_id == chunk_id => Some(chunk),
         _ => None,
     });
     chunk.ok_or_else(|| {
-        Error::new(
-            ErrorKind::Other,
-            AviError::new(format!(
-                "List '{}' is missing mandatory chunk '{}'",
-                format_fourcc(meta.list_type),
-                format_fourcc(chunk_id)
-            )),
-        )
+        PlayerError::Format(format!(
+            "List '{}' is missing mandatory chunk '{}'",
+            format_fourcc(meta.list_type),
+            format_fourcc(chunk_id)
+        ))
     })
 }
 
-#[derive(Debug)]
-struct AviError {
-    message: String,
-}
-
-impl AviError {
-    fn new(message: String) -> Self {
-        Self { message }
-    }
-}
-
-impl error::Error for AviError {}
-
-impl Display for AviError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "AviError: {}", &self.message)
-    }
-}
-
 #[derive(Debug)]
 pub enum AviEntry {
     MainHeader(AviMainHeader),
@@ -377,7 +742,6 @@ pub enum AviEntry {
 }
 
 #[derive(Debug)]
-#[repr(C)]
 pub struct AviMainHeader {
     pub micro_sec_per_frame: u32,
     pub max_bytes_per_sec: u32,
@@ -393,7 +757,6 @@ pub struct AviMainHeader {
 }
 
 #[derive(Debug)]
-#[repr(C)]
 pub struct AviStreamHeader {
     /// Stream type. Could be `auds`, `mids`, `txts`, `vids`.
     pub fcc_type: [u8; 4],
@@ -418,7 +781,6 @@ pub struct AviStreamHeader {
 // https://docs.fileformat.com/image/dib/
 
 #[derive(Debug)]
-#[repr(C)]
 pub struct BitMapInfo {
     pub header: BitMapInfoHeader,
     pub color_coding: ColorCoding,
@@ -428,10 +790,17 @@ pub struct BitMapInfo {
 #[derive(Debug)]
 pub enum ColorCoding {
     BGR,
+    /// One 8-bit sample per pixel with a grayscale palette, such as raw Bayer data
+    Y8,
+    /// One 16-bit sample per pixel, of which the low `significant_bits` are used
+    Y16 {
+        significant_bits: u32,
+    },
+    /// Each frame is a JPEG image
+    MJPEG,
 }
 
 #[derive(Debug)]
-#[repr(C)]
 pub struct BitMapInfoHeader {
     /// Specifies the number of bytes required by the structure. This value does not include the
     /// size of the color table or the size of the color masks, if they are appended to the
@@ -456,14 +825,13 @@ pub struct BitMapInfoHeader {
     pub bit_count: u16,
     pub compression: u32,
     pub size_image: u32,
-    pub x_pels_per_meter: u64,
-    pub y_pels_per_meter: u64,
+    pub x_pels_per_meter: i32,
+    pub y_pels_per_meter: i32,
     pub clr_used: u32,
     pub clr_important: u32,
 }
 
 #[derive(Debug)]
-#[repr(C)]
 pub struct RgbQuad {
     pub blue: u8,
     pub green: u8,
@@ -503,7 +871,215 @@ struct _aviindex_chunk {
 
 fn format_fourcc(value: FourCC) -> String {
     match str::from_utf8(&value) {
-        Ok(s) => s.to_string(),
+        Ok(s) if s.chars().all(|c| c.is_ascii_graphic() || c == ' ') => s.to_string(),
         _ => format!("{:x?}", value),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::testutil::{avi_bytes, avi_stream_bytes, AviStream};
+
+    /// An uncompressed 24-bit AVI file where every byte of frame `i` is `i`
+    fn build_avi(width: u32, height: u32, frame_count: usize) -> Vec<u8> {
+        let frame_size = (width * height * 3) as usize;
+        let frames = (0..frame_count)
+            .map(|i| vec![i as u8; frame_size])
+            .collect::<Vec<_>>();
+        avi_bytes(width, height, 0, &frames)
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        let bytes = build_avi(4, 2, 3);
+        for mode in [OpenMode::Lenient, OpenMode::Strict] {
+            let layout = AviLayout::parse(&bytes, mode).unwrap();
+            assert_eq!(4, layout.main_header.width);
+            assert_eq!(3, layout.frames.len());
+            let frame = &layout.frames[2];
+            assert_eq!(24, frame.data_size);
+            assert_eq!(&[2; 24], &bytes[frame.data_offset..frame.data_offset + 24]);
+        }
+    }
+
+    #[test]
+    fn test_parse_mjpeg_layout() {
+        let frames = vec![vec![0xff, 0xd8, 1], vec![0xff, 0xd8, 2, 3]];
+        let bytes = avi_stream_bytes(4, 2, 0, &AviStream::MJPEG, &frames);
+        for mode in [OpenMode::Lenient, OpenMode::Strict] {
+            let layout = AviLayout::parse(&bytes, mode).unwrap();
+            assert!(matches!(
+                layout.stream_format.color_coding,
+                ColorCoding::MJPEG
+            ));
+            assert_eq!(2, layout.frames.len());
+            assert_eq!(4, layout.frames[1].data_size);
+        }
+    }
+
+    #[test]
+    fn test_parse_raw8_layout() {
+        let bytes = avi_stream_bytes(4, 2, 0, &AviStream::RAW8, &[vec![7; 8]]);
+        let layout = AviLayout::parse(&bytes, OpenMode::Strict).unwrap();
+        assert!(matches!(layout.stream_format.color_coding, ColorCoding::Y8));
+        assert_eq!(256, layout.stream_format.rgb.len());
+        assert_eq!(8, layout.frames[0].data_size);
+
+        // a color palette
+        let mut bytes = bytes;
+        let palette = bytes
+            .windows(8)
+            .position(|w| w == [1, 1, 1, 0, 2, 2, 2, 0])
+            .unwrap();
+        bytes[palette] = 0;
+        assert!(AviLayout::parse(&bytes, OpenMode::Lenient).is_err());
+    }
+
+    #[test]
+    fn test_parse_raw16_layout() {
+        let frames = [vec![0; 16]];
+        let y16 = avi_stream_bytes(4, 2, 0, &AviStream::RAW16, &frames);
+        let layout = AviLayout::parse(&y16, OpenMode::Strict).unwrap();
+        assert!(matches!(
+            layout.stream_format.color_coding,
+            ColorCoding::Y16 {
+                significant_bits: 16
+            }
+        ));
+
+        let bitfields = AviStream {
+            compression: &[3, 0, 0, 0],
+            bit_masks: &[0x0fff, 0x0fff, 0x0fff],
+            ..AviStream::RAW16
+        };
+        let bytes = avi_stream_bytes(4, 2, 0, &bitfields, &frames);
+        let layout = AviLayout::parse(&bytes, OpenMode::Strict).unwrap();
+        assert!(matches!(
+            layout.stream_format.color_coding,
+            ColorCoding::Y16 {
+                significant_bits: 12
+            }
+        ));
+
+        // RGB565
+        let rgb565 = AviStream {
+            bit_masks: &[0xf800, 0x07e0, 0x001f],
+            ..bitfields
+        };
+        let bytes = avi_stream_bytes(4, 2, 0, &rgb565, &frames);
+        assert!(AviLayout::parse(&bytes, OpenMode::Lenient).is_err());
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let bytes = build_avi(4, 2, 3);
+        let layout = AviLayout::parse(&bytes, OpenMode::Strict).unwrap();
+        assert_eq!(3, layout.main_header.total_frames);
+        assert_eq!(2, layout.main_header.height);
+        assert_eq!(FOURCC_VIDS, layout.stream_header.fcc_type);
+        assert_eq!(AviStream::BGR.handler, &layout.stream_header.fcc_handler);
+        let header = &layout.stream_format.header;
+        assert_eq!((4, 2), (header.width, header.height));
+        assert_eq!(24, header.bit_count);
+        assert_eq!(24, header.size_image);
+    }
+
+    #[test]
+    fn test_mismatched_dimensions() {
+        let mut bytes = build_avi(4, 2, 3);
+        // claim a 65536x65536 image in the main header only
+        let width = bytes.windows(4).position(|w| w == b"avih").unwrap() + 8 + 32;
+        bytes[width..width + 8].copy_from_slice(&[0, 0, 1, 0, 0, 0, 1, 0]);
+        for mode in [OpenMode::Lenient, OpenMode::Strict] {
+            let err = AviLayout::parse(&bytes, mode).unwrap_err();
+            assert!(err.to_string().contains("65536x65536"));
+        }
+    }
+
+    #[test]
+    fn test_frames_larger_than_file() {
+        let mut bytes = build_avi(4, 2, 3);
+        // claim a 65536x65536 image in both headers
+        for (id, offset) in [(b"avih", 32), (b"strf", 4)] {
+            let field = bytes.windows(4).position(|w| w == id).unwrap() + 8 + offset;
+            bytes[field..field + 8].copy_from_slice(&[0, 0, 1, 0, 0, 0, 1, 0]);
+        }
+        for mode in [OpenMode::Lenient, OpenMode::Strict] {
+            let err = AviLayout::parse(&bytes, mode).unwrap_err();
+            assert!(err.to_string().contains("larger than the"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_truncated_headers() {
+        let bytes = build_avi(4, 2, 3);
+        let chunk = |size| ChunkMeta {
+            chunk_id: *b"strh",
+            data_offset: 0,
+            chunk_size: size,
+            data_size: size,
+        };
+        let err = parse_main_header(&bytes, &chunk(40)).unwrap_err();
+        assert!(err.to_string().contains("too small for the main header"));
+        let err = parse_stream_header(&bytes, &chunk(48)).unwrap_err();
+        assert!(err.to_string().contains("too small for the stream header"));
+        let err = parse_stream_format(&bytes, &chunk(36)).unwrap_err();
+        assert!(err.to_string().contains("too small for the stream format"));
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let bytes = build_avi(4, 2, 3);
+        let truncated = &bytes[..bytes.len() - 10];
+        let layout = AviLayout::parse(truncated, OpenMode::Lenient).unwrap();
+        assert_eq!(2, layout.frames.len());
+        assert!(AviLayout::parse(truncated, OpenMode::Strict).is_err());
+    }
+
+    #[test]
+    fn test_overlapping_chunk() {
+        let mut bytes = build_avi(4, 2, 3);
+        // make the 'indx' chunk extend past the end of the 'strl' and 'hdrl' lists
+        let indx = bytes.windows(4).position(|w| w == b"indx").unwrap();
+        bytes[indx + 4] = 100;
+        assert!(AviLayout::parse(&bytes, OpenMode::Strict).is_err());
+        let layout = AviLayout::parse(&bytes, OpenMode::Lenient);
+        assert!(layout.is_err(), "indx is mandatory");
+    }
+
+    /// Parsing arbitrary corruptions of a valid file must never panic, and any frames that
+    /// are returned must be within the bounds of the file
+    #[test]
+    fn test_corrupt_files() {
+        let valid = build_avi(4, 2, 3);
+        let mut rng = StdRng::seed_from_u64(1230);
+        for _ in 0..2000 {
+            let mut bytes = valid.clone();
+            match rng.gen_range(0..3) {
+                0 => bytes.truncate(rng.gen_range(0..valid.len())),
+                1 => {
+                    for _ in 0..rng.gen_range(1..4) {
+                        let i = rng.gen_range(0..bytes.len());
+                        bytes[i] = rng.gen();
+                    }
+                }
+                _ => {
+                    // corrupt a size field
+                    let i = rng.gen_range(0..bytes.len() / 4) * 4;
+                    bytes[i..i + 4].copy_from_slice(&rng.gen::<u32>().to_le_bytes());
+                }
+            }
+            for mode in [OpenMode::Lenient, OpenMode::Strict] {
+                if let Ok(layout) = AviLayout::parse(&bytes, mode) {
+                    for frame in &layout.frames {
+                        assert!(frame.data_offset + frame.data_size <= bytes.len());
+                    }
+                }
+            }
+        }
+    }
+}
@@ -20,58 +20,1056 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::error::Error;
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "gui")]
 use iced::{Application, Settings};
+use rayon::prelude::*;
+#[cfg(feature = "gui")]
+use structopt::clap::ErrorKind;
 use structopt::StructOpt;
+use tracing::{error, warn, Level};
+
+use astro_video_player::animation::{
+    self, parse_animation_format, AnimationFormat, AnimationOptions, ANIMATION_FORMAT_NAMES,
+};
+use astro_video_player::annotations::{annotations_path, load_annotations};
+#[cfg(feature = "gui")]
+use astro_video_player::codecs::{parse_codec_quality, CodecQuality, CODEC_QUALITY_NAMES};
+use astro_video_player::codecs::{CodecRegistry, CODEC_NAMES};
+use astro_video_player::config::Config;
+use astro_video_player::export::{
+    export_annotated_png, export_frame_with_buffer, parse_export_format, ExportFormat,
+    EXPORT_FORMAT_NAMES,
+};
+use astro_video_player::gaps::find_gaps;
+#[cfg(feature = "gui")]
+use astro_video_player::inputs::{expand_directories, expand_patterns, read_files_from};
+use astro_video_player::inputs::{input_files, output_names};
+use astro_video_player::pipeline::Pipeline;
+use astro_video_player::processing::{parse_color_map, ColorMap, Stretch, COLOR_MAP_NAMES};
+use astro_video_player::quality;
+use astro_video_player::rejection::{self, DEFAULT_SIGMA};
+use astro_video_player::seeing;
+use astro_video_player::ser;
+use astro_video_player::stack::{self, parse_combine, Combine, COMBINE_NAMES};
+#[cfg(feature = "gui")]
+use astro_video_player::ui::{VideoPlayer, VideoPlayerArgs};
+use astro_video_player::video_format::{
+    open_video_with, parse_bayer, parse_bit_depth, parse_endianness_override, parse_read_strategy,
+    video_info, Bayer, EndiannessOverride, FormatOverrides, OpenMode, OpenOptions, ReadStrategy,
+    READ_STRATEGY_NAMES,
+};
+use astro_video_player::{ImageCodec, Video};
 
-use astro_video_player::avi::{AviFile, ColorCoding};
-use astro_video_player::codec::{DebayerCodec, RgbCodec};
-use astro_video_player::ui::VideoPlayer;
-use astro_video_player::ui::VideoPlayerArgs;
-use astro_video_player::video_format::{AviVideo, SerVideo};
-use ser_io::{Bayer, SerFile};
+type CommandResult = std::result::Result<(), Box<dyn Error>>;
 
+/// Player and command line tools for astrophotography videos. Every subcommand except `play`
+/// works without a display, such as over SSH on a capture laptop. `astro-video-player FILE...`
+/// is short for `astro-video-player play FILE...`.
 #[derive(StructOpt, Debug)]
 struct Opt {
-    filename: String,
-}
-
-pub fn main() -> iced::Result {
-    let opt = Opt::from_args();
-
-    if opt.filename.as_str().to_lowercase().ends_with(".avi") {
-        let avi = AviFile::open(&opt.filename).unwrap();
-        println!("{:?}", avi.main_header());
-        println!("{:?}", avi.stream_header());
-        println!("{:?}", avi.stream_format());
-        println!("avi has {} frames", avi.frames().len());
-
-        let mut settings: Settings<VideoPlayerArgs> = Settings::default();
-        settings.flags.codec = Some(match &avi.stream_format().color_coding {
-            ColorCoding::BGR => Box::new(RgbCodec::new(Bayer::BGR)),
-        });
-        settings.flags.video = Some(Box::new(AviVideo { avi }));
-        VideoPlayer::run(settings)
-    } else if opt.filename.as_str().to_lowercase().ends_with(".ser") {
-        match SerFile::open(&opt.filename) {
-            Ok(ser) => match ser.bayer {
-                Bayer::RGGB => {
-                    let mut settings: Settings<VideoPlayerArgs> = Settings::default();
-                    settings.flags.video = Some(Box::new(SerVideo { ser }));
-                    settings.flags.codec = Some(Box::new(DebayerCodec {}));
-                    VideoPlayer::run(settings)
-                }
-                other => {
-                    println!("Unsupported bayer {:?}", other);
-                    Ok(())
-                }
+    #[structopt(subcommand)]
+    command: Command,
+    /// Log more detail about opening and decoding files (-v for debug, -vv for trace)
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    verbose: u8,
+    /// Only log errors
+    #[structopt(short, long, global = true)]
+    quiet: bool,
+    /// Configuration file to use instead of ~/.config/astro-video-player/config.toml
+    #[structopt(long, parse(from_os_str), global = true)]
+    config: Option<PathBuf>,
+    /// Number of decoding threads
+    #[structopt(long, global = true)]
+    threads: Option<usize>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Open a SER, AVI or FITS file in the player
+    #[cfg(feature = "gui")]
+    Play {
+        /// SER, AVI or FITS files to play, or directories containing them or a sequence of
+        /// FITS, TIFF or PNG images. The first file is opened and the player can switch
+        /// between the others.
+        filenames: Vec<String>,
+        /// Also play the files listed in this file, one per line, or read from stdin if "-",
+        /// such as `find /data/2021-09-14 -name '*.ser' | astro-video-player play --files-from -`
+        #[structopt(long, parse(from_os_str))]
+        files_from: Option<PathBuf>,
+        #[structopt(flatten)]
+        format: FormatArgs,
+        /// UI theme (dark, light or astronomy)
+        #[structopt(long)]
+        theme: Option<String>,
+        /// How color mosaics are debayered when the codec is auto: fast, which halves the
+        /// resolution, or best
+        #[structopt(
+            long,
+            possible_values = CODEC_QUALITY_NAMES,
+            parse(try_from_str = parse_codec_quality)
+        )]
+        codec_quality: Option<CodecQuality>,
+        /// Size of the decoded frame cache, in megabytes
+        #[structopt(long)]
+        cache_mb: Option<usize>,
+        /// Numbers of frames to jump by with the frame step buttons, such as 10,100
+        #[structopt(long, use_delimiter = true)]
+        frame_steps: Option<Vec<u32>>,
+        /// Playback frame rate (defaults to the rate recorded in the file)
+        #[structopt(long)]
+        fps: Option<f64>,
+        /// Video to blink each frame against, such as an earlier capture of the same field
+        #[structopt(long, parse(from_os_str))]
+        blink: Option<PathBuf>,
+        /// Follow SER files that are still being captured, showing new frames as they are
+        /// written like `tail -f`
+        #[structopt(long)]
+        follow: bool,
+    },
+    /// Print the metadata of SER, AVI or FITS files
+    Info {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Copy a range of raw frames to a new SER file, such as to convert an AVI capture for
+    /// stacking software. AVI files do not record the Bayer pattern of raw frames, so it is
+    /// detected from the frames; pass --bayer (and --bit-depth for 16-bit frames) to choose
+    /// the values stored in the SER header.
+    Convert {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        /// SER file to write, or a directory when converting several files
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(flatten)]
+        range: FrameRange,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Save a range of frames as numbered image files, either decoded as 8-bit PNG or as
+    /// TIFF or FITS with the raw samples. TIFF files have 8 bits per sample for 8-bit
+    /// captures and 16 otherwise.
+    #[structopt(alias = "extract")]
+    Export {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        /// Directory to write the images to. When exporting from several files, each file
+        /// gets its own subdirectory.
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        /// Prefix for the image filenames
+        #[structopt(long, default_value = "frame")]
+        prefix: String,
+        /// Image format to write
+        #[structopt(
+            long = "format",
+            default_value = "png",
+            possible_values = EXPORT_FORMAT_NAMES,
+            parse(try_from_str = parse_export_format)
+        )]
+        image_format: ExportFormat,
+        /// Draw the arrows and labels saved next to each file in the player into the PNG
+        /// images
+        #[structopt(long)]
+        annotations: bool,
+        #[structopt(flatten)]
+        range: FrameRange,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Read and decode every frame, reporting any that fail
+    Verify {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// List dropped frames and gaps in the capture, found from the frame timestamps in SER
+    /// files
+    Gaps {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Average or median combine a range of frames and save the result, either decoded as an
+    /// 8-bit PNG or as a TIFF or FITS image with the raw samples
+    Stack {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        /// Image file to write, or a directory when stacking several files
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(flatten)]
+        options: StackArgs,
+        #[structopt(flatten)]
+        range: FrameRange,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Render a range of frames into an animated GIF, or an MP4 video when built with the
+    /// `ffmpeg` feature
+    Animate {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        /// Animation file to write, or a directory when animating several files
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(flatten)]
+        options: AnimationArgs,
+        #[structopt(flatten)]
+        range: FrameRange,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Score the sharpness of every frame and save the frames from sharpest to least sharp
+    /// as CSV
+    Rank {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        /// CSV file to write, or a directory when ranking several files (defaults to
+        /// <name>_quality.csv next to each file)
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Find frames whose brightness deviates from the rest of the capture, such as frames
+    /// dimmed by cloud or crossed by a plane, and save them as CSV for --reject
+    Reject {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        /// CSV file to write, or a directory when checking several files (defaults to
+        /// <name>_rejected.csv next to each file)
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Reject frames whose mean or median brightness is more than this many standard
+        /// deviations from the typical frame (defaults to 3)
+        #[structopt(long)]
+        sigma: Option<f64>,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Measure the FWHM of the brightest star in every frame of star captures and save it as
+    /// CSV, to find the steadiest parts of a capture and record the seeing
+    Seeing {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        /// CSV file to write, or a directory when measuring several files (defaults to
+        /// <name>_seeing.csv next to each file)
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        #[structopt(flatten)]
+        format: FormatArgs,
+    },
+    /// Run a processing pipeline without launching the player
+    Run {
+        /// Pipeline description in TOML format
+        #[structopt(parse(from_os_str))]
+        pipeline: PathBuf,
+    },
+}
+
+/// SER, AVI or FITS files for batch commands
+#[derive(StructOpt, Debug)]
+struct InputArgs {
+    /// Files to process. Wildcards such as *.ser are expanded even when quoted.
+    files: Vec<String>,
+    /// Also process the files listed in this file, one per line, or read from stdin if "-"
+    #[structopt(long, parse(from_os_str))]
+    files_from: Option<PathBuf>,
+}
+
+impl InputArgs {
+    /// Run a command for each input file, with the name of its output when there are several
+    /// inputs. A failure is logged and the remaining files are still processed so that one
+    /// bad capture does not stop a whole session.
+    fn for_each(&self, mut f: impl FnMut(&str, Option<&str>) -> CommandResult) -> CommandResult {
+        let files = input_files(&self.files, self.files_from.as_deref())?;
+        if files.len() == 1 {
+            return f(&files[0], None);
+        }
+        let names = output_names(&files);
+        let mut failures = 0;
+        for (file, name) in files.iter().zip(&names) {
+            if let Err(e) = f(file, Some(name)) {
+                error!("{}: {}", file, e);
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            return Err(format!("{} of {} files failed", failures, files.len()).into());
+        }
+        Ok(())
+    }
+}
+
+/// Where to write the output for an input file. When there are several inputs, `output` is
+/// a directory and the output is given the input's name from `output_names`.
+fn output_path(output: &Path, name: Option<&str>, extension: &str) -> PathBuf {
+    let path = match name {
+        Some(name) => output.join(name),
+        None => return output.to_path_buf(),
+    };
+    if extension.is_empty() {
+        path
+    } else {
+        path.with_extension(extension)
+    }
+}
+
+/// Header values to use instead of the ones in the file
+#[derive(StructOpt, Debug)]
+struct FormatArgs {
+    /// Bayer pattern or color encoding (mono, rggb, grbg, gbrg, bggr, rgb, bgr). Defaults to
+    /// the value in the file, or the detected pattern for raw AVI files.
+    #[structopt(long, parse(try_from_str = parse_bayer))]
+    bayer: Option<Bayer>,
+    /// Bit depth of the samples, from 1 to 16
+    #[structopt(long, parse(try_from_str = parse_bit_depth))]
+    bit_depth: Option<u32>,
+    /// Byte order of 16-bit samples (little, big, or auto to judge it from the samples for
+    /// files with a wrong endianness flag)
+    #[structopt(long, parse(try_from_str = parse_endianness_override))]
+    endianness: Option<EndiannessOverride>,
+    /// Codec to decode frames with (defaults to `codec` in the config file for the player,
+    /// and auto otherwise)
+    #[structopt(long, possible_values = CODEC_NAMES)]
+    codec: Option<String>,
+    /// Reject files with out-of-bounds offsets, overlapping chunks or truncated frames
+    /// instead of reading as much as possible
+    #[structopt(long)]
+    strict: bool,
+    /// How SER frames are read: memory-mapped, or read from disk one frame at a time so that
+    /// memory use stays constant when playing files that are larger than RAM
+    #[structopt(
+        long,
+        default_value = "mmap",
+        possible_values = READ_STRATEGY_NAMES,
+        parse(try_from_str = parse_read_strategy)
+    )]
+    read: ReadStrategy,
+}
+
+impl FormatArgs {
+    fn overrides(&self) -> FormatOverrides {
+        FormatOverrides {
+            bayer: self.bayer,
+            pixel_depth_bits: self.bit_depth,
+            endianness: self.endianness,
+        }
+    }
+
+    fn mode(&self) -> OpenMode {
+        if self.strict {
+            OpenMode::Strict
+        } else {
+            OpenMode::Lenient
+        }
+    }
+
+    fn options(&self) -> OpenOptions {
+        OpenOptions {
+            overrides: self.overrides(),
+            mode: self.mode(),
+            read: self.read,
+            ..OpenOptions::default()
+        }
+    }
+
+    /// Open a video with the overrides applied, along with the codec to decode it
+    fn open(
+        &self,
+        filename: &str,
+    ) -> astro_video_player::Result<(Box<dyn Video>, Box<dyn ImageCodec>)> {
+        let video = open_video_with(filename, &self.options())?;
+        let codec = CodecRegistry::default()
+            .create(self.codec.as_deref().unwrap_or("auto"), video.as_ref())?;
+        Ok((video, codec))
+    }
+}
+
+/// How frames are chosen, aligned and combined when stacking
+#[derive(StructOpt, Debug)]
+struct StackArgs {
+    /// How to combine the frames
+    #[structopt(
+        long,
+        default_value = "mean",
+        possible_values = COMBINE_NAMES,
+        parse(try_from_str = parse_combine)
+    )]
+    method: Combine,
+    /// Image format to write (defaults to the format of the output file name, or PNG)
+    #[structopt(
+        long = "format",
+        possible_values = EXPORT_FORMAT_NAMES,
+        parse(try_from_str = parse_export_format)
+    )]
+    image_format: Option<ExportFormat>,
+    /// Only stack this percentage of the frames in the range, choosing the sharpest
+    #[structopt(long, parse(try_from_str = parse_percent))]
+    best: Option<f64>,
+    /// Align the frames with the sharpest frame before stacking them
+    #[structopt(long)]
+    align: bool,
+}
+
+impl StackArgs {
+    /// Format to write, which defaults to the format of the output file name
+    fn image_format(&self, output: &Path) -> ExportFormat {
+        self.image_format.unwrap_or_else(|| {
+            output
+                .extension()
+                .and_then(|extension| parse_export_format(&extension.to_string_lossy()).ok())
+                .unwrap_or(ExportFormat::Png)
+        })
+    }
+}
+
+/// How the frames of an animation are rendered
+#[derive(StructOpt, Debug)]
+struct AnimationArgs {
+    /// Animation format to write (defaults to the format of the output file name, or GIF)
+    #[structopt(
+        long = "format",
+        possible_values = ANIMATION_FORMAT_NAMES,
+        parse(try_from_str = parse_animation_format)
+    )]
+    animation_format: Option<AnimationFormat>,
+    /// Frames per second (defaults to the rate recorded in the file)
+    #[structopt(long)]
+    fps: Option<f64>,
+    /// Only keep every nth pixel in each direction, to make smaller files
+    #[structopt(long, default_value = "1")]
+    scale: u32,
+    /// Decoded level that is shown as black
+    #[structopt(long, default_value = "0")]
+    black: u8,
+    /// Decoded level that is shown as white
+    #[structopt(long, default_value = "255")]
+    white: u8,
+    /// Gamma applied between the black and white levels
+    #[structopt(long, default_value = "1")]
+    gamma: f32,
+    /// Colors used to show the brightness of the stretched frames
+    #[structopt(
+        long,
+        default_value = "grayscale",
+        possible_values = COLOR_MAP_NAMES,
+        parse(try_from_str = parse_color_map)
+    )]
+    color_map: ColorMap,
+}
+
+impl AnimationArgs {
+    /// Format to write, which defaults to the format of the output file name
+    fn animation_format(&self, output: &Path) -> AnimationFormat {
+        self.animation_format.unwrap_or_else(|| {
+            output
+                .extension()
+                .and_then(|extension| parse_animation_format(&extension.to_string_lossy()).ok())
+                .unwrap_or(AnimationFormat::Gif)
+        })
+    }
+
+    fn options(&self, video: &dyn Video) -> Result<AnimationOptions, String> {
+        if self.black >= self.white {
+            return Err(format!(
+                "The black level {} must be below the white level {}",
+                self.black, self.white
+            ));
+        }
+        let defaults = AnimationOptions::default();
+        Ok(AnimationOptions {
+            fps: self
+                .fps
+                .or_else(|| video.frame_rate())
+                .unwrap_or(defaults.fps),
+            scale: self.scale,
+            stretch: Stretch {
+                black: self.black,
+                white: self.white,
+                gamma: self.gamma,
             },
+            color_map: self.color_map,
+            ..defaults
+        })
+    }
+}
+
+/// Frames to process, from `start` up to (but not including) `end`
+#[derive(StructOpt, Debug)]
+struct FrameRange {
+    /// First frame to process
+    #[structopt(long, default_value = "0")]
+    start: usize,
+    /// Stop before this frame (defaults to the end of the video)
+    #[structopt(long)]
+    end: Option<usize>,
+    /// Only process every nth frame
+    #[structopt(long, default_value = "1")]
+    every: usize,
+    /// Skip the frames listed in this CSV file, such as a report written by the reject
+    /// command
+    #[structopt(long, parse(from_os_str))]
+    reject: Option<PathBuf>,
+}
+
+impl FrameRange {
+    fn indices(&self, frame_count: usize) -> astro_video_player::Result<Vec<usize>> {
+        let end = self.end.unwrap_or(frame_count).min(frame_count);
+        let rejected = match &self.reject {
+            Some(path) => rejection::read_rejected(path)?,
+            None => Default::default(),
+        };
+        Ok((self.start..end)
+            .step_by(self.every.max(1))
+            .filter(|index| !rejected.contains(index))
+            .collect())
+    }
+}
+
+impl Opt {
+    fn log_level(&self) -> Level {
+        match (self.quiet, self.verbose) {
+            (true, _) => Level::ERROR,
+            (false, 0) => Level::INFO,
+            (false, 1) => Level::DEBUG,
+            (false, _) => Level::TRACE,
+        }
+    }
+
+    /// Load the configuration file and apply any overrides from the command line
+    fn config(&self) -> astro_video_player::Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::load_from(path)?,
+            None => Config::load()?,
+        };
+        #[cfg(feature = "gui")]
+        if let Command::Play {
+            format,
+            theme,
+            codec_quality,
+            cache_mb,
+            frame_steps,
+            fps,
+            ..
+        } = &self.command
+        {
+            if let Some(codec) = &format.codec {
+                config.codec = codec.clone();
+            }
+            if fps.is_some() {
+                config.fps = *fps;
+            }
+            if let Some(theme) = theme {
+                config.theme = theme.clone();
+            }
+            if let Some(quality) = codec_quality {
+                config.codec_quality = *quality;
+            }
+            if let Some(cache_mb) = cache_mb {
+                config.cache_size_mb = *cache_mb;
+            }
+            if let Some(frame_steps) = frame_steps {
+                config.frame_steps = frame_steps.clone();
+            }
+        }
+        if self.threads.is_some() {
+            config.threads = self.threads;
+        }
+        Ok(config)
+    }
+}
+
+pub fn main() {
+    let opt = match Opt::from_iter_safe(std::env::args_os()) {
+        Ok(opt) => opt,
+        // `astro-video-player FILE` opens the file in the player, as it did before there were
+        // subcommands
+        #[cfg(feature = "gui")]
+        Err(e)
+            if matches!(
+                e.kind,
+                ErrorKind::UnknownArgument | ErrorKind::UnrecognizedSubcommand
+            ) =>
+        {
+            let mut args: Vec<_> = std::env::args_os().collect();
+            args.insert(args.len().min(1), "play".into());
+            Opt::from_iter_safe(args).unwrap_or_else(|_| e.exit())
+        }
+        Err(e) => e.exit(),
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(opt.log_level())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let config = match opt.config() {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Ignoring configuration file: {}", e);
+            Config::default()
+        }
+    };
+    if let Some(threads) = config.threads {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+        {
+            warn!("Ignoring thread count from configuration file: {}", e);
+        }
+    }
+
+    let result = match opt.command {
+        #[cfg(feature = "gui")]
+        Command::Play {
+            filenames,
+            files_from,
+            format,
+            blink,
+            follow,
+            ..
+        } => {
+            let config_path = opt.config.clone().or_else(Config::default_path);
+            play(
+                &filenames,
+                files_from.as_deref(),
+                format,
+                blink,
+                follow,
+                config,
+                config_path,
+            )
+        }
+        Command::Info { inputs, format } => inputs.for_each(|input, _| info(input, &format)),
+        Command::Convert {
+            inputs,
+            output,
+            range,
+            format,
+        } => inputs.for_each(|input, name| {
+            convert(input, &output_path(&output, name, "ser"), &range, &format)
+        }),
+        Command::Export {
+            inputs,
+            output,
+            prefix,
+            image_format,
+            annotations,
+            range,
+            format,
+        } => inputs.for_each(|input, name| {
+            export(
+                input,
+                &output_path(&output, name, ""),
+                &prefix,
+                image_format,
+                annotations,
+                &range,
+                &format,
+            )
+        }),
+        Command::Verify { inputs, format } => inputs.for_each(|input, _| verify(input, &format)),
+        Command::Gaps { inputs, format } => inputs.for_each(|input, _| gaps(input, &format)),
+        Command::Stack {
+            inputs,
+            output,
+            options,
+            range,
+            format,
+        } => {
+            let extension = options.image_format(&output).extension();
+            inputs.for_each(|input, name| {
+                stack(
+                    input,
+                    &output_path(&output, name, extension),
+                    &options,
+                    &range,
+                    &format,
+                )
+            })
+        }
+        Command::Animate {
+            inputs,
+            output,
+            options,
+            range,
+            format,
+        } => {
+            let extension = options.animation_format(&output).extension();
+            inputs.for_each(|input, name| {
+                animate(
+                    input,
+                    &output_path(&output, name, extension),
+                    &options,
+                    &range,
+                    &format,
+                )
+            })
+        }
+        Command::Rank {
+            inputs,
+            output,
+            format,
+        } => inputs.for_each(|input, name| {
+            let output = match &output {
+                Some(output) => output_path(output, name, "csv"),
+                None => quality::ranking_path(Some(Path::new(input))),
+            };
+            rank(input, &output, &format)
+        }),
+        Command::Reject {
+            inputs,
+            output,
+            sigma,
+            format,
+        } => inputs.for_each(|input, name| {
+            let output = match &output {
+                Some(output) => output_path(output, name, "csv"),
+                None => rejection::rejection_path(Some(Path::new(input))),
+            };
+            reject(input, &output, sigma.unwrap_or(DEFAULT_SIGMA), &format)
+        }),
+        Command::Seeing {
+            inputs,
+            output,
+            format,
+        } => inputs.for_each(|input, name| {
+            let output = match &output {
+                Some(output) => output_path(output, name, "csv"),
+                None => seeing::seeing_path(Some(Path::new(input))),
+            };
+            measure_seeing(input, &output, &format)
+        }),
+        Command::Run { pipeline } => Pipeline::load(&pipeline)
+            .and_then(|pipeline| pipeline.run())
+            .map(|count| println!("Processed {} frames", count))
+            .map_err(|e| e.into()),
+    };
+    if let Err(e) = result {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "gui")]
+fn play(
+    filenames: &[String],
+    files_from: Option<&Path>,
+    format: FormatArgs,
+    blink: Option<PathBuf>,
+    follow: bool,
+    config: Config,
+    config_path: Option<PathBuf>,
+) -> CommandResult {
+    check_display()?;
+    let mut files = expand_patterns(filenames)?;
+    if let Some(path) = files_from {
+        files.extend(read_files_from(path)?);
+    }
+    let files = expand_directories(files)?;
+    let filename = files.first().map(String::as_str);
+    // the player saves its settings itself when the window is closed
+    let mut settings: Settings<VideoPlayerArgs> = Settings {
+        exit_on_close_request: false,
+        ..Settings::default()
+    };
+    settings.flags.fps = config.fps;
+    settings.flags.codec_name = config.codec.clone();
+    settings.flags.config = config;
+    settings.flags.config_path = config_path;
+    settings.flags.blink = blink;
+    settings.flags.path = filename.map(PathBuf::from);
+    settings.flags.playlist = files.iter().map(PathBuf::from).collect();
+    settings.flags.overrides = format.overrides();
+    settings.flags.mode = format.mode();
+    settings.flags.read = format.read;
+    settings.flags.follow = follow;
+    if let Some(filename) = filename {
+        // the overrides are applied by the player so that they can be changed while it runs
+        let options = OpenOptions {
+            mode: format.mode(),
+            read: format.read,
+            follow,
+            ..OpenOptions::default()
+        };
+        match open_video_with(filename, &options) {
+            Ok(video) => settings.flags.video = Some(video),
             Err(e) => {
-                println!("Could not open SER file: {:?}", e);
-                Ok(())
+                error!("Could not open {}: {}", filename, e);
+                settings.flags.error = Some(format!("Could not open {}: {}", filename, e));
             }
         }
+    }
+    Ok(VideoPlayer::run(settings)?)
+}
+
+/// Make sure there is a display to open the player on, so that starting the player over SSH
+/// explains what to use instead rather than failing inside the windowing library
+#[cfg(feature = "gui")]
+fn check_display() -> CommandResult {
+    let has_display = |name| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    if cfg!(all(unix, not(target_os = "macos")))
+        && !has_display("DISPLAY")
+        && !has_display("WAYLAND_DISPLAY")
+    {
+        return Err(
+            "There is no display to open the player on. The info, export, stack, \
+            animate and convert subcommands work without one."
+                .into(),
+        );
+    }
+    Ok(())
+}
+
+fn info(filename: &str, format: &FormatArgs) -> CommandResult {
+    let video = open_video_with(filename, &format.options())?;
+    println!("File: {}", filename);
+    for (name, value) in video_info(video.as_ref()) {
+        println!("{}: {}", name, value);
+    }
+    Ok(())
+}
+
+fn convert(input: &str, output: &Path, range: &FrameRange, format: &FormatArgs) -> CommandResult {
+    let video = open_video_with(input, &format.options())?;
+    create_parent_dir(output)?;
+    let indices = range.indices(video.frame_count())?;
+    ser::write_clip(video.as_ref(), &indices, output)?;
+    println!("Wrote {} frames to {}", indices.len(), output.display());
+    Ok(())
+}
+
+fn export(
+    input: &str,
+    directory: &Path,
+    prefix: &str,
+    image_format: ExportFormat,
+    annotate: bool,
+    range: &FrameRange,
+    format: &FormatArgs,
+) -> CommandResult {
+    if annotate && image_format != ExportFormat::Png {
+        return Err("Annotations can only be drawn into PNG images".into());
+    }
+    let (video, codec) = format.open(input)?;
+    let annotations = if annotate {
+        load_annotations(&annotations_path(Some(Path::new(input))))?
     } else {
-        println!("Can only read AVI and SER");
-        Ok(())
+        vec![]
+    };
+    fs::create_dir_all(directory)?;
+    let indices = range.indices(video.frame_count())?;
+    // each thread reuses one buffer for the frames that it decodes
+    indices
+        .par_iter()
+        .try_for_each_init(Vec::new, |buffer, &index| {
+            let path = directory.join(format!(
+                "{}{:05}.{}",
+                prefix,
+                index,
+                image_format.extension()
+            ));
+            if annotate {
+                export_annotated_png(
+                    video.as_ref(),
+                    codec.as_ref(),
+                    index,
+                    &annotations,
+                    (0, 0),
+                    &path,
+                    buffer,
+                )
+            } else {
+                export_frame_with_buffer(
+                    video.as_ref(),
+                    codec.as_ref(),
+                    index,
+                    image_format,
+                    &path,
+                    buffer,
+                )
+            }
+        })?;
+    println!(
+        "Exported {} frames to {}",
+        indices.len(),
+        directory.display()
+    );
+    Ok(())
+}
+
+fn gaps(filename: &str, format: &FormatArgs) -> CommandResult {
+    let video = open_video_with(filename, &format.options())?;
+    if video.frame_timestamp(0).is_none() {
+        println!("{}: no frame timestamps", filename);
+        return Ok(());
+    }
+    let gaps = find_gaps(video.as_ref());
+    for gap in &gaps {
+        println!(
+            "{} frames {} to {}: {:.3} s gap, about {} frames dropped",
+            filename,
+            gap.after,
+            gap.after + 1,
+            gap.duration.as_secs_f64(),
+            gap.dropped
+        );
+    }
+    println!(
+        "{}: {} gaps, about {} frames dropped",
+        filename,
+        gaps.len(),
+        gaps.iter().map(|gap| gap.dropped).sum::<usize>()
+    );
+    Ok(())
+}
+
+fn verify(filename: &str, format: &FormatArgs) -> CommandResult {
+    let (video, codec) = format.open(filename)?;
+    let mut failures = 0;
+    let mut buffer = vec![];
+    for index in 0..video.frame_count() {
+        if let Err(e) = codec.decode_into(video.as_ref(), index, &mut buffer) {
+            println!("{} frame {}: {}", filename, index, e);
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        return Err(format!(
+            "{} of {} frames could not be decoded",
+            failures,
+            video.frame_count()
+        )
+        .into());
+    }
+    println!(
+        "{}: all {} frames decoded successfully",
+        filename,
+        video.frame_count()
+    );
+    Ok(())
+}
+
+fn stack(
+    input: &str,
+    output: &Path,
+    options: &StackArgs,
+    range: &FrameRange,
+    format: &FormatArgs,
+) -> CommandResult {
+    let (video, codec) = format.open(input)?;
+    let fraction = options.best.unwrap_or(100.0) / 100.0;
+    let (indices, shifts) = stack::lucky_frames(
+        video.as_ref(),
+        &range.indices(video.frame_count())?,
+        fraction,
+        options.align,
+    )?;
+    create_parent_dir(output)?;
+    stack::export_stack(
+        video.as_ref(),
+        codec.as_ref(),
+        &indices,
+        &shifts,
+        options.method,
+        options.image_format(output),
+        output,
+    )?;
+    println!("Stacked {} frames into {}", indices.len(), output.display());
+    Ok(())
+}
+
+fn animate(
+    input: &str,
+    output: &Path,
+    options: &AnimationArgs,
+    range: &FrameRange,
+    format: &FormatArgs,
+) -> CommandResult {
+    let (video, codec) = format.open(input)?;
+    let indices = range.indices(video.frame_count())?;
+    create_parent_dir(output)?;
+    animation::export_animation(
+        video.as_ref(),
+        codec.as_ref(),
+        &indices,
+        options.animation_format(output),
+        &options.options(video.as_ref())?,
+        output,
+    )?;
+    println!("Saved {} frames to {}", indices.len(), output.display());
+    Ok(())
+}
+
+fn rank(input: &str, output: &Path, format: &FormatArgs) -> CommandResult {
+    let video = open_video_with(input, &format.options())?;
+    let ranked = quality::rank(&quality::score_frames(video.as_ref())?);
+    create_parent_dir(output)?;
+    quality::write_csv(&ranked, &mut BufWriter::new(fs::File::create(output)?))?;
+    if let Some(best) = ranked.first() {
+        println!(
+            "Ranked {} frames into {} (sharpest is frame {})",
+            ranked.len(),
+            output.display(),
+            best.index
+        );
+    }
+    Ok(())
+}
+
+fn reject(input: &str, output: &Path, sigma: f64, format: &FormatArgs) -> CommandResult {
+    let video = open_video_with(input, &format.options())?;
+    let brightness = rejection::frame_brightness(video.as_ref())?;
+    let rejections = rejection::reject(&brightness, sigma);
+    create_parent_dir(output)?;
+    rejection::write_csv(&rejections, &mut BufWriter::new(fs::File::create(output)?))?;
+    println!(
+        "Rejected {} of {} frames into {}",
+        rejections.len(),
+        brightness.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn measure_seeing(input: &str, output: &Path, format: &FormatArgs) -> CommandResult {
+    let video = open_video_with(input, &format.options())?;
+    let measured = seeing::measure_seeing(video.as_ref())?;
+    create_parent_dir(output)?;
+    seeing::write_seeing_csv(&measured, &mut BufWriter::new(fs::File::create(output)?))?;
+    match seeing::median_fwhm(&measured) {
+        Some(median) => println!(
+            "Measured {} frames into {} (median FWHM {:.2} px)",
+            measured.len(),
+            output.display(),
+            median
+        ),
+        None => println!("{}: no stars found", input),
+    }
+    Ok(())
+}
+
+/// Parse a percentage greater than 0 and up to 100
+fn parse_percent(text: &str) -> Result<f64, String> {
+    match text.trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        _ => Err(format!("'{}' is not a percentage between 0 and 100", text)),
+    }
+}
+
+fn create_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
     }
 }
@@ -0,0 +1,126 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Blink comparator, which alternates between the current frame and a reference frame so that
+//! anything that moved or changed brightness between them stands out. The reference frame can
+//! be a frame of the same video or come from another video, such as an earlier capture of the
+//! same field.
+
+use crate::align::Shift;
+use crate::error::{PlayerError, Result};
+
+/// Blink rates that can be chosen, in images shown per second
+pub const BLINK_RATE_NAMES: &[&str] = &["1", "2", "4", "8"];
+
+/// Rate that blinking starts at
+pub const DEFAULT_BLINK_RATE: f64 = 2.0;
+
+pub fn parse_blink_rate(name: &str) -> Result<f64> {
+    match name.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Invalid blink rate '{}', expected a number of images per second such as {}",
+            name,
+            BLINK_RATE_NAMES.join(", ")
+        ))),
+    }
+}
+
+/// Which frame of the reference video the current frame is compared with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlinkTarget {
+    /// Always the same frame, such as a frame of the open video that was pinned
+    Frame(usize),
+    /// The frame with the same index as the current frame
+    SameIndex,
+}
+
+impl BlinkTarget {
+    /// Index of the reference frame for the `current` frame, limited to the frames in the
+    /// reference video, or `None` if it has no frames
+    pub fn index(&self, current: usize, frame_count: usize) -> Option<usize> {
+        let index = match self {
+            BlinkTarget::Frame(index) => *index,
+            BlinkTarget::SameIndex => current,
+        };
+        frame_count.checked_sub(1).map(|last| index.min(last))
+    }
+}
+
+/// State of the blink comparator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blink {
+    pub target: BlinkTarget,
+    /// Images shown per second
+    pub rate: f64,
+    /// Translation applied to the reference frame to line it up with the current frame
+    pub offset: Shift,
+    /// Whether the reference frame is shown rather than the current frame
+    pub showing_reference: bool,
+}
+
+impl Blink {
+    pub fn new(target: BlinkTarget, rate: f64) -> Self {
+        Self {
+            target,
+            rate,
+            offset: Shift::default(),
+            showing_reference: false,
+        }
+    }
+
+    /// Switch between the current frame and the reference frame
+    pub fn toggle(&mut self) {
+        self.showing_reference = !self.showing_reference;
+    }
+
+    /// Move the reference frame by the given number of pixels
+    pub fn nudge(&mut self, dx: i32, dy: i32) {
+        self.offset.dx += dx;
+        self.offset.dy += dy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blink() {
+        assert_eq!(Some(3), BlinkTarget::Frame(3).index(7, 10));
+        assert_eq!(Some(9), BlinkTarget::Frame(30).index(7, 10));
+        assert_eq!(Some(7), BlinkTarget::SameIndex.index(7, 10));
+        assert_eq!(Some(4), BlinkTarget::SameIndex.index(7, 5));
+        assert_eq!(None, BlinkTarget::SameIndex.index(7, 0));
+
+        let mut blink = Blink::new(BlinkTarget::SameIndex, DEFAULT_BLINK_RATE);
+        blink.toggle();
+        assert!(blink.showing_reference);
+        blink.nudge(1, 0);
+        blink.nudge(1, -1);
+        assert_eq!(Shift { dx: 2, dy: -1 }, blink.offset);
+
+        assert!((parse_blink_rate("4").unwrap() - 4.0).abs() < f64::EPSILON);
+        assert!(parse_blink_rate("0").is_err());
+        assert!(parse_blink_rate("fast").is_err());
+    }
+}
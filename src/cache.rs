@@ -0,0 +1,461 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Caching of decoded frames so that stepping back and forth through a video and playing it
+//! do not decode the same frames repeatedly.
+//!
+//! `LruCache` holds values up to a memory budget, `FrameCache` holds decoded frames and
+//! evicts the frames farthest from the one being shown, and `Prefetcher` fills the cache on
+//! background threads with the frames that are likely to be shown next.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+
+use tracing::{debug, trace};
+
+use crate::codec::ImageCodec;
+use crate::error::lock;
+use crate::video_format::Video;
+
+struct CacheEntry<V> {
+    value: V,
+    size: usize,
+    last_used: u64,
+}
+
+/// Cache that evicts the least recently used values once the total size of the values
+/// exceeds a budget
+pub struct LruCache<K, V> {
+    capacity: usize,
+    used: usize,
+    clock: u64,
+    entries: HashMap<K, CacheEntry<V>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    /// Create a cache that holds up to `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            entry.value.clone()
+        })
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Add a value that occupies `size` bytes, evicting the least recently used values to
+    /// make room. Values that are larger than the whole cache are not stored.
+    pub fn insert(&mut self, key: K, value: V, size: usize) {
+        self.insert_evicting(key, value, size, |_| 0)
+    }
+
+    /// Add a value like `insert`, evicting the values with the highest `priority` first and
+    /// the least recently used of those with the same priority. The value is not stored if
+    /// making room for it would evict values with a lower priority than its own, since it
+    /// would be the next value to go.
+    pub fn insert_evicting<P: Ord>(
+        &mut self,
+        key: K,
+        value: V,
+        size: usize,
+        priority: impl Fn(&K) -> P,
+    ) {
+        if size > self.capacity {
+            return;
+        }
+        let new_priority = priority(&key);
+        let evictable: usize = self
+            .entries
+            .iter()
+            .filter(|(entry_key, _)| **entry_key == key || priority(entry_key) >= new_priority)
+            .map(|(_, entry)| entry.size)
+            .sum();
+        if self.used - evictable + size > self.capacity {
+            return;
+        }
+        self.remove(&key);
+        while self.used + size > self.capacity {
+            let evicted = self
+                .entries
+                .iter()
+                .max_by_key(|(key, entry)| (priority(key), Reverse(entry.last_used)))
+                .map(|(key, _)| key.clone());
+            match evicted {
+                Some(evicted) => self.remove(&evicted),
+                None => break,
+            }
+        }
+        self.clock += 1;
+        self.used += size;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                size,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.used -= entry.size;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of the cached values, in bytes
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Decoded frames keyed by the epoch of the video and codec that decoded them, along with the
+/// frame index. The epoch changes whenever the video is reopened with different settings, so
+/// that frames decoded with the old settings are never returned.
+///
+/// Once the cache is full, frames from earlier epochs are evicted first, followed by the
+/// frames farthest from the current frame, so that the frames around the current position
+/// stay cached however the user moves through the video.
+pub struct FrameCache<V> {
+    frames: LruCache<(u64, usize), V>,
+    /// Epoch and index of the frame being shown
+    current: Option<(u64, usize)>,
+}
+
+impl<V: Clone> FrameCache<V> {
+    /// Create a cache that holds up to `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: LruCache::new(capacity),
+            current: None,
+        }
+    }
+
+    /// Set the frame being shown, which determines the frames that are evicted first
+    pub fn set_current(&mut self, epoch: u64, index: usize) {
+        self.current = Some((epoch, index));
+    }
+
+    pub fn get(&mut self, key: &(u64, usize)) -> Option<V> {
+        self.frames.get(key)
+    }
+
+    pub fn contains(&self, key: &(u64, usize)) -> bool {
+        self.frames.contains(key)
+    }
+
+    /// Add a frame that occupies `size` bytes, evicting the frames farthest from the current
+    /// frame to make room. A frame that is farther away than the frames it would replace is
+    /// not stored.
+    pub fn insert(&mut self, key: (u64, usize), value: V, size: usize) {
+        match self.current {
+            Some((epoch, current)) => {
+                self.frames
+                    .insert_evicting(key, value, size, |&(frame_epoch, index)| {
+                        (frame_epoch != epoch, distance(index, current))
+                    })
+            }
+            None => self.frames.insert(key, value, size),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Total size of the cached frames, in bytes
+    pub fn used(&self) -> usize {
+        self.frames.used()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.frames.capacity()
+    }
+}
+
+fn distance(a: usize, b: usize) -> usize {
+    a.max(b) - a.min(b)
+}
+
+struct PrefetchRequest {
+    id: u64,
+    epoch: u64,
+    video: Arc<dyn Video>,
+    codec: Arc<dyn ImageCodec>,
+    indices: Vec<usize>,
+    /// Position in `indices` of the next frame for a worker to decode
+    next: AtomicUsize,
+}
+
+/// State shared between the prefetcher and its worker threads
+struct PrefetchQueue {
+    request: Mutex<Option<Arc<PrefetchRequest>>>,
+    changed: Condvar,
+    latest: AtomicU64,
+    stopped: AtomicBool,
+}
+
+/// Decodes frames into a shared cache on background threads. Only the most recent request
+/// is worked on, so a request for frames ahead of the current frame replaces any earlier
+/// requests that have not been completed yet. The worker threads share the frames of a
+/// request between them, in the order they were requested.
+pub struct Prefetcher<V> {
+    queue: Arc<PrefetchQueue>,
+    cache: Arc<Mutex<FrameCache<V>>>,
+}
+
+impl<V: Clone + Send + 'static> Prefetcher<V> {
+    /// Start `threads` worker threads, or one if `threads` is 0. `convert` turns a decoded
+    /// BGRA frame into the value to cache along with its size in bytes. Each worker decodes
+    /// into the same buffer every time, so values that keep the pixels should take the buffer
+    /// and values that only need part of them should leave it to be reused.
+    pub fn new<F>(cache: Arc<Mutex<FrameCache<V>>>, threads: usize, convert: F) -> Self
+    where
+        F: Fn(u32, u32, &mut Vec<u8>) -> (V, usize) + Send + Sync + 'static,
+    {
+        let queue = Arc::new(PrefetchQueue {
+            request: Mutex::new(None),
+            changed: Condvar::new(),
+            latest: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+        });
+        let convert = Arc::new(convert);
+        for worker in 0..threads.max(1) {
+            let queue = queue.clone();
+            let cache = cache.clone();
+            let convert = convert.clone();
+            thread::Builder::new()
+                .name(format!("prefetch-{}", worker))
+                .spawn(move || prefetch_frames(&queue, &cache, convert.as_ref()))
+                .expect("failed to start prefetch thread");
+        }
+        Self { queue, cache }
+    }
+
+    /// Decode the given frames in the background, abandoning any earlier request
+    pub fn prefetch(
+        &self,
+        epoch: u64,
+        video: Arc<dyn Video>,
+        codec: Arc<dyn ImageCodec>,
+        indices: Vec<usize>,
+    ) {
+        let id = self.queue.latest.fetch_add(1, Ordering::SeqCst) + 1;
+        let request = PrefetchRequest {
+            id,
+            epoch,
+            video,
+            codec,
+            indices,
+            next: AtomicUsize::new(0),
+        };
+        *lock(&self.queue.request) = Some(Arc::new(request));
+        self.queue.changed.notify_all();
+    }
+
+    pub fn cache(&self) -> &Arc<Mutex<FrameCache<V>>> {
+        &self.cache
+    }
+}
+
+impl<V> Drop for Prefetcher<V> {
+    fn drop(&mut self) {
+        // the lock is held so that no worker misses the notification between checking the
+        // flag and waiting
+        let _request = lock(&self.queue.request);
+        self.queue.stopped.store(true, Ordering::SeqCst);
+        self.queue.changed.notify_all();
+    }
+}
+
+/// Loop of a prefetch worker thread, which ends when the prefetcher is dropped
+fn prefetch_frames<V, F>(queue: &PrefetchQueue, cache: &Mutex<FrameCache<V>>, convert: &F)
+where
+    V: Clone,
+    F: Fn(u32, u32, &mut Vec<u8>) -> (V, usize),
+{
+    let mut finished = 0;
+    let mut pixels = vec![];
+    loop {
+        let request = {
+            let mut request = lock(&queue.request);
+            loop {
+                if queue.stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                match &*request {
+                    Some(pending) if pending.id != finished => break pending.clone(),
+                    _ => {
+                        request = queue
+                            .changed
+                            .wait(request)
+                            .unwrap_or_else(PoisonError::into_inner)
+                    }
+                }
+            }
+        };
+        while let Some(&index) = request
+            .indices
+            .get(request.next.fetch_add(1, Ordering::SeqCst))
+        {
+            if queue.latest.load(Ordering::SeqCst) != request.id {
+                break;
+            }
+            let key = (request.epoch, index);
+            if lock(cache).contains(&key) {
+                continue;
+            }
+            match request
+                .codec
+                .decode_into(request.video.as_ref(), index, &mut pixels)
+            {
+                Ok((width, height)) => {
+                    trace!(frame = index, "prefetched frame");
+                    let (value, size) = convert(width, height, &mut pixels);
+                    lock(cache).insert(key, value, size);
+                }
+                Err(e) => debug!(frame = index, "failed to prefetch frame: {}", e),
+            }
+        }
+        finished = request.id;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::codec::codec_for;
+    use crate::testutil::{ser_format, ser_video};
+    use crate::video_format::Bayer;
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let mut cache = LruCache::new(30);
+        cache.insert(1, "a", 10);
+        cache.insert(2, "b", 10);
+        cache.insert(3, "c", 10);
+        assert_eq!(Some("a"), cache.get(&1));
+        cache.insert(4, "d", 10);
+        assert!(!cache.contains(&2));
+        assert!(cache.contains(&1));
+        assert_eq!(30, cache.used());
+
+        cache.insert(5, "e", 31);
+        assert!(!cache.contains(&5));
+        cache.insert(1, "a", 20);
+        assert_eq!(2, cache.len());
+        assert_eq!(30, cache.used());
+    }
+
+    #[test]
+    fn test_evict_farthest_frames() {
+        let mut cache = FrameCache::new(30);
+        cache.set_current(1, 5);
+        cache.insert((0, 5), "old epoch", 10);
+        cache.insert((1, 2), "far", 10);
+        cache.insert((1, 4), "near", 10);
+        // frames from earlier epochs go first, then the farthest from the current frame
+        cache.insert((1, 6), "next", 10);
+        assert!(!cache.contains(&(0, 5)));
+        cache.insert((1, 7), "after next", 10);
+        assert!(!cache.contains(&(1, 2)));
+        assert!(cache.contains(&(1, 4)));
+        cache.set_current(1, 9);
+        cache.insert((1, 9), "current", 10);
+        assert!(!cache.contains(&(1, 4)));
+        assert_eq!(3, cache.len());
+        // a frame farther away than everything cached would be the next to go
+        cache.insert((1, 1), "farthest", 10);
+        assert!(!cache.contains(&(1, 1)));
+        assert_eq!(3, cache.len());
+    }
+
+    #[test]
+    fn test_prefetch() {
+        let frames = (0..5).map(|i| vec![i * 50; 16]).collect::<Vec<_>>();
+        let video: Arc<dyn Video> = Arc::new(ser_video(&ser_format(4, 4, Bayer::RGGB, 8), &frames));
+        let codec: Arc<dyn ImageCodec> = Arc::from(codec_for(video.as_ref()).unwrap());
+
+        let cache = Arc::new(Mutex::new(FrameCache::new(1024)));
+        // only the first pixel is kept, so each worker reuses its buffer
+        let prefetcher = Prefetcher::new(cache, 2, |_, _, pixels: &mut Vec<u8>| {
+            (pixels[..4].to_vec(), 4)
+        });
+        prefetcher.prefetch(7, video, codec, vec![1, 2, 3]);
+        let start = Instant::now();
+        while prefetcher.cache().lock().unwrap().len() < 3 {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "prefetch timed out"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut cache = prefetcher.cache().lock().unwrap();
+        assert!(cache.contains(&(7, 3)));
+        assert!(!cache.contains(&(7, 0)));
+        assert_eq!(&[99, 99, 99, 255], &cache.get(&(7, 2)).unwrap()[0..4]);
+    }
+}
@@ -0,0 +1,103 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::error::{PlayerError, Result};
+
+/// Optional capabilities that are compiled in by enabling cargo features, so that the core
+/// player stays lightweight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    /// Debayering on the GPU (`gpu` feature)
+    GpuDebayer,
+    /// Decoding of MJPEG compressed AVI frames (`mjpeg` feature)
+    Mjpeg,
+    /// Exporting animations by piping frames to ffmpeg (`ffmpeg` feature)
+    FfmpegExport,
+    /// Live preview from Video4Linux cameras (`live` feature, Linux only)
+    LiveCamera,
+    /// Live preview from cameras on INDI servers (`indi` feature)
+    IndiCamera,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::GpuDebayer,
+        Capability::Mjpeg,
+        Capability::FfmpegExport,
+        Capability::LiveCamera,
+        Capability::IndiCamera,
+    ];
+
+    /// Name of the cargo feature that enables this capability
+    pub fn feature(&self) -> &'static str {
+        match self {
+            Capability::GpuDebayer => "gpu",
+            Capability::Mjpeg => "mjpeg",
+            Capability::FfmpegExport => "ffmpeg",
+            Capability::LiveCamera => "live",
+            Capability::IndiCamera => "indi",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Capability::GpuDebayer => "GPU debayering",
+            Capability::Mjpeg => "MJPEG decoding",
+            Capability::FfmpegExport => "ffmpeg export",
+            Capability::LiveCamera => "Live camera preview",
+            Capability::IndiCamera => "INDI camera preview",
+        }
+    }
+
+    /// Determine whether this capability was compiled in
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            Capability::GpuDebayer => cfg!(feature = "gpu"),
+            Capability::Mjpeg => cfg!(feature = "mjpeg"),
+            Capability::FfmpegExport => cfg!(feature = "ffmpeg"),
+            Capability::LiveCamera => cfg!(all(feature = "live", target_os = "linux")),
+            Capability::IndiCamera => cfg!(feature = "indi"),
+        }
+    }
+
+    /// Return an error explaining how to enable this capability if it was not compiled in
+    pub fn require(&self) -> Result<()> {
+        if self.is_enabled() {
+            Ok(())
+        } else {
+            Err(PlayerError::Unsupported(format!(
+                "{} requires building with the `{}` feature",
+                self.description(),
+                self.feature()
+            )))
+        }
+    }
+}
+
+/// All capabilities that were compiled into this build
+pub fn enabled_capabilities() -> Vec<Capability> {
+    Capability::ALL
+        .iter()
+        .copied()
+        .filter(Capability::is_enabled)
+        .collect()
+}
@@ -20,15 +20,284 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::video_format::Video;
-use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
-use ser_io::{Bayer, Endianness};
+use crate::capabilities::Capability;
+use crate::error::{PlayerError, Result};
+use crate::video_format::{
+    check_uncompressed, samples, stored_row, Bayer, Compression, CroppedFrame, Region, Video,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
 
-/// Trait for all debayering implementations
-pub trait ImageCodec {
-    fn decode(&self, video: &dyn Video, frame_index: usize) -> (u32, u32, Vec<u8>);
+/// Trait for all debayering implementations, which convert the raw frames of a `Video` into
+/// 8-bit BGRA images. Use `codec_for` to choose a codec based on the color encoding of a
+/// video, or `codec_named` to choose one by name.
+pub trait ImageCodec: Send + Sync {
+    /// Decode a frame at full resolution into BGRA pixels
+    fn decode(&self, video: &dyn Video, frame_index: usize) -> Result<(u32, u32, Vec<u8>)> {
+        self.decode_scaled(video, frame_index, 1)
+    }
+
+    /// Decode a frame at full resolution into `out`, replacing its contents but reusing its
+    /// allocation, and return the width and height. Callers that decode many frames can
+    /// keep one buffer rather than allocating a new image for every frame.
+    fn decode_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        self.decode_scaled_into(video, frame_index, 1, out)
+    }
+
+    /// Decode a frame into BGRA pixels, sampling only every `scale`th output pixel in each
+    /// direction. This is much cheaper than a full decode and is used to show a coarse
+    /// preview while the full resolution image is being decoded.
+    fn decode_scaled(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+    ) -> Result<(u32, u32, Vec<u8>)> {
+        let mut pixels = vec![];
+        let (width, height) = self.decode_scaled_into(video, frame_index, scale, &mut pixels)?;
+        Ok((width, height, pixels))
+    }
+
+    /// Decode a frame into `out` like `decode_into`, sampling every `scale`th output pixel
+    /// like `decode_scaled`. This is the method that codecs implement.
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)>;
+}
+
+/// Names that can be passed to `codec_named`: `auto` followed by the codecs of the default
+/// `CodecRegistry`
+pub const CODEC_NAMES: &[&str] = &[
+    "auto", "debayer", "bilinear", "mhc", "gpu", "rgb", "mono", "mjpeg",
+];
+
+/// Look up a codec by name in the default `CodecRegistry`, where `auto` chooses the codec
+/// based on the color encoding of the video
+pub fn codec_named(name: &str, video: &dyn Video) -> Result<Box<dyn ImageCodec>> {
+    CodecRegistry::default().create(name, video)
+}
+
+/// Choose the codec for decoding a video from the default `CodecRegistry`, based on its
+/// compression, color encoding and bit depth
+pub fn codec_for(video: &dyn Video) -> Result<Box<dyn ImageCodec>> {
+    CodecRegistry::default().select(video)
+}
+
+/// Names of the codec qualities, as accepted by `parse_codec_quality`
+pub const CODEC_QUALITY_NAMES: &[&str] = &["fast", "best"];
+
+/// How `auto` decodes color mosaics, see `CodecRegistry::with_quality`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecQuality {
+    /// Build each pixel from a 2x2 quad with `DebayerCodec`, which is fast but halves the
+    /// resolution
+    #[default]
+    Fast,
+    /// Debayer at full resolution with `MhcDebayerCodec`
+    Best,
+}
+
+pub fn parse_codec_quality(name: &str) -> Result<CodecQuality> {
+    match name.to_ascii_lowercase().as_str() {
+        "fast" => Ok(CodecQuality::Fast),
+        "best" => Ok(CodecQuality::Best),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown codec quality '{}', expected one of {}",
+            name,
+            CODEC_QUALITY_NAMES.join(", ")
+        ))),
+    }
 }
 
+/// Creates a codec for decoding a video
+pub type CodecFactory = fn(&dyn Video) -> Result<Box<dyn ImageCodec>>;
+
+/// The videos that a codec is chosen for automatically
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodecRule {
+    pub compression: Compression,
+    /// Color encodings of the frames, or any encoding if this is empty
+    pub bayers: &'static [Bayer],
+    /// Bit depths of the samples
+    pub bit_depths: RangeInclusive<u32>,
+}
+
+impl CodecRule {
+    /// Whether the codec should be chosen for a video
+    pub fn matches(&self, video: &dyn Video) -> bool {
+        self.compression == video.compression()
+            && (self.bayers.is_empty() || self.bayers.contains(video.bayer()))
+            && self.bit_depths.contains(&video.pixel_depth_bits())
+    }
+}
+
+/// Named codecs, and the rules for choosing one automatically. New decoders are added by
+/// registering a factory under a name, along with rules for the videos they should be
+/// chosen for.
+///
+/// ```no_run
+/// use astro_video_player::codecs::{CodecRegistry, CodecRule, MhcDebayerCodec};
+/// use astro_video_player::video_format::{Bayer, Compression};
+///
+/// // use the higher quality debayering for 16-bit RGGB frames
+/// let mut registry = CodecRegistry::default();
+/// registry.register("mhc", |_| Ok(Box::new(MhcDebayerCodec {})));
+/// registry.prefer(
+///     CodecRule {
+///         compression: Compression::None,
+///         bayers: &[Bayer::RGGB],
+///         bit_depths: 9..=16,
+///     },
+///     "mhc",
+/// );
+/// ```
+pub struct CodecRegistry {
+    codecs: Vec<(&'static str, CodecFactory)>,
+    rules: Vec<(CodecRule, &'static str)>,
+}
+
+impl CodecRegistry {
+    /// A registry without any codecs
+    pub fn new() -> Self {
+        Self {
+            codecs: vec![],
+            rules: vec![],
+        }
+    }
+
+    /// The default registry, with `auto` debayering color mosaics at the given quality
+    pub fn with_quality(quality: CodecQuality) -> Self {
+        let mut registry = Self::default();
+        if quality == CodecQuality::Best {
+            let rule = CodecRule {
+                compression: Compression::None,
+                bayers: BAYER_PATTERNS,
+                bit_depths: 0..=u32::MAX,
+            };
+            registry.prefer(rule, "mhc");
+        }
+        registry
+    }
+
+    /// Add a codec, replacing any codec that was registered with the same name
+    pub fn register(&mut self, name: &'static str, factory: CodecFactory) {
+        match self
+            .codecs
+            .iter_mut()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+        {
+            Some(codec) => codec.1 = factory,
+            None => self.codecs.push((name, factory)),
+        }
+    }
+
+    /// Choose the codec called `name` for videos that match `rule`. Rules that are added
+    /// later take priority, so they can override the default choices.
+    pub fn prefer(&mut self, rule: CodecRule, name: &'static str) {
+        self.rules.push((rule, name));
+    }
+
+    /// Names of the registered codecs, in the order they were registered
+    pub fn names(&self) -> Vec<&'static str> {
+        self.codecs.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Create the codec called `name`, ignoring case, where `auto` chooses the codec with
+    /// `select`
+    pub fn create(&self, name: &str, video: &dyn Video) -> Result<Box<dyn ImageCodec>> {
+        if name.eq_ignore_ascii_case("auto") {
+            return self.select(video);
+        }
+        match self
+            .codecs
+            .iter()
+            .find(|(registered, _)| registered.eq_ignore_ascii_case(name))
+        {
+            Some((_, factory)) => factory(video),
+            None => Err(PlayerError::InvalidArgument(format!(
+                "Unknown codec '{}', expected one of auto, {}",
+                name,
+                self.names().join(", ")
+            ))),
+        }
+    }
+
+    /// Choose a codec based on the compression, color encoding and bit depth of a video
+    pub fn select(&self, video: &dyn Video) -> Result<Box<dyn ImageCodec>> {
+        match self
+            .rules
+            .iter()
+            .rev()
+            .find(|(rule, _)| rule.matches(video))
+        {
+            Some((_, name)) => self.create(name, video),
+            None => Err(PlayerError::Unsupported(format!(
+                "Unsupported bayer {:?} with {}-bit samples in {:?} compressed frames",
+                video.bayer(),
+                video.pixel_depth_bits(),
+                video.compression()
+            ))),
+        }
+    }
+}
+
+impl Default for CodecRegistry {
+    /// The codecs in `CODEC_NAMES`, choosing MJPEG for compressed frames and otherwise
+    /// debayering, RGB or mono depending on the color encoding
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register("debayer", |_| Ok(Box::new(DebayerCodec {})));
+        registry.register("bilinear", |_| Ok(Box::new(BilinearDebayerCodec {})));
+        registry.register("mhc", |_| Ok(Box::new(MhcDebayerCodec {})));
+        registry.register("gpu", |_| gpu_codec());
+        registry.register("rgb", |video| {
+            Ok(Box::new(RgbCodec::new(match video.bayer() {
+                Bayer::RGB => Bayer::RGB,
+                _ => Bayer::BGR,
+            })))
+        });
+        registry.register("mono", |_| Ok(Box::new(MonoCodec {})));
+        registry.register("mjpeg", |_| {
+            Capability::Mjpeg.require()?;
+            Ok(Box::new(MjpegCodec {}))
+        });
+        let rules = [
+            (Compression::None, BAYER_PATTERNS, "debayer"),
+            (Compression::None, &[Bayer::RGB, Bayer::BGR][..], "rgb"),
+            (Compression::None, &[Bayer::Mono][..], "mono"),
+            (Compression::Mjpeg, &[][..], "mjpeg"),
+        ];
+        for (compression, bayers, name) in rules.iter() {
+            let rule = CodecRule {
+                compression: *compression,
+                bayers,
+                bit_depths: 0..=u32::MAX,
+            };
+            registry.prefer(rule, name);
+        }
+        registry
+    }
+}
+
+/// Color filter patterns that the debayering codecs support
+const BAYER_PATTERNS: &[Bayer] = &[Bayer::RGGB, Bayer::BGGR, Bayer::GBRG, Bayer::GRBG];
+
+/// Decodes frames that are already in color, with 3 samples per pixel in red, green, blue
+/// (`Bayer::RGB`) or blue, green, red (`Bayer::BGR`) order. Samples are 8 or 16-bit.
 pub struct RgbCodec {
     bayer: Bayer,
 }
@@ -40,36 +309,123 @@ impl RgbCodec {
 }
 
 impl ImageCodec for RgbCodec {
-    fn decode(&self, video: &dyn Video, frame_index: usize) -> (u32, u32, Vec<u8>) {
-        let bytes = video.get_frame(frame_index).unwrap();
-        let mut pixels =
-            Vec::with_capacity((video.image_width() * video.image_height() * 4) as usize);
-        let bytes_per_row = video.image_width() * 3;
-        let alpha = 255;
-        for y in 0..video.image_height() {
-            for x in 0..video.image_width() {
-                let y_offset = y * bytes_per_row;
-                let x_offset = x * 3;
-                let offset = y_offset as usize + x_offset as usize;
-
-                let (r, g, b) = match self.bayer {
-                    Bayer::BGR => {
-                        let b = bytes[offset];
-                        let g = bytes[offset + 1];
-                        let r = bytes[offset + 2];
-                        (r, g, b)
-                    }
-                    _ => todo!(),
-                };
-
-                // BGRa
-                pixels.push(b);
-                pixels.push(g);
-                pixels.push(r);
-                pixels.push(alpha);
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        // position of the blue and red samples of each pixel
+        let (blue, red) = match self.bayer {
+            Bayer::BGR => (0, 2),
+            Bayer::RGB => (2, 0),
+            other => {
+                return Err(PlayerError::Unsupported(format!(
+                    "RGB codec does not support {:?}",
+                    other
+                )))
             }
+        };
+        let frame = video.read_frame(frame_index)?;
+        let bytes: &[u8] = &frame;
+        let bytes_per_sample = video.bytes_per_pixel().max(1) as usize;
+        let bytes_per_row = frame_size(video.image_width(), 1, 3 * bytes_per_sample)?;
+        check_frame_size(
+            bytes,
+            frame_size(
+                video.image_width(),
+                video.image_height(),
+                3 * bytes_per_sample,
+            )?,
+        )?;
+        let scale = scale.max(1);
+        let width = sample_count(video.image_width(), scale, 1);
+        let height = sample_count(video.image_height(), scale, 1);
+        let out = output_buffer(out, width, height);
+        if bytes_per_sample == 1 && self.bayer == Bayer::BGR {
+            for_each_row(out, width, |out_y, out_row| {
+                let y = stored_row(video, out_y as u32 * scale) as usize;
+                let row = &bytes[y * bytes_per_row..(y + 1) * bytes_per_row];
+                let pixels = row.chunks_exact(3).step_by(scale as usize);
+                for (bgra, bgr) in out_row.chunks_exact_mut(4).zip(pixels) {
+                    bgra[..3].copy_from_slice(bgr);
+                    bgra[3] = 255;
+                }
+            });
+            return Ok((width, height));
         }
-        (video.image_width(), video.image_height(), pixels)
+
+        // samples are scaled by the bit depth, like mono frames
+        let depth = match video.pixel_depth_bits() {
+            bits @ 1..=16 => bits.min(8 * bytes_per_sample as u32),
+            _ => 8 * bytes_per_sample as u32,
+        };
+        let max_value = (1_u32 << depth) - 1;
+        let lut = DisplayLut::from_fn(max_value, |value| (value * 255 / max_value) as u8);
+        for_each_row(out, width, |out_y, out_row| {
+            let y = stored_row(video, out_y as u32 * scale) as usize;
+            let row = &bytes[y * bytes_per_row..(y + 1) * bytes_per_row];
+            let row_samples = samples(video, row).collect::<Vec<_>>();
+            let pixels = row_samples.chunks_exact(3).step_by(scale as usize);
+            for (bgra, pixel) in out_row.chunks_exact_mut(4).zip(pixels) {
+                bgra.copy_from_slice(&[
+                    lut.get(pixel[blue]),
+                    lut.get(pixel[1]),
+                    lut.get(pixel[red]),
+                    255,
+                ]);
+            }
+        });
+        Ok((width, height))
+    }
+}
+
+/// Decodes grayscale frames with one 8 or 16-bit sample per pixel
+pub struct MonoCodec {}
+
+impl ImageCodec for MonoCodec {
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        let frame = video.read_frame(frame_index)?;
+        let bytes: &[u8] = &frame;
+        let (width, height) = (video.image_width(), video.image_height());
+        check_frame_size(
+            bytes,
+            frame_size(width, height, video.bytes_per_pixel() as usize)?,
+        )?;
+        let scale = scale.max(1);
+        let out_width = sample_count(width, scale, 1);
+        let out_height = sample_count(height, scale, 1);
+
+        // samples are scaled by the bit depth rather than by the size of the sample, so that
+        // 12-bit samples stored in 16 bits are not dark
+        let depth = match video.pixel_depth_bits() {
+            bits @ 1..=16 => bits.min(8 * video.bytes_per_pixel() as u32),
+            _ => 8 * video.bytes_per_pixel() as u32,
+        };
+        let max_value = (1_u32 << depth) - 1;
+        let lut = DisplayLut::from_fn(max_value, |value| (value * 255 / max_value) as u8);
+        let bytes_per_row = width as usize * video.bytes_per_pixel() as usize;
+        for_each_row(
+            output_buffer(out, out_width, out_height),
+            out_width,
+            |out_y, out_row| {
+                let y = stored_row(video, out_y as u32 * scale) as usize;
+                let row = &bytes[y * bytes_per_row..(y + 1) * bytes_per_row];
+                let row_samples = samples(video, row).step_by(scale as usize);
+                for (bgra, sample) in out_row.chunks_exact_mut(4).zip(row_samples) {
+                    let value = lut.get(sample);
+                    bgra.copy_from_slice(&[value, value, value, 255]);
+                }
+            },
+        );
+        Ok((out_width, out_height))
     }
 }
 
@@ -77,80 +433,660 @@ impl ImageCodec for RgbCodec {
 pub struct DebayerCodec {}
 
 impl ImageCodec for DebayerCodec {
-    fn decode(&self, video: &dyn Video, frame_index: usize) -> (u32, u32, Vec<u8>) {
-        let bytes = video.get_frame(frame_index).unwrap();
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        let frame = video.read_frame(frame_index)?;
+        let bytes: &[u8] = &frame;
 
         let width = video.image_width();
         let height = video.image_height();
+        check_frame_size(
+            bytes,
+            frame_size(width, height, video.bytes_per_pixel() as usize)?,
+        )?;
+
+        // position of each color within the quad
+        let mosaic = mosaic(video.bayer())?;
+        let position = |channel| mosaic.iter().position(|c| *c == channel).unwrap();
+        let (red_index, blue_index) = (position(Channel::Red), position(Channel::Blue));
+        let green_index = position(Channel::Green);
+        let green2_index = 3 - green_index;
+
+        // each output pixel is built from a 2x2 bayer quad
+        let step = 2 * scale.max(1);
+        let out_width = sample_count(width, step, 2);
+        let out_height = sample_count(height, step, 2);
+
+        let depth = match video.pixel_depth_bits() {
+            bits @ 1..=16 => bits.min(8 * video.bytes_per_pixel() as u32),
+            _ => 8 * video.bytes_per_pixel() as u32,
+        };
+        let lut = DisplayLut::for_depth(depth);
+        let to_u8 = |value: u16| lut.get(value);
+
+        let bytes_per_row = width as usize * video.bytes_per_pixel() as usize;
+        let row = |y: u32| {
+            let y = stored_row(video, y) as usize;
+            &bytes[y * bytes_per_row..(y + 1) * bytes_per_row]
+        };
+        for_each_row(
+            output_buffer(out, out_width, out_height),
+            out_width,
+            |out_y, out_row| {
+                let y = out_y as u32 * step;
+                let top = samples(video, row(y)).collect::<Vec<_>>();
+                let bottom = samples(video, row(y + 1)).collect::<Vec<_>>();
+                for (out_x, bgra) in out_row.chunks_exact_mut(4).enumerate() {
+                    let x = out_x * step as usize;
+                    let quad = [top[x], top[x + 1], bottom[x], bottom[x + 1]];
+
+                    // this is not real debayering, just using raw values without interpolation
+                    let red = quad[red_index];
+                    let green = ((quad[green_index] as u32 + quad[green2_index] as u32) / 2) as u16;
+                    let blue = quad[blue_index];
+
+                    bgra.copy_from_slice(&[to_u8(blue), to_u8(green), to_u8(red), 255]);
+                }
+            },
+        );
+        Ok((out_width, out_height))
+    }
+}
+
+/// Full resolution debayer that estimates the missing colors at each pixel by averaging the
+/// nearest pixels of each color
+pub struct BilinearDebayerCodec {}
+
+impl ImageCodec for BilinearDebayerCodec {
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        let mosaic = Mosaic::new(video, frame_index)?;
+        Ok(mosaic.demosaic(scale, out, |x, y| {
+            // within the 3x3 neighborhood, the pixels of each color are the nearest ones
+            let mut sums = [0_u32; 3];
+            let mut counts = [0_u32; 3];
+            for ny in y.saturating_sub(1)..(y + 2).min(mosaic.height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(mosaic.width) {
+                    let channel = mosaic.channel(nx, ny) as usize;
+                    sums[channel] += mosaic.sample(nx, ny) as u32;
+                    counts[channel] += 1;
+                }
+            }
+            let own = mosaic.channel(x, y) as usize;
+            let mut rgb = [0; 3];
+            for channel in 0..3 {
+                rgb[channel] = if channel == own {
+                    mosaic.sample(x, y) as i32
+                } else {
+                    (sums[channel] / counts[channel].max(1)) as i32
+                };
+            }
+            rgb
+        }))
+    }
+}
+
+/// Malvar-He-Cutler kernels, in sixteenths, for estimating green at red and blue pixels
+const MHC_GREEN_AT_RED_BLUE: [[i32; 5]; 5] = [
+    [0, 0, -2, 0, 0],
+    [0, 0, 4, 0, 0],
+    [-2, 4, 8, 4, -2],
+    [0, 0, 4, 0, 0],
+    [0, 0, -2, 0, 0],
+];
+
+/// Estimate red or blue at a green pixel where that color is to the left and right
+const MHC_AT_GREEN_ROW: [[i32; 5]; 5] = [
+    [0, 0, 1, 0, 0],
+    [0, -2, 0, -2, 0],
+    [-2, 8, 10, 8, -2],
+    [0, -2, 0, -2, 0],
+    [0, 0, 1, 0, 0],
+];
 
-        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
-        let alpha = 255;
-
-        let base: i32 = 2;
-        let max_value = base.pow(video.pixel_depth_bits()) as f32;
-
-        let mut quad = [0_u16; 4];
-
-        let bytes_per_row = width * video.bytes_per_pixel() as u32;
-        let mut y = 0;
-        while y < height {
-            let mut x = 0;
-
-            // each pixel on x axis has either 1 or 2 bytes
-            while x < width {
-                let y_offset = y * bytes_per_row;
-                let x_offset = x * video.bytes_per_pixel() as u32;
-                let offset = y_offset as usize + x_offset as usize;
-                let next_row_offset = (y_offset + bytes_per_row + x_offset) as usize;
-
-                if video.bytes_per_pixel() == 2 {
-                    let mut pixel0 = &bytes[offset..offset + 2];
-                    let mut pixel1 = &bytes[offset + 2..offset + 4];
-                    let mut pixel2 = &bytes[next_row_offset..next_row_offset + 2];
-                    let mut pixel3 = &bytes[next_row_offset + 2..next_row_offset + 4];
-
-                    match video.endianness() {
-                        Endianness::LittleEndian => {
-                            quad[0] = pixel0.read_u16::<LittleEndian>().unwrap();
-                            quad[1] = pixel1.read_u16::<LittleEndian>().unwrap();
-                            quad[2] = pixel2.read_u16::<LittleEndian>().unwrap();
-                            quad[3] = pixel3.read_u16::<LittleEndian>().unwrap();
+/// Estimate red or blue at a green pixel where that color is above and below
+const MHC_AT_GREEN_COLUMN: [[i32; 5]; 5] = [
+    [0, 0, -2, 0, 0],
+    [0, -2, 8, -2, 0],
+    [1, 0, 10, 0, 1],
+    [0, -2, 8, -2, 0],
+    [0, 0, -2, 0, 0],
+];
+
+/// Estimate red at blue pixels and blue at red pixels
+const MHC_RED_BLUE_AT_BLUE_RED: [[i32; 5]; 5] = [
+    [0, 0, -3, 0, 0],
+    [0, 4, 0, 4, 0],
+    [-3, 0, 12, 0, -3],
+    [0, 4, 0, 4, 0],
+    [0, 0, -3, 0, 0],
+];
+
+/// High quality full resolution debayer using the gradient-corrected linear interpolation
+/// described by Malvar, He and Cutler, which reduces the color fringing of bilinear
+/// interpolation at edges
+pub struct MhcDebayerCodec {}
+
+impl ImageCodec for MhcDebayerCodec {
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        let mosaic = Mosaic::new(video, frame_index)?;
+        Ok(mosaic.demosaic(scale, out, |x, y| {
+            let convolve = |kernel: &[[i32; 5]; 5]| {
+                let mut sum = 0;
+                for (ky, row) in kernel.iter().enumerate() {
+                    for (kx, weight) in row.iter().enumerate() {
+                        if *weight != 0 {
+                            let sample = mosaic.reflected_sample(
+                                x as isize + kx as isize - 2,
+                                y as isize + ky as isize - 2,
+                            );
+                            sum += weight * sample as i32;
                         }
-                        Endianness::BigEndian => {
-                            quad[0] = pixel0.read_u16::<BigEndian>().unwrap();
-                            quad[1] = pixel1.read_u16::<BigEndian>().unwrap();
-                            quad[2] = pixel2.read_u16::<BigEndian>().unwrap();
-                            quad[3] = pixel3.read_u16::<BigEndian>().unwrap();
+                    }
+                }
+                sum / 16
+            };
+            let own = mosaic.sample(x, y) as i32;
+            match mosaic.channel(x, y) {
+                Channel::Red => [
+                    own,
+                    convolve(&MHC_GREEN_AT_RED_BLUE),
+                    convolve(&MHC_RED_BLUE_AT_BLUE_RED),
+                ],
+                Channel::Blue => [
+                    convolve(&MHC_RED_BLUE_AT_BLUE_RED),
+                    convolve(&MHC_GREEN_AT_RED_BLUE),
+                    own,
+                ],
+                Channel::Green => {
+                    let row = convolve(&MHC_AT_GREEN_ROW);
+                    let column = convolve(&MHC_AT_GREEN_COLUMN);
+                    if mosaic.channel(x + 1, y) == Channel::Red {
+                        [row, own, column]
+                    } else {
+                        [column, own, row]
+                    }
+                }
+            }
+        }))
+    }
+}
+
+/// Names of the binning factors offered for fast previews, see `parse_binning`
+pub const BINNING_NAMES: &[&str] = &["off", "2x2", "4x4"];
+
+/// Parse a binning factor name such as "2x2", returning 1 when binning is off
+pub fn parse_binning(name: &str) -> Result<u32> {
+    match name.to_ascii_lowercase().as_str() {
+        "off" | "1" | "1x1" => Ok(1),
+        "2" | "2x2" => Ok(2),
+        "4" | "4x4" => Ok(4),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown binning '{}', expected one of {}",
+            name,
+            BINNING_NAMES.join(", ")
+        ))),
+    }
+}
+
+/// Decodes frames at reduced resolution by software binning, where each output pixel is the
+/// average of the raw samples of each color in a block of `factor` x `factor` pixels. This
+/// skips debayering entirely, so it is much faster than a full decode of very large frames
+/// while being less noisy than sampling every nth pixel. Bayer mosaics are binned in whole
+/// quads, so odd factors are rounded up to an even number for them. A `scale` greater than 1
+/// bins correspondingly larger blocks.
+pub struct BinningCodec {
+    factor: u32,
+}
+
+impl BinningCodec {
+    pub fn new(factor: u32) -> Self {
+        Self { factor }
+    }
+}
+
+impl ImageCodec for BinningCodec {
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        check_uncompressed(video)?;
+        let bayer = video.bayer();
+        // channel of each sample of a pixel for color frames, or of each pixel of a quad for
+        // mosaics, with mono samples counted as green and copied to every channel
+        let (planes, colors) = match bayer {
+            Bayer::Mono => (1, None),
+            Bayer::RGB => (3, None),
+            Bayer::BGR => (3, None),
+            bayer => (1, Some(mosaic(bayer)?)),
+        };
+        let factor = match colors {
+            Some(_) => self.factor.max(2).div_ceil(2) * 2,
+            None => self.factor.max(1),
+        };
+        let block = factor * scale.max(1);
+
+        let frame = video.read_frame(frame_index)?;
+        let bytes: &[u8] = &frame;
+        let (width, height) = (video.image_width(), video.image_height());
+        let bytes_per_sample = video.bytes_per_pixel().max(1) as u32;
+        let pixel_size = (planes * bytes_per_sample) as usize;
+        let bytes_per_row = frame_size(width, 1, pixel_size)?;
+        check_frame_size(bytes, frame_size(width, height, pixel_size)?)?;
+        // partial blocks at the right and bottom edges are dropped, unless the image is
+        // smaller than a single block
+        let out_width = (width / block).max(width.min(1));
+        let out_height = (height / block).max(height.min(1));
+
+        let depth = match video.pixel_depth_bits() {
+            bits @ 1..=16 => bits.min(8 * bytes_per_sample),
+            _ => 8 * bytes_per_sample,
+        };
+        let lut = DisplayLut::for_depth(depth);
+        let (block, planes) = (block as usize, planes as usize);
+        for_each_row(
+            output_buffer(out, out_width, out_height),
+            out_width,
+            |out_y, out_row| {
+                let top = out_y * block;
+                let bottom = (top + block).min(height as usize);
+                for (out_x, bgra) in out_row.chunks_exact_mut(4).enumerate() {
+                    let left = out_x * block;
+                    let right = (left + block).min(width as usize);
+                    let mut sums = [0_u64; 3];
+                    let mut counts = [0_u64; 3];
+                    for y in top..bottom {
+                        let start = stored_row(video, y as u32) as usize * bytes_per_row;
+                        let pixels = &bytes[start + left * pixel_size..start + right * pixel_size];
+                        for (i, sample) in samples(video, pixels).enumerate() {
+                            let x = left + i / planes;
+                            let channel = match (colors, bayer) {
+                                (Some(colors), _) => colors[(y % 2) * 2 + x % 2] as usize,
+                                (None, Bayer::RGB) => i % planes,
+                                (None, Bayer::BGR) => 2 - i % planes,
+                                _ => Channel::Green as usize,
+                            };
+                            sums[channel] += sample as u64;
+                            counts[channel] += 1;
                         }
                     }
+                    let mean = |channel: Channel| {
+                        let channel = match bayer {
+                            Bayer::Mono => Channel::Green as usize,
+                            _ => channel as usize,
+                        };
+                        let count = counts[channel].max(1);
+                        lut.get((sums[channel] / count).min(u16::MAX as u64) as u16)
+                    };
+                    bgra.copy_from_slice(&[
+                        mean(Channel::Blue),
+                        mean(Channel::Green),
+                        mean(Channel::Red),
+                        255,
+                    ]);
+                }
+            },
+        );
+        Ok((out_width, out_height))
+    }
+}
+
+/// Lookup table that converts raw samples to the 0-255 display range. Building the table
+/// converts every possible sample value once, after which each sample of a frame costs a
+/// single table read instead of a division. The table has an entry for every 16-bit value so
+/// that reads need no bounds checks.
+pub struct DisplayLut {
+    table: Box<[u8; 1 << 16]>,
+}
+
+impl DisplayLut {
+    /// Table for samples up to `max_sample`, converting each value with `convert`. Larger
+    /// samples are converted like `max_sample`.
+    pub fn from_fn(max_sample: u32, convert: impl Fn(u32) -> u8) -> Self {
+        let max_sample = max_sample.min(u16::MAX as u32);
+        let white = convert(max_sample);
+        let table = (0..=u16::MAX as u32)
+            .map(|value| {
+                if value <= max_sample {
+                    convert(value)
                 } else {
-                    let mut pixel0 = &bytes[offset..offset + 1];
-                    let mut pixel1 = &bytes[offset + 1..offset + 2];
-                    let mut pixel2 = &bytes[next_row_offset..next_row_offset + 1];
-                    let mut pixel3 = &bytes[next_row_offset + 1..next_row_offset + 2];
-
-                    quad[0] = pixel0.read_u8().unwrap() as u16;
-                    quad[1] = pixel1.read_u8().unwrap() as u16;
-                    quad[2] = pixel2.read_u8().unwrap() as u16;
-                    quad[3] = pixel3.read_u8().unwrap() as u16;
+                    white
+                }
+            })
+            .collect::<Vec<_>>()
+            .into_boxed_slice()
+            .try_into()
+            .unwrap();
+        Self { table }
+    }
+
+    /// Table that scales samples with `pixel_depth_bits` significant bits to the display
+    /// range, rounding down and clamping larger samples to white
+    pub fn for_depth(pixel_depth_bits: u32) -> Self {
+        let bits = pixel_depth_bits.min(32);
+        let max_value = (1_u64 << bits) - 1;
+        Self::from_fn(max_value.min(u32::MAX as u64) as u32, |value| {
+            ((value as u64 * 255) >> bits).min(255) as u8
+        })
+    }
+
+    /// Display value of a sample
+    #[inline]
+    pub fn get(&self, sample: u16) -> u8 {
+        self.table[sample as usize]
+    }
+}
+
+/// The raw samples of a frame along with the color of each pixel, for debayering algorithms
+/// that interpolate between neighboring pixels
+pub(crate) struct Mosaic {
+    pub(crate) samples: Vec<u16>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) colors: [Channel; 4],
+    pub(crate) pixel_depth_bits: u32,
+}
+
+impl Mosaic {
+    pub(crate) fn new(video: &dyn Video, frame_index: usize) -> Result<Self> {
+        let colors = mosaic(video.bayer())?;
+        let frame = video.read_frame(frame_index)?;
+        let bytes: &[u8] = &frame;
+        let (width, height) = (video.image_width(), video.image_height());
+        check_frame_size(
+            bytes,
+            frame_size(width, height, video.bytes_per_pixel() as usize)?,
+        )?;
+        let pixel_count = width as usize * height as usize;
+        let mut samples = samples(video, bytes).take(pixel_count).collect::<Vec<_>>();
+        if video.bottom_up() {
+            samples = samples
+                .chunks_exact(width.max(1) as usize)
+                .rev()
+                .flatten()
+                .copied()
+                .collect();
+        }
+        Ok(Self {
+            samples,
+            width: width as usize,
+            height: height as usize,
+            colors,
+            pixel_depth_bits: video.pixel_depth_bits(),
+        })
+    }
+
+    /// Color of the pixel at a position
+    pub(crate) fn channel(&self, x: usize, y: usize) -> Channel {
+        self.colors[(y % 2) * 2 + x % 2]
+    }
+
+    pub(crate) fn sample(&self, x: usize, y: usize) -> u16 {
+        self.samples[y * self.width + x]
+    }
+
+    /// Sample at a position that may be outside the image, in which case the position is
+    /// reflected back into the image in a way that preserves the color at that position
+    pub(crate) fn reflected_sample(&self, x: isize, y: isize) -> u16 {
+        self.sample(reflect(x, self.width), reflect(y, self.height))
+    }
+
+    /// Write BGRA pixels for every `scale`th pixel in each direction into `out`, using `rgb`
+    /// to estimate the red, green and blue values at each position. Returns the width and
+    /// height of the output.
+    pub(crate) fn demosaic(
+        &self,
+        scale: u32,
+        out: &mut Vec<u8>,
+        rgb: impl Fn(usize, usize) -> [i32; 3] + Sync,
+    ) -> (u32, u32) {
+        let scale = scale.max(1);
+        let out_width = sample_count(self.width as u32, scale, 1);
+        let out_height = sample_count(self.height as u32, scale, 1);
+        let lut = DisplayLut::for_depth(self.pixel_depth_bits);
+        let to_u8 = |value: i32| lut.get(value.max(0).min(u16::MAX as i32) as u16);
+        for_each_row(
+            output_buffer(out, out_width, out_height),
+            out_width,
+            |out_y, out_row| {
+                let y = out_y * scale as usize;
+                for (out_x, bgra) in out_row.chunks_exact_mut(4).enumerate() {
+                    let [red, green, blue] = rgb(out_x * scale as usize, y);
+                    bgra.copy_from_slice(&[to_u8(blue), to_u8(green), to_u8(red), 255]);
                 }
+            },
+        );
+        (out_width, out_height)
+    }
+}
 
-                // this is not real debayering, just using raw values without interpolation
-                let r = quad[0];
-                let g = quad[1];
-                let b = quad[3];
+/// Reflect a coordinate into `0..size` without repeating the edge pixel, so that `-1` maps to
+/// `1` and `size` maps to `size - 2`
+fn reflect(position: isize, size: usize) -> usize {
+    let last = size as isize - 1;
+    let reflected = if position < 0 {
+        -position
+    } else if position > last {
+        2 * last - position
+    } else {
+        position
+    };
+    reflected.max(0).min(last) as usize
+}
 
-                // BGRA
-                pixels.push(((b as f32 / max_value) * 255.0) as u8);
-                pixels.push(((g as f32 / max_value) * 255.0) as u8);
-                pixels.push(((r as f32 / max_value) * 255.0) as u8);
-                pixels.push(alpha);
+/// Color of a pixel in a Bayer mosaic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Channel {
+    Red = 0,
+    Green = 1,
+    Blue = 2,
+}
 
-                x += 2;
+/// Colors of the top-left, top-right, bottom-left and bottom-right pixels of each 2x2 quad in
+/// a Bayer mosaic
+pub(crate) fn mosaic(bayer: &Bayer) -> Result<[Channel; 4]> {
+    use Channel::*;
+    match bayer {
+        Bayer::RGGB => Ok([Red, Green, Green, Blue]),
+        Bayer::BGGR => Ok([Blue, Green, Green, Red]),
+        Bayer::GRBG => Ok([Green, Red, Blue, Green]),
+        Bayer::GBRG => Ok([Green, Blue, Red, Green]),
+        other => Err(PlayerError::Unsupported(format!(
+            "Cannot debayer {:?} images",
+            other
+        ))),
+    }
+}
+
+/// Decodes frames that are JPEG images, as written to AVI files by some capture software.
+/// This requires the `mjpeg` feature.
+pub struct MjpegCodec {}
+
+impl ImageCodec for MjpegCodec {
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        let (image_width, image_height, rgb) = decode_jpeg(&video.read_frame(frame_index)?)?;
+        let scale = scale.max(1);
+        let width = sample_count(image_width, scale, 1);
+        let height = sample_count(image_height, scale, 1);
+        out.clear();
+        out.reserve((width * height * 4) as usize);
+        for y in (0..image_height).step_by(scale as usize) {
+            for x in (0..image_width).step_by(scale as usize) {
+                let offset = ((y * image_width + x) * 3) as usize;
+                // BGRa
+                out.extend_from_slice(&[rgb[offset + 2], rgb[offset + 1], rgb[offset], 255]);
             }
-            y += 2;
         }
-        (width / 2, height / 2, pixels)
+        Ok((width, height))
+    }
+}
+
+/// Decodes a region of each frame using another codec. Uncompressed frames are cropped
+/// before they are decoded, so the cost depends on the size of the region rather than the
+/// size of the sensor. Compressed frames are decoded in full and then cropped.
+pub struct RegionCodec {
+    inner: Arc<dyn ImageCodec>,
+    region: Region,
+}
+
+impl RegionCodec {
+    pub fn new(inner: Arc<dyn ImageCodec>, region: Region) -> Self {
+        Self { inner, region }
+    }
+}
+
+impl ImageCodec for RegionCodec {
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        if check_uncompressed(video).is_ok() {
+            let cropped = CroppedFrame::new(video, frame_index, self.region)?;
+            return self.inner.decode_scaled_into(&cropped, 0, scale, out);
+        }
+        let region = self.region.fit_to(video)?;
+        let (width, height) = self
+            .inner
+            .decode_scaled_into(video, frame_index, scale, out)?;
+        // the decoded image may be scaled, so the region is scaled to match
+        let (video_width, video_height) = (video.image_width().max(1), video.image_height().max(1));
+        let scale_x = |value: u32| (value as u64 * width as u64 / video_width as u64) as u32;
+        let scale_y = |value: u32| (value as u64 * height as u64 / video_height as u64) as u32;
+        let (x, y) = (scale_x(region.x), scale_y(region.y));
+        let crop_width = scale_x(region.width).max(1).min(width.saturating_sub(x));
+        let crop_height = scale_y(region.height).max(1).min(height.saturating_sub(y));
+        if crop_width == 0 || crop_height == 0 {
+            return Err(PlayerError::Decode(format!(
+                "Region is outside the {}x{} decoded frame",
+                width, height
+            )));
+        }
+        // rows only ever move towards the start of the buffer, so they can be moved in place
+        let row_size = crop_width as usize * 4;
+        for row in 0..crop_height as usize {
+            let start = ((y as usize + row) * width as usize + x as usize) * 4;
+            out.copy_within(start..start + row_size, row * row_size);
+        }
+        out.truncate(row_size * crop_height as usize);
+        Ok((crop_width, crop_height))
+    }
+}
+
+/// Decode a JPEG image into RGB pixels
+#[cfg(feature = "mjpeg")]
+fn decode_jpeg(bytes: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    let image = image::load_from_memory_with_format(bytes, image::ImageFormat::Jpeg)
+        .map_err(|e| PlayerError::Decode(format!("Invalid JPEG frame: {}", e)))?
+        .into_rgb8();
+    Ok((image.width(), image.height(), image.into_raw()))
+}
+
+#[cfg(not(feature = "mjpeg"))]
+fn decode_jpeg(_bytes: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    Capability::Mjpeg.require()?;
+    unreachable!("MJPEG decoding is only compiled in with the mjpeg feature")
+}
+
+#[cfg(all(feature = "gpu", not(target_arch = "wasm32")))]
+fn gpu_codec() -> Result<Box<dyn ImageCodec>> {
+    Ok(Box::new(crate::gpu::GpuDebayerCodec::new()))
+}
+
+#[cfg(not(all(feature = "gpu", not(target_arch = "wasm32"))))]
+fn gpu_codec() -> Result<Box<dyn ImageCodec>> {
+    Capability::GpuDebayer.require()?;
+    Err(PlayerError::Unsupported(
+        "GPU debayering is not available in the browser".to_string(),
+    ))
+}
+
+/// Number of bytes in a `width` x `height` image with `bytes_per_pixel` bytes per pixel,
+/// checked so that dimensions from a corrupt header cannot wrap around to a small size
+fn frame_size(width: u32, height: u32, bytes_per_pixel: usize) -> Result<usize> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(bytes_per_pixel))
+        .ok_or_else(|| {
+            PlayerError::Decode(format!(
+                "A {}x{} frame with {} bytes per pixel is too large",
+                width, height, bytes_per_pixel
+            ))
+        })
+}
+
+/// Make sure a frame contains enough bytes for the image dimensions before decoding it
+fn check_frame_size(bytes: &[u8], expected: usize) -> Result<()> {
+    if bytes.len() < expected {
+        Err(PlayerError::Decode(format!(
+            "Frame has {} bytes but at least {} were expected",
+            bytes.len(),
+            expected
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Call `f` with the index and bytes of each row of a BGRA image that is `width` pixels wide.
+/// Output rows are independent, so they are converted in parallel, except in the browser
+/// where rayon has no threads to use.
+fn for_each_row<F>(out: &mut [u8], width: u32, f: F)
+where
+    F: Fn(usize, &mut [u8]) + Send + Sync,
+{
+    let row_size = (width as usize * 4).max(1);
+    #[cfg(not(target_arch = "wasm32"))]
+    out.par_chunks_mut(row_size)
+        .enumerate()
+        .for_each(|(y, row)| f(y, row));
+    #[cfg(target_arch = "wasm32")]
+    out.chunks_mut(row_size)
+        .enumerate()
+        .for_each(|(y, row)| f(y, row));
+}
+
+/// Resize `out` to hold a BGRA image of the given size, reusing its allocation
+fn output_buffer(out: &mut Vec<u8>, width: u32, height: u32) -> &mut [u8] {
+    out.clear();
+    out.resize(width as usize * height as usize * 4, 0);
+    out
+}
+
+/// Number of output pixels produced when sampling blocks of `footprint` input pixels every
+/// `step` pixels along an axis of `size` input pixels
+fn sample_count(size: u32, step: u32, footprint: u32) -> u32 {
+    if size < footprint {
+        0
+    } else {
+        (size - footprint) / step + 1
     }
 }
 
@@ -158,92 +1094,504 @@ impl ImageCodec for DebayerCodec {
 mod tests {
     use super::*;
     use crate::avi::AviFile;
-    use crate::video_format::{AviVideo, SerVideo};
+    use crate::ser::SerBytes;
+    use crate::testutil::{
+        avi_bytes, avi_stream_bytes, gradient_frame, ser_bytes, ser_format, ser_video, AviStream,
+        TempFile,
+    };
+    use crate::video_format::{
+        AviVideo, FormatOverrides, FrameView, MemoryVideo, OverrideVideo, SerVideo,
+    };
     use ser_io::SerFile;
+    use std::sync::Arc;
 
     #[test]
     fn test_decode_avi() {
-        // AVI from ZWO ASI 224 MC
+        // uncompressed BGR AVI, as written by SharpCap for a ZWO ASI 224 MC
+        let frames = (0..4)
+            .map(|i| gradient_frame(&ser_format(64, 48, Bayer::BGR, 8), i))
+            .collect::<Vec<_>>();
+        let file = TempFile::new(".avi", &avi_bytes(64, 48, 333333, &frames));
         let video: Box<dyn Video> = Box::new(AviVideo {
-            avi: AviFile::open("/home/andy/Documents/2021-09-05-0312_7-CapObj.AVI").unwrap(),
+            avi: AviFile::open(file.path_str()).unwrap(),
         });
-        assert_eq!(1304, video.image_width());
-        assert_eq!(976, video.image_height());
-        assert_eq!(44, video.frame_count());
+        assert_eq!(64, video.image_width());
+        assert_eq!(48, video.image_height());
+        assert_eq!(4, video.frame_count());
         assert_eq!(1, video.bytes_per_pixel());
         assert_eq!(8, video.pixel_depth_bits());
-        let frame0 = video.get_frame(0).unwrap();
-        assert_eq!(1304 * 976 * 3, frame0.len());
-
-        /*
-        AviMainHeader {
-            micro_sec_per_frame: 333333,
-            max_bytes_per_sec: 0,
-            padding_granularity: 512,
-            flags: 2064,
-            total_frames: 44,
-            initial_frames: 0, streams: 1,
-            suggested_buffer_size: 3819000,
-            width: 1304, height: 976, reserved: [0, 0, 0, 0] }
-        AviStreamHeader {
-            fcc_type: [118, 105, 100, 115],
-            fcc_handler: [68, 73, 66, 32], // "hsf2"
-            flags: 0,
-            priority: 0,
-            language: 0, initial_frames: 0, scale: 3333333, rate: 10000000, start: 0, length: 44,
-            suggested_buffer_size: 3819000, quality: 0,
-            sample_size: 0, left: 0, top: 0, right: 0, bottom: 0 }
-        BitMapInfo {
-            header: BitMapInfoHeader {
-                size: 40,
-                width: 1304,
-                height: -976,
-                planes: 1,
-                bit_count: 24,
-                compression: 0,
-                size_image: 3818112,
-                x_pels_per_meter: 4294967297,
-                y_pels_per_meter: 0,
-                clr_used: 0,
-                clr_important: 65793 },
-            rgb: RgbQuad { blue: 2, green: 2, red: 2, reserved: 0 }
-        }
-
-         */
+        let frame0 = video.read_frame(0).unwrap();
+        assert_eq!(64 * 48 * 3, frame0.len());
+        assert_eq!(frames[0], *frame0);
 
         let codec = RgbCodec::new(Bayer::BGR);
-        let (w, h, pixels) = codec.decode(video.as_ref(), 0);
-        assert_eq!(1304, w);
-        assert_eq!(976, h);
+        let (w, h, pixels) = codec.decode(video.as_ref(), 0).unwrap();
+        assert_eq!(64, w);
+        assert_eq!(48, h);
         assert_eq!(
             pixels.len(),
             video.image_height() as usize * video.image_width() as usize * 4
         );
     }
 
+    #[test]
+    fn test_decode_ser_rgb() {
+        for &(bayer, bits) in &[
+            (Bayer::RGB, 8),
+            (Bayer::BGR, 8),
+            (Bayer::RGB, 16),
+            (Bayer::BGR, 12),
+        ] {
+            // an orange pixel and a blue pixel, stored in the channel order of the color ID
+            let max = (1_u32 << bits) - 1;
+            let samples = [[max, max / 2, 0], [0, 0, max]]
+                .iter()
+                .flat_map(|&[red, green, blue]| match bayer {
+                    Bayer::RGB => vec![red, green, blue],
+                    _ => vec![blue, green, red],
+                })
+                .collect::<Vec<_>>();
+            let frame = samples
+                .iter()
+                .flat_map(|&sample| match bits {
+                    8 => vec![sample as u8],
+                    _ => (sample as u16).to_le_bytes().to_vec(),
+                })
+                .collect::<Vec<_>>();
+            let format = ser_format(2, 1, bayer, bits);
+            let file = TempFile::new(".ser", &ser_bytes(&format, &[vec![0; frame.len()], frame]));
+            let video = crate::video_format::open_video(file.path_str()).unwrap();
+            let codec = codec_for(video.as_ref()).unwrap();
+            let (_, _, pixels) = codec.decode(video.as_ref(), 1).unwrap();
+            assert_eq!(
+                vec![0, 127, 255, 255, 255, 0, 0, 255],
+                pixels,
+                "{:?} {}",
+                bayer,
+                bits
+            );
+            // the constructor reads whole RGB frames without the checked header
+            let video = SerVideo::new(SerFile::open(file.path_str()).unwrap()).unwrap();
+            assert_eq!(pixels, codec.decode(&video, 1).unwrap().2);
+        }
+    }
+
+    #[test]
+    fn test_decode_into_reuses_buffer() {
+        let frames = (0..2)
+            .map(|i| gradient_frame(&ser_format(64, 48, Bayer::BGR, 8), i))
+            .collect::<Vec<_>>();
+        let file = TempFile::new(".avi", &avi_bytes(64, 48, 333333, &frames));
+        let video = AviVideo {
+            avi: AviFile::open(file.path_str()).unwrap(),
+        };
+        let codec = RgbCodec::new(Bayer::BGR);
+        let mut buffer = vec![];
+        assert_eq!((64, 48), codec.decode_into(&video, 0, &mut buffer).unwrap());
+        let allocation = buffer.as_ptr();
+        assert_eq!((64, 48), codec.decode_into(&video, 1, &mut buffer).unwrap());
+        assert_eq!(allocation, buffer.as_ptr());
+        assert_eq!(codec.decode(&video, 1).unwrap().2, buffer);
+        // a smaller scaled frame still fits in the same allocation
+        assert_eq!(
+            (32, 24),
+            codec.decode_scaled_into(&video, 0, 2, &mut buffer).unwrap()
+        );
+        assert_eq!(allocation, buffer.as_ptr());
+        assert_eq!(32 * 24 * 4, buffer.len());
+    }
+
+    #[test]
+    fn test_region_codec() {
+        let format = ser_format(16, 12, Bayer::RGGB, 8);
+        let video = ser_video(&format, &[gradient_frame(&format, 0)]);
+        let region = Region {
+            x: 4,
+            y: 2,
+            width: 8,
+            height: 6,
+        };
+        let codec = RegionCodec::new(Arc::new(DebayerCodec {}), region);
+        let (width, height, pixels) = codec.decode(&video, 0).unwrap();
+        // each output pixel is a 2x2 quad, so this is the same as quads (2, 1) to (5, 3) of
+        // the whole frame
+        assert_eq!((4, 3), (width, height));
+        let (full_width, _, full) = DebayerCodec {}.decode(&video, 0).unwrap();
+        for y in 0..3 {
+            let start = (((y + 1) * full_width + 2) * 4) as usize;
+            let row = (y * width * 4) as usize;
+            assert_eq!(&full[start..start + 16], &pixels[row..row + 16]);
+        }
+        let (width, height, _) = codec.decode_scaled(&video, 0, 2).unwrap();
+        assert_eq!((2, 2), (width, height));
+    }
+
+    #[test]
+    fn test_bottom_up_avi() {
+        // a blue row above a red row
+        let (blue, red) = ([255, 0, 0], [0, 0, 255]);
+        let top_down = [blue, blue, red, red].concat();
+        let bottom_up = [red, red, blue, blue].concat();
+        for (stream, frame) in [
+            (AviStream::BGR, bottom_up),
+            (
+                AviStream {
+                    bottom_up: false,
+                    ..AviStream::BGR
+                },
+                top_down,
+            ),
+        ] {
+            let file = TempFile::new(".avi", &avi_stream_bytes(2, 2, 0, &stream, &[frame]));
+            let video = AviVideo {
+                avi: AviFile::open(file.path_str()).unwrap(),
+            };
+            assert_eq!(stream.bottom_up, video.bottom_up());
+            let (_, _, pixels) = RgbCodec::new(Bayer::BGR).decode(&video, 0).unwrap();
+            assert_eq!(&[255, 0, 0, 255], &pixels[0..4]);
+            assert_eq!(&[0, 0, 255, 255], &pixels[8..12]);
+        }
+    }
+
+    #[test]
+    fn test_raw8_avi() {
+        let quad = vec![64, 128, 128, 192];
+        let file = TempFile::new(
+            ".avi",
+            &avi_stream_bytes(2, 2, 0, &AviStream::RAW8, &[quad]),
+        );
+        let video: Arc<dyn Video> = Arc::new(AviVideo {
+            avi: AviFile::open(file.path_str()).unwrap(),
+        });
+        assert_eq!(Bayer::Mono, *video.bayer());
+        let overrides = FormatOverrides {
+            bayer: Some(Bayer::RGGB),
+            ..FormatOverrides::default()
+        };
+        let video = OverrideVideo::new(video, overrides);
+        let (w, h, pixels) = codec_for(&video).unwrap().decode(&video, 0).unwrap();
+        assert_eq!((1, 1), (w, h));
+        assert_eq!(vec![191, 127, 63, 255], pixels);
+    }
+
+    /// A frame size that overflows 32 bits must be detected before the output is allocated.
+    /// Files with such headers are rejected when they are opened, so this uses a frame that is
+    /// already in memory.
+    #[test]
+    fn test_huge_dimensions() {
+        let bytes = [0; 16];
+        for bayer in [Bayer::Mono, Bayer::RGGB, Bayer::BGR] {
+            let format = MemoryVideo::new(ser_format(65536, 65536, bayer, 8));
+            let video = FrameView::new(&format, &bytes);
+            let err = codec_for(&video).unwrap().decode(&video, 0).unwrap_err();
+            assert!(err.to_string().contains("Frame has 16 bytes"), "{}", err);
+            let err = BinningCodec::new(2).decode(&video, 0).unwrap_err();
+            assert!(err.to_string().contains("Frame has 16 bytes"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_raw16_avi() {
+        // 12-bit samples in 16 bits, as described by the color masks
+        let stream = AviStream {
+            compression: &[3, 0, 0, 0],
+            bit_masks: &[0x0fff, 0x0fff, 0x0fff],
+            ..AviStream::RAW16
+        };
+        let frame = [0_u16, 4095].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let file = TempFile::new(".avi", &avi_stream_bytes(2, 1, 0, &stream, &[frame]));
+        let video = AviVideo {
+            avi: AviFile::open(file.path_str()).unwrap(),
+        };
+        assert_eq!(2, video.bytes_per_pixel());
+        assert_eq!(12, video.pixel_depth_bits());
+        let (_, _, pixels) = codec_for(&video).unwrap().decode(&video, 0).unwrap();
+        assert_eq!(vec![0, 0, 0, 255, 255, 255, 255, 255], pixels);
+    }
+
+    #[test]
+    fn test_mjpeg_avi() {
+        let file = TempFile::new(
+            ".avi",
+            &avi_stream_bytes(64, 48, 40000, &AviStream::MJPEG, &[jpeg_frame(64, 48)]),
+        );
+        let video: Box<dyn Video> = Box::new(AviVideo {
+            avi: AviFile::open(file.path_str()).unwrap(),
+        });
+        assert_eq!(Compression::Mjpeg, video.compression());
+        assert_eq!(1, video.frame_count());
+        let codec = codec_for(video.as_ref());
+        if cfg!(feature = "mjpeg") {
+            let (w, h, pixels) = codec.unwrap().decode(video.as_ref(), 0).unwrap();
+            assert_eq!((64, 48), (w, h));
+            // the frame is mid gray, which survives compression almost unchanged
+            assert!(pixels[..3].iter().all(|&v| (120..=136).contains(&v)));
+            assert_eq!(255, pixels[3]);
+        } else {
+            assert!(codec.is_err());
+        }
+    }
+
+    /// A mid gray JPEG image, or a placeholder when JPEG support is not compiled in
+    #[cfg(feature = "mjpeg")]
+    fn jpeg_frame(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = vec![];
+        image::codecs::jpeg::JpegEncoder::new(&mut bytes)
+            .encode(
+                &vec![128; (width * height * 3) as usize],
+                width,
+                height,
+                image::ColorType::Rgb8,
+            )
+            .unwrap();
+        bytes
+    }
+
+    #[cfg(not(feature = "mjpeg"))]
+    fn jpeg_frame(_width: u32, _height: u32) -> Vec<u8> {
+        vec![0xff, 0xd8, 0xff, 0xd9]
+    }
+
     #[test]
     fn test_decode_ser() {
-        // RAW16 SER from ZWO ASI 294 MC
-        let ser = SerFile::open("/home/andy/Documents/2021-09-20-0323_1-CapObj.SER").unwrap();
-        assert_eq!(4144, ser.image_width);
-        assert_eq!(2822, ser.image_height);
-        assert_eq!(4144 * 2822 * 2, ser.image_frame_size);
+        // RAW16 SER, as written by SharpCap for a ZWO ASI 294 MC
+        let format = ser_format(64, 48, Bayer::RGGB, 16);
+        let frames = (0..3)
+            .map(|i| gradient_frame(&format, i))
+            .collect::<Vec<_>>();
+        let file = TempFile::new(".ser", &ser_bytes(&format, &frames));
+        let ser = SerFile::open(file.path_str()).unwrap();
+        assert_eq!(64, ser.image_width);
+        assert_eq!(48, ser.image_height);
+        assert_eq!(64 * 48 * 2, ser.image_frame_size);
         assert_eq!(2, ser.bytes_per_pixel);
 
-        let video: Box<dyn Video> = Box::new(SerVideo { ser });
-        assert_eq!(4144, video.image_width());
-        assert_eq!(2822, video.image_height());
-        assert_eq!(100, video.frame_count());
+        let video: Box<dyn Video> = Box::new(SerVideo::new(ser).unwrap());
+        assert_eq!(64, video.image_width());
+        assert_eq!(48, video.image_height());
+        assert_eq!(3, video.frame_count());
         assert_eq!(2, video.bytes_per_pixel());
         assert_eq!(16, video.pixel_depth_bits());
+        assert_eq!(frames[2], *video.read_frame(2).unwrap());
 
         let codec = DebayerCodec {};
-        let (w, h, pixels) = codec.decode(video.as_ref(), 0);
-        assert_eq!(4144 / 2, w);
-        assert_eq!(2822 / 2, h);
+        let (w, h, pixels) = codec.decode(video.as_ref(), 0).unwrap();
+        assert_eq!(64 / 2, w);
+        assert_eq!(48 / 2, h);
         assert_eq!(
             pixels.len() / 4,
             (video.image_height() as usize / 2) * (video.image_width() as usize / 2)
         );
     }
+
+    /// A single 8-bit 2x2 quad with the given raw values
+    fn quad_video(bayer: Bayer, quad: [u8; 4]) -> SerBytes {
+        ser_video(&ser_format(2, 2, bayer, 8), &[quad.to_vec()])
+    }
+
+    #[test]
+    fn test_debayer_patterns() {
+        let scale = |value: u8| (value as f32 / 256.0 * 255.0) as u8;
+        let (r, g, b) = (64, 128, 192);
+        for (bayer, quad) in [
+            (Bayer::RGGB, [r, g, g, b]),
+            (Bayer::BGGR, [b, g, g, r]),
+            (Bayer::GRBG, [g, r, b, g]),
+            (Bayer::GBRG, [g, b, r, g]),
+        ] {
+            let video = quad_video(bayer, quad);
+            let codec = codec_for(&video).unwrap();
+            let (width, height, pixels) = codec.decode(&video, 0).unwrap();
+            assert_eq!((1, 1), (width, height));
+            assert_eq!(
+                vec![scale(b), scale(g), scale(r), 255],
+                pixels,
+                "{:?}",
+                bayer
+            );
+        }
+        assert!(codec_for(&quad_video(Bayer::CYYM, [0; 4])).is_err());
+    }
+
+    #[test]
+    fn test_debayer_out_of_range_depth() {
+        // bit depths that do not fit the samples are treated as the size of the sample
+        let video: Arc<dyn Video> = Arc::new(quad_video(Bayer::RGGB, [64, 128, 128, 192]));
+        let (_, _, expected) = DebayerCodec {}.decode(video.as_ref(), 0).unwrap();
+        for bits in [0, 31, 32] {
+            let overrides = FormatOverrides {
+                pixel_depth_bits: Some(bits),
+                ..FormatOverrides::default()
+            };
+            let video = OverrideVideo::new(video.clone(), overrides);
+            let (_, _, pixels) = DebayerCodec {}.decode(&video, 0).unwrap();
+            assert_eq!(expected, pixels, "{} bits", bits);
+        }
+    }
+
+    #[test]
+    fn test_interpolating_debayer() {
+        // RGGB with red = 100, green = 50 and blue = 10 everywhere
+        let row0 = [100, 50, 100, 50];
+        let row1 = [50, 10, 50, 10];
+        let frame = [row0, row1, row0, row1].concat();
+        let video = ser_video(&ser_format(4, 4, Bayer::RGGB, 8), &[frame]);
+
+        let scale = |value: u32| (value * 255 / 256) as u8;
+        for name in ["bilinear", "mhc"] {
+            let codec = codec_named(name, &video).unwrap();
+            let (width, height, pixels) = codec.decode(&video, 0).unwrap();
+            assert_eq!((4, 4), (width, height));
+            for pixel in pixels.chunks_exact(4) {
+                assert_eq!(&[scale(10), scale(50), scale(100), 255], pixel, "{}", name);
+            }
+
+            let (width, height, _) = codec.decode_scaled(&video, 0, 3).unwrap();
+            assert_eq!((2, 2), (width, height));
+        }
+    }
+
+    #[test]
+    fn test_mono() {
+        let frame = [0_u16, 4095, 2048, 8191]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        let video = ser_video(&ser_format(2, 2, Bayer::Mono, 12), &[frame]);
+        let codec = codec_for(&video).unwrap();
+        let (width, height, pixels) = codec.decode(&video, 0).unwrap();
+        assert_eq!((2, 2), (width, height));
+        assert_eq!(
+            vec![0, 0, 0, 255, 255, 255, 255, 255, 127, 127, 127, 255, 255, 255, 255, 255],
+            pixels
+        );
+
+        let (width, height, pixels) = codec.decode_scaled(&video, 0, 2).unwrap();
+        assert_eq!((1, 1, vec![0, 0, 0, 255]), (width, height, pixels));
+    }
+
+    /// Decodes every frame into one red pixel
+    struct RedCodec {}
+
+    impl ImageCodec for RedCodec {
+        fn decode_scaled_into(
+            &self,
+            _video: &dyn Video,
+            _frame_index: usize,
+            _scale: u32,
+            out: &mut Vec<u8>,
+        ) -> Result<(u32, u32)> {
+            *out = vec![0, 0, 255, 255];
+            Ok((1, 1))
+        }
+    }
+
+    #[test]
+    fn test_codec_registry() {
+        let mut registry = CodecRegistry::default();
+        assert_eq!(&CODEC_NAMES[1..], &registry.names()[..]);
+        registry.register("red", |_| Ok(Box::new(RedCodec {})));
+        registry.prefer(
+            CodecRule {
+                compression: Compression::None,
+                bayers: &[Bayer::Mono],
+                bit_depths: 1..=8,
+            },
+            "red",
+        );
+        let decode = |video: &dyn Video, name: &str| {
+            let codec = registry.create(name, video).unwrap();
+            codec.decode(video, 0).unwrap()
+        };
+        let mono8 = ser_video(&ser_format(1, 1, Bayer::Mono, 8), &[vec![100]]);
+        assert_eq!((1, 1, vec![0, 0, 255, 255]), decode(&mono8, "auto"));
+        assert_eq!((1, 1, vec![0, 0, 255, 255]), decode(&mono8, "RED"));
+        assert_eq!((1, 1, vec![100, 100, 100, 255]), decode(&mono8, "mono"));
+        // the rule does not match 16-bit frames, which are still decoded as mono
+        let mono16 = ser_video(&ser_format(1, 1, Bayer::Mono, 16), &[vec![255, 255]]);
+        assert_eq!((1, 1, vec![255, 255, 255, 255]), decode(&mono16, "auto"));
+        assert!(registry.create("ahd", &mono8).is_err());
+        assert!(CodecRegistry::new().select(&mono8).is_err());
+    }
+
+    #[test]
+    fn test_codec_quality() {
+        let video = quad_video(Bayer::RGGB, [64, 128, 128, 192]);
+        let decode = |quality: CodecQuality| {
+            let codec = CodecRegistry::with_quality(quality).select(&video).unwrap();
+            let (width, height, _) = codec.decode(&video, 0).unwrap();
+            (width, height)
+        };
+        assert_eq!((1, 1), decode(CodecQuality::Fast));
+        assert_eq!((2, 2), decode(CodecQuality::Best));
+        // mono frames are not debayered at either quality
+        let mono = ser_video(&ser_format(2, 2, Bayer::Mono, 8), &[vec![0; 4]]);
+        let codec = CodecRegistry::with_quality(CodecQuality::Best)
+            .select(&mono)
+            .unwrap();
+        assert_eq!(2, codec.decode(&mono, 0).unwrap().0);
+        assert_eq!(CodecQuality::Best, parse_codec_quality("BEST").unwrap());
+        assert!(parse_codec_quality("slow").is_err());
+    }
+
+    #[test]
+    fn test_binning_codec() {
+        // RGGB quads of 10/20/30/40 and 50/60/70/80, then two rows of 90/100/110/120
+        let frame = vec![
+            10, 20, 50, 60, //
+            30, 40, 70, 80, //
+            90, 100, 90, 100, //
+            110, 120, 110, 120,
+        ];
+        let lut = DisplayLut::for_depth(8);
+        let bgra = |red, green, blue| vec![lut.get(blue), lut.get(green), lut.get(red), 255];
+        let video = ser_video(&ser_format(4, 4, Bayer::RGGB, 8), &[frame]);
+        let (w, h, pixels) = BinningCodec::new(2).decode(&video, 0).unwrap();
+        assert_eq!((2, 2), (w, h));
+        assert_eq!(bgra(10, 25, 40), &pixels[..4]);
+        assert_eq!(bgra(50, 65, 80), &pixels[4..8]);
+        assert_eq!(bgra(90, 105, 120), &pixels[8..12]);
+        // 4x4 bins average every quad, and odd factors are rounded up to whole quads
+        let (w, h, pixels) = BinningCodec::new(4).decode(&video, 0).unwrap();
+        assert_eq!((1, 1, bgra(60, 75, 90)), (w, h, pixels));
+        let (w, h, _) = BinningCodec::new(1).decode(&video, 0).unwrap();
+        assert_eq!((2, 2), (w, h));
+        let (w, h, _) = BinningCodec::new(2).decode_scaled(&video, 0, 2).unwrap();
+        assert_eq!((1, 1), (w, h));
+
+        let mono = ser_video(
+            &ser_format(3, 2, Bayer::Mono, 8),
+            &[vec![10, 20, 90, 30, 40, 90]],
+        );
+        let (w, h, pixels) = BinningCodec::new(2).decode(&mono, 0).unwrap();
+        assert_eq!((1, 1, bgra(25, 25, 25)), (w, h, pixels));
+
+        assert_eq!(1, parse_binning("off").unwrap());
+        assert_eq!(4, parse_binning("4X4").unwrap());
+        assert!(parse_binning("3x3").is_err());
+    }
+
+    #[test]
+    fn test_reflect() {
+        assert_eq!(2, reflect(-2, 5));
+        assert_eq!(1, reflect(-1, 5));
+        assert_eq!(4, reflect(4, 5));
+        assert_eq!(3, reflect(5, 5));
+        assert_eq!(2, reflect(6, 5));
+        assert_eq!(0, reflect(-1, 1));
+    }
+
+    #[test]
+    fn test_display_lut() {
+        for bits in 1..=16 {
+            let lut = DisplayLut::for_depth(bits);
+            let max_value = (1_u32 << bits) - 1;
+            for sample in (0..=u16::MAX).step_by(7) {
+                let expected = (((sample as u32).min(max_value) as u64 * 255) >> bits) as u8;
+                assert_eq!(expected, lut.get(sample), "{} at {} bits", sample, bits);
+            }
+        }
+        let lut = DisplayLut::from_fn(3, |value| value as u8 * 10);
+        assert_eq!((0, 30, 30), (lut.get(0), lut.get(3), lut.get(u16::MAX)));
+    }
 }
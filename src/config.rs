@@ -0,0 +1,208 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::codec::CodecQuality;
+use crate::error::{PlayerError, Result};
+use crate::locale::ENGLISH;
+use crate::processing::Stretch;
+
+/// User defaults loaded from `~/.config/astro-video-player/config.toml`. Every field is
+/// optional in the file and command line flags take precedence over the file. The player
+/// saves the codec, display stretch, playback frame rate, theme and language back to the file
+/// when its window is closed if they were changed in the player, along with the last directory
+/// and the plate scales entered for instruments.
+///
+/// ```toml
+/// theme = "astronomy"
+/// language = "de"
+/// codec_quality = "best"
+/// cache_size_mb = 1024
+/// threads = 4
+/// frame_steps = [10, 100, 1000]
+/// codec = "bilinear"
+/// fps = 30.0
+/// last_directory = "/home/me/captures"
+///
+/// [stretch]
+/// black = 10
+/// white = 200
+/// gamma = 1.5
+///
+/// [keys]
+/// exclude = "Delete"
+/// play = "P"
+///
+/// [plate_scale]
+/// "ZWO ASI294MC" = 0.38
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// UI theme: `dark`, `light` or `astronomy`, a red-light mode for use at the telescope
+    pub theme: String,
+    /// UI language, either `en` or the name of a catalog in the `locales` directory next to
+    /// this file, see `locale.rs`
+    pub language: String,
+    /// How color mosaics are debayered when `codec` is `auto`, either `fast` or `best`
+    pub codec_quality: CodecQuality,
+    /// Size of the decoded frame cache, in megabytes
+    pub cache_size_mb: usize,
+    /// Number of threads used for decoding and analysis, defaulting to one per core
+    pub threads: Option<usize>,
+    /// Numbers of frames to jump by with the frame step buttons, in each direction
+    pub frame_steps: Vec<u32>,
+    /// Codec to decode frames with, see `codec_named`
+    pub codec: String,
+    /// Playback frame rate, defaulting to the rate recorded in the file
+    pub fps: Option<f64>,
+    /// Directory of the last file opened in the player
+    pub last_directory: Option<PathBuf>,
+    /// Display stretch applied to decoded frames
+    pub stretch: Stretch,
+    /// Keys for player actions, keyed by action name, such as `exclude = "Delete"`
+    pub keys: BTreeMap<String, String>,
+    /// Plate scale in arcseconds per pixel, keyed by instrument name
+    pub plate_scale: HashMap<String, f64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            theme: "dark".to_string(),
+            language: ENGLISH.to_string(),
+            codec_quality: CodecQuality::Fast,
+            cache_size_mb: 512,
+            threads: None,
+            frame_steps: vec![10, 100],
+            codec: "auto".to_string(),
+            fps: None,
+            last_directory: None,
+            stretch: Stretch::default(),
+            keys: BTreeMap::new(),
+            plate_scale: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Default location of the configuration file
+    pub fn default_path() -> Option<PathBuf> {
+        dirs_next::config_dir().map(|dir| dir.join("astro-video-player").join("config.toml"))
+    }
+
+    /// Load the configuration from the default location, falling back to the defaults if
+    /// there is no configuration file
+    pub fn load() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Load the configuration from a file, falling back to the defaults if the file does
+    /// not exist
+    pub fn load_from(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text)
+                .map_err(|e| PlayerError::Format(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        toml::from_str(text).map_err(|e| PlayerError::Format(e.to_string()))
+    }
+
+    /// Save the configuration to a file, creating its directory if needed. Comments in an
+    /// existing file are not kept.
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string(self).map_err(|e| PlayerError::Format(e.to_string()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Plate scale configured for an instrument, if any
+    pub fn plate_scale_for(&self, instrument: &str) -> Option<f64> {
+        self.plate_scale.get(instrument.trim()).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_partial_config() {
+        let config = Config::parse(
+            r#"
+            threads = 4
+
+            [plate_scale]
+            "ZWO ASI294MC" = 0.38
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Some(4), config.threads);
+        assert_eq!(512, config.cache_size_mb);
+        assert_eq!("dark", config.theme);
+        assert_eq!("en", config.language);
+        assert_eq!(CodecQuality::Fast, config.codec_quality);
+        assert_eq!(vec![10, 100], config.frame_steps);
+        assert_eq!(Some(0.38), config.plate_scale_for("ZWO ASI294MC "));
+        assert_eq!(None, config.plate_scale_for("ZWO ASI224MC"));
+    }
+
+    #[test]
+    fn test_save_config() {
+        let dir = std::env::temp_dir().join(format!("avp-config-{}", std::process::id()));
+        let path = dir.join("astro-video-player").join("config.toml");
+        assert_eq!(Config::default(), Config::load_from(&path).unwrap());
+
+        let mut config = Config::parse("[keys]\nplay = \"P\"").unwrap();
+        config.codec = "bilinear".to_string();
+        config.codec_quality = CodecQuality::Best;
+        config.fps = Some(25.0);
+        config.last_directory = Some(PathBuf::from("/captures"));
+        config.stretch.black = 10;
+        config.plate_scale.insert("ZWO ASI294MC".to_string(), 0.38);
+        config.save_to(&path).unwrap();
+        let loaded = Config::load_from(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(config, loaded);
+    }
+
+    #[test]
+    fn test_parse_unknown_field() {
+        assert!(Config::parse("colour = \"red\"").is_err());
+    }
+}
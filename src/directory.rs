@@ -0,0 +1,396 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Image sequences: a directory of numbered FITS, TIFF or PNG files, such as the frames of a
+//! DSLR or CMOS still sequence or a calibration sequence exported by other tools, which is
+//! played as one video with a frame per file. 8 and 16-bit images are read without
+//! conversion, so 16-bit frames keep their full precision.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+use byteorder::{ByteOrder, LittleEndian};
+use image::GenericImageView;
+
+use crate::error::{PlayerError, Result};
+use crate::inputs::{has_extension, IMAGE_EXTENSIONS};
+use crate::video_format::{Bayer, Endianness, FitsVideo, OpenMode, Video};
+
+/// File extensions of FITS images, which are read with the FITS reader rather than the
+/// `image` crate
+const FITS_EXTENSIONS: &[&str] = &["fits", "fit", "fts"];
+
+/// A directory of image files, sorted naturally so that `frame_2` comes before `frame_10`.
+/// Each file is read when its frame is requested, and every file must have the same size
+/// and sample format as the first.
+pub struct DirectoryVideo {
+    directory: PathBuf,
+    files: Vec<PathBuf>,
+    width: u32,
+    height: u32,
+    bayer: Bayer,
+    pixel_depth_bits: u32,
+    /// Whether the first file records its color encoding, which FITS files and color images
+    /// do but grayscale TIFF and PNG files, which may hold raw Bayer data, do not
+    declares_bayer: bool,
+}
+
+/// Samples of one image file, laid out like a SER frame: one sample per pixel or
+/// interleaved RGB, with 16-bit samples in little endian order
+struct ImageFrame {
+    width: u32,
+    height: u32,
+    bayer: Bayer,
+    pixel_depth_bits: u32,
+    bytes: Vec<u8>,
+}
+
+impl DirectoryVideo {
+    pub fn open(directory: &str) -> Result<Self> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            if has_extension(&path, IMAGE_EXTENSIONS) && path.is_file() {
+                files.push(path);
+            }
+        }
+        if files.is_empty() {
+            return Err(PlayerError::Format(format!(
+                "No FITS, TIFF or PNG files in {}",
+                directory
+            )));
+        }
+        files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        Self::from_files(files)
+    }
+
+    /// A sequence of the given image files, in order, such as a single TIFF or PNG file
+    pub fn from_files(files: Vec<PathBuf>) -> Result<Self> {
+        let first_file = files
+            .first()
+            .ok_or_else(|| PlayerError::InvalidArgument("No image files were given".to_string()))?;
+        let first = read_image(first_file)?;
+        Ok(Self {
+            directory: first_file.parent().map(PathBuf::from).unwrap_or_default(),
+            width: first.width,
+            height: first.height,
+            declares_bayer: has_extension(&files[0], FITS_EXTENSIONS) || first.bayer != Bayer::Mono,
+            bayer: first.bayer,
+            pixel_depth_bits: first.pixel_depth_bits,
+            files,
+        })
+    }
+
+    /// Image files in the order that they are played
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+}
+
+impl Video for DirectoryVideo {
+    fn image_width(&self) -> u32 {
+        self.width
+    }
+
+    fn image_height(&self) -> u32 {
+        self.height
+    }
+
+    fn frame_count(&self) -> usize {
+        self.files.len()
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        if self.pixel_depth_bits > 8 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.pixel_depth_bits
+    }
+
+    fn bayer(&self) -> &Bayer {
+        &self.bayer
+    }
+
+    fn endianness(&self) -> &Endianness {
+        &Endianness::LittleEndian
+    }
+
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        let path = self
+            .files
+            .get(index)
+            .ok_or_else(|| PlayerError::Decode(format!("Invalid frame index {}", index)))?;
+        let frame = read_image(path)?;
+        if (
+            frame.width,
+            frame.height,
+            frame.bayer,
+            frame.pixel_depth_bits,
+        ) != (self.width, self.height, self.bayer, self.pixel_depth_bits)
+        {
+            return Err(PlayerError::Decode(format!(
+                "{} is a {}x{} {}-bit {:?} image but the first image of the sequence is {}x{} \
+                 {}-bit {:?}",
+                path.display(),
+                frame.width,
+                frame.height,
+                frame.pixel_depth_bits,
+                frame.bayer,
+                self.width,
+                self.height,
+                self.pixel_depth_bits,
+                self.bayer
+            )));
+        }
+        Ok(Cow::Owned(frame.bytes))
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        let name = |path: &Path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        vec![
+            (
+                "Directory".to_string(),
+                self.directory.display().to_string(),
+            ),
+            ("First file".to_string(), name(&self.files[0])),
+            (
+                "Last file".to_string(),
+                name(&self.files[self.files.len() - 1]),
+            ),
+        ]
+    }
+
+    fn declares_bayer(&self) -> bool {
+        self.declares_bayer
+    }
+}
+
+/// Read the samples of a single FITS image, or of a TIFF or PNG file. Alpha channels are
+/// dropped.
+fn read_image(path: &Path) -> Result<ImageFrame> {
+    let error =
+        |e: &dyn std::fmt::Display| PlayerError::Format(format!("{}: {}", path.display(), e));
+    if has_extension(path, FITS_EXTENSIONS) {
+        let fits = FitsVideo::from_bytes(std::fs::read(path)?, OpenMode::Lenient)
+            .map_err(|e| error(&e))?;
+        let header = fits.header();
+        if header.frame_count != 1 {
+            return Err(error(&format!(
+                "image sequences must have one frame per file but this file has {}",
+                header.frame_count
+            )));
+        }
+        let mut bytes = fits.read_frame(0)?.into_owned();
+        if header.bitpix == 16 {
+            // FITS samples are big endian
+            for sample in bytes.chunks_exact_mut(2) {
+                sample.swap(0, 1);
+            }
+        }
+        return Ok(ImageFrame {
+            width: header.width,
+            height: header.height,
+            bayer: header.bayer,
+            pixel_depth_bits: header.bitpix as u32,
+            bytes,
+        });
+    }
+    // the format is chosen from the signature, since single images are opened by signature
+    let image = image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| error(&e))?;
+    let color = image.color();
+    let sixteen_bit = color.bytes_per_pixel() / color.channel_count() == 2;
+    let (bayer, bytes) = match (color.has_color(), sixteen_bit) {
+        (false, false) => (Bayer::Mono, image.to_luma8().into_raw()),
+        (false, true) => (Bayer::Mono, le_bytes(&image.to_luma16().into_raw())),
+        (true, false) => (Bayer::RGB, image.to_rgb8().into_raw()),
+        (true, true) => (Bayer::RGB, le_bytes(&image.to_rgb16().into_raw())),
+    };
+    Ok(ImageFrame {
+        width: image.width(),
+        height: image.height(),
+        bayer,
+        pixel_depth_bits: if sixteen_bit { 16 } else { 8 },
+        bytes,
+    })
+}
+
+fn le_bytes(samples: &[u16]) -> Vec<u8> {
+    let mut bytes = vec![0; samples.len() * 2];
+    LittleEndian::write_u16_into(samples, &mut bytes);
+    bytes
+}
+
+/// Compare file names so that runs of digits are compared by their numeric value, as in
+/// `frame_2.png` before `frame_10.png`. Other characters are compared without regard to case.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (a_chunk, a_rest) = split_chunk(a);
+        let (b_chunk, b_rest) = split_chunk(b);
+        let ordering = match (a_chunk, b_chunk) {
+            ("", "") => return Ordering::Equal,
+            (x, y) if is_number(x) && is_number(y) => {
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            (x, y) => x.to_lowercase().cmp(&y.to_lowercase()),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a = a_rest;
+        b = b_rest;
+    }
+}
+
+fn is_number(chunk: &str) -> bool {
+    chunk.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Split off the leading run of digits or of other characters
+fn split_chunk(s: &str) -> (&str, &str) {
+    let digits = is_number(s);
+    let end = s
+        .find(|c: char| c.is_ascii_digit() != digits)
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{fits_bytes, TempFile};
+    use crate::video_format::open_video;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec![
+            "frame_10.png",
+            "Frame_2.png",
+            "frame_1.png",
+            "frame_02b.png",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            vec![
+                "frame_1.png",
+                "Frame_2.png",
+                "frame_02b.png",
+                "frame_10.png"
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn test_directory_video() {
+        let dir = std::env::temp_dir().join(format!("avp-sequence-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for index in [10_u16, 9].iter() {
+            let samples: Vec<u16> = (0..6).map(|i| i * 1000 + index).collect();
+            image::ImageBuffer::<image::Luma<u16>, _>::from_raw(3, 2, samples)
+                .unwrap()
+                .save(dir.join(format!("frame_{}.png", index)))
+                .unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), "seeing 2/5").unwrap();
+        let video = DirectoryVideo::open(dir.to_str().unwrap()).unwrap();
+        let frame = video.read_frame(1).map(Cow::into_owned);
+        let names: Vec<_> = video
+            .files()
+            .iter()
+            .map(|f| f.file_name().unwrap().to_owned())
+            .collect();
+
+        // a FITS frame that does not match the PNG frames
+        std::fs::write(
+            dir.join("frame_11.fits"),
+            fits_bytes(8, &[3, 2], &[], &[0; 6]),
+        )
+        .unwrap();
+        let mismatched = DirectoryVideo::open(dir.to_str().unwrap())
+            .unwrap()
+            .read_frame(2)
+            .is_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec!["frame_9.png", "frame_10.png"], names);
+        assert_eq!(
+            (3, 2, 2),
+            (
+                video.image_width(),
+                video.image_height(),
+                video.frame_count()
+            )
+        );
+        assert_eq!(Bayer::Mono, *video.bayer());
+        assert!(!video.declares_bayer());
+        assert_eq!(vec![10, 0, 0xF2, 0x03], frame.unwrap()[..4].to_vec());
+        assert!(mismatched);
+    }
+
+    #[test]
+    fn test_16_bit_images() {
+        let samples: Vec<u16> = (0..8).map(|i| 0x0100 * i + 0x0A).collect();
+        let rgba = image::ImageBuffer::<image::Rgba<u16>, _>::from_raw(2, 1, samples).unwrap();
+        let png = TempFile::new(".png", &[]);
+        rgba.save(png.path()).unwrap();
+        let video = DirectoryVideo::from_files(vec![png.path().to_path_buf()]).unwrap();
+        assert_eq!(
+            (Bayer::RGB, 16, 2),
+            (
+                *video.bayer(),
+                video.pixel_depth_bits(),
+                video.bytes_per_pixel()
+            )
+        );
+        // the alpha samples are dropped
+        assert_eq!(
+            vec![0x0A, 0, 0x0A, 1, 0x0A, 2, 0x0A, 4, 0x0A, 5, 0x0A, 6],
+            video.read_frame(0).unwrap().into_owned()
+        );
+
+        let gray = image::ImageBuffer::<image::Luma<u16>, _>::from_raw(2, 2, vec![1, 2, 0xFFFF, 4])
+            .unwrap();
+        let tiff = TempFile::new(".tiff", &[]);
+        gray.save(tiff.path()).unwrap();
+        let video = open_video(tiff.path_str()).unwrap();
+        assert_eq!((16, 1), (video.pixel_depth_bits(), video.frame_count()));
+        assert_eq!(
+            vec![1, 0, 2, 0, 0xFF, 0xFF, 4, 0],
+            video.read_frame(0).unwrap().into_owned()
+        );
+    }
+}
@@ -0,0 +1,65 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+
+use thiserror::Error;
+use tracing::warn;
+
+/// Error type used throughout the crate
+#[derive(Debug, Error)]
+pub enum PlayerError {
+    /// Error reading from or writing to the underlying file
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    /// The file is malformed or truncated
+    #[error("Invalid file format: {0}")]
+    Format(String),
+    /// The file is valid but uses a feature that is not supported yet
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    /// The frame data could not be decoded
+    #[error("Decode error: {0}")]
+    Decode(String),
+    /// An option given by the user is not valid
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+}
+
+pub type Result<T> = std::result::Result<T, PlayerError>;
+
+/// Convert an error from encoding or writing an image
+pub(crate) fn image_error(e: image::ImageError) -> PlayerError {
+    PlayerError::Io(io::Error::other(e))
+}
+
+/// Lock a mutex, recovering the value if another thread panicked while it held the lock.
+/// The values that the player shares between threads, such as the frame cache and the
+/// prefetch queue, are left consistent between operations, so a panic while decoding one
+/// frame does not need to take down every other thread that uses them.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("recovering a lock that was held by a thread that panicked");
+        poisoned.into_inner()
+    })
+}
@@ -0,0 +1,318 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::{ColorType, ImageBuffer, Luma, Rgb};
+
+use crate::annotations::{burn_annotations, Annotation};
+use crate::codec::{ImageCodec, RegionCodec};
+use crate::error::{image_error, PlayerError, Result};
+use crate::fits::write_fits;
+use crate::processing::DecodedFrame;
+use crate::sidecar::sidecar_path;
+use crate::video_format::{
+    check_uncompressed, samples, top_down_frame, Bayer, CroppedFrame, Region, Video,
+};
+
+/// Image formats that a single frame can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The decoded frame as an 8-bit PNG
+    Png,
+    /// The raw samples as a TIFF, with 8 bits per sample for 8-bit captures and 16 otherwise,
+    /// preserving the full bit depth of the capture
+    Tiff,
+    /// The raw samples as a 16-bit FITS image
+    Fits,
+}
+
+/// Names of the export formats, as accepted by `parse_export_format`
+pub const EXPORT_FORMAT_NAMES: &[&str] = &["png", "tiff", "fits"];
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Tiff => "tiff",
+            ExportFormat::Fits => "fits",
+        }
+    }
+}
+
+/// Parse an export format name such as `png` or `tiff`
+pub fn parse_export_format(name: &str) -> Result<ExportFormat> {
+    match name.to_ascii_lowercase().as_str() {
+        "png" => Ok(ExportFormat::Png),
+        "tif" | "tiff" => Ok(ExportFormat::Tiff),
+        "fit" | "fits" | "fts" => Ok(ExportFormat::Fits),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown export format '{}'",
+            name
+        ))),
+    }
+}
+
+/// Save a decoded frame as an 8-bit RGBA PNG
+pub fn save_png(frame: &DecodedFrame, path: &Path) -> Result<()> {
+    save_bgra_png(&mut frame.pixels.clone(), frame.width, frame.height, path)
+}
+
+/// Save BGRA pixels as a PNG, converting them to RGBA in place
+fn save_bgra_png(pixels: &mut [u8], width: u32, height: u32, path: &Path) -> Result<()> {
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    image::save_buffer(path, pixels, width, height, ColorType::Rgba8).map_err(image_error)
+}
+
+/// Save the raw samples of a frame as a TIFF without debayering or scaling them. 8-bit
+/// captures are saved with 8 bits per sample, since viewers would show their values in a
+/// 16-bit image as almost black, and all other captures with 16. Color frames are saved as
+/// RGB and all other frames as grayscale.
+pub fn save_raw_tiff(video: &dyn Video, index: usize, path: &Path) -> Result<()> {
+    let (width, height) = (video.image_width(), video.image_height());
+    let values = raw_samples(video, index)?;
+    let color = matches!(video.bayer(), Bayer::RGB | Bayer::BGR);
+    let result = if video.bytes_per_pixel() <= 1 {
+        let values = values.iter().map(|&value| value as u8).collect::<Vec<_>>();
+        if color {
+            ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, values).map(|image| image.save(path))
+        } else {
+            ImageBuffer::<Luma<u8>, _>::from_raw(width, height, values)
+                .map(|image| image.save(path))
+        }
+    } else if color {
+        ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, values).map(|image| image.save(path))
+    } else {
+        ImageBuffer::<Luma<u16>, _>::from_raw(width, height, values).map(|image| image.save(path))
+    };
+    match result {
+        Some(result) => result.map_err(image_error),
+        None => Err(PlayerError::Decode(format!(
+            "Frame {} is too small for a {}x{} image",
+            index, width, height
+        ))),
+    }
+}
+
+/// Save the raw samples of a frame as a 16-bit FITS image without debayering or scaling
+/// them. The Bayer pattern of raw frames is recorded in the header.
+pub fn save_raw_fits(video: &dyn Video, index: usize, path: &Path) -> Result<()> {
+    let values = raw_samples(video, index)?;
+    let mut out = BufWriter::new(File::create(path)?);
+    write_fits(
+        &mut out,
+        video.image_width(),
+        video.image_height(),
+        video.bayer(),
+        &values,
+    )
+}
+
+/// The raw samples of a frame in top-down order, with the channels of color frames in RGB
+/// order
+fn raw_samples(video: &dyn Video, index: usize) -> Result<Vec<u16>> {
+    check_uncompressed(video)?;
+    let (width, height) = (video.image_width(), video.image_height());
+    let frame = video.read_frame(index)?;
+    let bytes = top_down_frame(video, &frame);
+    let mut values: Vec<u16> = samples(video, &bytes).collect();
+    let planes = video.bayer().planes();
+    if *video.bayer() == Bayer::BGR {
+        for pixel in values.chunks_exact_mut(3) {
+            pixel.swap(0, 2);
+        }
+    }
+    let len = width as usize * height as usize * planes;
+    if values.len() < len {
+        return Err(PlayerError::Decode(format!(
+            "Frame {} is too small for a {}x{} image",
+            index, width, height
+        )));
+    }
+    values.truncate(len);
+    Ok(values)
+}
+
+/// Export a single frame of a video in the given format
+pub fn export_frame(
+    video: &dyn Video,
+    codec: &dyn ImageCodec,
+    index: usize,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<()> {
+    export_frame_with_buffer(video, codec, index, format, path, &mut vec![])
+}
+
+/// Export a single frame like `export_frame`, decoding it into `buffer` so that one
+/// allocation can be reused when exporting many frames
+pub fn export_frame_with_buffer(
+    video: &dyn Video,
+    codec: &dyn ImageCodec,
+    index: usize,
+    format: ExportFormat,
+    path: &Path,
+    buffer: &mut Vec<u8>,
+) -> Result<()> {
+    match format {
+        ExportFormat::Png => {
+            let (width, height) = codec.decode_into(video, index, buffer)?;
+            save_bgra_png(buffer, width, height, path)
+        }
+        ExportFormat::Tiff => save_raw_tiff(video, index, path),
+        ExportFormat::Fits => save_raw_fits(video, index, path),
+    }
+}
+
+/// Export a single frame as a PNG with the annotations on that frame burned into it.
+/// `origin` is the frame pixel at the top left of the decoded image, for codecs that decode
+/// a region of interest.
+pub fn export_annotated_png(
+    video: &dyn Video,
+    codec: &dyn ImageCodec,
+    index: usize,
+    annotations: &[Annotation],
+    origin: (u32, u32),
+    path: &Path,
+    buffer: &mut Vec<u8>,
+) -> Result<()> {
+    let (width, height) = codec.decode_into(video, index, buffer)?;
+    let annotations: Vec<Annotation> = annotations
+        .iter()
+        .filter(|annotation| annotation.frame == index)
+        .cloned()
+        .collect();
+    burn_annotations(buffer, width, height, &annotations, origin);
+    save_bgra_png(buffer, width, height, path)
+}
+
+/// Export a region of a single frame. TIFF and FITS images contain only the raw samples
+/// inside the region.
+pub fn export_region(
+    video: &dyn Video,
+    codec: Arc<dyn ImageCodec>,
+    index: usize,
+    region: Region,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<()> {
+    match format {
+        ExportFormat::Png => {
+            export_frame(video, &RegionCodec::new(codec, region), index, format, path)
+        }
+        _ => export_frame(
+            &CroppedFrame::new(video, index, region)?,
+            codec.as_ref(),
+            0,
+            format,
+            path,
+        ),
+    }
+}
+
+/// Default name for an exported frame, which is the name of the video followed by the frame
+/// number, such as `jupiter_00042.png`, in the same directory as the video
+pub fn frame_path(video_path: Option<&Path>, index: usize, format: ExportFormat) -> PathBuf {
+    let suffix = format!("_{:05}.{}", index, format.extension());
+    match video_path {
+        Some(_) => sidecar_path(video_path, &suffix),
+        None => PathBuf::from(format!("frame{}", suffix)),
+    }
+}
+
+/// Default name for a clip saved from a video, such as `jupiter_clip.ser`, in the same
+/// directory as the video
+pub fn clip_path(video_path: Option<&Path>) -> PathBuf {
+    sidecar_path(video_path, "_clip.ser")
+}
+
+/// Default name for a stack of frames from a video, such as `jupiter_stack.tiff`, in the
+/// same directory as the video
+pub fn stack_path(video_path: Option<&Path>, format: ExportFormat) -> PathBuf {
+    sidecar_path(video_path, &format!("_stack.{}", format.extension()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ser::SerFormat;
+    use crate::testutil::{ser_format, ser_video, TempFile};
+    use crate::video_format::Endianness;
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(
+            PathBuf::from("/data/jupiter_00042.png"),
+            frame_path(Some(Path::new("/data/jupiter.ser")), 42, ExportFormat::Png)
+        );
+        assert_eq!(
+            PathBuf::from("frame_00001.tiff"),
+            frame_path(None, 1, ExportFormat::Tiff)
+        );
+        assert_eq!(
+            PathBuf::from("/data/jupiter_clip.ser"),
+            clip_path(Some(Path::new("/data/jupiter.ser")))
+        );
+        assert_eq!(
+            PathBuf::from("/data/jupiter_stack.fits"),
+            stack_path(Some(Path::new("/data/jupiter.ser")), ExportFormat::Fits)
+        );
+    }
+
+    #[test]
+    fn test_parse_export_format() {
+        assert_eq!(ExportFormat::Png, parse_export_format("PNG").unwrap());
+        assert_eq!(ExportFormat::Tiff, parse_export_format("tif").unwrap());
+        assert_eq!(ExportFormat::Fits, parse_export_format("FIT").unwrap());
+        assert!(parse_export_format("jpeg").is_err());
+    }
+
+    #[test]
+    fn test_save_raw_tiff() {
+        let format = SerFormat {
+            width: 3,
+            height: 2,
+            bayer: Bayer::RGGB,
+            pixel_depth_bits: 16,
+            endianness: Endianness::BigEndian,
+        };
+        let frame: Vec<u8> = (0..6_u16).flat_map(|i| (i * 1000).to_be_bytes()).collect();
+        let video = ser_video(&format, &[frame]);
+
+        let file = TempFile::new(".tiff", &[]);
+        save_raw_tiff(&video, 0, file.path()).unwrap();
+        let image = image::open(file.path()).unwrap().into_luma16();
+        assert_eq!((3, 2), image.dimensions());
+        assert_eq!(5000, image.get_pixel(2, 1)[0]);
+
+        let video = ser_video(&ser_format(3, 1, Bayer::Mono, 8), &[vec![0, 128, 255]]);
+        save_raw_tiff(&video, 0, file.path()).unwrap();
+        let image = image::open(file.path()).unwrap();
+        assert_eq!(ColorType::L8, image.color());
+        assert_eq!(&[0, 128, 255], image.as_bytes());
+    }
+}
@@ -0,0 +1,282 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! C interface to the decoders, built with the `ffi` feature. The declarations are in
+//! `include/astro_video_player.h`.
+//!
+//! Functions that can fail return `AVP_OK` on success or a negative status code, and the
+//! reason for the most recent failure on the calling thread is available from
+//! `avp_last_error`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Mutex;
+
+use crate::codec::{codec_for, ImageCodec};
+use crate::error::{lock, PlayerError, Result};
+use crate::video_format::{open_video, Endianness, Video};
+
+pub const AVP_OK: c_int = 0;
+pub const AVP_ERROR: c_int = -1;
+pub const AVP_INVALID_ARGUMENT: c_int = -2;
+pub const AVP_BUFFER_TOO_SMALL: c_int = -3;
+
+/// An open video, owned by the caller until it is passed to `avp_close`
+pub struct AvpVideo {
+    video: Box<dyn Video>,
+    codec: Box<dyn ImageCodec>,
+    /// Decoded pixels of the last frame, which are copied to the caller's buffer. The
+    /// allocation is reused for every frame.
+    pixels: Mutex<Vec<u8>>,
+}
+
+/// Header values of an open video
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct AvpMetadata {
+    pub width: u32,
+    pub height: u32,
+    pub frame_count: u64,
+    pub bytes_per_pixel: u32,
+    pub bit_depth: u32,
+    /// SER color ID, such as 0 for mono or 8 for RGGB
+    pub color_id: u32,
+    /// Non-zero if 16-bit samples are big endian
+    pub big_endian: u32,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Run `f`, recording any error or panic as the last error rather than letting a panic
+/// unwind into C code
+fn guard(f: impl FnOnce() -> Result<c_int>) -> c_int {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(status)) => status,
+        Ok(Err(PlayerError::InvalidArgument(message))) => {
+            set_last_error(message);
+            AVP_INVALID_ARGUMENT
+        }
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            AVP_ERROR
+        }
+        Err(_) => {
+            set_last_error("Internal error".to_string());
+            AVP_ERROR
+        }
+    }
+}
+
+/// Open a SER, AVI or FITS file. Returns null on failure.
+///
+/// # Safety
+///
+/// `path` must be a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn avp_open(path: *const c_char) -> *mut AvpVideo {
+    let mut video = ptr::null_mut();
+    guard(|| {
+        if path.is_null() {
+            return Err(PlayerError::InvalidArgument("path is null".to_string()));
+        }
+        let path = CStr::from_ptr(path)
+            .to_str()
+            .map_err(|_| PlayerError::InvalidArgument("path is not UTF-8".to_string()))?;
+        let opened = open_video(path)?;
+        let codec = codec_for(opened.as_ref())?;
+        video = Box::into_raw(Box::new(AvpVideo {
+            video: opened,
+            codec,
+            pixels: Mutex::new(vec![]),
+        }));
+        Ok(AVP_OK)
+    });
+    video
+}
+
+/// Fill in `metadata` with the header values of the video
+///
+/// # Safety
+///
+/// `video` must have been returned by `avp_open` and `metadata` must point to writable
+/// memory.
+#[no_mangle]
+pub unsafe extern "C" fn avp_get_metadata(
+    video: *const AvpVideo,
+    metadata: *mut AvpMetadata,
+) -> c_int {
+    guard(|| {
+        let (video, metadata) = match (video.as_ref(), metadata.as_mut()) {
+            (Some(video), Some(metadata)) => (&video.video, metadata),
+            _ => return Err(PlayerError::InvalidArgument("null pointer".to_string())),
+        };
+        *metadata = AvpMetadata {
+            width: video.image_width(),
+            height: video.image_height(),
+            frame_count: video.frame_count() as u64,
+            bytes_per_pixel: video.bytes_per_pixel() as u32,
+            bit_depth: video.pixel_depth_bits(),
+            color_id: video.bayer().color_id(),
+            big_endian: (*video.endianness() == Endianness::BigEndian) as u32,
+        };
+        Ok(AVP_OK)
+    })
+}
+
+/// Decode a frame into `buffer` as 8-bit BGRA pixels and store the dimensions of the
+/// decoded image in `width` and `height`. If the buffer is too small, nothing is written to
+/// it, `AVP_BUFFER_TOO_SMALL` is returned and the dimensions are still stored so that the
+/// caller can allocate a large enough buffer.
+///
+/// # Safety
+///
+/// `video` must have been returned by `avp_open`, `buffer` must point to at least
+/// `buffer_len` writable bytes and `width` and `height` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn avp_decode_frame_into_buffer(
+    video: *const AvpVideo,
+    index: u64,
+    buffer: *mut u8,
+    buffer_len: usize,
+    width: *mut u32,
+    height: *mut u32,
+) -> c_int {
+    guard(|| {
+        let video = video
+            .as_ref()
+            .ok_or_else(|| PlayerError::InvalidArgument("video is null".to_string()))?;
+        if buffer.is_null() || width.is_null() || height.is_null() {
+            return Err(PlayerError::InvalidArgument("null pointer".to_string()));
+        }
+        // the buffer only holds pixels while this call uses it, so a panic that poisoned the
+        // lock left nothing that needs to be cleaned up
+        let mut pixels = lock(&video.pixels);
+        let (w, h) = video
+            .codec
+            .decode_into(video.video.as_ref(), index as usize, &mut pixels)?;
+        *width = w;
+        *height = h;
+        if pixels.len() > buffer_len {
+            set_last_error(format!(
+                "Frame needs {} bytes but the buffer is {} bytes",
+                pixels.len(),
+                buffer_len
+            ));
+            return Ok(AVP_BUFFER_TOO_SMALL);
+        }
+        ptr::copy_nonoverlapping(pixels.as_ptr(), buffer, pixels.len());
+        Ok(AVP_OK)
+    })
+}
+
+/// Close a video that was returned by `avp_open`. Passing null does nothing.
+///
+/// # Safety
+///
+/// `video` must have been returned by `avp_open` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn avp_close(video: *mut AvpVideo) {
+    if !video.is_null() {
+        drop(Box::from_raw(video));
+    }
+}
+
+/// The reason for the most recent failure on this thread, or null if nothing has failed.
+/// The string is owned by the library and is valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn avp_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map(|message| message.as_ptr())
+            .unwrap_or(ptr::null())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{ser_bytes, ser_format, TempFile};
+    use crate::video_format::Bayer;
+
+    #[test]
+    fn test_decode_through_c_api() {
+        let format = ser_format(4, 4, Bayer::RGGB, 8);
+        let file = TempFile::new(".ser", &ser_bytes(&format, &[vec![100; 16]]));
+
+        unsafe {
+            let c_path = CString::new(file.path_str()).unwrap();
+            let video = avp_open(c_path.as_ptr());
+            assert!(!video.is_null());
+
+            let mut metadata = AvpMetadata::default();
+            assert_eq!(AVP_OK, avp_get_metadata(video, &mut metadata));
+            assert_eq!(1, metadata.frame_count);
+            assert_eq!(8, metadata.color_id);
+
+            let (mut width, mut height) = (0, 0);
+            let mut small = [0u8; 4];
+            let status = avp_decode_frame_into_buffer(
+                video,
+                0,
+                small.as_mut_ptr(),
+                small.len(),
+                &mut width,
+                &mut height,
+            );
+            assert_eq!(AVP_BUFFER_TOO_SMALL, status);
+            let mut buffer = vec![0u8; (width * height * 4) as usize];
+            let status = avp_decode_frame_into_buffer(
+                video,
+                0,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut width,
+                &mut height,
+            );
+            assert_eq!(AVP_OK, status);
+            assert_eq!((2, 2), (width, height));
+
+            let status = avp_decode_frame_into_buffer(
+                video,
+                1,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut width,
+                &mut height,
+            );
+            assert_eq!(AVP_ERROR, status);
+            assert!(!avp_last_error().is_null());
+            avp_close(video);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Native open-file dialog, shown with [rfd](https://github.com/PolyMeilex/rfd). On Linux
+//! and the BSDs this is the dialog of the XDG desktop portal, falling back to `zenity` when
+//! there is no portal.
+
+use std::path::{Path, PathBuf};
+
+use rfd::FileDialog;
+
+/// Extensions of the files the player opens, see `probe::format_from_extension`
+const EXTENSIONS: [&str; 8] = ["ser", "avi", "fits", "fit", "fts", "tif", "tiff", "png"];
+
+/// Let the user choose a video with the native file dialog, starting in `directory`. This
+/// blocks until the dialog is closed, and returns `None` when it is cancelled.
+pub fn choose_video(directory: Option<&Path>) -> Option<PathBuf> {
+    let mut dialog = FileDialog::new()
+        .set_title("Open video")
+        .add_filter("Videos", &EXTENSIONS);
+    if let Some(directory) = directory {
+        dialog = dialog.set_directory(directory);
+    }
+    dialog.pick_file()
+}
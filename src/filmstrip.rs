@@ -0,0 +1,52 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Thumbnail filmstrip, which shows frames spread evenly through the video so that the
+//! interesting part of a long capture can be found at a glance.
+
+/// Frames to show thumbnails of: `count` frames spread evenly from the first frame, or every
+/// frame of a video with fewer frames than that
+pub fn thumbnail_frames(frame_count: usize, count: usize) -> Vec<usize> {
+    let count = count.min(frame_count);
+    (0..count).map(|i| i * frame_count / count).collect()
+}
+
+/// Sampling step that reduces a frame `height` pixels high to at most `target` pixels high
+pub fn thumbnail_scale(height: u32, target: u32) -> u32 {
+    let target = target.max(1);
+    height.div_ceil(target).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thumbnail_frames() {
+        assert_eq!(vec![0, 25, 50, 75], thumbnail_frames(100, 4));
+        assert_eq!(vec![0, 1, 2], thumbnail_frames(3, 4));
+        assert!(thumbnail_frames(0, 4).is_empty());
+        assert_eq!(1, thumbnail_scale(48, 64));
+        assert_eq!(2, thumbnail_scale(128, 64));
+        assert_eq!(3, thumbnail_scale(129, 64));
+    }
+}
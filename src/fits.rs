@@ -0,0 +1,498 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Parsing of FITS image cubes, as saved by capture tools such as INDI/Ekos. Each plane of a
+//! three-dimensional image is a frame, except in color images that have three planes and a
+//! `COLORTYP` keyword naming their channel order. Files are read with `FitsVideo` and single
+//! 16-bit images are written with `write_fits`.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Read;
+use std::io::Write;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::error::{PlayerError, Result};
+use crate::video_format::{parse_bayer, Bayer, OpenMode};
+
+/// Headers and data are stored in blocks of this size
+pub(crate) const BLOCK_SIZE: usize = 2880;
+
+/// Size of a header record
+const CARD_SIZE: usize = 80;
+
+/// The values in the primary header of a FITS file that are needed to read the frames
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitsHeader {
+    pub width: u32,
+    pub height: u32,
+    pub frame_count: usize,
+    /// Bits per sample, which is 8 for unsigned bytes or 16 for signed 16-bit integers
+    pub bitpix: i32,
+    /// Physical values are `bzero + bscale * stored value`
+    pub bzero: f64,
+    pub bscale: f64,
+    pub bayer: Bayer,
+    /// Offset of the first frame, after the header blocks
+    pub data_offset: usize,
+    /// Keyword and value of every card in the header, with quotes and comments removed
+    pub cards: Vec<(String, String)>,
+}
+
+impl FitsHeader {
+    /// Parse the primary header at the start of `bytes`, which need not contain the data.
+    /// Frames that extend past `file_len` are dropped, or are an error in strict mode.
+    pub fn parse(bytes: &[u8], file_len: u64, mode: OpenMode) -> Result<Self> {
+        if bytes.len() < CARD_SIZE || !bytes.starts_with(b"SIMPLE  =") {
+            return Err(PlayerError::Format("Not a FITS file".to_string()));
+        }
+        let mut cards = vec![];
+        let mut end = None;
+        for (i, card) in bytes.chunks_exact(CARD_SIZE).enumerate() {
+            if card.starts_with(b"END     ") {
+                end = Some((i + 1) * CARD_SIZE);
+                break;
+            }
+            if let Some(card) = parse_card(card) {
+                cards.push(card);
+            }
+        }
+        let end = end.ok_or_else(|| PlayerError::Format("FITS header has no END".to_string()))?;
+        let value = |keyword: &str| {
+            cards
+                .iter()
+                .rev()
+                .find(|(k, _)| k == keyword)
+                .map(|(_, v)| v.as_str())
+        };
+        let integer = |keyword: &str| -> Result<Option<i64>> {
+            value(keyword)
+                .map(|v| {
+                    v.parse::<i64>().map_err(|_| {
+                        PlayerError::Format(format!("Invalid FITS {} value '{}'", keyword, v))
+                    })
+                })
+                .transpose()
+        };
+        let real = |keyword: &str, default: f64| -> Result<f64> {
+            match value(keyword) {
+                // Fortran style exponents are allowed
+                Some(v) => v.replace('D', "E").parse::<f64>().map_err(|_| {
+                    PlayerError::Format(format!("Invalid FITS {} value '{}'", keyword, v))
+                }),
+                None => Ok(default),
+            }
+        };
+        let required = |keyword: &str| {
+            integer(keyword)?.ok_or_else(|| {
+                PlayerError::Format(format!("FITS header has no {} keyword", keyword))
+            })
+        };
+
+        if mode == OpenMode::Strict && value("SIMPLE") != Some("T") {
+            return Err(PlayerError::Format(
+                "FITS file does not conform to the standard".to_string(),
+            ));
+        }
+        let bitpix = required("BITPIX")?;
+        if bitpix != 8 && bitpix != 16 {
+            return Err(PlayerError::Unsupported(format!(
+                "FITS files with BITPIX {} are not supported",
+                bitpix
+            )));
+        }
+        let naxis = required("NAXIS")?;
+        if !(2..=3).contains(&naxis) {
+            return Err(PlayerError::Unsupported(format!(
+                "FITS files with {} axes are not supported",
+                naxis
+            )));
+        }
+        let axis = |n: i64| -> Result<u32> {
+            let keyword = format!("NAXIS{}", n);
+            let size = required(&keyword)?;
+            if size <= 0 || size > u32::MAX as i64 {
+                return Err(PlayerError::Format(format!(
+                    "Invalid FITS {} value {}",
+                    keyword, size
+                )));
+            }
+            Ok(size as u32)
+        };
+        let width = axis(1)?;
+        let height = axis(2)?;
+        let mut frame_count = if naxis == 3 { axis(3)? as usize } else { 1 };
+
+        let color = match value("COLORTYP") {
+            Some(order) if frame_count == 3 => match parse_bayer(order) {
+                Ok(bayer @ Bayer::RGB) | Ok(bayer @ Bayer::BGR) => Some(bayer),
+                _ => {
+                    return Err(PlayerError::Unsupported(format!(
+                        "Unknown FITS color type '{}'",
+                        order
+                    )))
+                }
+            },
+            _ => None,
+        };
+        if color.is_some() {
+            // the three planes are the channels of a single frame
+            frame_count = 1;
+        }
+        let bayer = match (color, value("BAYERPAT")) {
+            (Some(color), _) => color,
+            (None, Some(pattern)) => {
+                let x_offset = integer("XBAYROFF")?.unwrap_or(0);
+                let y_offset = integer("YBAYROFF")?.unwrap_or(0);
+                parse_bayer(&shift_pattern(pattern, x_offset, y_offset)).map_err(|_| {
+                    PlayerError::Unsupported(format!("Unknown FITS Bayer pattern '{}'", pattern))
+                })?
+            }
+            (None, None) => Bayer::Mono,
+        };
+
+        let bzero = real("BZERO", 0.0)?;
+        let bscale = real("BSCALE", 1.0)?;
+        let header = Self {
+            width,
+            height,
+            frame_count,
+            bitpix: bitpix as i32,
+            bzero,
+            bscale,
+            bayer,
+            data_offset: block_align(end),
+            cards,
+        };
+        let frame_size = header.checked_frame_size().ok_or_else(|| {
+            PlayerError::Format(format!("Invalid FITS image size {}x{}", width, height))
+        })?;
+        let available =
+            (file_len.saturating_sub(header.data_offset as u64) / frame_size as u64) as usize;
+        if available < frame_count {
+            if mode == OpenMode::Strict {
+                return Err(PlayerError::Format(format!(
+                    "FITS header declares {} frames but the file only contains {}",
+                    frame_count, available
+                )));
+            }
+            frame_count = available;
+        }
+        Ok(Self {
+            frame_count,
+            ..header
+        })
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        (self.bitpix / 8) as usize
+    }
+
+    /// Size of a frame in bytes, including all three planes of color images
+    pub fn frame_size(&self) -> usize {
+        self.width as usize * self.height as usize * self.bytes_per_sample() * self.bayer.planes()
+    }
+
+    fn checked_frame_size(&self) -> Option<usize> {
+        (self.width as usize)
+            .checked_mul(self.height as usize)?
+            .checked_mul(self.bytes_per_sample() * self.bayer.planes())
+    }
+
+    /// Convert the stored bytes of a frame to the layout of frames in other videos: unsigned
+    /// samples, with the planes of color images interleaved into pixels
+    pub(crate) fn decode_frame(&self, frame: &mut [u8]) {
+        self.convert_to_unsigned(frame);
+        let planes = self.bayer.planes();
+        if planes > 1 {
+            let sample = self.bytes_per_sample();
+            let planar = frame.to_vec();
+            let plane_size = planar.len() / planes;
+            for (i, pixel) in frame.chunks_exact_mut(sample * planes).enumerate() {
+                for (plane, value) in pixel.chunks_exact_mut(sample).enumerate() {
+                    let offset = plane * plane_size + i * sample;
+                    value.copy_from_slice(&planar[offset..offset + sample]);
+                }
+            }
+        }
+    }
+
+    /// Replace stored values with physical values, clamped to the range of unsigned 8 or
+    /// 16-bit samples. 16-bit samples remain big endian.
+    // the scaling values are compared exactly to find the encodings that need no arithmetic
+    #[allow(clippy::float_cmp)]
+    pub(crate) fn convert_to_unsigned(&self, data: &mut [u8]) {
+        let (bzero, bscale) = (self.bzero, self.bscale);
+        if self.bitpix == 8 {
+            if bzero != 0.0 || bscale != 1.0 {
+                for sample in data.iter_mut() {
+                    *sample = (bzero + bscale * *sample as f64).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        } else if bzero == 32768.0 && bscale == 1.0 {
+            // the usual encoding of unsigned samples, where only the sign bit changes
+            data.chunks_exact_mut(2)
+                .for_each(|sample| sample[0] ^= 0x80);
+        } else {
+            for sample in data.chunks_exact_mut(2) {
+                let value = bzero + bscale * BigEndian::read_i16(sample) as f64;
+                BigEndian::write_u16(sample, value.round().clamp(0.0, 65535.0) as u16);
+            }
+        }
+    }
+}
+
+/// Read the header blocks at the start of a FITS file, up to the block that holds the `END`
+/// record, leaving `reader` at the start of the data
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn read_header(reader: &mut dyn Read) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    loop {
+        let start = bytes.len();
+        let read = reader.take(BLOCK_SIZE as u64).read_to_end(&mut bytes)?;
+        let end = bytes[start..]
+            .chunks_exact(CARD_SIZE)
+            .any(|card| card.starts_with(b"END     "));
+        if end || read < BLOCK_SIZE {
+            return Ok(bytes);
+        }
+    }
+}
+
+/// Write a 16-bit image as a FITS file with unsigned samples. Mono and raw frames are
+/// written as a single plane, with the Bayer pattern of raw frames in the `BAYERPAT`
+/// keyword. Color images are given as interleaved samples and are written as three planes,
+/// one for each channel, with the channel order in the `COLORTYP` keyword. Rows are written
+/// top-down.
+pub fn write_fits(
+    out: &mut dyn Write,
+    width: u32,
+    height: u32,
+    bayer: &Bayer,
+    values: &[u16],
+) -> Result<()> {
+    let planes = bayer.planes();
+    let pixels = width as usize * height as usize;
+    if values.len() != pixels * planes {
+        return Err(PlayerError::InvalidArgument(format!(
+            "{} samples do not make a {}x{} image with {} planes",
+            values.len(),
+            width,
+            height,
+            planes
+        )));
+    }
+    let mut cards = vec![
+        format!("{:<8}= {:>20}", "SIMPLE", "T"),
+        format!("{:<8}= {:>20}", "BITPIX", 16),
+        format!("{:<8}= {:>20}", "NAXIS", if planes == 1 { 2 } else { 3 }),
+        format!("{:<8}= {:>20}", "NAXIS1", width),
+        format!("{:<8}= {:>20}", "NAXIS2", height),
+    ];
+    if planes == 3 {
+        cards.push(format!("{:<8}= {:>20}", "NAXIS3", planes));
+    }
+    cards.push(format!("{:<8}= {:>20}", "BZERO", 32768));
+    cards.push(format!("{:<8}= {:>20}", "BSCALE", 1));
+    cards.push(format!("{:<8}= {:<20}", "ROWORDER", "'TOP-DOWN'"));
+    match bayer {
+        Bayer::Mono | Bayer::Unknown(_) => {}
+        Bayer::RGB | Bayer::BGR => cards.push(format!(
+            "{:<8}= '{:<8}'",
+            "COLORTYP",
+            format!("{:?}", bayer)
+        )),
+        pattern => cards.push(format!(
+            "{:<8}= '{:<8}'",
+            "BAYERPAT",
+            format!("{:?}", pattern)
+        )),
+    }
+    cards.push("END".to_string());
+
+    let mut bytes = cards
+        .iter()
+        .flat_map(|card| format!("{:80}", card).into_bytes())
+        .collect::<Vec<_>>();
+    bytes.resize(block_align(bytes.len()), b' ');
+    for plane in 0..planes {
+        for value in values.iter().skip(plane).step_by(planes) {
+            // the sign bit is flipped to store unsigned values with a zero point of 32768
+            bytes.extend_from_slice(&(value ^ 0x8000).to_be_bytes());
+        }
+    }
+    bytes.resize(block_align(bytes.len()), 0);
+    out.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Round an offset up to a multiple of the block size
+fn block_align(offset: usize) -> usize {
+    offset.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+/// Split a header record into its keyword and value, removing quotes from strings and any
+/// comment. Records without a value, such as `COMMENT`, are ignored.
+fn parse_card(card: &[u8]) -> Option<(String, String)> {
+    if &card[8..10] != b"= " {
+        return None;
+    }
+    let keyword = String::from_utf8_lossy(&card[0..8]).trim_end().to_string();
+    let text = String::from_utf8_lossy(&card[10..]);
+    let text = text.trim_start();
+    let value = if let Some(quoted) = text.strip_prefix('\'') {
+        // quotes within strings are written as ''
+        let mut value = String::new();
+        let mut chars = quoted.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\'' {
+                if chars.peek() == Some(&'\'') {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            value.push(c);
+        }
+        value.trim_end().to_string()
+    } else {
+        text.split('/').next().unwrap_or("").trim().to_string()
+    };
+    Some((keyword, value))
+}
+
+/// The Bayer pattern at the first pixel, when the pattern in the header starts at the given
+/// offset
+fn shift_pattern(pattern: &str, x_offset: i64, y_offset: i64) -> String {
+    let mut colors = pattern.trim().chars().collect::<Vec<_>>();
+    if colors.len() != 4 {
+        return pattern.to_string();
+    }
+    if x_offset % 2 != 0 {
+        colors.swap(0, 1);
+        colors.swap(2, 3);
+    }
+    if y_offset % 2 != 0 {
+        colors.swap(0, 2);
+        colors.swap(1, 3);
+    }
+    colors.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::fits_bytes;
+
+    #[test]
+    fn test_parse_header() {
+        let data = [0_i16, -32768, 32767, 1000, 0, 0]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect::<Vec<_>>();
+        let cards = [
+            "BZERO   =                32768",
+            "BAYERPAT= 'GRBG    '",
+            "XBAYROFF= 1",
+        ];
+        let bytes = fits_bytes(16, &[3, 1, 2], &cards, &data);
+        let header = FitsHeader::parse(&bytes, bytes.len() as u64, OpenMode::Strict).unwrap();
+        assert_eq!((3, 1, 2), (header.width, header.height, header.frame_count));
+        assert_eq!(BLOCK_SIZE, header.data_offset);
+        assert_eq!(Bayer::RGGB, header.bayer);
+
+        let mut frames = bytes[header.data_offset..].to_vec();
+        header.convert_to_unsigned(&mut frames);
+        let values = frames
+            .chunks_exact(2)
+            .map(BigEndian::read_u16)
+            .collect::<Vec<_>>();
+        assert_eq!(&[32768, 0, 65535, 33768], &values[0..4]);
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let bytes = fits_bytes(8, &[4, 2, 3], &[], &[7; 24]);
+        let truncated = &bytes[..BLOCK_SIZE + 20];
+        let header =
+            FitsHeader::parse(truncated, truncated.len() as u64, OpenMode::Lenient).unwrap();
+        assert_eq!(2, header.frame_count);
+        assert!(FitsHeader::parse(truncated, truncated.len() as u64, OpenMode::Strict).is_err());
+        // no END record
+        assert!(FitsHeader::parse(&bytes[..160], 160, OpenMode::Lenient).is_err());
+    }
+
+    #[test]
+    fn test_write_fits() {
+        let mut bytes = vec![];
+        write_fits(&mut bytes, 2, 1, &Bayer::GRBG, &[0, 65535]).unwrap();
+        assert_eq!(2 * BLOCK_SIZE, bytes.len());
+        let header = FitsHeader::parse(&bytes, bytes.len() as u64, OpenMode::Strict).unwrap();
+        assert_eq!((2, 1, 1), (header.width, header.height, header.frame_count));
+        assert_eq!(Bayer::GRBG, header.bayer);
+        let mut data = bytes[header.data_offset..header.data_offset + 4].to_vec();
+        header.convert_to_unsigned(&mut data);
+        assert_eq!(vec![0, 0, 255, 255], data);
+
+        // color images are written as a plane for each channel and read back as one frame
+        let mut bytes = vec![];
+        write_fits(&mut bytes, 2, 1, &Bayer::BGR, &[1, 2, 3, 4, 5, 6]).unwrap();
+        let header = FitsHeader::parse(&bytes, bytes.len() as u64, OpenMode::Strict).unwrap();
+        assert_eq!(1, header.frame_count);
+        assert_eq!(Bayer::BGR, header.bayer);
+        assert_eq!(12, header.frame_size());
+        let mut data = bytes[header.data_offset..header.data_offset + 12].to_vec();
+        header.decode_frame(&mut data);
+        let values = data
+            .chunks_exact(2)
+            .map(BigEndian::read_u16)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], values);
+        assert!(write_fits(&mut vec![], 2, 2, &Bayer::Mono, &[0; 3]).is_err());
+    }
+
+    #[test]
+    fn test_read_header() {
+        let bytes = fits_bytes(8, &[4, 2, 3], &[], &[7; 24]);
+        let mut reader = &bytes[..];
+        let header = read_header(&mut reader).unwrap();
+        assert_eq!(BLOCK_SIZE, header.len());
+        assert_eq!(bytes.len() - BLOCK_SIZE, reader.len());
+        // a header without an END record is read to the end of the file
+        let header = read_header(&mut &bytes[..160]).unwrap();
+        assert_eq!(160, header.len());
+    }
+
+    #[test]
+    fn test_parse_card() {
+        let card = |text: &str| format!("{:80}", text).into_bytes();
+        assert_eq!(
+            Some(("OBJECT".to_string(), "M42 'Orion'".to_string())),
+            parse_card(&card("OBJECT  = 'M42 ''Orion'''      / target name"))
+        );
+        assert_eq!(
+            Some(("EXPTIME".to_string(), "0.5".to_string())),
+            parse_card(&card("EXPTIME =                  0.5 / seconds"))
+        );
+        assert_eq!(None, parse_card(&card("COMMENT   no value")));
+    }
+}
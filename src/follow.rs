@@ -0,0 +1,353 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Following a SER file while capture software is still writing it, like `tail -f`, so that
+//! a capture can be checked while it is in progress.
+//!
+//! Capture software writes the header first, usually with a frame count of zero, appends the
+//! frames and then writes the final frame count and the timestamp trailer when the capture
+//! ends. `FollowedSerVideo` polls the file for complete frames and notifies a `FrameWatch`
+//! when there are more of them.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use byteorder::{ByteOrder, LittleEndian};
+use tracing::{debug, info, warn};
+
+use crate::error::{lock, PlayerError, Result};
+use crate::live::FrameWatch;
+use crate::ser::{self, SerFormat, SerHeader, FRAME_COUNT_OFFSET, HEADER_SIZE};
+use crate::video_format::{Bayer, Endianness, OpenMode, Video};
+
+/// How often the file is checked for new frames
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Number of complete frames in a SER file of `file_len` bytes that may still be written,
+/// given the frame count in its header, and whether the capture has finished, which is when
+/// the file ends with the timestamp trailer of the declared frames
+pub fn followed_frame_count(declared: usize, file_len: u64, frame_size: usize) -> (usize, bool) {
+    let frames_len = |count: usize| HEADER_SIZE as u64 + count as u64 * frame_size as u64;
+    if declared > 0 && file_len == frames_len(declared) + 8 * declared as u64 {
+        return (declared, true);
+    }
+    // the header count is not used until the end, because capture software that updates it
+    // during the capture may write it before or after the frames
+    let written = file_len.saturating_sub(HEADER_SIZE as u64) / frame_size.max(1) as u64;
+    // but bytes after the declared frames may be the trailer, which is not written at once
+    if declared > 0 && written > declared as u64 {
+        return (declared, false);
+    }
+    (written as usize, false)
+}
+
+struct FollowShared {
+    path: PathBuf,
+    format: SerFormat,
+    file: Mutex<File>,
+    frame_count: AtomicUsize,
+    /// Timestamps from the trailer, which is only written when the capture has finished
+    timestamps: Mutex<Vec<u64>>,
+    watch: FrameWatch,
+    /// Set when the video is dropped, so that polling stops
+    closed: AtomicBool,
+}
+
+impl FollowShared {
+    /// Check the file for new frames, returning whether the capture has finished
+    fn poll(&self) -> Result<bool> {
+        let mut file = lock(&self.file);
+        let file_len = file.metadata()?.len();
+        let mut field = [0; 4];
+        file.seek(SeekFrom::Start(FRAME_COUNT_OFFSET))?;
+        file.read_exact(&mut field)?;
+        let declared = LittleEndian::read_u32(&field) as usize;
+        let (frame_count, finished) =
+            followed_frame_count(declared, file_len, self.format.frame_size());
+        if finished {
+            let mut trailer = vec![];
+            file.seek(SeekFrom::Start(
+                HEADER_SIZE as u64 + (frame_count * self.format.frame_size()) as u64,
+            ))?;
+            (&mut *file)
+                .take(8 * frame_count as u64)
+                .read_to_end(&mut trailer)?;
+            *lock(&self.timestamps) = ser::parse_timestamps(&trailer, frame_count);
+        }
+        drop(file);
+        // the count never goes down, so that frames that have been shown stay readable
+        if frame_count > self.frame_count.fetch_max(frame_count, Ordering::SeqCst) {
+            debug!(frame_count, "new frames in the followed file");
+            self.watch.notify(frame_count);
+        }
+        Ok(finished)
+    }
+}
+
+/// A SER file that is still being captured. Frames are read from disk as they are written,
+/// and the frame count grows until the capture finishes or the video is dropped.
+pub struct FollowedSerVideo {
+    shared: Arc<FollowShared>,
+    header_fields: Vec<(String, String)>,
+}
+
+impl FollowedSerVideo {
+    /// Open a SER file and start checking it for new frames
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        (&mut file)
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut header)?;
+        // the declared frames may not have been written yet, so they are not checked against
+        // the length of the file
+        let header = SerHeader::parse(&header, u64::MAX, OpenMode::Lenient)?;
+        let shared = Arc::new(FollowShared {
+            path: path.to_path_buf(),
+            format: header.format.clone(),
+            file: Mutex::new(file),
+            frame_count: AtomicUsize::new(0),
+            timestamps: Mutex::new(vec![]),
+            watch: FrameWatch::default(),
+            closed: AtomicBool::new(false),
+        });
+        if !shared.poll()? {
+            info!(path = %path.display(), "following the capture");
+            let poller = shared.clone();
+            thread::Builder::new()
+                .name("follow".to_string())
+                .spawn(move || follow(poller))?;
+        }
+        Ok(Self {
+            shared,
+            header_fields: header.fields(),
+        })
+    }
+
+    /// Whether the capture has finished, after which there will be no more frames
+    pub fn is_finished(&self) -> bool {
+        !lock(&self.shared.timestamps).is_empty()
+    }
+}
+
+/// Check the file for new frames until the capture finishes or the video is dropped
+fn follow(shared: Arc<FollowShared>) {
+    while !shared.closed.load(Ordering::SeqCst) {
+        thread::sleep(POLL_INTERVAL);
+        match shared.poll() {
+            Ok(false) => {}
+            Ok(true) => {
+                info!(path = %shared.path.display(), "the capture has finished");
+                return;
+            }
+            Err(e) => {
+                warn!("stopped following {}: {}", shared.path.display(), e);
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for FollowedSerVideo {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Video for FollowedSerVideo {
+    fn image_width(&self) -> u32 {
+        self.shared.format.width
+    }
+
+    fn image_height(&self) -> u32 {
+        self.shared.format.height
+    }
+
+    fn frame_count(&self) -> usize {
+        self.shared.frame_count.load(Ordering::SeqCst)
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        if self.shared.format.pixel_depth_bits > 8 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.shared.format.pixel_depth_bits
+    }
+
+    fn bayer(&self) -> &Bayer {
+        &self.shared.format.bayer
+    }
+
+    fn endianness(&self) -> &Endianness {
+        &self.shared.format.endianness
+    }
+
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        if index >= self.frame_count() {
+            return Err(PlayerError::Decode(format!(
+                "Invalid frame index {}",
+                index
+            )));
+        }
+        let frame_size = self.shared.format.frame_size();
+        let mut frame = vec![0; frame_size];
+        let mut file = lock(&self.shared.file);
+        file.seek(SeekFrom::Start(
+            HEADER_SIZE as u64 + (index * frame_size) as u64,
+        ))?;
+        file.read_exact(&mut frame)?;
+        Ok(Cow::Owned(frame))
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        ser::frame_rate(&lock(&self.shared.timestamps))
+    }
+
+    fn frame_timestamp(&self, index: usize) -> Option<SystemTime> {
+        lock(&self.shared.timestamps)
+            .get(index)
+            .and_then(|ticks| ser::timestamp_to_time(*ticks))
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.header_fields.clone()
+    }
+
+    fn frame_watch(&self) -> Option<FrameWatch> {
+        Some(self.shared.watch.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::{Cursor, Write};
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+    use crate::ser::SerWriter;
+    use crate::testutil::{ser_bytes, ser_format, TempFile};
+
+    #[test]
+    fn test_followed_frame_count() {
+        let frame = 100;
+        let header = HEADER_SIZE as u64;
+        assert_eq!((0, false), followed_frame_count(0, header, frame));
+        assert_eq!((2, false), followed_frame_count(0, header + 250, frame));
+        assert_eq!((2, false), followed_frame_count(2, header + 300, frame));
+        assert_eq!((2, true), followed_frame_count(2, header + 216, frame));
+        // a declared count that has not been written yet
+        assert_eq!((1, false), followed_frame_count(5, header + 100, frame));
+        // part of the trailer of a capture with small frames
+        assert_eq!((3, false), followed_frame_count(3, header + 12 + 12, 4));
+    }
+
+    #[test]
+    fn test_follow_capture() -> Result<()> {
+        let format = ser_format(2, 2, Bayer::RGGB, 8);
+        let file = TempFile::new(".ser", &ser_bytes(&format, &[]));
+        let video = FollowedSerVideo::open(file.path())?;
+        let watch = video.frame_watch().unwrap();
+        assert_eq!(0, video.frame_count());
+        assert!(video.read_frame(0).is_err());
+
+        // append frames like capture software does, including half of a frame
+        let mut out = OpenOptions::new().append(true).open(file.path())?;
+        out.write_all(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10])?;
+        out.flush()?;
+        let mut count = 0;
+        while count < 2 {
+            count = watch.wait(count, Duration::from_secs(10));
+        }
+        assert_eq!(2, video.frame_count());
+        assert_eq!(&[5, 6, 7, 8], &*video.read_frame(1)?);
+        assert!(!video.is_finished());
+
+        // finish the capture with the frame count and timestamps
+        let mut writer = SerWriter::new(Cursor::new(vec![]), &format)?;
+        for i in 0..3 {
+            let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000 + i);
+            writer.write_frame_at(&[i as u8; 4], time)?;
+        }
+        // in one write, so that the file is never seen with the trailer but without the count
+        let mut out = OpenOptions::new().write(true).open(file.path())?;
+        out.write_all(&writer.finish()?.into_inner())?;
+        while count < 3 {
+            count = watch.wait(count, Duration::from_secs(10));
+        }
+        assert!(video.is_finished());
+        assert_eq!(Some(1.0), video.frame_rate());
+        assert_eq!(&[2, 2, 2, 2], &*video.read_frame(2)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_follow_trailer_in_pieces() -> Result<()> {
+        let format = ser_format(2, 2, Bayer::Mono, 8);
+        let mut writer = SerWriter::new(Cursor::new(vec![]), &format)?;
+        for i in 0..3 {
+            let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000 + i);
+            writer.write_frame_at(&[i as u8; 4], time)?;
+        }
+        let finished = writer.finish()?.into_inner();
+        let (frames, trailer) = finished.split_at(HEADER_SIZE + 12);
+
+        let file = TempFile::new(".ser", &ser_bytes(&format, &[]));
+        let video = FollowedSerVideo::open(file.path())?;
+        let watch = video.frame_watch().unwrap();
+        let mut out = OpenOptions::new().append(true).open(file.path())?;
+        out.write_all(&frames[HEADER_SIZE..])?;
+        let mut count = 0;
+        while count < 3 {
+            count = watch.wait(count, Duration::from_secs(10));
+        }
+
+        // the count is written before the trailer, which is large enough to hold frames
+        let mut header = OpenOptions::new().write(true).open(file.path())?;
+        header.seek(SeekFrom::Start(FRAME_COUNT_OFFSET))?;
+        header.write_all(&3_u32.to_le_bytes())?;
+        for piece in trailer.chunks(8) {
+            out.write_all(piece)?;
+            thread::sleep(2 * POLL_INTERVAL);
+            assert_eq!(3, video.frame_count());
+        }
+        let start = SystemTime::now();
+        while !video.is_finished() {
+            assert!(start.elapsed().unwrap() < Duration::from_secs(10));
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(3, video.frame_count());
+        assert_eq!(Some(1.0), video.frame_rate());
+        Ok(())
+    }
+}
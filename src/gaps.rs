@@ -0,0 +1,138 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Detection of dropped frames and capture gaps from the frame timestamps that SER files
+//! record, such as when the capture software could not keep up with the camera or the
+//! capture was paused.
+
+use std::time::{Duration, SystemTime};
+
+use crate::video_format::Video;
+
+/// Intervals between frames that are longer than this multiple of the typical interval are
+/// reported as gaps, which allows for jitter in the timestamps
+pub const GAP_FACTOR: f64 = 1.5;
+
+/// A gap in the capture between two consecutive frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    /// Index of the frame before the gap
+    pub after: usize,
+    /// Time between the frames on either side of the gap
+    pub duration: Duration,
+    /// Estimated number of frames that were not recorded in the gap
+    pub dropped: usize,
+}
+
+/// Gaps in the capture of a video, which are only found when every frame has a timestamp
+pub fn find_gaps(video: &dyn Video) -> Vec<Gap> {
+    let timestamps: Option<Vec<SystemTime>> = (0..video.frame_count())
+        .map(|index| video.frame_timestamp(index))
+        .collect();
+    timestamps.map_or_else(Vec::new, |timestamps| gaps_between(&timestamps))
+}
+
+/// Gaps between consecutive timestamps that are much longer than the median interval.
+/// Timestamps that go backwards are ignored.
+pub fn gaps_between(timestamps: &[SystemTime]) -> Vec<Gap> {
+    let intervals: Vec<Option<Duration>> = timestamps
+        .windows(2)
+        .map(|pair| pair[1].duration_since(pair[0]).ok())
+        .collect();
+    let mut sorted: Vec<Duration> = intervals
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|interval| !interval.is_zero())
+        .collect();
+    if sorted.is_empty() {
+        return vec![];
+    }
+    sorted.sort_unstable();
+    let typical = sorted[sorted.len() / 2].as_secs_f64();
+    intervals
+        .iter()
+        .enumerate()
+        .filter_map(|(after, interval)| {
+            let duration = (*interval)?;
+            let frames = duration.as_secs_f64() / typical;
+            if frames > GAP_FACTOR {
+                Some(Gap {
+                    after,
+                    duration,
+                    dropped: (frames.round() as usize).saturating_sub(1).max(1),
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::ser::{SerBytes, SerWriter};
+    use crate::testutil::{ser_format, ser_video};
+    use crate::video_format::{Bayer, OpenMode};
+
+    #[test]
+    fn test_gaps_between() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let offsets_ms = [0, 10, 21, 30, 70, 80, 90, 590, 600];
+        let timestamps: Vec<_> = offsets_ms
+            .iter()
+            .map(|&ms| start + Duration::from_millis(ms))
+            .collect();
+        let gaps = gaps_between(&timestamps);
+        assert_eq!(2, gaps.len());
+        assert_eq!(3, gaps[0].after);
+        assert_eq!(Duration::from_millis(40), gaps[0].duration);
+        assert_eq!(3, gaps[0].dropped);
+        assert_eq!(6, gaps[1].after);
+        assert_eq!(49, gaps[1].dropped);
+        assert!(gaps_between(&timestamps[..1]).is_empty());
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let format = ser_format(2, 2, Bayer::Mono, 8);
+        let frames = vec![vec![0; 4]; 4];
+        assert!(find_gaps(&ser_video(&format, &frames)).is_empty());
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let mut writer = SerWriter::new(Cursor::new(vec![]), &format).unwrap();
+        for (frame, ms) in frames.iter().zip(&[0, 20, 40, 100]) {
+            writer
+                .write_frame_at(frame, start + Duration::from_millis(*ms))
+                .unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+        let video = SerBytes::new(bytes, OpenMode::Strict).unwrap();
+        let gaps = find_gaps(&video);
+        assert_eq!(1, gaps.len());
+        assert_eq!(2, gaps[0].after);
+        assert_eq!(2, gaps[0].dropped);
+    }
+}
@@ -0,0 +1,333 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Debayering on the GPU with a wgpu compute shader, for real-time playback of large RAW
+//! captures. The shader implements the same bilinear interpolation as `BilinearDebayerCodec`,
+//! which is used instead when no GPU adapter is available.
+
+use std::borrow::Cow;
+
+use futures::executor::block_on;
+use tracing::{debug, info, warn};
+use wgpu::util::DeviceExt;
+
+use crate::codec::{BilinearDebayerCodec, ImageCodec, Mosaic};
+use crate::error::{PlayerError, Result};
+use crate::video_format::Video;
+
+const SHADER: &str = include_str!("shaders/debayer.wgsl");
+
+/// Width and height of the shader's workgroups, in output pixels
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Full resolution bilinear debayer that runs on the GPU, falling back to the CPU when no
+/// adapter is available, the samples are deeper than 16 bits or the GPU fails to decode a frame
+pub struct GpuDebayerCodec {
+    gpu: Option<Gpu>,
+    fallback: BilinearDebayerCodec,
+}
+
+impl GpuDebayerCodec {
+    /// Create the codec, connecting to the default GPU adapter if there is one
+    pub fn new() -> Self {
+        let gpu = match Gpu::new() {
+            Ok(gpu) => Some(gpu),
+            Err(e) => {
+                info!("debayering on the CPU: {}", e);
+                None
+            }
+        };
+        Self {
+            gpu,
+            fallback: BilinearDebayerCodec {},
+        }
+    }
+
+    /// Whether frames are decoded on the GPU rather than by the CPU fallback
+    pub fn is_accelerated(&self) -> bool {
+        self.gpu.is_some()
+    }
+}
+
+impl Default for GpuDebayerCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImageCodec for GpuDebayerCodec {
+    fn decode_scaled_into(
+        &self,
+        video: &dyn Video,
+        frame_index: usize,
+        scale: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(u32, u32)> {
+        let gpu = match &self.gpu {
+            // the shader packs samples into 16 bits
+            Some(gpu) if video.pixel_depth_bits() <= 16 => gpu,
+            _ => {
+                return self
+                    .fallback
+                    .decode_scaled_into(video, frame_index, scale, out)
+            }
+        };
+        let mosaic = Mosaic::new(video, frame_index)?;
+        match gpu.debayer(&mosaic, scale.max(1), out) {
+            Ok(size) => Ok(size),
+            Err(e) => {
+                warn!("debayering frame {} on the CPU: {}", frame_index, e);
+                self.fallback
+                    .decode_scaled_into(video, frame_index, scale, out)
+            }
+        }
+    }
+}
+
+/// Layout of the shader's `Params` uniform
+#[derive(Debug, Clone, Copy)]
+struct Params {
+    width: u32,
+    height: u32,
+    scale: u32,
+    out_width: u32,
+    out_height: u32,
+    pixel_depth_bits: u32,
+    colors: u32,
+}
+
+impl Params {
+    fn to_bytes(self) -> Vec<u8> {
+        [
+            self.width,
+            self.height,
+            self.scale,
+            self.out_width,
+            self.out_height,
+            self.pixel_depth_bits,
+            self.colors,
+            0,
+        ]
+        .iter()
+        .flat_map(|value| value.to_le_bytes().to_vec())
+        .collect()
+    }
+}
+
+/// A GPU device along with the compiled debayer pipeline
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Gpu {
+    fn new() -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+        }))
+        .ok_or_else(|| PlayerError::Unsupported("No GPU adapter is available".to_string()))?;
+        let adapter_info = adapter.get_info();
+        let (device, queue) = block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("debayer"),
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::default(),
+            },
+            None,
+        ))
+        .map_err(|e| PlayerError::Unsupported(format!("Could not open the GPU: {}", e)))?;
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("debayer"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+            flags: wgpu::ShaderFlags::all(),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("debayer"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+        });
+        info!(adapter = %adapter_info.name, backend = ?adapter_info.backend, "debayering on the GPU");
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+        })
+    }
+
+    /// Debayer every `scale`th pixel of a mosaic into BGRA pixels
+    fn debayer(&self, mosaic: &Mosaic, scale: u32, out: &mut Vec<u8>) -> Result<(u32, u32)> {
+        let width = mosaic.width as u32;
+        let height = mosaic.height as u32;
+        let out_width = width.div_ceil(scale);
+        let out_height = height.div_ceil(scale);
+        out.clear();
+        if out_width == 0 || out_height == 0 {
+            return Ok((out_width, out_height));
+        }
+
+        let params = Params {
+            width,
+            height,
+            scale,
+            out_width,
+            out_height,
+            pixel_depth_bits: mosaic.pixel_depth_bits,
+            colors: mosaic
+                .colors
+                .iter()
+                .enumerate()
+                .map(|(i, channel)| (*channel as u32) << (8 * i))
+                .sum(),
+        };
+        let samples = mosaic
+            .samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let output_size = out_width as u64 * out_height as u64 * 4;
+
+        let params = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("debayer params"),
+                contents: &params.to_bytes(),
+                usage: wgpu::BufferUsage::UNIFORM,
+            });
+        let samples = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("debayer samples"),
+                contents: &samples,
+                usage: wgpu::BufferUsage::STORAGE,
+            });
+        let pixels = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("debayer pixels"),
+            size: output_size,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("debayer readback"),
+            size: output_size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("debayer"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: samples.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: pixels.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("debayer"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("debayer"),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch(
+                out_width.div_ceil(WORKGROUP_SIZE),
+                out_height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        encoder.copy_buffer_to_buffer(&pixels, 0, &readback, 0, output_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        if let Err(e) = block_on(mapped) {
+            warn!("could not read debayered pixels from the GPU: {:?}", e);
+            return Err(PlayerError::Decode(
+                "Could not read debayered pixels from the GPU".to_string(),
+            ));
+        }
+        out.extend_from_slice(&slice.get_mapped_range());
+        readback.unmap();
+        debug!(out_width, out_height, "debayered on the GPU");
+        Ok((out_width, out_height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{gradient_frame, ser_format, ser_video};
+    use crate::video_format::Bayer;
+
+    // run with `cargo test --features gpu -- --ignored` on a machine with a GPU
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_matches_cpu() {
+        let codec = GpuDebayerCodec::new();
+        assert!(codec.is_accelerated(), "no GPU adapter");
+        for bayer in [Bayer::RGGB, Bayer::BGGR, Bayer::GBRG, Bayer::GRBG].iter() {
+            for bits in [8, 12, 16].iter() {
+                let format = ser_format(10, 6, *bayer, *bits);
+                let video = ser_video(&format, &[gradient_frame(&format, 3)]);
+                for scale in [1, 3].iter() {
+                    let expected = BilinearDebayerCodec {}
+                        .decode_scaled(&video, 0, *scale)
+                        .unwrap();
+                    let actual = codec.decode_scaled(&video, 0, *scale).unwrap();
+                    assert_eq!(expected, actual, "{:?} {} bits at 1/{}", bayer, bits, scale);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let codec = GpuDebayerCodec {
+            gpu: None,
+            fallback: BilinearDebayerCodec {},
+        };
+        assert!(!codec.is_accelerated());
+        let format = ser_format(10, 6, Bayer::RGGB, 12);
+        let video = ser_video(&format, &[gradient_frame(&format, 3)]);
+        let expected = BilinearDebayerCodec {}.decode_scaled(&video, 0, 2).unwrap();
+        assert_eq!(expected, codec.decode_scaled(&video, 0, 2).unwrap());
+        assert!(codec.decode_scaled(&video, 1, 1).is_err());
+    }
+}
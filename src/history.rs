@@ -0,0 +1,110 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+/// Undo and redo stacks for a value that is changed by the user
+pub struct History<T> {
+    undo: Vec<T>,
+    redo: Vec<T>,
+    /// Maximum number of undo steps to keep
+    limit: usize,
+}
+
+impl<T> History<T> {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo: vec![],
+            redo: vec![],
+            limit,
+        }
+    }
+
+    /// Record the value from before a change. This discards any changes that were undone.
+    pub fn record(&mut self, previous: T) {
+        self.undo.push(previous);
+        if self.undo.len() > self.limit {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// Return the value from before the most recent change, saving `current` so that the
+    /// change can be redone
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let previous = self.undo.pop()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// Return the value from before the most recent undo, saving `current` so that it can
+    /// be undone again
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let next = self.redo.pop()?;
+        self.undo.push(current);
+        Some(next)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::new(10);
+        let mut value = 1;
+        for next in 2..=4 {
+            history.record(value);
+            value = next;
+        }
+        value = history.undo(value).unwrap();
+        assert_eq!(3, value);
+        value = history.undo(value).unwrap();
+        assert_eq!(2, value);
+        value = history.redo(value).unwrap();
+        assert_eq!(3, value);
+
+        // a new change discards the redo stack
+        history.record(value);
+        value = 10;
+        assert!(!history.can_redo());
+        assert_eq!(Some(3), history.undo(value));
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut history = History::new(2);
+        for value in 0..5 {
+            history.record(value);
+        }
+        assert_eq!(Some(4), history.undo(5));
+        assert_eq!(Some(3), history.undo(4));
+        assert_eq!(None, history.undo(3));
+    }
+}
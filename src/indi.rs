@@ -0,0 +1,622 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Live preview of cameras that are controlled by an INDI server, such as the cameras of a
+//! remote observatory.
+//!
+//! INDI clients exchange XML messages with the server over TCP. `IndiVideo` asks a camera
+//! driver to stream video, receives the frames as base64 encoded BLOBs (binary property
+//! values) and feeds them into a `LiveVideo`. The frame size, bit depth and Bayer pattern are
+//! taken from the driver's `CCD_STREAM_FRAME`, `CCD_INFO` and `CCD_CFA` properties.
+
+use std::borrow::Cow;
+use std::io::{self, BufReader, Chain, Cursor, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use tracing::{debug, info, warn};
+use xml::escape::escape_str_attribute;
+use xml::reader::{self, EventReader, ParserConfig, XmlEvent};
+
+use crate::error::{PlayerError, Result};
+use crate::live::{live_video, FrameWatch, LiveFeed, LiveVideo, DEFAULT_LIVE_MEMORY};
+use crate::ser::SerFormat;
+use crate::video_format::{parse_bayer, Bayer, Endianness, Video};
+
+/// Port that INDI servers listen on unless another one is given
+pub const DEFAULT_PORT: u16 = 7624;
+
+/// How long to wait for the first frame, which includes the time the driver takes to
+/// describe its properties and start streaming
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Switch property of camera drivers that starts and stops streaming
+const VIDEO_STREAM: &str = "CCD_VIDEO_STREAM";
+
+/// BLOB format of uncompressed streamed frames
+const STREAM_FORMAT: &str = ".stream";
+
+/// Split an `indi://host[:port][/device]` address into the server address and the device
+/// name. Spaces in the device name may be written as `%20`.
+pub fn parse_url(url: &str) -> Option<(String, Option<String>)> {
+    let rest = url.strip_prefix("indi://")?;
+    let (host, device) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], Some(&rest[slash + 1..])),
+        None => (rest, None),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, DEFAULT_PORT)
+    };
+    let device = device
+        .filter(|device| !device.is_empty())
+        .map(|device| device.replace("%20", " "));
+    Some((address, device))
+}
+
+/// Properties of the driver that describe the streamed frames
+#[derive(Debug, Default, Clone, PartialEq)]
+struct StreamProperties {
+    /// Size of streamed frames, which may be a region of the sensor
+    stream_frame: (Option<u32>, Option<u32>),
+    /// Size of the frames of exposures, for drivers that do not report `stream_frame`
+    ccd_frame: (Option<u32>, Option<u32>),
+    bits_per_pixel: Option<u32>,
+    cfa: Option<String>,
+}
+
+impl StreamProperties {
+    fn update(&mut self, vector: &str, element: &str, value: &str) {
+        let number = || value.parse::<f64>().ok().map(|value| value as u32);
+        match (vector, element) {
+            ("CCD_STREAM_FRAME", "WIDTH") => self.stream_frame.0 = number(),
+            ("CCD_STREAM_FRAME", "HEIGHT") => self.stream_frame.1 = number(),
+            ("CCD_FRAME", "WIDTH") => self.ccd_frame.0 = number(),
+            ("CCD_FRAME", "HEIGHT") => self.ccd_frame.1 = number(),
+            ("CCD_INFO", "CCD_BITSPERPIXEL") => self.bits_per_pixel = number(),
+            ("CCD_CFA", "CFA_TYPE") => {
+                self.cfa = Some(value.to_string()).filter(|cfa| !cfa.is_empty())
+            }
+            _ => {}
+        }
+    }
+
+    /// Format of a streamed frame of `size` bytes. Frames are 8 or 16 bits per sample, so the
+    /// sample size is taken from the frame size rather than from `CCD_BITSPERPIXEL`, which
+    /// gives the depth of the sensor.
+    fn frame_format(&self, size: usize) -> Result<SerFormat> {
+        let (width, height) = match (self.stream_frame, self.ccd_frame) {
+            ((Some(width), Some(height)), _) | (_, (Some(width), Some(height))) => (width, height),
+            _ => {
+                return Err(PlayerError::Format(
+                    "The camera did not report the size of its frames".to_string(),
+                ))
+            }
+        };
+        let pixels = (width as usize * height as usize).max(1);
+        let bayer = self
+            .cfa
+            .as_deref()
+            .and_then(|cfa| parse_bayer(cfa).ok())
+            .unwrap_or(Bayer::Mono);
+        let sensor_bits = self
+            .bits_per_pixel
+            .filter(|bits| (9..=16).contains(bits))
+            .unwrap_or(16);
+        let (bayer, pixel_depth_bits) = match (size % pixels, size / pixels) {
+            (0, 1) => (bayer, 8),
+            (0, 2) => (bayer, sensor_bits),
+            (0, 3) => (Bayer::RGB, 8),
+            (0, 6) => (Bayer::RGB, sensor_bits),
+            _ => {
+                return Err(PlayerError::Format(format!(
+                    "A frame of {} bytes does not match the {}x{} frame size of the camera",
+                    size, width, height
+                )))
+            }
+        };
+        Ok(SerFormat {
+            width,
+            height,
+            bayer,
+            pixel_depth_bits,
+            // drivers stream 16-bit samples in the byte order of the host, which is little
+            // endian on the platforms that INDI runs on
+            endianness: Endianness::LittleEndian,
+        })
+    }
+}
+
+/// The vector property that the element being parsed belongs to
+struct Vector {
+    tag: String,
+    device: String,
+    name: String,
+}
+
+/// Reads messages from an INDI server, keeping track of the properties of one device
+struct Client<R: Read, W: Write> {
+    events: EventReader<Chain<Cursor<&'static [u8]>, R>>,
+    writer: W,
+    /// Device to stream from, or `None` to use the first device that can stream video
+    device: Option<String>,
+    streaming: bool,
+    properties: StreamProperties,
+    /// Whether BLOBs in other formats have been reported, to only report them once
+    skipped: bool,
+}
+
+impl<R: Read, W: Write> Client<R, W> {
+    /// Start a session by asking the server to describe the properties of its devices
+    fn new(reader: R, writer: W, device: Option<String>) -> Result<Self> {
+        // the protocol is a sequence of elements without a root element, so one is added
+        let reader = Cursor::new(&b"<indi>"[..]).chain(reader);
+        let events = ParserConfig::new()
+            .cdata_to_characters(true)
+            .create_reader(reader);
+        let mut client = Self {
+            events,
+            writer,
+            device,
+            streaming: false,
+            properties: StreamProperties::default(),
+            skipped: false,
+        };
+        let command = match &client.device {
+            Some(device) => format!(
+                r#"<getProperties version="1.7" device="{}"/>"#,
+                escape_str_attribute(device)
+            ),
+            None => r#"<getProperties version="1.7"/>"#.to_string(),
+        };
+        client.send(&command)?;
+        Ok(client)
+    }
+
+    fn send(&mut self, message: &str) -> Result<()> {
+        debug!(message, "sending to the INDI server");
+        self.writer.write_all(message.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Start streaming once the device has described its streaming property
+    fn defined(&mut self, vector: &Vector) -> Result<()> {
+        if vector.tag != "defSwitchVector" || vector.name != VIDEO_STREAM || self.streaming {
+            return Ok(());
+        }
+        let device = match &self.device {
+            Some(device) if *device != vector.device => return Ok(()),
+            _ => vector.device.clone(),
+        };
+        info!(device = device.as_str(), "starting the INDI video stream");
+        self.send(&format!(
+            r#"<enableBLOB device="{}">Also</enableBLOB>"#,
+            escape_str_attribute(&device)
+        ))?;
+        self.send(&stream_command(&device, true))?;
+        self.device = Some(device);
+        self.streaming = true;
+        Ok(())
+    }
+
+    /// Read messages until the device sends a BLOB, returning its format and decoded bytes
+    fn next_blob(&mut self) -> Result<(String, Vec<u8>)> {
+        let mut vector: Option<Vector> = None;
+        // name and format of the element whose value is in `text`
+        let mut element: Option<(String, String)> = None;
+        let mut text = String::new();
+        loop {
+            match self.events.next().map_err(xml_error)? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let attribute = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|attribute| attribute.name.local_name == key)
+                            .map(|attribute| attribute.value.clone())
+                            .unwrap_or_default()
+                    };
+                    if name.local_name.ends_with("Vector") {
+                        let started = Vector {
+                            tag: name.local_name.clone(),
+                            device: attribute("device"),
+                            name: attribute("name"),
+                        };
+                        self.defined(&started)?;
+                        vector = Some(started);
+                    } else {
+                        element = Some((attribute("name"), attribute("format")));
+                        text.clear();
+                    }
+                }
+                XmlEvent::Characters(characters) => text.push_str(&characters),
+                XmlEvent::EndElement { name } => {
+                    if name.local_name.ends_with("Vector") {
+                        vector = None;
+                        continue;
+                    }
+                    let (vector, (element_name, format)) = match (&vector, element.take()) {
+                        (Some(vector), Some(element)) => (vector, element),
+                        _ => continue,
+                    };
+                    if self.device.as_ref() != Some(&vector.device) {
+                        continue;
+                    }
+                    if name.local_name == "oneBLOB" {
+                        return Ok((format, decode_base64(&text)?));
+                    }
+                    self.properties
+                        .update(&vector.name, &element_name, text.trim());
+                }
+                XmlEvent::EndDocument => {
+                    return Err(PlayerError::Io(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the INDI server closed the connection",
+                    )))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Read messages until the device sends an uncompressed streamed frame
+    fn next_frame(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let (format, bytes) = self.next_blob()?;
+            if format == STREAM_FORMAT {
+                return Ok(bytes);
+            }
+            if !self.skipped {
+                warn!(
+                    "skipping {} BLOBs from the INDI server, only uncompressed {} frames are shown",
+                    format, STREAM_FORMAT
+                );
+                self.skipped = true;
+            }
+        }
+    }
+}
+
+/// Message that turns streaming on or off
+fn stream_command(device: &str, on: bool) -> String {
+    let (stream_on, stream_off) = if on { ("On", "Off") } else { ("Off", "On") };
+    format!(
+        r#"<newSwitchVector device="{}" name="{}"><oneSwitch name="STREAM_ON">{}</oneSwitch><oneSwitch name="STREAM_OFF">{}</oneSwitch></newSwitchVector>"#,
+        escape_str_attribute(device),
+        VIDEO_STREAM,
+        stream_on,
+        stream_off
+    )
+}
+
+fn xml_error(e: reader::Error) -> PlayerError {
+    match e.kind() {
+        reader::ErrorKind::Io(io) => PlayerError::Io(io::Error::new(io.kind(), io.to_string())),
+        _ => PlayerError::Format(format!("Invalid message from the INDI server: {}", e)),
+    }
+}
+
+/// Decode the base64 value of a BLOB, which may be split into lines
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            _ => {
+                return Err(PlayerError::Decode(format!(
+                    "Invalid character '{}' in a BLOB from the INDI server",
+                    c as char
+                )))
+            }
+        };
+        buffer = (buffer << 6 | value as u32) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Live stream from a camera on an INDI server. Frames are received on a background thread
+/// until the video is dropped, and only the most recent frames are kept.
+pub struct IndiVideo {
+    live: LiveVideo,
+    /// Connection to the server, which is shut down when the video is dropped so that the
+    /// receiving thread stops
+    stream: TcpStream,
+    device: String,
+}
+
+impl IndiVideo {
+    /// Connect to the server of an `indi://host[:port][/device]` address, see `parse_url`
+    pub fn open(url: &str) -> Result<Self> {
+        let (address, device) = parse_url(url).ok_or_else(|| {
+            PlayerError::InvalidArgument(format!(
+                "Invalid INDI address {}, expected indi://host[:port][/device]",
+                url
+            ))
+        })?;
+        Self::connect(&address, device.as_deref())
+    }
+
+    /// Connect to the INDI server at `address` and stream from `device`, or from the first
+    /// device that can stream video
+    pub fn connect(address: &str, device: Option<&str>) -> Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(FIRST_FRAME_TIMEOUT))?;
+        let mut client = Client::new(
+            BufReader::new(stream.try_clone()?),
+            stream.try_clone()?,
+            device.map(String::from),
+        )?;
+        let first = client.next_frame().map_err(|e| match e {
+            PlayerError::Io(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                PlayerError::Unsupported(format!(
+                    "No video from {} on {} within {} seconds",
+                    device.unwrap_or("any camera"),
+                    address,
+                    FIRST_FRAME_TIMEOUT.as_secs()
+                ))
+            }
+            e => e,
+        })?;
+        stream.set_read_timeout(None)?;
+        let format = client.properties.frame_format(first.len())?;
+        let device = client.device.clone().unwrap_or_default();
+        info!(
+            server = address,
+            device = device.as_str(),
+            width = format.width,
+            height = format.height,
+            bits = format.pixel_depth_bits,
+            "streaming from INDI camera"
+        );
+        let (live, feed) = live_video(format, DEFAULT_LIVE_MEMORY);
+        let live = live.with_header_fields(vec![
+            ("INDI server".to_string(), address.to_string()),
+            ("Camera".to_string(), device.clone()),
+        ]);
+        feed.push_frame(first, Some(SystemTime::now()))?;
+        thread::Builder::new()
+            .name("indi".to_string())
+            .spawn(move || receive(client, feed))?;
+        Ok(Self {
+            live,
+            stream,
+            device,
+        })
+    }
+}
+
+/// Receive frames into `feed` until the video is closed or the connection fails
+fn receive<R: Read, W: Write>(mut client: Client<R, W>, feed: LiveFeed) {
+    while !feed.is_closed() {
+        match client.next_frame() {
+            Ok(frame) => {
+                if let Err(e) = feed.push_frame(frame, Some(SystemTime::now())) {
+                    warn!("dropped a frame from the INDI server: {}", e);
+                }
+            }
+            Err(e) => {
+                if !feed.is_closed() {
+                    warn!("stopped streaming from the INDI server: {}", e);
+                }
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for IndiVideo {
+    fn drop(&mut self) {
+        let _ = (&self.stream).write_all(stream_command(&self.device, false).as_bytes());
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl Video for IndiVideo {
+    fn image_width(&self) -> u32 {
+        self.live.image_width()
+    }
+
+    fn image_height(&self) -> u32 {
+        self.live.image_height()
+    }
+
+    fn frame_count(&self) -> usize {
+        self.live.frame_count()
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        self.live.bytes_per_pixel()
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.live.pixel_depth_bits()
+    }
+
+    fn bayer(&self) -> &Bayer {
+        self.live.bayer()
+    }
+
+    fn endianness(&self) -> &Endianness {
+        self.live.endianness()
+    }
+
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        self.live.read_frame(index)
+    }
+
+    fn frame_timestamp(&self, index: usize) -> Option<SystemTime> {
+        self.live.frame_timestamp(index)
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.live.header_fields()
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn frame_watch(&self) -> Option<FrameWatch> {
+        self.live.frame_watch()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            Some(("localhost:7624".to_string(), None)),
+            parse_url("indi://localhost")
+        );
+        assert_eq!(
+            Some((
+                "observatory:7625".to_string(),
+                Some("CCD Simulator".to_string())
+            )),
+            parse_url("indi://observatory:7625/CCD%20Simulator")
+        );
+        assert_eq!(None, parse_url("indi:///camera"));
+        assert_eq!(None, parse_url("/dev/video0"));
+    }
+
+    #[test]
+    fn test_decode_base64() -> Result<()> {
+        assert_eq!(b"INDI".to_vec(), decode_base64("SU5E\nSQ==")?);
+        assert_eq!(vec![0, 255, 16], decode_base64("AP8Q")?);
+        assert!(decode_base64("SU5E*").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_frames() -> Result<()> {
+        let messages = r#"
+<defTextVector device="Telescope Simulator" name="DRIVER_INFO"/>
+<defSwitchVector device="CCD Simulator" name="CCD_VIDEO_STREAM" state="Idle">
+    <defSwitch name="STREAM_ON">Off</defSwitch>
+    <defSwitch name="STREAM_OFF">On</defSwitch>
+</defSwitchVector>
+<defNumberVector device="CCD Simulator" name="CCD_INFO">
+    <defNumber name="CCD_BITSPERPIXEL">12</defNumber>
+</defNumberVector>
+<defNumberVector device="CCD Simulator" name="CCD_STREAM_FRAME">
+    <defNumber name="WIDTH">
+      2
+    </defNumber>
+    <defNumber name="HEIGHT">2</defNumber>
+</defNumberVector>
+<defTextVector device="CCD Simulator" name="CCD_CFA">
+    <defText name="CFA_TYPE">GRBG</defText>
+</defTextVector>
+<message device="CCD Simulator" message="streaming"/>
+<setBLOBVector device="CCD Simulator" name="CCD1">
+    <oneBLOB name="CCD1" size="3" format=".fits">AAAA</oneBLOB>
+</setBLOBVector>
+<setBLOBVector device="CCD Simulator" name="CCD1">
+    <oneBLOB name="CCD1" size="8" format=".stream">AQID
+BAUGBwg=</oneBLOB>
+</setBLOBVector>
+"#;
+        let mut sent = vec![];
+        let mut client = Client::new(messages.as_bytes(), &mut sent, None)?;
+        let frame = client.next_frame()?;
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], frame);
+        let format = client.properties.frame_format(frame.len())?;
+        assert_eq!(
+            (2, 2, 12),
+            (format.width, format.height, format.pixel_depth_bits)
+        );
+        assert_eq!(Bayer::GRBG, format.bayer);
+        assert!(client.properties.frame_format(5).is_err());
+        assert!(client.next_frame().is_err());
+        drop(client);
+        let sent = String::from_utf8(sent).unwrap();
+        assert!(sent.starts_with(r#"<getProperties version="1.7"/>"#));
+        assert!(sent.contains(r#"<enableBLOB device="CCD Simulator">Also</enableBLOB>"#));
+        assert!(sent.contains(&stream_command("CCD Simulator", true)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_indi_video() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let server = thread::spawn(move || -> io::Result<Vec<u8>> {
+            let (mut stream, _) = listener.accept()?;
+            stream.write_all(
+                br#"<defSwitchVector device="Camera" name="CCD_VIDEO_STREAM"/>
+<defNumberVector device="Camera" name="CCD_FRAME">
+<defNumber name="WIDTH">3</defNumber><defNumber name="HEIGHT">1</defNumber>
+</defNumberVector>
+<setBLOBVector device="Camera" name="CCD1">
+<oneBLOB name="CCD1" format=".stream">AQIDBAUGBwgJ</oneBLOB>
+</setBLOBVector>"#,
+            )?;
+            // the client stops the stream and disconnects when the video is dropped
+            let mut received = vec![];
+            stream.read_to_end(&mut received)?;
+            Ok(received)
+        });
+        let video = IndiVideo::open(&format!("indi://{}/Camera", address))?;
+        assert!(video.is_live());
+        assert_eq!(
+            (3, 1, 1),
+            (
+                video.image_width(),
+                video.image_height(),
+                video.frame_count()
+            )
+        );
+        assert_eq!(Bayer::RGB, *video.bayer());
+        assert_eq!(
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+            video.read_frame(0)?.to_vec()
+        );
+        drop(video);
+        let received = String::from_utf8(server.join().unwrap()?).unwrap();
+        assert!(received.ends_with(&stream_command("Camera", false)));
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Expanding the files given to batch commands into a list of paths
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::error::{PlayerError, Result};
+
+/// Collect the files to process from patterns given on the command line, which may contain
+/// glob wildcards, followed by the paths listed in `files_from`. A `files_from` of `-`
+/// reads the list from stdin.
+pub fn input_files(patterns: &[String], files_from: Option<&Path>) -> Result<Vec<String>> {
+    let mut files = expand_patterns(patterns)?;
+    if let Some(path) = files_from {
+        files.extend(read_files_from(path)?);
+    }
+    if files.is_empty() {
+        return Err(PlayerError::InvalidArgument(
+            "No input files were given".to_string(),
+        ));
+    }
+    Ok(files)
+}
+
+/// Expand glob wildcards, for shells that do not expand them (or patterns that were quoted
+/// to avoid argument length limits). Wildcards ignore case, so `*.ser` also matches the
+/// `.SER` files that some capture software writes. Patterns without wildcards are returned
+/// unchanged.
+pub fn expand_patterns(patterns: &[String]) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for pattern in patterns {
+        if !pattern.contains(&['*', '?', '['][..]) {
+            files.push(pattern.clone());
+            continue;
+        }
+        let options = glob::MatchOptions {
+            case_sensitive: false,
+            ..glob::MatchOptions::default()
+        };
+        let paths = glob::glob_with(pattern, options)
+            .map_err(|e| PlayerError::InvalidArgument(format!("{}: {}", pattern, e)))?;
+        let mut matches = vec![];
+        for path in paths {
+            let path = path.map_err(|e| PlayerError::Io(e.into_error()))?;
+            matches.push(path.to_string_lossy().into_owned());
+        }
+        if matches.is_empty() {
+            return Err(PlayerError::InvalidArgument(format!(
+                "No files match {}",
+                pattern
+            )));
+        }
+        files.extend(matches);
+    }
+    Ok(files)
+}
+
+/// File extensions of the formats that can be opened, used to find videos in directories
+const VIDEO_EXTENSIONS: &[&str] = &["ser", "avi", "fits", "fit", "fts"];
+
+/// File extensions of the still images that a directory can be played as a sequence of, see
+/// `DirectoryVideo`
+pub const IMAGE_EXTENSIONS: &[&str] = &["fits", "fit", "fts", "tif", "tiff", "png"];
+
+/// Whether a path has one of the given extensions, ignoring case
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extensions
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// Replace any directories in `paths` with the videos they contain, sorted by name. A
+/// directory without SER or AVI files but with FITS, TIFF or PNG images is kept, so that it
+/// is played as an image sequence. Subdirectories are not searched.
+pub fn expand_directories(paths: Vec<String>) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for path in paths {
+        if !Path::new(&path).is_dir() {
+            files.push(path);
+            continue;
+        }
+        let mut videos = vec![];
+        let mut images = false;
+        for entry in std::fs::read_dir(&path)? {
+            let entry_path = entry?.path();
+            if !entry_path.is_file() {
+                continue;
+            }
+            if has_extension(&entry_path, VIDEO_EXTENSIONS) {
+                videos.push(entry_path.to_string_lossy().into_owned());
+            }
+            images |= has_extension(&entry_path, IMAGE_EXTENSIONS);
+        }
+        let sequence = videos
+            .iter()
+            .all(|video| has_extension(Path::new(video), IMAGE_EXTENSIONS));
+        if images && sequence {
+            files.push(path);
+            continue;
+        }
+        if videos.is_empty() {
+            return Err(PlayerError::InvalidArgument(format!(
+                "No SER, AVI, FITS, TIFF or PNG files in {}",
+                path
+            )));
+        }
+        videos.sort();
+        files.extend(videos);
+    }
+    Ok(files)
+}
+
+/// Read the paths listed in a file, one per line, or from stdin if `path` is `-`, such as
+/// when a script pipes the output of `find` to the player
+pub fn read_files_from(path: &Path) -> Result<Vec<String>> {
+    if path == Path::new("-") {
+        read_file_list(io::stdin().lock())
+    } else {
+        read_file_list(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Read newline separated paths, ignoring blank lines
+pub fn read_file_list(reader: impl BufRead) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if !line.trim().is_empty() {
+            files.push(line.to_string());
+        }
+    }
+    Ok(files)
+}
+
+/// Names for the outputs of several input files written to one directory, which are the file
+/// names without the extension. Inputs with the same name in different directories would
+/// overwrite each other's output, so later ones get a `_2`, `_3`, ... suffix. Names are
+/// compared ignoring case, as they are on the file systems of Windows and macOS.
+pub fn output_names(files: &[String]) -> Vec<String> {
+    let stems = files
+        .iter()
+        .map(|file| {
+            let stem = Path::new(file).file_stem().unwrap_or_default();
+            stem.to_string_lossy().into_owned()
+        })
+        .collect::<Vec<_>>();
+    // a suffix must not take the name of a later input
+    let mut taken = stems
+        .iter()
+        .map(|stem| stem.to_lowercase())
+        .collect::<HashSet<_>>();
+    let mut used = HashSet::new();
+    stems
+        .into_iter()
+        .map(|stem| {
+            if used.insert(stem.to_lowercase()) {
+                return stem;
+            }
+            (2..)
+                .map(|n| format!("{}_{}", stem, n))
+                .find(|name| taken.insert(name.to_lowercase()))
+                .expect("there is always an unused suffix")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempFile;
+
+    #[test]
+    fn test_read_file_list() {
+        let list = "a.ser\n\nb c.avi\r\n  \n/tmp/d.ser";
+        assert_eq!(
+            vec!["a.ser", "b c.avi", "/tmp/d.ser"],
+            read_file_list(list.as_bytes()).unwrap()
+        );
+        let file = TempFile::new(".txt", list.as_bytes());
+        assert_eq!(3, read_files_from(file.path()).unwrap().len());
+    }
+
+    #[test]
+    fn test_output_names() {
+        let files = [
+            "a/jupiter.ser",
+            "b/jupiter.ser",
+            "mars.avi",
+            "jupiter_2.ser",
+            "c/Jupiter.SER",
+        ]
+        .iter()
+        .map(|file| file.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["jupiter", "jupiter_3", "mars", "jupiter_2", "Jupiter_4"],
+            output_names(&files)
+        );
+    }
+
+    #[test]
+    fn test_expand_patterns() {
+        let dir = std::env::temp_dir().join(format!("avp-inputs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in &["b.ser", "a.ser", "c.avi", "d.SER"] {
+            File::create(dir.join(name)).unwrap();
+        }
+        let pattern = dir.join("*.ser").to_string_lossy().into_owned();
+        let files = expand_patterns(&[pattern, "literal.avi".to_string()]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(4, files.len());
+        assert!(files[0].ends_with("a.ser"));
+        assert!(files[1].ends_with("b.ser"));
+        assert!(files[2].ends_with("d.SER"));
+        assert_eq!("literal.avi", files[3]);
+    }
+
+    #[test]
+    fn test_expand_directories() {
+        let dir = std::env::temp_dir().join(format!("avp-dirs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in &["b.SER", "a.avi", "notes.txt"] {
+            File::create(dir.join(name)).unwrap();
+        }
+        let paths = vec!["x.ser".to_string(), dir.to_string_lossy().into_owned()];
+        let files = expand_directories(paths).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(3, files.len());
+        assert_eq!("x.ser", files[0]);
+        assert!(files[1].ends_with("a.avi"));
+        assert!(files[2].ends_with("b.SER"));
+    }
+
+    #[test]
+    fn test_expand_image_sequence() {
+        let dir = std::env::temp_dir().join(format!("avp-images-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in &["light_001.fits", "light_002.FIT", "notes.txt"] {
+            File::create(dir.join(name)).unwrap();
+        }
+        let path = dir.to_string_lossy().into_owned();
+        let files = expand_directories(vec![path.clone()]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec![path], files);
+    }
+}
@@ -0,0 +1,297 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Raw sample values of single pixels, for judging the exposure of a capture and whether
+//! bright features such as planetary limbs are saturated.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::codec::{mosaic, Channel};
+use crate::error::{PlayerError, Result};
+use crate::video_format::{check_uncompressed, samples, stored_row, Bayer, Video};
+
+/// Raw values around a pixel, before white balance or any display stretch is applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct PixelReadout {
+    pub x: u32,
+    pub y: u32,
+    /// The color and value of each sample: the single sample of mono images, the three
+    /// samples of color images, or the four samples of the 2x2 quad containing the pixel in
+    /// Bayer mosaics, in the order of the pattern
+    pub samples: Vec<(&'static str, u16)>,
+    /// Largest value a sample can have at the bit depth of the video
+    pub max_value: u16,
+}
+
+impl PixelReadout {
+    /// A sample value as a percentage of the largest possible value
+    pub fn percent(&self, value: u16) -> f64 {
+        value as f64 * 100.0 / self.max_value.max(1) as f64
+    }
+}
+
+impl fmt::Display for PixelReadout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "x {}, y {}:", self.x, self.y)?;
+        for (color, value) in &self.samples {
+            write!(f, "  {} {} ({:.1}%)", color, value, self.percent(*value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the raw samples of the pixel at `x`, `y` of a frame, in image coordinates from the
+/// top left. The values are those stored in the file, so 12-bit samples are between 0 and
+/// 4095.
+pub fn read_pixel(video: &dyn Video, frame_index: usize, x: u32, y: u32) -> Result<PixelReadout> {
+    PixelReader::new(video, frame_index)?.read(x, y)
+}
+
+/// Reads the raw samples of pixels in one frame, which is only fetched once
+pub struct PixelReader<'a> {
+    video: &'a dyn Video,
+    frame_index: usize,
+    bytes: Cow<'a, [u8]>,
+    bytes_per_sample: usize,
+    planes: usize,
+    /// Bytes in each row
+    row_size: usize,
+    max_value: u16,
+}
+
+impl<'a> PixelReader<'a> {
+    pub fn new(video: &'a dyn Video, frame_index: usize) -> Result<Self> {
+        check_uncompressed(video)?;
+        let bytes = video.read_frame(frame_index)?;
+        let bytes_per_sample = video.bytes_per_pixel().max(1) as usize;
+        let planes = video.bayer().planes();
+        let row_size = video.image_width() as usize * planes * bytes_per_sample;
+        let bits = match video.pixel_depth_bits() {
+            0 => 8 * bytes_per_sample as u32,
+            bits => bits.min(16),
+        };
+        Ok(Self {
+            video,
+            frame_index,
+            bytes,
+            bytes_per_sample,
+            planes,
+            row_size,
+            max_value: ((1_u32 << bits) - 1) as u16,
+        })
+    }
+
+    /// Read the samples of the pixel at `x`, `y`, in image coordinates from the top left
+    pub fn read(&self, x: u32, y: u32) -> Result<PixelReadout> {
+        let (width, height) = (self.video.image_width(), self.video.image_height());
+        if x >= width || y >= height {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Pixel {}, {} is outside the {}x{} image",
+                x, y, width, height
+            )));
+        }
+        let samples = match self.video.bayer() {
+            Bayer::Mono => vec![("Mono", self.sample(x, y, 0)?)],
+            Bayer::RGB => vec![
+                ("R", self.sample(x, y, 0)?),
+                ("G", self.sample(x, y, 1)?),
+                ("B", self.sample(x, y, 2)?),
+            ],
+            Bayer::BGR => vec![
+                ("R", self.sample(x, y, 2)?),
+                ("G", self.sample(x, y, 1)?),
+                ("B", self.sample(x, y, 0)?),
+            ],
+            bayer => {
+                let colors = mosaic(bayer)?;
+                // the quad is clamped to the image so that images with an odd size still have
+                // a full quad at their edges
+                let left = (x & !1).min(width.saturating_sub(2));
+                let top = (y & !1).min(height.saturating_sub(2));
+                let mut quad = Vec::with_capacity(4);
+                for (i, color) in colors.iter().enumerate() {
+                    let (dx, dy) = ((i % 2) as u32, (i / 2) as u32);
+                    quad.push((channel_name(*color), self.sample(left + dx, top + dy, 0)?));
+                }
+                quad
+            }
+        };
+        Ok(PixelReadout {
+            x,
+            y,
+            samples,
+            max_value: self.max_value,
+        })
+    }
+
+    /// One sample of the pixel at `x`, `y`, where `plane` is the index of the sample for
+    /// pixels with several samples
+    fn sample(&self, x: u32, y: u32, plane: usize) -> Result<u16> {
+        let offset = stored_row(self.video, y) as usize * self.row_size
+            + (x as usize * self.planes + plane) * self.bytes_per_sample;
+        self.bytes
+            .get(offset..offset + self.bytes_per_sample)
+            .and_then(|sample| samples(self.video, sample).next())
+            .ok_or_else(|| PlayerError::Decode(format!("Frame {} is too small", self.frame_index)))
+    }
+}
+
+fn channel_name(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Red => "R",
+        Channel::Green => "G",
+        Channel::Blue => "B",
+    }
+}
+
+/// Raw values of each color channel at points along a line across the image
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineProfile {
+    /// Name of each channel, such as `R`, with its value at each point. Channels with
+    /// several samples per pixel, such as the two green samples of a Bayer quad, are
+    /// averaged.
+    pub channels: Vec<(&'static str, Vec<f32>)>,
+    /// Largest value a sample can have at the bit depth of the video
+    pub max_value: u16,
+}
+
+impl LineProfile {
+    /// Number of points along the line
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, |(_, values)| values.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Sample a frame at points one pixel apart along the line from `start` to `end`, in image
+/// coordinates from the top left, using the same samples as `read_pixel`
+pub fn line_profile(
+    video: &dyn Video,
+    frame_index: usize,
+    start: (u32, u32),
+    end: (u32, u32),
+) -> Result<LineProfile> {
+    let reader = PixelReader::new(video, frame_index)?;
+    let (dx, dy) = (end.0 as f64 - start.0 as f64, end.1 as f64 - start.1 as f64);
+    let steps = dx.abs().max(dy.abs()).round() as usize;
+    let mut channels: Vec<(&'static str, Vec<f32>)> = vec![];
+    for step in 0..=steps {
+        let t = if steps == 0 {
+            0.0
+        } else {
+            step as f64 / steps as f64
+        };
+        let x = (start.0 as f64 + t * dx).round() as u32;
+        let y = (start.1 as f64 + t * dy).round() as u32;
+        let readout = reader.read(x, y)?;
+        for (name, value) in &readout.samples {
+            let index = match channels.iter().position(|(channel, _)| channel == name) {
+                Some(index) => index,
+                None => {
+                    channels.push((name, vec![]));
+                    channels.len() - 1
+                }
+            };
+            let count = readout
+                .samples
+                .iter()
+                .filter(|(other, _)| other == name)
+                .count();
+            let values = &mut channels[index].1;
+            if values.len() == step {
+                values.push(0.0);
+            }
+            values[step] += *value as f32 / count as f32;
+        }
+    }
+    Ok(LineProfile {
+        channels,
+        max_value: reader.max_value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{ser_format, ser_video};
+
+    #[test]
+    fn test_read_pixel() {
+        // 4x2 RGGB frame with 12-bit samples
+        let values = [4095_u16, 100, 200, 300, 400, 500, 600, 700];
+        let frame = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        let video = ser_video(&ser_format(4, 2, Bayer::RGGB, 12), &[frame]);
+        let readout = read_pixel(&video, 0, 1, 1).unwrap();
+        assert_eq!(
+            vec![("R", 4095), ("G", 100), ("G", 400), ("B", 500)],
+            readout.samples
+        );
+        assert_eq!(4095, readout.max_value);
+        assert_eq!(
+            "x 1, y 1:  R 4095 (100.0%)  G 100 (2.4%)  G 400 (9.8%)  B 500 (12.2%)",
+            readout.to_string()
+        );
+        let readout = read_pixel(&video, 0, 2, 0).unwrap();
+        assert_eq!(("R", 200), readout.samples[0]);
+        assert!(read_pixel(&video, 0, 4, 0).is_err());
+        assert!(read_pixel(&video, 1, 0, 0).is_err());
+
+        let video = ser_video(&ser_format(2, 1, Bayer::BGR, 8), &[vec![1, 2, 3, 4, 5, 6]]);
+        assert_eq!(
+            vec![("R", 6), ("G", 5), ("B", 4)],
+            read_pixel(&video, 0, 1, 0).unwrap().samples
+        );
+        let video = ser_video(&ser_format(2, 1, Bayer::Mono, 8), &[vec![10, 255]]);
+        let readout = read_pixel(&video, 0, 1, 0).unwrap();
+        assert_eq!(vec![("Mono", 255)], readout.samples);
+        assert!((readout.percent(255) - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_line_profile() {
+        // 4x2 GRBG frame
+        let frame = vec![10, 20, 30, 40, 50, 60, 70, 80];
+        let video = ser_video(&ser_format(4, 2, Bayer::GRBG, 8), &[frame]);
+        let profile = line_profile(&video, 0, (0, 0), (3, 1)).unwrap();
+        assert_eq!(4, profile.len());
+        assert_eq!(255, profile.max_value);
+        // the first two points are in the left quad and the last two in the right quad
+        assert_eq!(
+            vec![
+                ("G", vec![35.0, 35.0, 55.0, 55.0]),
+                ("R", vec![20.0, 20.0, 40.0, 40.0]),
+                ("B", vec![50.0, 50.0, 70.0, 70.0]),
+            ],
+            profile.channels
+        );
+        let profile = line_profile(&video, 0, (1, 1), (1, 1)).unwrap();
+        assert_eq!(1, profile.len());
+        assert!(line_profile(&video, 0, (0, 0), (4, 0)).is_err());
+    }
+}
@@ -20,7 +20,107 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+//! Decoding of astrophotography videos in SER and AVI format, along with a video player
+//! built on top of the decoders.
+//!
+//! Files are opened with `open_video`, which returns a `Video` providing the raw frames and
+//! the metadata needed to decode them. Frames are decoded into images with an `ImageCodec`.
+//!
+//! ```no_run
+//! use astro_video_player::{codecs, open_video};
+//!
+//! let video = open_video("capture.ser").unwrap();
+//! let codec = codecs::codec_for(video.as_ref()).unwrap();
+//! let (width, height, bgra) = codec.decode(video.as_ref(), 0).unwrap();
+//! ```
+//!
+//! The player UI is behind the `gui` feature, which is enabled by default. Tools that only
+//! need the decoders can avoid the UI dependencies with:
+//!
+//! ```toml
+//! astro-video-player = { version = "0.1", default-features = false }
+//! ```
+
+pub mod align;
+pub mod analysis;
+pub mod animation;
+pub mod annotations;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod avi;
+pub mod blink;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cache;
+pub mod capabilities;
 pub mod codec;
+pub mod config;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod directory;
+pub mod error;
+pub mod export;
+#[cfg(all(feature = "ffi", not(target_arch = "wasm32")))]
+pub mod ffi;
+#[cfg(all(feature = "gui", not(target_arch = "wasm32")))]
+mod file_dialog;
+pub mod filmstrip;
+pub mod fits;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod follow;
+pub mod gaps;
+#[cfg(all(feature = "gpu", not(target_arch = "wasm32")))]
+pub mod gpu;
+pub mod history;
+#[cfg(all(feature = "indi", not(target_arch = "wasm32")))]
+pub mod indi;
+pub mod inputs;
+pub mod inspect;
+pub mod live;
+pub mod locale;
+pub mod marks;
+pub mod orientation;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pipeline;
+pub mod playback;
+pub mod playlist;
+pub mod probe;
+pub mod processing;
+#[cfg(feature = "python")]
+mod python;
+pub mod quality;
+pub mod rejection;
+pub mod seeing;
+pub mod ser;
+pub mod sidecar;
+pub mod stack;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod stream;
+#[cfg(test)]
+mod testutil;
+#[cfg(all(feature = "gui", not(target_arch = "wasm32")))]
+mod theme;
+pub mod tiles;
+#[cfg(all(feature = "gui", not(target_arch = "wasm32")))]
 pub mod ui;
+#[cfg(all(feature = "live", target_os = "linux"))]
+pub mod v4l2;
 pub mod video_format;
+#[cfg(all(feature = "gui", not(target_arch = "wasm32")))]
+mod viewport;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use avi::AviFile;
+pub use codec::ImageCodec;
+pub use error::{PlayerError, Result};
+#[cfg(not(target_arch = "wasm32"))]
+pub use video_format::{open_video, open_video_with};
+pub use video_format::{MemoryVideo, OpenOptions, Video};
+
+/// Codecs for converting raw frames into BGRA images
+pub mod codecs {
+    pub use crate::codec::{
+        codec_for, codec_named, parse_binning, parse_codec_quality, BilinearDebayerCodec,
+        BinningCodec, CodecFactory, CodecQuality, CodecRegistry, CodecRule, DebayerCodec,
+        MhcDebayerCodec, MonoCodec, RgbCodec, BINNING_NAMES, CODEC_NAMES, CODEC_QUALITY_NAMES,
+    };
+}
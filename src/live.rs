@@ -0,0 +1,314 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Videos whose frames arrive while they are being shown, such as the stream from a camera
+//! that is used for focusing or framing.
+//!
+//! `live_video` creates a `LiveVideo`, which is shown like any other video, along with a
+//! `LiveFeed` that a capture thread pushes frames into. Only the most recent frames are
+//! kept, so the frame count keeps growing while older frames can no longer be read.
+//!
+//! Videos that grow while they are shown return a `FrameWatch` from `Video::frame_watch`,
+//! which wakes the player when frames are added rather than having it poll the frame count.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{lock, PlayerError, Result};
+use crate::ser::SerFormat;
+use crate::video_format::{Bayer, Endianness, Video};
+
+/// Memory used for the recent frames of a live video when no other limit is given
+pub const DEFAULT_LIVE_MEMORY: usize = 256 << 20;
+
+/// Notifies waiting threads when frames are added to a video that is still growing, such as
+/// a camera stream or a file that is still being captured
+#[derive(Clone, Default)]
+pub struct FrameWatch {
+    /// The latest frame count and the condition that is signalled when it changes
+    state: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl FrameWatch {
+    // waiting on the condition variable needs a mutex rather than an atomic count
+    #[allow(clippy::mutex_atomic)]
+    pub fn new(frame_count: usize) -> Self {
+        Self {
+            state: Arc::new((Mutex::new(frame_count), Condvar::new())),
+        }
+    }
+
+    /// Record that the video now has `frame_count` frames, waking any waiting threads
+    pub fn notify(&self, frame_count: usize) {
+        let (count, changed) = &*self.state;
+        *lock(count) = frame_count;
+        changed.notify_all();
+    }
+
+    /// Wait until the video has more than `frame_count` frames or `timeout` has passed,
+    /// returning the latest frame count
+    pub fn wait(&self, frame_count: usize, timeout: Duration) -> usize {
+        let (count, changed) = &*self.state;
+        let count = lock(count);
+        let (count, _) = changed
+            .wait_timeout_while(count, timeout, |count| *count <= frame_count)
+            .unwrap_or_else(PoisonError::into_inner);
+        *count
+    }
+}
+
+struct LiveFrames {
+    /// Index of the first frame in `frames`
+    first: usize,
+    frames: VecDeque<(Vec<u8>, Option<SystemTime>)>,
+}
+
+struct LiveShared {
+    format: SerFormat,
+    /// Number of recent frames to keep
+    capacity: usize,
+    frames: Mutex<LiveFrames>,
+    /// Set when the video is dropped, so that the producer can stop capturing
+    closed: AtomicBool,
+    watch: FrameWatch,
+}
+
+/// Whether a path names a camera rather than a file, such as `/dev/video0` or a camera on an
+/// INDI server
+pub fn is_camera_device(path: &str) -> bool {
+    path.starts_with("/dev/video") || is_indi_url(path)
+}
+
+/// Whether a path is the `indi://host[:port][/device]` address of a camera on an INDI server
+pub fn is_indi_url(path: &str) -> bool {
+    path.starts_with("indi://")
+}
+
+/// A video that frames are added to by a `LiveFeed` while it is being shown. Frames are
+/// numbered from the first frame that was pushed, and only the most recent frames are kept.
+pub struct LiveVideo {
+    shared: Arc<LiveShared>,
+    header_fields: Vec<(String, String)>,
+}
+
+/// Handle for adding frames to a `LiveVideo`, usually from a capture thread
+#[derive(Clone)]
+pub struct LiveFeed {
+    shared: Arc<LiveShared>,
+}
+
+/// Create a live video of frames in the given format along with the feed that adds frames
+/// to it. The most recent frames that fit in `memory` bytes are kept, and at least two.
+pub fn live_video(format: SerFormat, memory: usize) -> (LiveVideo, LiveFeed) {
+    let capacity = (memory / format.frame_size().max(1)).max(2);
+    let shared = Arc::new(LiveShared {
+        format,
+        capacity,
+        frames: Mutex::new(LiveFrames {
+            first: 0,
+            frames: VecDeque::new(),
+        }),
+        closed: AtomicBool::new(false),
+        watch: FrameWatch::default(),
+    });
+    let video = LiveVideo {
+        shared: shared.clone(),
+        header_fields: vec![],
+    };
+    (video, LiveFeed { shared })
+}
+
+impl LiveVideo {
+    /// Set the values reported by `Video::header_fields`, such as the name of the camera
+    pub fn with_header_fields(mut self, header_fields: Vec<(String, String)>) -> Self {
+        self.header_fields = header_fields;
+        self
+    }
+
+    /// Number of recent frames that are kept
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Index of the oldest frame that can still be read
+    pub fn oldest_frame(&self) -> usize {
+        lock(&self.shared.frames).first
+    }
+}
+
+impl Drop for LiveVideo {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+impl LiveFeed {
+    /// Add a frame, which must be `format.frame_size()` bytes long, discarding the oldest
+    /// frame if the video is full
+    pub fn push_frame(&self, frame: Vec<u8>, time: Option<SystemTime>) -> Result<()> {
+        let format = &self.shared.format;
+        if frame.len() != format.frame_size() {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Live frame is {} bytes but {}x{} {:?} frames with {}-bit samples are {} bytes",
+                frame.len(),
+                format.width,
+                format.height,
+                format.bayer,
+                format.pixel_depth_bits,
+                format.frame_size()
+            )));
+        }
+        let mut frames = lock(&self.shared.frames);
+        if frames.frames.len() == self.shared.capacity {
+            frames.frames.pop_front();
+            frames.first += 1;
+        }
+        frames.frames.push_back((frame, time));
+        self.shared.watch.notify(frames.first + frames.frames.len());
+        Ok(())
+    }
+
+    /// Whether the video has been dropped, after which the producer should stop
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Video for LiveVideo {
+    fn image_width(&self) -> u32 {
+        self.shared.format.width
+    }
+
+    fn image_height(&self) -> u32 {
+        self.shared.format.height
+    }
+
+    fn frame_count(&self) -> usize {
+        let frames = lock(&self.shared.frames);
+        frames.first + frames.frames.len()
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        if self.shared.format.pixel_depth_bits > 8 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.shared.format.pixel_depth_bits
+    }
+
+    fn bayer(&self) -> &Bayer {
+        &self.shared.format.bayer
+    }
+
+    fn endianness(&self) -> &Endianness {
+        &self.shared.format.endianness
+    }
+
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        // frames are copied out of the video, because the feed may discard them at any time
+        let frames = lock(&self.shared.frames);
+        match index
+            .checked_sub(frames.first)
+            .and_then(|position| frames.frames.get(position))
+        {
+            Some((frame, _)) => Ok(Cow::Owned(frame.clone())),
+            None if index < frames.first => Err(PlayerError::Decode(format!(
+                "Frame {} is no longer held, only the last {} frames of live videos are kept",
+                index, self.shared.capacity
+            ))),
+            None => Err(PlayerError::Decode(format!(
+                "Invalid frame index {}",
+                index
+            ))),
+        }
+    }
+
+    fn frame_timestamp(&self, index: usize) -> Option<SystemTime> {
+        let frames = lock(&self.shared.frames);
+        let position = index.checked_sub(frames.first)?;
+        frames.frames.get(position).and_then(|(_, time)| *time)
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.header_fields.clone()
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn frame_watch(&self) -> Option<FrameWatch> {
+        Some(self.shared.watch.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::ser_format;
+
+    #[test]
+    fn test_live_video() {
+        let (video, feed) = live_video(ser_format(2, 1, Bayer::Mono, 8), 5);
+        assert_eq!(2, video.capacity());
+        assert_eq!(0, video.frame_count());
+        for i in 0..3 {
+            feed.push_frame(vec![i, i], None).unwrap();
+        }
+        assert!(feed.push_frame(vec![0; 3], None).is_err());
+        assert_eq!(3, video.frame_count());
+        assert_eq!(1, video.oldest_frame());
+        assert!(video.read_frame(0).is_err());
+        assert_eq!(&[2, 2], &*video.read_frame(2).unwrap());
+        assert!(video.read_frame(3).is_err());
+        assert!(video.is_live());
+        assert!(!feed.is_closed());
+        drop(video);
+        assert!(feed.is_closed());
+    }
+
+    #[test]
+    fn test_frame_watch() {
+        let (video, feed) = live_video(ser_format(2, 1, Bayer::Mono, 8), 64);
+        let watch = video.frame_watch().unwrap();
+        // times out without new frames
+        assert_eq!(0, watch.wait(0, Duration::from_millis(1)));
+        let producer = std::thread::spawn(move || {
+            for i in 0..3 {
+                feed.push_frame(vec![i, i], None).unwrap();
+            }
+        });
+        let mut count = 0;
+        while count < 3 {
+            count = watch.wait(count, Duration::from_secs(10));
+        }
+        producer.join().unwrap();
+        assert_eq!(3, video.frame_count());
+    }
+}
@@ -0,0 +1,267 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Translations of the player UI. Messages are looked up by id in catalogs written in a
+//! subset of the [Fluent](https://projectfluent.org) syntax: `id = text` lines, `#` comments,
+//! indented continuation lines and `{ $name }` placeables for arguments. The English catalog
+//! is built into the player, and catalogs for other languages are read at runtime from
+//! `{language}.ftl` files so that they can be added without rebuilding. Messages missing from
+//! a catalog are shown in English. The names of drop-down choices are the same keywords that
+//! the configuration file and command line accept, and are not translated.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{PlayerError, Result};
+
+/// Language of the built-in catalog
+pub const ENGLISH: &str = "en";
+
+const ENGLISH_CATALOG: &str = include_str!("locales/en.ftl");
+
+/// Messages of one language, keyed by id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut messages: HashMap<String, String> = HashMap::new();
+        let mut current: Option<String> = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with(char::is_whitespace) {
+                // continuation of a multiline message
+                let value = current
+                    .as_ref()
+                    .and_then(|id| messages.get_mut(id))
+                    .ok_or_else(|| {
+                        PlayerError::Format(format!(
+                            "line {}: text without a message id",
+                            number + 1
+                        ))
+                    })?;
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line.trim_start());
+                continue;
+            }
+            let (id, value) = line
+                .split_once('=')
+                .map(|(id, value)| (id.trim(), value.trim()))
+                .filter(|(id, _)| is_message_id(id))
+                .ok_or_else(|| {
+                    PlayerError::Format(format!("line {}: expected `id = text`", number + 1))
+                })?;
+            messages.insert(id.to_string(), value.to_string());
+            current = Some(id.to_string());
+        }
+        Ok(Self { messages })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| PlayerError::Format(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&str> {
+        self.messages.get(id).map(String::as_str)
+    }
+}
+
+/// Message ids start with a letter followed by letters, digits, `-` or `_`
+fn is_message_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Translates UI messages into the selected language
+#[derive(Debug, Clone)]
+pub struct Locale {
+    language: String,
+    catalog: Catalog,
+    english: Catalog,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::english()
+    }
+}
+
+impl Locale {
+    pub fn english() -> Self {
+        let english = Catalog::parse(ENGLISH_CATALOG).expect("built-in English catalog");
+        Self {
+            language: ENGLISH.to_string(),
+            catalog: english.clone(),
+            english,
+        }
+    }
+
+    /// Load the catalog of a language from `{language}.ftl` in a directory, unless it is the
+    /// built-in English catalog
+    pub fn load(language: &str, dir: &Path) -> Result<Self> {
+        let mut locale = Self::english();
+        if language != ENGLISH {
+            locale.catalog = Catalog::load(&dir.join(format!("{}.ftl", language)))?;
+            locale.language = language.to_string();
+        }
+        Ok(locale)
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    /// The message with an id, falling back to English and then to the id itself
+    pub fn text(&self, id: &str) -> String {
+        self.catalog
+            .get(id)
+            .or_else(|| self.english.get(id))
+            .unwrap_or(id)
+            .to_string()
+    }
+
+    /// The name of a header field, which the file formats only give in English, from the
+    /// message `info-{name}` with the words of the name in lowercase joined by `-`, such as
+    /// `info-capture-start-utc` for "Capture start (UTC)". Names without a message, such as
+    /// FITS keywords, are shown as they are.
+    pub fn field_name(&self, name: &str) -> String {
+        let words: Vec<String> = name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_ascii_lowercase())
+            .collect();
+        let id = format!("info-{}", words.join("-"));
+        self.catalog
+            .get(&id)
+            .or_else(|| self.english.get(&id))
+            .unwrap_or(name)
+            .to_string()
+    }
+
+    /// The message with an id, with each `{ $name }` placeable replaced by its argument.
+    /// Placeables without an argument are left as they are.
+    pub fn format(&self, id: &str, args: &[(&str, &dyn Display)]) -> String {
+        let text = self.text(id);
+        let mut formatted = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find('{') {
+            formatted.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            let placeable = &rest[start..=end];
+            let name = placeable[1..placeable.len() - 1].trim();
+            match name
+                .strip_prefix('$')
+                .and_then(|name| args.iter().find(|(arg, _)| *arg == name))
+            {
+                Some((_, value)) => formatted.push_str(&value.to_string()),
+                None => formatted.push_str(placeable),
+            }
+            rest = &rest[end + 1..];
+        }
+        formatted.push_str(rest);
+        formatted
+    }
+}
+
+/// Languages that can be selected: English and those with a catalog in a directory, sorted
+pub fn languages(dir: Option<&Path>) -> Vec<String> {
+    let mut languages: Vec<String> = dir
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ftl"))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .filter(|language| language != ENGLISH)
+        .collect();
+    languages.push(ENGLISH.to_string());
+    languages.sort();
+    languages
+}
+
+/// Directory of the catalogs of other languages, next to the configuration file
+pub fn locales_dir(config_path: &Path) -> Option<PathBuf> {
+    config_path.parent().map(|dir| dir.join("locales"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_catalog() {
+        let catalog =
+            Catalog::parse("# comment\nopen = Öffnen\n\nhelp = Erste Zeile\n    zweite Zeile\n")
+                .unwrap();
+        assert_eq!(Some("Öffnen"), catalog.get("open"));
+        assert_eq!(Some("Erste Zeile\nzweite Zeile"), catalog.get("help"));
+        assert!(Catalog::parse("no message id").is_err());
+        assert!(Catalog::parse("  orphan text").is_err());
+    }
+
+    #[test]
+    fn test_translate_with_fallback() {
+        let dir = std::env::temp_dir().join(format!("avp-locale-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("de.ftl"),
+            "open = Öffnen\nsaved-frames = { $count } Bilder in { $path } gespeichert\n\
+             info-capture-start-utc = Aufnahmebeginn (UTC)\n",
+        )
+        .unwrap();
+        assert_eq!(vec!["de", "en"], languages(Some(&dir)));
+        let locale = Locale::load("de", &dir).unwrap();
+        assert_eq!("Öffnen", locale.text("open"));
+        assert_eq!("Dismiss", locale.text("dismiss"));
+        assert_eq!("missing-id", locale.text("missing-id"));
+        assert_eq!(
+            "3 Bilder in a.ser gespeichert",
+            locale.format("saved-frames", &[("count", &3), ("path", &"a.ser")])
+        );
+        assert_eq!(
+            "Saved { $path }",
+            Locale::english().format("saved", &[("count", &1)])
+        );
+        assert_eq!(
+            "Aufnahmebeginn (UTC)",
+            locale.field_name("Capture start (UTC)")
+        );
+        assert_eq!("Telescope", locale.field_name("Telescope"));
+        assert_eq!("OBJECT", locale.field_name("OBJECT"));
+        assert!(Locale::load("fr", &dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+# English messages of the player UI. Translations are files with the same ids named after
+# their language, such as `de.ftl`, in the `locales` directory next to the configuration
+# file. Messages missing from a translation are shown in English.
+
+## Image view
+open-prompt = Open a file or drop one on the window
+no-frame = No frame to show
+decoding = Decoding...
+
+## File
+file = File
+file-path-placeholder = Path to a SER, AVI or FITS file
+open = Open
+choose-file = Open...
+error = Error: { $error }
+dismiss = Dismiss
+files = Files ({ $count })
+previous-file = << File
+next-file = File >>
+
+## Playback
+play = Play
+pause = Pause
+follow-newest = Follow newest
+live-stack = Live stack
+stacked-count = { $count } stacked
+reset = Reset
+fps = { $fps } fps
+marker-a = A
+marker-b = B
+marker-range = A-B: { $from } to { $to }
+clear-markers = Clear A-B
+frame-position = Frame { $frame } of { $count }
+excluded = excluded
+mark-good = good
+mark-bad = bad
+sharpness-rank = Sharpness rank { $rank } of { $count }
+include = Include
+exclude = Exclude
+undo = Undo
+redo = Redo
+
+## Saving and panels
+save-frame = Save Frame
+save-clip = Save Clip
+save-animation = Save Animation
+histogram = Histogram
+filmstrip = Filmstrip
+info = Info
+attach-panels = Attach Panels
+detach-panels = Detach Panels
+line-profile = Line Profile
+cancel-line = Cancel Line
+fit = Fit
+actual-size = 1:1
+center = Center
+analysis-title = Analysis (drag to move)
+clipped = Clipped: R { $red }% G { $green }% B { $blue }%
+profile-title = Line profile
+profile-length = Line profile ({ $length } px)
+clear = Clear
+export = Export
+seeing-fwhm = FWHM { $fwhm } px (median { $median } px)
+seeing-median = FWHM median { $median } px
+no-stars = No stars found
+info-file = File: { $path }
+
+## File information
+info-width = Width
+info-height = Height
+info-frames = Frames
+info-bytes-per-pixel = Bytes per pixel
+info-pixel-depth = Pixel depth
+info-bits = { $bits } bits
+info-bayer = Bayer
+info-endianness = Endianness
+info-compression = Compression
+info-frame-rate = Frame rate
+info-capture-start = Capture start
+info-capture-gaps = Capture gaps
+info-gaps = { $count }, about { $dropped } frames dropped
+info-gap-after = Gap after frame { $frame }
+info-gap = { $seconds } s, about { $dropped } frames dropped
+
+# Names of header fields, with ids made from the English names, see `Locale::field_name`
+info-ser-color-id = SER color ID
+info-ser-luid = SER LuID
+info-observer = Observer
+info-instrument = Instrument
+info-telescope = Telescope
+info-capture-start-local = Capture start (local)
+info-capture-start-utc = Capture start (UTC)
+info-microseconds-per-frame = Microseconds per frame
+info-max-bytes-per-second = Max bytes per second
+info-main-header-flags = Main header flags
+info-main-header-frames = Main header frames
+info-streams = Streams
+info-suggested-buffer-size = Suggested buffer size
+info-stream-handler = Stream handler
+info-stream-rate = Stream rate
+info-stream-start = Stream start
+info-stream-length = Stream length
+info-stream-quality = Stream quality
+info-dib-compression = DIB compression
+info-dib-bit-count = DIB bit count
+info-dib-height = DIB height
+info-dib-image-size = DIB image size
+info-pixels-per-meter = Pixels per meter
+info-colors-used = Colors used
+info-directory = Directory
+info-first-file = First file
+info-last-file = Last file
+info-camera = Camera
+info-driver = Driver
+info-bus = Bus
+info-pixel-format = Pixel format
+info-indi-server = INDI server
+
+## Format
+bayer = Bayer
+bit-depth = Bit depth
+endianness = Endianness
+codec = Codec
+preview = Preview
+compare-with = Compare with
+select-region = Select Region
+cancel-selection = Cancel Selection
+full-frame = Full Frame
+crop-exports = Crop saved frames
+
+## Frame quality
+score-frames = Score Frames
+scoring = Scoring...
+export-ranking = Export Ranking
+measure-seeing = Measure Seeing
+measuring = Measuring...
+export-marks = Export Marks
+stack = Stack
+stacking = Stacking...
+order = Order
+align = Align
+
+## Blinking
+blink-frame = Blink Frame
+blink-with-file = Blink With File
+blink-rate = Rate
+per-second = per second
+blink-reference-file = Reference: frame { $frame } of { $file }
+blink-reference = Reference: frame { $frame }
+no-reference = No reference frame
+blink-current = Current: frame { $frame }
+blink-offset = Offset { $dx }, { $dy }
+reset-offset = Reset Offset
+stop-blink = Stop Blink
+
+## Display
+black = Black
+white = White
+gamma = Gamma { $gamma }
+auto-stretch = Auto Stretch
+colors = Colors
+red-gain = Red { $gain }
+green-gain = Green { $gain }
+blue-gain = Blue { $gain }
+auto-white-balance = Auto White Balance
+
+## Overlays and annotations
+scale-bar = Scale bar
+pixel-scale = Arcsec/pixel
+rotation = Rotation { $degrees }°
+mirrored = Mirrored
+annotate = Annotate
+done = Done
+label-placeholder = Label
+clear-frame = Clear Frame
+burn-annotations = Burn into PNGs
+
+## Status
+open-failed = Could not open { $path }: { $error }
+saved = Saved { $path }
+saved-frames = Saved { $count } frames to { $path }
+saving-frames = Saving { $count } frames...
+save-frame-failed = Could not save frame: { $error }
+save-clip-failed = Could not save clip: { $error }
+save-animation-failed = Could not save animation: { $error }
+stacking-frames = Stacking { $count } frames...
+stacked-frames = Stacked { $count } frames into { $path }
+stack-failed = Could not stack frames: { $error }
+stack-frame-failed = Could not stack the frame: { $error }
+scoring-frames = Scoring frames...
+scored-frames = Scored { $count } frames
+score-failed = Could not score frames: { $error }
+save-ranking-failed = Could not save ranking: { $error }
+save-marks-failed = Could not save marks: { $error }
+measuring-seeing = Measuring seeing...
+seeing-measured = Measured a star in { $measured } of { $count } frames
+seeing-failed = Could not measure seeing: { $error }
+save-seeing-failed = Could not save seeing: { $error }
+load-annotations-failed = Could not load annotations: { $error }
+save-annotations-failed = Could not save annotations: { $error }
+compare-failed = Could not compare codecs: { $error }
+decode-with-failed = Could not decode with { $codec }: { $error }
+reference-decode-failed = Could not decode reference frame: { $error }
+language-failed = Could not load language { $language }: { $error }
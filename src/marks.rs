@@ -0,0 +1,102 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Frames marked as good or bad while reviewing a video, and the export of the marked frame
+//! list for stacking software and scripts
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::sidecar::sidecar_path;
+
+/// Verdict on a single frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    Good,
+    Bad,
+}
+
+impl Mark {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mark::Good => "good",
+            Mark::Bad => "bad",
+        }
+    }
+}
+
+/// Mark a frame, or remove the mark if the frame already has the same one
+pub fn toggle_mark(marks: &mut BTreeMap<usize, Mark>, index: usize, mark: Mark) {
+    if marks.get(&index) == Some(&mark) {
+        marks.remove(&index);
+    } else {
+        marks.insert(index, mark);
+    }
+}
+
+/// File that the marked frames of a video are exported to, which is named after the video and
+/// written next to it
+pub fn marks_path(video_path: Option<&Path>) -> PathBuf {
+    sidecar_path(video_path, "_marks.csv")
+}
+
+/// Write marked frames as CSV with `frame` and `mark` columns, in frame order. Frame indices
+/// start at zero, as in the other exported files.
+pub fn write_marks_csv(marks: &BTreeMap<usize, Mark>, out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "frame,mark")?;
+    for (index, mark) in marks {
+        writeln!(out, "{},{}", index, mark.name())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marks() {
+        let mut marks = BTreeMap::new();
+        toggle_mark(&mut marks, 7, Mark::Good);
+        toggle_mark(&mut marks, 2, Mark::Bad);
+        toggle_mark(&mut marks, 4, Mark::Good);
+        // marking again removes the mark, a different mark replaces it
+        toggle_mark(&mut marks, 4, Mark::Good);
+        toggle_mark(&mut marks, 2, Mark::Good);
+
+        let mut csv = vec![];
+        write_marks_csv(&marks, &mut csv).unwrap();
+        assert_eq!(
+            "frame,mark\n2,good\n7,good\n",
+            String::from_utf8(csv).unwrap()
+        );
+
+        assert_eq!(
+            Path::new("/data/jupiter_marks.csv"),
+            marks_path(Some(Path::new("/data/jupiter.ser")))
+        );
+        assert_eq!(Path::new("video_marks.csv"), marks_path(None));
+    }
+}
@@ -0,0 +1,152 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Image scale and orientation on the sky, for drawing a scale bar and compass over frames
+//! so that the size and position of planetary features and double stars can be judged
+
+/// Lengths that a scale bar can show, in arcseconds, from 0.1" to 10°
+const BAR_LENGTHS: &[f64] = &[
+    0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0,
+    3600.0, 7200.0, 18000.0, 36000.0,
+];
+
+/// Which way the sky is turned in the frame. With no rotation north is up and, unless the
+/// image is mirrored such as by a star diagonal, east is to the left.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Orientation {
+    /// Angle of north from the top of the frame, in degrees counterclockwise
+    pub rotation: f64,
+    /// Whether the frame is mirrored, which puts east to the right of north
+    pub mirrored: bool,
+}
+
+impl Orientation {
+    /// Unit vector towards north in image coordinates, where y increases downwards
+    pub fn north(&self) -> (f64, f64) {
+        let angle = self.rotation.to_radians();
+        (-angle.sin(), -angle.cos())
+    }
+
+    /// Unit vector towards east in image coordinates, where y increases downwards
+    pub fn east(&self) -> (f64, f64) {
+        let (x, y) = self.north();
+        // a quarter turn counterclockwise on screen, or clockwise when mirrored
+        if self.mirrored {
+            (-y, x)
+        } else {
+            (y, -x)
+        }
+    }
+}
+
+/// Length and label of a scale bar
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScaleBar {
+    /// Length in image pixels
+    pub pixels: f64,
+    pub label: String,
+}
+
+/// Longest scale bar of a round length that is no longer than `max_pixels`, for an image
+/// scale in arcseconds per pixel. Returns `None` if even the shortest bar does not fit.
+pub fn scale_bar(arcsec_per_pixel: f64, max_pixels: f64) -> Option<ScaleBar> {
+    if arcsec_per_pixel <= 0.0 || !arcsec_per_pixel.is_finite() {
+        return None;
+    }
+    BAR_LENGTHS
+        .iter()
+        .rev()
+        .map(|&arcsec| (arcsec, arcsec / arcsec_per_pixel))
+        .find(|(_, pixels)| *pixels <= max_pixels)
+        .map(|(arcsec, pixels)| ScaleBar {
+            pixels,
+            label: angle_label(arcsec),
+        })
+}
+
+/// Angle in degrees, arcminutes or arcseconds, whichever gives a whole number
+fn angle_label(arcsec: f64) -> String {
+    if arcsec >= 3600.0 {
+        format!("{}°", arcsec / 3600.0)
+    } else if arcsec >= 60.0 {
+        format!("{}'", arcsec / 60.0)
+    } else {
+        format!("{}\"", arcsec)
+    }
+}
+
+/// Parse an image scale in arcseconds per pixel, which must be greater than zero
+pub fn parse_pixel_scale(text: &str) -> Option<f64> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|scale| *scale > 0.0 && scale.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near((x, y): (f64, f64), (expected_x, expected_y): (f64, f64)) {
+        assert!(
+            (x - expected_x).abs() < 1e-9 && (y - expected_y).abs() < 1e-9,
+            "({}, {}) != ({}, {})",
+            x,
+            y,
+            expected_x,
+            expected_y
+        );
+    }
+
+    #[test]
+    fn test_orientation() {
+        let mut orientation = Orientation::default();
+        assert_near((0.0, -1.0), orientation.north());
+        assert_near((-1.0, 0.0), orientation.east());
+        orientation.mirrored = true;
+        assert_near((1.0, 0.0), orientation.east());
+        // north turned a quarter counterclockwise points left, with east down
+        orientation = Orientation {
+            rotation: 90.0,
+            mirrored: false,
+        };
+        assert_near((-1.0, 0.0), orientation.north());
+        assert_near((0.0, 1.0), orientation.east());
+    }
+
+    #[test]
+    fn test_scale_bar() {
+        // a planetary scale of 0.1"/px fits a 10" bar in 150 pixels
+        let bar = scale_bar(0.1, 150.0).unwrap();
+        assert!((bar.pixels - 100.0).abs() < 1e-9);
+        assert_eq!("10\"", bar.label);
+        assert_eq!("5'", scale_bar(2.0, 200.0).unwrap().label);
+        assert_eq!("1°", scale_bar(30.0, 150.0).unwrap().label);
+        assert_eq!("0.5\"", scale_bar(0.01, 60.0).unwrap().label);
+        assert_eq!(None, scale_bar(0.001, 50.0));
+        assert_eq!(None, scale_bar(0.0, 50.0));
+
+        assert_eq!(Some(0.38), parse_pixel_scale(" 0.38 "));
+        assert_eq!(None, parse_pixel_scale("-1"));
+        assert_eq!(None, parse_pixel_scale("fast"));
+    }
+}
@@ -0,0 +1,434 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Headless batch processing described by a TOML file, for example:
+//!
+//! ```toml
+//! input = "jupiter.ser"
+//!
+//! [[steps]]
+//! type = "calibrate"
+//! dark = "dark.ser"
+//!
+//! [[steps]]
+//! type = "select"
+//! start = 100
+//! end = 500
+//!
+//! [[steps]]
+//! type = "debayer"
+//!
+//! [[steps]]
+//! type = "crop"
+//! x = 1800
+//! y = 1200
+//! width = 600
+//! height = 600
+//!
+//! [[steps]]
+//! type = "stretch"
+//! black = 10
+//! white = 200
+//!
+//! [[steps]]
+//! type = "output"
+//! directory = "frames"
+//! ```
+//!
+//! Steps that operate on raw frames (`calibrate`, `select`) must come before `debayer` and
+//! steps that operate on decoded images (`crop`, `stretch`, `output`) must come after it.
+//! Relative paths are resolved against the directory containing the pipeline file.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::codec::codec_for;
+use crate::error::{PlayerError, Result};
+use crate::export::save_png;
+use crate::processing::{crop, stretch, DecodedFrame};
+use crate::video_format::{open_video, samples, write_samples, FrameView, Video};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    /// Video to process
+    pub input: PathBuf,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Step {
+    /// Subtract a master dark, averaged from all frames of another video, from each frame
+    Calibrate { dark: PathBuf },
+    /// Only process frames from `start` up to (but not including) `end`, taking every
+    /// `every`th frame
+    Select {
+        #[serde(default)]
+        start: usize,
+        end: Option<usize>,
+        #[serde(default = "default_every")]
+        every: usize,
+    },
+    /// Decode raw frames using the codec for the video
+    Debayer,
+    /// Crop the decoded image
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Linear stretch of the decoded image
+    Stretch { black: u8, white: u8 },
+    /// Write each image to `directory` as a numbered PNG file
+    Output {
+        directory: PathBuf,
+        #[serde(default = "default_prefix")]
+        prefix: String,
+    },
+}
+
+fn default_every() -> usize {
+    1
+}
+
+fn default_prefix() -> String {
+    "frame".to_string()
+}
+
+impl Step {
+    /// Determine whether this step operates on raw frames rather than decoded images
+    fn is_raw(&self) -> bool {
+        matches!(self, Step::Calibrate { .. } | Step::Select { .. })
+    }
+}
+
+impl Pipeline {
+    /// Load a pipeline from a TOML file, resolving relative paths against the directory
+    /// containing the file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut pipeline = Self::parse(&text)
+            .map_err(|e| PlayerError::Format(format!("{}: {}", path.display(), e)))?;
+        if let Some(base) = path.parent() {
+            pipeline.resolve_paths(base);
+        }
+        Ok(pipeline)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let pipeline: Self =
+            toml::from_str(text).map_err(|e| PlayerError::Format(e.to_string()))?;
+        pipeline.validate()?;
+        Ok(pipeline)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        self.input = base.join(&self.input);
+        for step in &mut self.steps {
+            match step {
+                Step::Calibrate { dark } => *dark = base.join(&dark),
+                Step::Output { directory, .. } => *directory = base.join(&directory),
+                _ => {}
+            }
+        }
+    }
+
+    /// Make sure that the steps are in an order that can be executed
+    fn validate(&self) -> Result<()> {
+        let debayer_count = self.steps.iter().filter(|s| **s == Step::Debayer).count();
+        if debayer_count != 1 {
+            return Err(PlayerError::Format(format!(
+                "Pipeline must contain exactly one debayer step but found {}",
+                debayer_count
+            )));
+        }
+        let mut decoded = false;
+        for step in &self.steps {
+            if *step == Step::Debayer {
+                decoded = true;
+            } else if step.is_raw() == decoded {
+                return Err(PlayerError::Format(format!(
+                    "Step {:?} must come {} the debayer step",
+                    step,
+                    if decoded { "before" } else { "after" }
+                )));
+            }
+        }
+        if !self.steps.iter().any(|s| matches!(s, Step::Output { .. })) {
+            return Err(PlayerError::Format(
+                "Pipeline does not contain an output step".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Run the pipeline, returning the number of frames that were processed
+    #[instrument(skip(self), fields(input = %self.input.display()))]
+    pub fn run(&self) -> Result<usize> {
+        self.validate()?;
+        let filename = path_str(&self.input)?;
+        let video = open_video(filename)?;
+        let codec = codec_for(video.as_ref())?;
+
+        let mut darks = vec![];
+        for step in &self.steps {
+            if let Step::Calibrate { dark } = step {
+                darks.push(master_dark(video.as_ref(), dark)?);
+            }
+        }
+        for step in &self.steps {
+            if let Step::Output { directory, .. } = step {
+                fs::create_dir_all(directory)?;
+            }
+        }
+
+        let indices = self.selected_frames(video.frame_count());
+        let mut raw_samples = vec![];
+        let mut buf = vec![];
+        for &index in &indices {
+            let bytes = video.read_frame(index)?;
+            let raw: &[u8] = if darks.is_empty() {
+                &bytes
+            } else {
+                raw_samples.clear();
+                raw_samples.extend(samples(video.as_ref(), &bytes));
+                for dark in &darks {
+                    for (value, dark) in raw_samples.iter_mut().zip(dark) {
+                        *value = value.saturating_sub(*dark);
+                    }
+                }
+                write_samples(video.as_ref(), &raw_samples, &mut buf);
+                &buf
+            };
+
+            let mut frame: DecodedFrame = codec
+                .decode(&FrameView::new(video.as_ref(), raw), 0)?
+                .into();
+            for step in self.steps.iter().filter(|step| !step.is_raw()) {
+                match step {
+                    Step::Crop {
+                        x,
+                        y,
+                        width,
+                        height,
+                    } => frame = crop(&frame, *x, *y, *width, *height)?,
+                    Step::Stretch { black, white } => stretch(&mut frame, *black, *white),
+                    Step::Output { directory, prefix } => save_png(
+                        &frame,
+                        &directory.join(format!("{}{:05}.png", prefix, index)),
+                    )?,
+                    _ => {}
+                }
+            }
+        }
+        info!(frames = indices.len(), "pipeline finished");
+        Ok(indices.len())
+    }
+
+    /// Indices of the frames that remain after applying all of the select steps in order
+    fn selected_frames(&self, frame_count: usize) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..frame_count).collect();
+        for step in &self.steps {
+            if let Step::Select { start, end, every } = step {
+                let end = end.unwrap_or(frame_count);
+                indices = indices
+                    .into_iter()
+                    .filter(|i| *i >= *start && *i < end)
+                    .step_by((*every).max(1))
+                    .collect();
+            }
+        }
+        indices
+    }
+}
+
+/// Average all frames of a dark video into a master dark
+fn master_dark(video: &dyn Video, filename: &Path) -> Result<Vec<u16>> {
+    let dark = open_video(path_str(filename)?)?;
+    if dark.image_width() != video.image_width()
+        || dark.image_height() != video.image_height()
+        || dark.bytes_per_pixel() != video.bytes_per_pixel()
+        || dark.bayer().planes() != video.bayer().planes()
+    {
+        return Err(PlayerError::Format(format!(
+            "Dark frame {} does not match the dimensions or planes of the video",
+            filename.display()
+        )));
+    }
+    let mut sum: Vec<u64> = vec![];
+    let mut count = 0;
+    dark.for_each_frame(|_, frame| {
+        if sum.is_empty() {
+            sum = samples(dark.as_ref(), frame.bytes).map(u64::from).collect();
+        } else {
+            for (total, value) in sum.iter_mut().zip(samples(dark.as_ref(), frame.bytes)) {
+                *total += value as u64;
+            }
+        }
+        count += 1;
+        Ok(())
+    })?;
+    Ok(sum
+        .into_iter()
+        .map(|total| (total / count) as u16)
+        .collect())
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| PlayerError::Format(format!("Invalid path {}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{ser_bytes, ser_format};
+    use crate::video_format::Bayer;
+
+    #[test]
+    fn test_parse_pipeline() {
+        let pipeline = Pipeline::parse(
+            r#"
+            input = "jupiter.ser"
+
+            [[steps]]
+            type = "select"
+            start = 10
+            end = 20
+            every = 3
+
+            [[steps]]
+            type = "debayer"
+
+            [[steps]]
+            type = "output"
+            directory = "out"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(3, pipeline.steps.len());
+        assert_eq!(vec![10, 13, 16, 19], pipeline.selected_frames(100));
+        assert_eq!(vec![10, 13], pipeline.selected_frames(15));
+    }
+
+    #[test]
+    fn test_invalid_step_order() {
+        let result = Pipeline::parse(
+            r#"
+            input = "jupiter.ser"
+
+            [[steps]]
+            type = "debayer"
+
+            [[steps]]
+            type = "select"
+            start = 10
+
+            [[steps]]
+            type = "output"
+            directory = "out"
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("avp-pipeline-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let format = ser_format(4, 2, Bayer::Mono, 8);
+        let frames = (0..4).map(|i| vec![100 + i; 8]).collect::<Vec<_>>();
+        fs::write(dir.join("light.ser"), ser_bytes(&format, &frames)).unwrap();
+        let darks = vec![vec![10; 8], vec![20; 8]];
+        fs::write(dir.join("dark.ser"), ser_bytes(&format, &darks)).unwrap();
+        let color = ser_format(4, 2, Bayer::RGB, 8);
+        fs::write(dir.join("color.ser"), ser_bytes(&color, &[vec![0; 24]])).unwrap();
+        let pipeline = |dark: &str| {
+            let path = dir.join("pipeline.toml");
+            fs::write(
+                &path,
+                format!(
+                    r#"
+                    input = "light.ser"
+
+                    [[steps]]
+                    type = "calibrate"
+                    dark = "{}"
+
+                    [[steps]]
+                    type = "select"
+                    start = 1
+                    every = 2
+
+                    [[steps]]
+                    type = "debayer"
+
+                    [[steps]]
+                    type = "crop"
+                    x = 1
+                    y = 0
+                    width = 2
+                    height = 2
+
+                    [[steps]]
+                    type = "stretch"
+                    black = 0
+                    white = 255
+
+                    [[steps]]
+                    type = "output"
+                    directory = "out"
+                    prefix = "calibrated"
+                    "#,
+                    dark
+                ),
+            )
+            .unwrap();
+            Pipeline::load(&path).unwrap()
+        };
+
+        assert_eq!(2, pipeline("dark.ser").run().unwrap());
+        let mut names = fs::read_dir(dir.join("out"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(vec!["calibrated00001.png", "calibrated00003.png"], names);
+        let image = image::open(dir.join("out").join("calibrated00003.png"))
+            .unwrap()
+            .to_rgb8();
+        assert_eq!((2, 2), image.dimensions());
+        // frame 3 has values of 103 and the master dark is the average of 10 and 20
+        assert_eq!(&[88, 88, 88], &image.get_pixel(1, 1).0);
+
+        // a dark with the same size in bytes but a plane for each color
+        let err = pipeline("color.ser").run().unwrap_err();
+        assert!(err.to_string().contains("planes"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
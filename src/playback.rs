@@ -0,0 +1,305 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Which frame is shown next during playback, depending on the playback mode and the A-B
+//! loop region, and how long each frame is shown for when replaying at the capture cadence
+
+use std::time::{Duration, SystemTime};
+
+use crate::error::{PlayerError, Result};
+
+/// Speeds that real-time playback can run at, relative to the capture
+pub const SPEED_NAMES: &[&str] = &["0.25x", "0.5x", "1x", "2x", "4x", "8x"];
+
+/// Limits on how long a frame is shown for in real-time playback, so that frames with the
+/// same timestamp still advance and long capture gaps do not stall playback
+const MIN_REAL_TIME_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_REAL_TIME_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Parse a playback speed such as `2x` or `0.5`
+pub fn parse_speed(name: &str) -> Result<f64> {
+    let trimmed = name.trim();
+    let number = trimmed.strip_suffix('x').unwrap_or(trimmed);
+    match number.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Invalid playback speed '{}', expected a multiple of the capture speed such as {}",
+            name,
+            SPEED_NAMES.join(", ")
+        ))),
+    }
+}
+
+/// How long to show a frame captured at `this` for when the next frame was captured at `next`
+/// and playback runs at `speed` times the capture speed. The interval is rounded to the
+/// millisecond so that jitter in the timestamps does not restart the playback timer.
+pub fn real_time_interval(this: SystemTime, next: SystemTime, speed: f64) -> Duration {
+    let captured = next
+        .duration_since(this)
+        .or_else(|_| this.duration_since(next))
+        .unwrap_or_default();
+    let millis = (captured.as_secs_f64() * 1000.0 / speed).round();
+    Duration::from_millis(millis.min(u64::MAX as f64) as u64)
+        .max(MIN_REAL_TIME_INTERVAL)
+        .min(MAX_REAL_TIME_INTERVAL)
+}
+
+/// What happens when playback reaches the last frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaybackMode {
+    /// Stop at the last frame
+    #[default]
+    Once,
+    /// Start again from the first frame
+    Loop,
+    /// Play backwards to the first frame, then forwards again
+    PingPong,
+}
+
+/// Names of the playback modes, as accepted by `parse_playback_mode`
+pub const PLAYBACK_MODE_NAMES: &[&str] = &["once", "loop", "ping-pong"];
+
+pub fn parse_playback_mode(name: &str) -> Result<PlaybackMode> {
+    match name.to_ascii_lowercase().as_str() {
+        "once" => Ok(PlaybackMode::Once),
+        "loop" => Ok(PlaybackMode::Loop),
+        "ping-pong" => Ok(PlaybackMode::PingPong),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown playback mode '{}', expected one of {}",
+            name,
+            PLAYBACK_MODE_NAMES.join(", ")
+        ))),
+    }
+}
+
+impl PlaybackMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlaybackMode::Once => "once",
+            PlaybackMode::Loop => "loop",
+            PlaybackMode::PingPong => "ping-pong",
+        }
+    }
+}
+
+/// Playback state: the mode, the A-B markers and the current direction of ping-pong playback
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Playback {
+    pub mode: PlaybackMode,
+    /// Frame marked as the start of the loop region
+    pub marker_a: Option<usize>,
+    /// Frame marked as the end of the loop region
+    pub marker_b: Option<usize>,
+    /// Whether ping-pong playback is currently going backwards
+    reverse: bool,
+}
+
+impl Playback {
+    /// Frames that are played between the markers, inclusive, once both are set. The markers
+    /// can be set in either order.
+    pub fn region(&self) -> Option<(usize, usize)> {
+        match (self.marker_a, self.marker_b) {
+            (Some(a), Some(b)) => Some((a.min(b), a.max(b))),
+            _ => None,
+        }
+    }
+
+    pub fn clear_markers(&mut self) {
+        self.marker_a = None;
+        self.marker_b = None;
+    }
+
+    /// The frames of `order` that are played, which are those in the loop region if there
+    /// is one
+    fn frames(&self, order: &[usize]) -> Vec<usize> {
+        match self.region() {
+            Some((start, end)) => order
+                .iter()
+                .copied()
+                .filter(|index| (start..=end).contains(index))
+                .collect(),
+            None => order.to_vec(),
+        }
+    }
+
+    /// Prepare to start playing from `current`, returning the frame to jump to first if
+    /// playback cannot continue from `current`: when it is outside the loop region, or when
+    /// playing once and it is the last frame
+    pub fn start(&mut self, order: &[usize], current: usize) -> Option<usize> {
+        self.reverse = false;
+        let frames = self.frames(order);
+        let first = *frames.first()?;
+        let inside = match self.region() {
+            Some((start, end)) => (start..=end).contains(&current),
+            None => true,
+        };
+        let at_end = self.mode == PlaybackMode::Once && self.advance(order, current).is_none();
+        if !inside || at_end {
+            Some(first)
+        } else {
+            None
+        }
+    }
+
+    /// Frame to show after `current` when playing `order`, or `None` when playback stops
+    pub fn advance(&mut self, order: &[usize], current: usize) -> Option<usize> {
+        let frames = self.frames(order);
+        let last = frames.len().checked_sub(1)?;
+        let position = match frames.iter().position(|&index| index == current) {
+            Some(position) => position,
+            // continue from the frame after one that was skipped by the order
+            None => {
+                return match frames.iter().find(|&&index| index > current) {
+                    Some(&next) => Some(next),
+                    None if self.mode == PlaybackMode::Once => None,
+                    None => Some(frames[0]),
+                }
+            }
+        };
+        if self.reverse {
+            if position > 0 {
+                return Some(frames[position - 1]);
+            }
+            self.reverse = false;
+            return frames.get(1).or_else(|| frames.first()).copied();
+        }
+        if position < last {
+            return Some(frames[position + 1]);
+        }
+        match self.mode {
+            PlaybackMode::Once => None,
+            PlaybackMode::Loop => Some(frames[0]),
+            PlaybackMode::PingPong => {
+                self.reverse = true;
+                Some(frames[last.saturating_sub(1)])
+            }
+        }
+    }
+
+    /// Up to `count` distinct frames that are shown after `current` if playback continues, in
+    /// the order they are shown. This follows the direction of ping-pong playback and the
+    /// loop region without changing the playback state.
+    pub fn upcoming(&self, order: &[usize], current: usize, count: usize) -> Vec<usize> {
+        let mut playback = self.clone();
+        let mut frames = vec![];
+        let mut index = current;
+        // short loops revisit the same frames, so give up after a bounded number of steps
+        for _ in 0..2 * count + 2 {
+            if frames.len() == count {
+                break;
+            }
+            index = match playback.advance(order, index) {
+                Some(next) => next,
+                None => break,
+            };
+            if index != current && !frames.contains(&index) {
+                frames.push(index);
+            }
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames shown by `count` steps of playback from `current`
+    fn play(
+        playback: &mut Playback,
+        order: &[usize],
+        mut current: usize,
+        count: usize,
+    ) -> Vec<usize> {
+        let mut shown = vec![];
+        for _ in 0..count {
+            match playback.advance(order, current) {
+                Some(next) => {
+                    shown.push(next);
+                    current = next;
+                }
+                None => break,
+            }
+        }
+        shown
+    }
+
+    #[test]
+    fn test_modes() {
+        let order = (0..4).collect::<Vec<_>>();
+        let mut playback = Playback::default();
+        assert_eq!(vec![2, 3], play(&mut playback, &order, 1, 5));
+        assert_eq!(Some(0), playback.start(&order, 3));
+
+        playback.mode = PlaybackMode::Loop;
+        assert_eq!(None, playback.start(&order, 3));
+        assert_eq!(vec![2, 3, 0, 1, 2], play(&mut playback, &order, 1, 5));
+
+        playback.mode = PlaybackMode::PingPong;
+        assert_eq!(vec![2, 3, 2, 1, 0, 1, 2], play(&mut playback, &order, 1, 7));
+        assert_eq!(vec![3, 1, 0], playback.upcoming(&order, 2, 5));
+        assert_eq!(
+            PlaybackMode::PingPong,
+            parse_playback_mode("Ping-Pong").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_region() {
+        let order = vec![9, 2, 5, 4, 7, 1];
+        let mut playback = Playback {
+            mode: PlaybackMode::Loop,
+            marker_a: Some(7),
+            marker_b: Some(4),
+            ..Playback::default()
+        };
+        assert_eq!(Some((4, 7)), playback.region());
+        assert_eq!(Some(5), playback.start(&order, 1));
+        assert_eq!(None, playback.start(&order, 4));
+        assert_eq!(vec![7, 5, 4, 7], play(&mut playback, &order, 4, 4));
+        // a frame inside the region that was skipped by the order
+        assert_eq!(Some(7), playback.advance(&order, 6));
+    }
+
+    #[test]
+    fn test_real_time_interval() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let next = start + Duration::from_micros(33_333);
+        assert_eq!(
+            Duration::from_millis(33),
+            real_time_interval(start, next, 1.0)
+        );
+        assert_eq!(
+            Duration::from_millis(17),
+            real_time_interval(next, start, 2.0)
+        );
+        assert_eq!(
+            MIN_REAL_TIME_INTERVAL,
+            real_time_interval(start, start, 1.0)
+        );
+        let gap = start + Duration::from_secs(60);
+        assert_eq!(MAX_REAL_TIME_INTERVAL, real_time_interval(start, gap, 4.0));
+        assert!((parse_speed("0.5x").unwrap() - 0.5).abs() < f64::EPSILON);
+        assert!((parse_speed("2").unwrap() - 2.0).abs() < f64::EPSILON);
+        assert!(parse_speed("0x").is_err());
+    }
+}
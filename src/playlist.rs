@@ -0,0 +1,274 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The list of files opened in the player, along with the frame last viewed in each and
+//! other settings that belong to a file. The settings are also saved next to each file so
+//! that reviewing a capture can carry on in a later session.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::error::{PlayerError, Result};
+use crate::processing::{Stretch, WhiteBalance};
+use crate::sidecar::sidecar_path;
+use crate::video_format::Region;
+
+/// Settings that are restored when a file is opened again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FileState {
+    /// Frame that was being viewed
+    pub frame: u32,
+    pub white_balance: WhiteBalance,
+    /// Display stretch, or `None` to keep the stretch of the previous file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stretch: Option<Stretch>,
+    /// Region of interest that the frames were cropped to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    /// A-B loop markers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marker_a: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marker_b: Option<usize>,
+}
+
+/// File that the state of a video is saved in between sessions, which is named after the
+/// video and written next to it, such as `jupiter.avpstate.json` for `jupiter.ser`
+pub fn state_path(video_path: &Path) -> PathBuf {
+    sidecar_path(Some(video_path), ".avpstate.json")
+}
+
+/// Load the state saved next to a video, if there is one
+pub fn load_state(video_path: &Path) -> Result<Option<FileState>> {
+    let path = state_path(video_path);
+    match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| PlayerError::Format(format!("{}: {}", path.display(), e))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Save the state of a video next to it
+pub fn save_state(video_path: &Path, state: &FileState) -> Result<()> {
+    let text =
+        serde_json::to_string_pretty(state).map_err(|e| PlayerError::Format(e.to_string()))?;
+    fs::write(state_path(video_path), text)?;
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct Playlist {
+    files: Vec<PathBuf>,
+    /// Index into `files` of the file that is open
+    current: Option<usize>,
+    /// State of each file when it was last closed
+    states: HashMap<PathBuf, FileState>,
+}
+
+impl Playlist {
+    pub fn new(files: Vec<PathBuf>) -> Self {
+        let current = if files.is_empty() { None } else { Some(0) };
+        Self {
+            files,
+            current,
+            states: HashMap::new(),
+        }
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Make `path` the current file, adding it to the end of the list if it is not already
+    /// in it. Returns the state of the file when it was last closed.
+    pub fn select(&mut self, path: &Path) -> FileState {
+        let index = match self.files.iter().position(|file| file == path) {
+            Some(index) => index,
+            None => {
+                self.files.push(path.to_path_buf());
+                self.files.len() - 1
+            }
+        };
+        self.current = Some(index);
+        self.state(path)
+    }
+
+    /// Remember the state of the current file, so that it can be restored when the file is
+    /// reopened
+    pub fn remember(&mut self, state: FileState) {
+        if let Some(index) = self.current {
+            self.states.insert(self.files[index].clone(), state);
+        }
+    }
+
+    /// State of `path` when it was last closed, or else the state saved next to it in an
+    /// earlier session, or else the defaults
+    pub fn state(&self, path: &Path) -> FileState {
+        self.states
+            .get(path)
+            .copied()
+            .or_else(|| {
+                load_state(path).unwrap_or_else(|e| {
+                    warn!(
+                        "Could not load the saved state of {}: {}",
+                        path.display(),
+                        e
+                    );
+                    None
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// The file after the current one, if there is one
+    pub fn next(&self) -> Option<&Path> {
+        let index = self.current.map_or(0, |current| current + 1);
+        self.files.get(index).map(PathBuf::as_path)
+    }
+
+    /// The file before the current one, if there is one
+    pub fn previous(&self) -> Option<&Path> {
+        let index = self.current?.checked_sub(1)?;
+        self.files.get(index).map(PathBuf::as_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_navigation() {
+        let mut playlist = Playlist::new(vec!["a.ser".into(), "b.ser".into()]);
+        assert_eq!(Some(0), playlist.current());
+        assert_eq!(None, playlist.previous());
+        assert_eq!(Some(Path::new("b.ser")), playlist.next());
+
+        let state = FileState {
+            frame: 12,
+            white_balance: WhiteBalance {
+                red: 1.5,
+                ..WhiteBalance::default()
+            },
+            ..FileState::default()
+        };
+        playlist.remember(state);
+        assert_eq!(FileState::default(), playlist.select(Path::new("b.ser")));
+        assert_eq!(None, playlist.next());
+        assert_eq!(Some(Path::new("a.ser")), playlist.previous());
+        assert_eq!(state, playlist.select(Path::new("a.ser")));
+
+        // opening a file that is not in the list appends it
+        assert_eq!(0, playlist.select(Path::new("c.avi")).frame);
+        assert_eq!(3, playlist.len());
+        assert_eq!(Some(2), playlist.current());
+    }
+
+    #[test]
+    fn test_saved_state() {
+        let video = std::env::temp_dir().join(format!(
+            "astro_video_player_state_{}.ser",
+            std::process::id()
+        ));
+        assert_eq!(
+            video.with_file_name(format!(
+                "astro_video_player_state_{}.avpstate.json",
+                std::process::id()
+            )),
+            state_path(&video)
+        );
+        let state = FileState {
+            frame: 42,
+            stretch: Some(Stretch {
+                black: 10,
+                white: 200,
+                gamma: 1.5,
+            }),
+            region: Some(Region {
+                x: 8,
+                y: 16,
+                width: 64,
+                height: 32,
+            }),
+            marker_a: Some(5),
+            white_balance: WhiteBalance {
+                red: 1.8,
+                green: 1.0,
+                blue: 1.4,
+            },
+            ..FileState::default()
+        };
+        save_state(&video, &state).unwrap();
+        assert_eq!(Some(state), load_state(&video).unwrap());
+
+        // the saved state is used until the file has been closed in this session
+        let mut playlist = Playlist::new(vec![video.clone()]);
+        assert_eq!(state, playlist.select(&video));
+        playlist.remember(FileState::default());
+        assert_eq!(FileState::default(), playlist.select(&video));
+
+        // settings missing from the file keep their defaults
+        fs::write(state_path(&video), "{\"frame\": 3}").unwrap();
+        assert_eq!(
+            Some(FileState {
+                frame: 3,
+                ..FileState::default()
+            }),
+            load_state(&video).unwrap()
+        );
+        fs::remove_file(state_path(&video)).unwrap();
+        assert_eq!(None, load_state(&video).unwrap());
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut playlist = Playlist::default();
+        assert!(playlist.is_empty());
+        assert_eq!(None, playlist.next());
+        playlist.remember(FileState {
+            frame: 5,
+            ..FileState::default()
+        });
+        assert_eq!(0, playlist.select(Path::new("a.ser")).frame);
+        assert_eq!(Some(0), playlist.current());
+    }
+}
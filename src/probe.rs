@@ -0,0 +1,192 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Identifying files from the signature at the start of the file rather than the file
+//! extension, so that renamed files open and files in formats that cannot be played are
+//! rejected with an explanation instead of a decoding error.
+
+use std::path::Path;
+
+use crate::inputs::has_extension;
+use crate::video_format::VideoFormat;
+
+/// Number of bytes at the start of a file that are needed to identify it
+pub const SIGNATURE_SIZE: usize = 16;
+
+/// What the signature at the start of a file says it is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Probe {
+    /// A format that can be opened
+    Supported(VideoFormat),
+    /// A known format that cannot be opened, with its name and what to do instead
+    Unsupported {
+        name: &'static str,
+        advice: &'static str,
+    },
+    /// Anything else
+    Unknown,
+}
+
+/// Advice for video containers with compressed frames
+const CONVERT_VIDEO: &str = "Convert it to an uncompressed AVI or SER file first, for example \
+                             with ffmpeg -i <input> -c:v rawvideo <output>.avi";
+
+/// Identify a file from its first `SIGNATURE_SIZE` bytes
+pub fn probe(header: &[u8]) -> Probe {
+    let supported = if header.len() >= 14 && header[0..14].eq_ignore_ascii_case(b"LUCAM-RECORDER") {
+        Some(VideoFormat::Ser)
+    } else if riff_type(header) == Some(b"AVI ") {
+        Some(VideoFormat::Avi)
+    } else if header.starts_with(b"SIMPLE  =") {
+        Some(VideoFormat::Fits)
+    } else if header.starts_with(b"\x89PNG\r\n\x1a\n")
+        || header.starts_with(b"II*\0")
+        || header.starts_with(b"MM\0*")
+    {
+        Some(VideoFormat::Image)
+    } else {
+        None
+    };
+    if let Some(format) = supported {
+        return Probe::Supported(format);
+    }
+    let (name, advice) = match header {
+        _ if header.len() >= 12 && &header[4..8] == b"ftyp" => {
+            if &header[8..12] == b"qt  " {
+                ("a QuickTime movie, such as Apple ProRes", CONVERT_VIDEO)
+            } else {
+                ("an MP4 video", CONVERT_VIDEO)
+            }
+        }
+        // older QuickTime files start with an atom other than ftyp
+        _ if header.len() >= 8
+            && [&b"moov"[..], b"mdat", b"wide", b"free", b"skip"].contains(&&header[4..8]) =>
+        {
+            ("a QuickTime movie, such as Apple ProRes", CONVERT_VIDEO)
+        }
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => ("a Matroska or WebM video", CONVERT_VIDEO),
+        _ if riff_type(header).is_some() => (
+            "a RIFF file that is not an AVI video, such as a WAV or WebP file",
+            "Only AVI videos can be opened",
+        ),
+        [0xFF, 0xD8, 0xFF, ..] => (
+            "a JPEG image",
+            "Save still images as TIFF, PNG or FITS instead",
+        ),
+        _ if header.starts_with(b"XISF0100") => (
+            "a PixInsight XISF image",
+            "Save it as FITS or 16-bit TIFF from PixInsight instead",
+        ),
+        [0x1F, 0x8B, ..] => (
+            "a gzip compressed file, such as a .fits.gz image",
+            "Decompress it first",
+        ),
+        _ if header.starts_with(b"PK\x03\x04") => {
+            ("a ZIP archive", "Extract the captures from it first")
+        }
+        _ => return Probe::Unknown,
+    };
+    Probe::Unsupported { name, advice }
+}
+
+/// Guess the format of a file from its extension, ignoring case. This is only used for files
+/// whose signature is not recognized, so that the reader for the format can explain what is
+/// wrong with the file.
+pub fn format_from_extension(path: &Path) -> Option<VideoFormat> {
+    if has_extension(path, &["ser"]) {
+        Some(VideoFormat::Ser)
+    } else if has_extension(path, &["avi"]) {
+        Some(VideoFormat::Avi)
+    } else if has_extension(path, &["fits", "fit", "fts"]) {
+        Some(VideoFormat::Fits)
+    } else if has_extension(path, &["tif", "tiff", "png"]) {
+        Some(VideoFormat::Image)
+    } else {
+        None
+    }
+}
+
+/// Form type of a RIFF file, such as `AVI `
+fn riff_type(header: &[u8]) -> Option<&[u8]> {
+    if header.len() >= 12 && &header[0..4] == b"RIFF" {
+        Some(&header[8..12])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe() {
+        assert_eq!(
+            Probe::Supported(VideoFormat::Ser),
+            probe(b"LUCAM-RECORDER\0\0")
+        );
+        assert_eq!(
+            Probe::Supported(VideoFormat::Avi),
+            probe(b"RIFF\x10\0\0\0AVI LIST")
+        );
+        assert_eq!(
+            Probe::Supported(VideoFormat::Fits),
+            probe(b"SIMPLE  =                    T")
+        );
+        assert_eq!(
+            Probe::Supported(VideoFormat::Image),
+            probe(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR")
+        );
+        assert_eq!(
+            Probe::Supported(VideoFormat::Image),
+            probe(b"II*\0\x08\0\0\0")
+        );
+        assert_eq!(Probe::Unknown, probe(b"SIMPLE"));
+        assert_eq!(Probe::Unknown, probe(b""));
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        let format = |name: &str| format_from_extension(Path::new(name));
+        assert_eq!(Some(VideoFormat::Ser), format("jupiter.ser"));
+        assert_eq!(Some(VideoFormat::Ser), format("/captures/Jupiter.SER"));
+        assert_eq!(Some(VideoFormat::Avi), format("saturn.Avi"));
+        assert_eq!(Some(VideoFormat::Fits), format("m42.FIT"));
+        assert_eq!(Some(VideoFormat::Image), format("flat.TIFF"));
+        assert_eq!(None, format("jupiter"));
+        assert_eq!(None, format("ser"));
+    }
+
+    #[test]
+    fn test_probe_unsupported() {
+        let name = |header: &[u8]| match probe(header) {
+            Probe::Unsupported { name, .. } => name,
+            other => panic!("{:?}", other),
+        };
+        assert!(name(b"\0\0\0\x14ftypqt  \0\0\0\0").contains("ProRes"));
+        assert!(name(b"\0\0\0\x08wide\0\0\0\0mdat").contains("QuickTime"));
+        assert!(name(b"\0\0\0\x20ftypisom\0\0\x02\0").contains("MP4"));
+        assert!(name(b"\x1A\x45\xDF\xA3\x01\0\0\0").contains("Matroska"));
+        assert!(name(b"RIFF\x10\0\0\0WAVEfmt ").contains("RIFF"));
+        assert!(name(b"\xFF\xD8\xFF\xE0\0\x10JFIF").contains("JPEG"));
+    }
+}
@@ -0,0 +1,571 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::Histogram;
+use crate::annotations::Annotation;
+use crate::error::{PlayerError, Result};
+use crate::marks::Mark;
+
+/// Fraction of samples that auto-stretch clips to black and to white. The white fraction is
+/// small because planets often cover only a small part of the frame.
+const AUTO_STRETCH_BLACK: f64 = 0.001;
+const AUTO_STRETCH_WHITE: f64 = 0.0001;
+
+/// Fraction of the brightest samples in each channel that max-RGB white balance ignores, so
+/// that hot pixels do not decide the gains
+const MAX_RGB_IGNORED: f64 = 0.0001;
+
+/// Adjustments made by the user while reviewing a video. These can be undone and redone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Adjustments {
+    /// Frames that have been excluded from further processing
+    pub excluded: BTreeSet<usize>,
+    /// Frames that have been marked as good or bad
+    pub marks: BTreeMap<usize, Mark>,
+    /// Arrows and labels placed on frames
+    pub annotations: Vec<Annotation>,
+}
+
+/// A decoded frame in BGRA format, as produced by an `ImageCodec`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl DecodedFrame {
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+impl From<(u32, u32, Vec<u8>)> for DecodedFrame {
+    fn from((width, height, pixels): (u32, u32, Vec<u8>)) -> Self {
+        Self::new(width, height, pixels)
+    }
+}
+
+/// Extract a rectangular region of a frame
+pub fn crop(frame: &DecodedFrame, x: u32, y: u32, width: u32, height: u32) -> Result<DecodedFrame> {
+    // the region comes from user input, so its edges may not fit in a u32
+    let outside =
+        |start: u32, len: u32, size: u32| start.checked_add(len).is_none_or(|end| end > size);
+    if width == 0
+        || height == 0
+        || outside(x, width, frame.width)
+        || outside(y, height, frame.height)
+    {
+        return Err(PlayerError::Decode(format!(
+            "Crop region {}x{}+{}+{} is outside the {}x{} frame",
+            width, height, x, y, frame.width, frame.height
+        )));
+    }
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for row in y..y + height {
+        let start = ((row * frame.width + x) * 4) as usize;
+        pixels.extend_from_slice(&frame.pixels[start..start + (width * 4) as usize]);
+    }
+    Ok(DecodedFrame::new(width, height, pixels))
+}
+
+/// Combine two BGRA images of the same size for a side-by-side comparison: columns to the
+/// left of `divider` come from `left` and the rest from `right`, with a white line marking
+/// the divider
+pub fn split_bgra(width: u32, left: &[u8], right: &[u8], divider: u32) -> Vec<u8> {
+    let row_len = width as usize * 4;
+    let split = divider.min(width) as usize * 4;
+    let mut pixels = right.to_vec();
+    for (row, left_row) in pixels
+        .chunks_exact_mut(row_len.max(1))
+        .zip(left.chunks_exact(row_len.max(1)))
+    {
+        row[..split].copy_from_slice(&left_row[..split]);
+        if split < row_len {
+            row[split..split + 4].copy_from_slice(&[255, 255, 255, 255]);
+        }
+    }
+    pixels
+}
+
+/// Linearly stretch the color channels so that `black` maps to 0 and `white` maps to 255
+pub fn stretch(frame: &mut DecodedFrame, black: u8, white: u8) {
+    Stretch {
+        black,
+        white,
+        gamma: 1.0,
+    }
+    .apply(&mut frame.pixels);
+}
+
+/// Display stretch of decoded images, which maps `black` to 0 and `white` to 255 and then
+/// applies a gamma curve to the values in between
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Stretch {
+    pub black: u8,
+    pub white: u8,
+    /// Values above 1 brighten the midtones and values below 1 darken them
+    pub gamma: f32,
+}
+
+impl Default for Stretch {
+    fn default() -> Self {
+        Self {
+            black: 0,
+            white: 255,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Stretch {
+    /// Choose black and white points that clip a small fraction of the samples in the
+    /// histogram of an unstretched image, keeping the current gamma
+    pub fn auto(&self, histogram: &Histogram) -> Self {
+        let counts = (0..256)
+            .map(|level| histogram.red[level] + histogram.green[level] + histogram.blue[level])
+            .collect::<Vec<_>>();
+        let total = counts.iter().map(|count| *count as u64).sum::<u64>();
+        // the first level at which more than `fraction` of the samples are at or below it
+        let percentile = |fraction: f64| {
+            let mut sum = 0;
+            counts
+                .iter()
+                .position(|count| {
+                    sum += *count as u64;
+                    sum as f64 > fraction * total as f64
+                })
+                .unwrap_or(255) as u8
+        };
+        let black = percentile(AUTO_STRETCH_BLACK).min(254);
+        let white = percentile(1.0 - AUTO_STRETCH_WHITE).max(black + 1);
+        Self {
+            black,
+            white,
+            gamma: self.gamma,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Move the black point, keeping it below the white point
+    pub fn set_black(&mut self, black: u8) {
+        self.black = black.min(self.white.saturating_sub(1));
+    }
+
+    /// Move the white point, keeping it above the black point
+    pub fn set_white(&mut self, white: u8) {
+        self.white = white.max(self.black.saturating_add(1));
+    }
+
+    /// Output value for each 8-bit input value
+    pub fn lut(&self) -> [u8; 256] {
+        // black and white come from user settings, so they may be equal or out of order
+        let range = (self.white as i32 - self.black as i32).max(1) as f32;
+        let exponent = 1.0 / self.gamma.max(0.01);
+        let mut lut = [0; 256];
+        for (value, out) in lut.iter_mut().enumerate() {
+            let scaled = ((value as f32 - self.black as f32) / range).clamp(0.0, 1.0);
+            *out = (scaled.powf(exponent) * 255.0) as u8;
+        }
+        lut
+    }
+
+    /// Stretch the color channels of BGRA pixels, leaving alpha untouched
+    pub fn apply(&self, pixels: &mut [u8]) {
+        let lut = self.lut();
+        for pixel in pixels.chunks_exact_mut(4) {
+            for channel in &mut pixel[0..3] {
+                *channel = lut[*channel as usize];
+            }
+        }
+    }
+}
+
+/// Gains applied to the color channels of decoded images, after debayering, to correct the
+/// color cast of one-shot color cameras
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WhiteBalance {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+impl Default for WhiteBalance {
+    fn default() -> Self {
+        Self {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        }
+    }
+}
+
+/// Ways of choosing the white balance gains from the histogram of a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WhiteBalanceMethod {
+    /// Assume that the average color of the frame is gray
+    GrayWorld,
+    /// Assume that the brightest part of the frame is white
+    MaxRgb,
+}
+
+impl WhiteBalance {
+    /// Choose gains that balance the red and blue channels against the green channel of a
+    /// histogram of an unbalanced image
+    pub fn auto(histogram: &Histogram, method: WhiteBalanceMethod) -> Self {
+        let level = |counts: &[u32; 256]| match method {
+            WhiteBalanceMethod::GrayWorld => mean_level(counts),
+            WhiteBalanceMethod::MaxRgb => max_level(counts),
+        };
+        let green = level(&histogram.green);
+        let gain = |counts: &[u32; 256]| {
+            let level = level(counts);
+            if level > 0.0 && green > 0.0 {
+                (green / level) as f32
+            } else {
+                1.0
+            }
+        };
+        Self {
+            red: gain(&histogram.red),
+            green: 1.0,
+            blue: gain(&histogram.blue),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Scale the color channels of BGRA pixels, leaving alpha untouched
+    pub fn apply(&self, pixels: &mut [u8]) {
+        let lut = |gain: f32| {
+            let mut lut = [0; 256];
+            for (value, out) in lut.iter_mut().enumerate() {
+                *out = (value as f32 * gain).round().min(255.0) as u8;
+            }
+            lut
+        };
+        let luts = [lut(self.blue), lut(self.green), lut(self.red)];
+        for pixel in pixels.chunks_exact_mut(4) {
+            for (channel, lut) in pixel[0..3].iter_mut().zip(&luts) {
+                *channel = lut[*channel as usize];
+            }
+        }
+    }
+}
+
+/// Names of the color maps, as accepted by `parse_color_map`
+pub const COLOR_MAP_NAMES: &[&str] = &["grayscale", "inverted", "viridis", "heat"];
+
+/// Colors used to show the brightness of decoded images, after the display stretch. False
+/// colors reveal faint structure in mono and narrowband captures, where the eye separates
+/// hues better than shades of gray. Color images are shown by their luminance when any map
+/// other than grayscale is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMap {
+    /// The decoded colors, unchanged
+    #[default]
+    Grayscale,
+    /// Dark features are shown bright, as on a photographic negative
+    Inverted,
+    /// Perceptually uniform dark blue through green to yellow
+    Viridis,
+    /// Black through red and yellow to white
+    Heat,
+}
+
+/// Colors of viridis at evenly spaced levels, which are interpolated between
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+impl ColorMap {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMap::Grayscale => "grayscale",
+            ColorMap::Inverted => "inverted",
+            ColorMap::Viridis => "viridis",
+            ColorMap::Heat => "heat",
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == ColorMap::Grayscale
+    }
+
+    /// Red, green and blue for each brightness level
+    pub fn lut(&self) -> [[u8; 3]; 256] {
+        let mut lut = [[0; 3]; 256];
+        for (level, rgb) in lut.iter_mut().enumerate() {
+            let value = level as f32 / 255.0;
+            *rgb = match self {
+                ColorMap::Grayscale => [level as u8; 3],
+                ColorMap::Inverted => [255 - level as u8; 3],
+                ColorMap::Viridis => {
+                    let position = value * (VIRIDIS.len() - 1) as f32;
+                    let below = (position as usize).min(VIRIDIS.len() - 2);
+                    let t = position - below as f32;
+                    let mut rgb = [0; 3];
+                    for (i, channel) in rgb.iter_mut().enumerate() {
+                        let (from, to) = (VIRIDIS[below][i] as f32, VIRIDIS[below + 1][i] as f32);
+                        *channel = (from + t * (to - from)).round() as u8;
+                    }
+                    rgb
+                }
+                ColorMap::Heat => {
+                    // red rises over the first 3/8, then green, then blue
+                    let ramp = |start: f32, end: f32| {
+                        (((value - start) / (end - start)).clamp(0.0, 1.0) * 255.0).round() as u8
+                    };
+                    [ramp(0.0, 0.375), ramp(0.375, 0.75), ramp(0.75, 1.0)]
+                }
+            };
+        }
+        lut
+    }
+
+    /// Replace the colors of BGRA pixels with the color of their luminance, leaving alpha
+    /// untouched
+    pub fn apply(&self, pixels: &mut [u8]) {
+        if self.is_identity() {
+            return;
+        }
+        let lut = self.lut();
+        for pixel in pixels.chunks_exact_mut(4) {
+            let (b, g, r) = (pixel[0] as u32, pixel[1] as u32, pixel[2] as u32);
+            let luminance = (218 * r + 732 * g + 74 * b) >> 10;
+            let [red, green, blue] = lut[luminance.min(255) as usize];
+            pixel[0..3].copy_from_slice(&[blue, green, red]);
+        }
+    }
+}
+
+/// Parse a color map name from `COLOR_MAP_NAMES`, ignoring case
+pub fn parse_color_map(name: &str) -> Result<ColorMap> {
+    match name.to_ascii_lowercase().as_str() {
+        "grayscale" | "greyscale" | "gray" | "grey" => Ok(ColorMap::Grayscale),
+        "inverted" | "invert" => Ok(ColorMap::Inverted),
+        "viridis" => Ok(ColorMap::Viridis),
+        "heat" | "hot" => Ok(ColorMap::Heat),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown color map '{}', expected one of {}",
+            name,
+            COLOR_MAP_NAMES.join(", ")
+        ))),
+    }
+}
+
+/// Average level of the samples counted in a histogram channel
+fn mean_level(counts: &[u32; 256]) -> f64 {
+    let total = counts.iter().map(|count| *count as u64).sum::<u64>();
+    let sum = counts
+        .iter()
+        .enumerate()
+        .map(|(level, count)| level as u64 * *count as u64)
+        .sum::<u64>();
+    if total == 0 {
+        0.0
+    } else {
+        sum as f64 / total as f64
+    }
+}
+
+/// Brightest level in a histogram channel, ignoring the brightest `MAX_RGB_IGNORED` of the
+/// samples
+fn max_level(counts: &[u32; 256]) -> f64 {
+    let total = counts.iter().map(|count| *count as u64).sum::<u64>();
+    let ignored = (total as f64 * MAX_RGB_IGNORED) as u64;
+    let mut sum = 0;
+    counts
+        .iter()
+        .rposition(|count| {
+            sum += *count as u64;
+            sum > ignored
+        })
+        .unwrap_or(0) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crop() {
+        let pixels = (0..4 * 3 * 4).map(|i| i as u8).collect();
+        let frame = DecodedFrame::new(4, 3, pixels);
+        let cropped = crop(&frame, 1, 1, 2, 2).unwrap();
+        assert_eq!(2, cropped.width);
+        assert_eq!(2, cropped.height);
+        assert_eq!(&[20, 21, 22, 23], &cropped.pixels[0..4]);
+        assert_eq!(&[36, 37, 38, 39], &cropped.pixels[8..12]);
+        assert!(crop(&frame, 3, 0, 2, 1).is_err());
+        assert!(crop(&frame, u32::MAX, 0, 2, 1).is_err());
+        assert!(crop(&frame, 0, u32::MAX, 1, 2).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let left = vec![10; 3 * 2 * 4];
+        let right = vec![20; 3 * 2 * 4];
+        let split = split_bgra(3, &left, &right, 1);
+        let row = [10, 10, 10, 10, 255, 255, 255, 255, 20, 20, 20, 20];
+        assert_eq!([row, row].concat(), split);
+        assert_eq!(left, split_bgra(3, &left, &right, 3));
+    }
+
+    #[test]
+    fn test_stretch() {
+        let mut frame = DecodedFrame::new(1, 1, vec![10, 60, 200, 255]);
+        stretch(&mut frame, 10, 110);
+        assert_eq!(vec![0, 127, 255, 255], frame.pixels);
+
+        let gamma = Stretch {
+            gamma: 2.0,
+            ..Stretch::default()
+        };
+        assert_eq!(179, gamma.lut()[127]);
+        assert_eq!(255, gamma.lut()[255]);
+
+        // a black point of 255 clips everything below it without dividing by zero
+        let clipped = Stretch {
+            black: 255,
+            ..Stretch::default()
+        };
+        assert_eq!(0, clipped.lut()[254]);
+        assert_eq!(0, clipped.lut()[255]);
+    }
+
+    #[test]
+    fn test_set_stretch_points() {
+        let mut stretch = Stretch::default();
+        stretch.set_black(255);
+        assert_eq!((254, 255), (stretch.black, stretch.white));
+        stretch.set_white(0);
+        assert_eq!((254, 255), (stretch.black, stretch.white));
+
+        // points restored from a file may already be out of order
+        let mut stretch = Stretch {
+            black: 255,
+            white: 0,
+            gamma: 1.0,
+        };
+        stretch.set_white(10);
+        assert_eq!(255, stretch.white);
+        stretch.set_black(10);
+        assert_eq!(10, stretch.black);
+    }
+
+    #[test]
+    fn test_auto_stretch() {
+        // dark background with a small bright object
+        let mut pixels = vec![];
+        for i in 0..10_000 {
+            let value = match i {
+                0..=4 => 0,
+                5..=9_899 => 12 + (i % 3) as u8,
+                _ => 100 + (i % 50) as u8,
+            };
+            pixels.extend_from_slice(&[value, value, value, 255]);
+        }
+        let histogram = Histogram::from_bgra(&pixels);
+        let stretch = Stretch::default().auto(&histogram);
+        assert_eq!(12, stretch.black);
+        assert_eq!(149, stretch.white);
+        assert!((stretch.gamma - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_white_balance() {
+        // green cast, with a bright white-ish highlight
+        let mut pixels = vec![];
+        for i in 0..1000 {
+            let pixel = if i < 10 {
+                [200, 250, 100, 255]
+            } else {
+                [20, 40, 10, 255]
+            };
+            pixels.extend_from_slice(&pixel);
+        }
+        let histogram = Histogram::from_bgra(&pixels);
+
+        let gray_world = WhiteBalance::auto(&histogram, WhiteBalanceMethod::GrayWorld);
+        assert!((gray_world.red - 4.0).abs() < 0.2);
+        assert!((gray_world.blue - 2.0).abs() < 0.1);
+        assert!((gray_world.green - 1.0).abs() < f32::EPSILON);
+
+        let max_rgb = WhiteBalance::auto(&histogram, WhiteBalanceMethod::MaxRgb);
+        assert!((max_rgb.red - 2.5).abs() < f32::EPSILON);
+        assert!((max_rgb.blue - 1.25).abs() < f32::EPSILON);
+
+        max_rgb.apply(&mut pixels);
+        assert_eq!(&[250, 250, 250, 255], &pixels[0..4]);
+        assert_eq!(&[25, 40, 25, 255], &pixels[40..44]);
+        assert!(WhiteBalance::default().is_identity());
+    }
+
+    #[test]
+    fn test_color_map() {
+        for name in COLOR_MAP_NAMES {
+            assert_eq!(*name, parse_color_map(name).unwrap().name());
+        }
+        assert!(parse_color_map("jet").is_err());
+
+        let viridis = ColorMap::Viridis.lut();
+        assert_eq!(VIRIDIS[0], viridis[0]);
+        assert_eq!(VIRIDIS[8], viridis[255]);
+        let heat = ColorMap::Heat.lut();
+        assert_eq!([0, 0, 0], heat[0]);
+        // red is saturated before green starts to rise
+        assert_eq!([255, 1, 0], heat[96]);
+        assert_eq!([255, 255, 255], heat[255]);
+
+        // mid gray, and a saturated green that has the same luminance as white
+        let mut pixels = vec![128, 128, 128, 255, 255, 255, 255, 7];
+        ColorMap::Inverted.apply(&mut pixels);
+        assert_eq!(vec![127, 127, 127, 255, 0, 0, 0, 7], pixels);
+        let mut pixels = vec![10, 20, 30, 255];
+        ColorMap::Grayscale.apply(&mut pixels);
+        assert_eq!(vec![10, 20, 30, 255], pixels);
+    }
+}
@@ -0,0 +1,186 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Python bindings, built with the `python` feature. For example:
+//!
+//! ```python
+//! import astro_video_player
+//!
+//! video = astro_video_player.open("jupiter.ser")
+//! print(video.metadata())
+//! raw = video.read_frame(0)   # uint8 or uint16 array of raw samples
+//! rgb = video.decode(0)       # uint8 array of shape (height, width, 3)
+//! ```
+
+use numpy::ndarray::{Array, IxDyn};
+use numpy::{IntoPyArray, PyArrayDyn};
+use pyo3::exceptions::{PyIOError, PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::codec::{codec_named, ImageCodec};
+use crate::error::PlayerError;
+use crate::video_format::{open_video, samples, top_down_frame, Bayer, Endianness, Video};
+
+impl From<PlayerError> for PyErr {
+    fn from(e: PlayerError) -> Self {
+        match e {
+            PlayerError::Io(e) => PyIOError::new_err(e.to_string()),
+            other => PyValueError::new_err(other.to_string()),
+        }
+    }
+}
+
+/// A SER, AVI or FITS file that has been opened for reading
+#[pyclass(name = "Video")]
+struct PyVideo {
+    video: Box<dyn Video>,
+    codec: Box<dyn ImageCodec>,
+}
+
+#[pymethods]
+impl PyVideo {
+    #[getter]
+    fn width(&self) -> u32 {
+        self.video.image_width()
+    }
+
+    #[getter]
+    fn height(&self) -> u32 {
+        self.video.image_height()
+    }
+
+    #[getter]
+    fn frame_count(&self) -> usize {
+        self.video.frame_count()
+    }
+
+    #[getter]
+    fn bit_depth(&self) -> u32 {
+        self.video.pixel_depth_bits()
+    }
+
+    #[getter]
+    fn bayer(&self) -> String {
+        format!("{:?}", self.video.bayer())
+    }
+
+    fn __len__(&self) -> usize {
+        self.video.frame_count()
+    }
+
+    /// Header values as a dict
+    fn metadata<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        dict.set_item("width", self.video.image_width())?;
+        dict.set_item("height", self.video.image_height())?;
+        dict.set_item("frame_count", self.video.frame_count())?;
+        dict.set_item("bytes_per_pixel", self.video.bytes_per_pixel())?;
+        dict.set_item("bit_depth", self.video.pixel_depth_bits())?;
+        dict.set_item("bayer", format!("{:?}", self.video.bayer()))?;
+        dict.set_item(
+            "endianness",
+            match self.video.endianness() {
+                Endianness::LittleEndian => "little",
+                Endianness::BigEndian => "big",
+            },
+        )?;
+        Ok(dict)
+    }
+
+    /// Raw samples of a frame with shape (height, width), or (height, width, 3) for RGB and
+    /// BGR videos, with the top row first. The dtype is uint8 for 8-bit videos and uint16
+    /// otherwise.
+    fn read_frame(&self, py: Python, index: usize) -> PyResult<PyObject> {
+        self.check_index(index)?;
+        let frame = self.video.read_frame(index)?;
+        let bytes = top_down_frame(self.video.as_ref(), &frame);
+        let (height, width) = (
+            self.video.image_height() as usize,
+            self.video.image_width() as usize,
+        );
+        let shape = match self.video.bayer() {
+            Bayer::RGB | Bayer::BGR => vec![height, width, 3],
+            _ => vec![height, width],
+        };
+        let expected: usize = shape.iter().product();
+        if self.video.bytes_per_pixel() == 1 {
+            Ok(array(py, shape, bytes[..expected.min(bytes.len())].to_vec())?.to_object(py))
+        } else {
+            let values: Vec<u16> = samples(self.video.as_ref(), &bytes)
+                .take(expected)
+                .collect();
+            Ok(array(py, shape, values)?.to_object(py))
+        }
+    }
+
+    /// Decode a frame into an 8-bit RGB image with shape (height, width, 3)
+    fn decode<'py>(&self, py: Python<'py>, index: usize) -> PyResult<&'py PyArrayDyn<u8>> {
+        self.check_index(index)?;
+        let (width, height, bgra) =
+            py.allow_threads(|| self.codec.decode(self.video.as_ref(), index))?;
+        let rgb = bgra
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+            .collect();
+        array(py, vec![height as usize, width as usize, 3], rgb)
+    }
+}
+
+impl PyVideo {
+    fn check_index(&self, index: usize) -> PyResult<()> {
+        if index < self.video.frame_count() {
+            Ok(())
+        } else {
+            Err(PyIndexError::new_err(format!(
+                "frame {} is out of range for a video with {} frames",
+                index,
+                self.video.frame_count()
+            )))
+        }
+    }
+}
+
+fn array<T: numpy::Element>(
+    py: Python<'_>,
+    shape: Vec<usize>,
+    values: Vec<T>,
+) -> PyResult<&PyArrayDyn<T>> {
+    let array = Array::from_shape_vec(IxDyn(&shape), values)
+        .map_err(|e| PyValueError::new_err(format!("frame is truncated: {}", e)))?;
+    Ok(array.into_pyarray(py))
+}
+
+/// Open a SER, AVI or FITS file. `codec` chooses how frames are decoded, see `CODEC_NAMES`.
+#[pyfunction(codec = "\"auto\"")]
+fn open(path: &str, codec: &str) -> PyResult<PyVideo> {
+    let video = open_video(path)?;
+    let codec = codec_named(codec, video.as_ref())?;
+    Ok(PyVideo { video, codec })
+}
+
+#[pymodule]
+fn astro_video_player(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyVideo>()?;
+    m.add_function(wrap_pyfunction!(open, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,249 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Frame quality scoring for lucky imaging. Each frame is scored by the variance of the
+//! Laplacian of its brightness, which is higher for sharper frames, so that the best frames
+//! of a capture can be selected for stacking.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::error::Result;
+use crate::sidecar::sidecar_path;
+use crate::video_format::{check_uncompressed, samples, Bayer, Video};
+
+/// Sharpness of a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameScore {
+    pub index: usize,
+    /// Variance of the Laplacian, with samples scaled to the range 0 to 1 so that scores
+    /// of videos with different bit depths are comparable
+    pub sharpness: f64,
+}
+
+/// Score a single frame
+pub fn sharpness(video: &dyn Video, index: usize) -> Result<f64> {
+    check_uncompressed(video)?;
+    let (width, height, brightness) = brightness(video, &video.read_frame(index)?);
+    Ok(laplacian_variance(width, height, &brightness))
+}
+
+/// Score every frame of a video in parallel, returning the scores in frame order
+pub fn score_frames(video: &dyn Video) -> Result<Vec<FrameScore>> {
+    check_uncompressed(video)?;
+    video.par_map_frames(|index, frame| {
+        let (width, height, brightness) = brightness(video, frame.bytes);
+        FrameScore {
+            index,
+            sharpness: laplacian_variance(width, height, &brightness),
+        }
+    })
+}
+
+/// Score the frames with the given indices in parallel, returning the scores in the same
+/// order as the indices
+pub fn score_selected(video: &dyn Video, indices: &[usize]) -> Result<Vec<FrameScore>> {
+    check_uncompressed(video)?;
+    indices
+        .par_iter()
+        .map(|&index| sharpness(video, index).map(|sharpness| FrameScore { index, sharpness }))
+        .collect()
+}
+
+/// Sort scores from the sharpest frame to the least sharp, keeping frame order for ties
+pub fn rank(scores: &[FrameScore]) -> Vec<FrameScore> {
+    let mut ranked = scores.to_vec();
+    ranked.sort_by(|a, b| {
+        b.sharpness
+            .partial_cmp(&a.sharpness)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.index.cmp(&b.index))
+    });
+    ranked
+}
+
+/// Indices of the sharpest fraction of frames (at least one frame), in frame order
+pub fn sharpest(ranked: &[FrameScore], fraction: f64) -> Vec<usize> {
+    let count = ((ranked.len() as f64 * fraction).ceil() as usize).clamp(1, ranked.len().max(1));
+    let mut indices = ranked
+        .iter()
+        .take(count)
+        .map(|score| score.index)
+        .collect::<Vec<_>>();
+    indices.sort_unstable();
+    indices
+}
+
+/// File that the ranking of a video is exported to, which is named after the video and
+/// written next to it
+pub fn ranking_path(video_path: Option<&Path>) -> PathBuf {
+    sidecar_path(video_path, "_quality.csv")
+}
+
+/// Write ranked scores as CSV with `rank`, `frame` and `sharpness` columns, where the best
+/// frame has rank 1
+pub fn write_csv(ranked: &[FrameScore], out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "rank,frame,sharpness")?;
+    for (rank, score) in ranked.iter().enumerate() {
+        writeln!(out, "{},{},{:e}", rank + 1, score.index, score.sharpness)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Brightness of each pixel of a frame, scaled to the range 0 to 1. Bayer frames are
+/// reduced to half resolution by summing each 2x2 quad, so that the color filter pattern is
+/// not mistaken for detail.
+pub(crate) fn brightness(video: &dyn Video, bytes: &[u8]) -> (usize, usize, Vec<f32>) {
+    let (width, height) = (video.image_width() as usize, video.image_height() as usize);
+    let bits = match video.pixel_depth_bits() {
+        bits @ 1..=16 => bits.min(8 * video.bytes_per_pixel() as u32),
+        _ => 8 * video.bytes_per_pixel() as u32,
+    };
+    let max = ((1_u32 << bits) - 1) as f32;
+    match video.bayer() {
+        Bayer::RGB | Bayer::BGR => {
+            let values = samples(video, bytes)
+                .collect::<Vec<_>>()
+                .chunks_exact(3)
+                .take(width * height)
+                .map(|rgb| (rgb[0] as f32 + rgb[1] as f32 + rgb[2] as f32) / (3.0 * max))
+                .collect::<Vec<_>>();
+            (width, values.len() / width.max(1), values)
+        }
+        Bayer::Mono => {
+            let values = samples(video, bytes)
+                .take(width * height)
+                .map(|value| value as f32 / max)
+                .collect::<Vec<_>>();
+            (width, values.len() / width.max(1), values)
+        }
+        _ => {
+            let raw = samples(video, bytes)
+                .take(width * height)
+                .collect::<Vec<_>>();
+            let (out_width, out_height) = (width / 2, (raw.len() / width.max(1)) / 2);
+            let mut values = Vec::with_capacity(out_width * out_height);
+            for y in 0..out_height {
+                let (top, bottom) = (2 * y * width, (2 * y + 1) * width);
+                for x in 0..out_width {
+                    let sum = raw[top + 2 * x] as f32
+                        + raw[top + 2 * x + 1] as f32
+                        + raw[bottom + 2 * x] as f32
+                        + raw[bottom + 2 * x + 1] as f32;
+                    values.push(sum / (4.0 * max));
+                }
+            }
+            (out_width, out_height, values)
+        }
+    }
+}
+
+/// Variance of the 4-neighbour Laplacian over the interior of an image
+fn laplacian_variance(width: usize, height: usize, values: &[f32]) -> f64 {
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let (mut sum, mut sum_squares) = (0.0_f64, 0.0_f64);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let i = y * width + x;
+            let laplacian = 4.0 * values[i]
+                - values[i - 1]
+                - values[i + 1]
+                - values[i - width]
+                - values[i + width];
+            sum += laplacian as f64;
+            sum_squares += (laplacian * laplacian) as f64;
+        }
+    }
+    let count = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / count;
+    sum_squares / count - mean * mean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{ser_format, ser_video};
+
+    #[test]
+    fn test_sharp_frames_rank_first() {
+        // a hard edge, the same edge blurred, and a blank frame
+        let sharp = (0..64).map(|i| if i % 8 < 4 { 0 } else { 200 }).collect();
+        let blurred = (0..64)
+            .map(|i| [0, 0, 50, 100, 150, 200, 200, 200][i % 8])
+            .collect();
+        let blank = vec![100; 64];
+        let video = ser_video(&ser_format(8, 8, Bayer::Mono, 8), &[blurred, blank, sharp]);
+        let scores = score_frames(&video).unwrap();
+        assert_eq!(
+            vec![0, 1, 2],
+            scores.iter().map(|s| s.index).collect::<Vec<_>>()
+        );
+        assert!(scores[1].sharpness.abs() < 1e-12);
+
+        let ranked = rank(&scores);
+        assert_eq!(
+            vec![2, 0, 1],
+            ranked.iter().map(|s| s.index).collect::<Vec<_>>()
+        );
+
+        let mut csv = vec![];
+        write_csv(&ranked, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!("rank,frame,sharpness", lines[0]);
+        assert!(lines[1].starts_with("1,2,"));
+        assert_eq!("3,1,0e0", lines[3]);
+
+        assert_eq!(vec![0, 2], sharpest(&ranked, 0.5));
+        assert_eq!(vec![2], sharpest(&ranked, 0.1));
+    }
+
+    #[test]
+    fn test_ranking_path() {
+        assert_eq!(
+            PathBuf::from("/data/jupiter_quality.csv"),
+            ranking_path(Some(Path::new("/data/jupiter.ser")))
+        );
+        assert_eq!(PathBuf::from("video_quality.csv"), ranking_path(None));
+    }
+
+    #[test]
+    fn test_bayer_pattern_is_not_detail() {
+        // a uniformly lit RGGB frame, where the colors differ but the quads are identical
+        let frame = (0..64)
+            .map(|i| {
+                if (i / 8) % 2 == 0 {
+                    [200, 100][i % 2]
+                } else {
+                    [100, 20][i % 2]
+                }
+            })
+            .collect();
+        let video = ser_video(&ser_format(8, 8, Bayer::RGGB, 8), &[frame]);
+        assert!(sharpness(&video, 0).unwrap().abs() < 1e-12);
+    }
+}
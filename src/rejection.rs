@@ -0,0 +1,299 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Automatic rejection of frames whose brightness stands out from the rest of a capture, such
+//! as frames dimmed by passing cloud or dew, or brightened by a passing plane or satellite.
+//! The rejection report lists the frames so that they can be left out of exports and stacks.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::{PlayerError, Result};
+use crate::marks::Mark;
+use crate::sidecar::sidecar_path;
+use crate::video_format::{check_uncompressed, samples, Video};
+
+/// Default number of standard deviations that a frame's brightness may differ from the
+/// typical brightness before it is rejected
+pub const DEFAULT_SIGMA: f64 = 3.0;
+
+/// Scale factor from the median absolute deviation to the standard deviation of normally
+/// distributed values
+const MAD_SCALE: f64 = 1.4826;
+
+/// Brightness of the raw samples of a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameBrightness {
+    pub index: usize,
+    pub mean: f64,
+    pub median: f64,
+}
+
+/// A frame whose brightness deviates too far from the rest of the sequence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rejection {
+    pub brightness: FrameBrightness,
+    /// Largest deviation of the mean or median from the sequence, in standard deviations.
+    /// Negative for frames that are darker than usual.
+    pub deviation: f64,
+}
+
+/// Measure the brightness of every frame of a video in parallel, returning the
+/// measurements in frame order
+pub fn frame_brightness(video: &dyn Video) -> Result<Vec<FrameBrightness>> {
+    check_uncompressed(video)?;
+    video.par_map_frames(|index, frame| measure(video, index, frame.bytes))
+}
+
+fn measure(video: &dyn Video, index: usize, bytes: &[u8]) -> FrameBrightness {
+    // the median is found from a count of each sample value rather than by sorting, which
+    // would be slow for large frames
+    let mut counts = vec![0_u64; 1 << 16];
+    let mut sum = 0_u64;
+    let mut count = 0_u64;
+    for value in samples(video, bytes) {
+        counts[value as usize] += 1;
+        sum += value as u64;
+        count += 1;
+    }
+    let value_at = |rank: u64| {
+        let mut seen = 0;
+        counts
+            .iter()
+            .position(|&n| {
+                seen += n;
+                seen > rank
+            })
+            .unwrap_or(0) as f64
+    };
+    let (mean, median) = if count == 0 {
+        (0.0, 0.0)
+    } else {
+        (
+            sum as f64 / count as f64,
+            (value_at((count - 1) / 2) + value_at(count / 2)) / 2.0,
+        )
+    };
+    FrameBrightness {
+        index,
+        mean,
+        median,
+    }
+}
+
+/// Frames whose mean or median brightness deviates by more than `sigma` standard deviations
+/// from the sequence, in frame order. The spread is estimated from the median absolute
+/// deviation, so that a run of cloudy frames does not hide itself by inflating it.
+pub fn reject(brightness: &[FrameBrightness], sigma: f64) -> Vec<Rejection> {
+    let means = deviations(&brightness.iter().map(|b| b.mean).collect::<Vec<_>>());
+    let medians = deviations(&brightness.iter().map(|b| b.median).collect::<Vec<_>>());
+    brightness
+        .iter()
+        .zip(means.iter().zip(&medians))
+        .filter_map(|(&brightness, (&mean, &median))| {
+            let deviation = if mean.abs() >= median.abs() {
+                mean
+            } else {
+                median
+            };
+            if deviation.abs() > sigma {
+                Some(Rejection {
+                    brightness,
+                    deviation,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Deviation of each value from the median of the values, in robust standard deviations.
+/// The plain standard deviation is used when more than half of the values are identical,
+/// and every deviation is zero when all of them are.
+fn deviations(values: &[f64]) -> Vec<f64> {
+    let center = median(&mut values.to_vec());
+    let mad = median(
+        &mut values
+            .iter()
+            .map(|value| (value - center).abs())
+            .collect::<Vec<_>>(),
+    );
+    let mut spread = MAD_SCALE * mad;
+    if spread <= 0.0 && !values.is_empty() {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        spread = (values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / values.len() as f64)
+            .sqrt();
+    }
+    values
+        .iter()
+        .map(|value| {
+            if spread > 0.0 {
+                (value - center) / spread
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// Median of some values, which are reordered. Zero if there are none.
+fn median(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// File that the rejection report of a video is written to, which is named after the video
+/// and written next to it
+pub fn rejection_path(video_path: Option<&Path>) -> PathBuf {
+    sidecar_path(video_path, "_rejected.csv")
+}
+
+/// Write rejected frames as CSV with `frame`, `mean`, `median` and `deviation` columns, in
+/// frame order
+pub fn write_csv(rejections: &[Rejection], out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "frame,mean,median,deviation")?;
+    for rejection in rejections {
+        let brightness = &rejection.brightness;
+        writeln!(
+            out,
+            "{},{:.3},{:.3},{:.2}",
+            brightness.index, brightness.mean, brightness.median, rejection.deviation
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Read the frames listed in a rejection report, or any other CSV file with a `frame` column.
+/// Only the frames marked bad are read from files with a `mark` column, such as exported
+/// marks.
+pub fn read_rejected(path: &Path) -> Result<BTreeSet<usize>> {
+    parse_rejected(&fs::read_to_string(path)?)
+}
+
+fn parse_rejected(text: &str) -> Result<BTreeSet<usize>> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().unwrap_or_default().split(',');
+    let names = header.map(str::trim).collect::<Vec<_>>();
+    let frame_column = names
+        .iter()
+        .position(|&name| name == "frame")
+        .ok_or_else(|| PlayerError::Format("The rejection list has no frame column".to_string()))?;
+    let mark_column = names.iter().position(|&name| name == "mark");
+    let mut rejected = BTreeSet::new();
+    for line in lines {
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        if let Some(column) = mark_column {
+            if fields.get(column) != Some(&Mark::Bad.name()) {
+                continue;
+            }
+        }
+        let index = fields
+            .get(frame_column)
+            .and_then(|field| field.parse().ok())
+            .ok_or_else(|| {
+                PlayerError::Format(format!("Invalid line in the rejection list: {}", line))
+            })?;
+        rejected.insert(index);
+    }
+    Ok(rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{ser_format, ser_video};
+    use crate::video_format::Bayer;
+
+    #[test]
+    fn test_reject_outliers() {
+        // steady frames with a little noise, a cloudy frame and a plane crossing a frame
+        let mut frames = (0..10_u8)
+            .map(|i| vec![100 + i % 3; 64])
+            .collect::<Vec<_>>();
+        frames[3] = vec![40; 64];
+        frames[7][..8].copy_from_slice(&[255; 8]);
+        let video = ser_video(&ser_format(8, 8, Bayer::Mono, 8), &frames);
+        let brightness = frame_brightness(&video).unwrap();
+        assert_eq!(10, brightness.len());
+        assert!((brightness[3].median - 40.0).abs() < 1e-9);
+        assert!((brightness[7].median - 101.0).abs() < 1e-9);
+
+        let rejections = reject(&brightness, DEFAULT_SIGMA);
+        assert_eq!(
+            vec![3, 7],
+            rejections
+                .iter()
+                .map(|r| r.brightness.index)
+                .collect::<Vec<_>>()
+        );
+        assert!(rejections[0].deviation < -DEFAULT_SIGMA);
+        assert!(rejections[1].deviation > DEFAULT_SIGMA);
+
+        // identical frames are never rejected
+        assert!(reject(&brightness[..1], DEFAULT_SIGMA).is_empty());
+
+        let mut csv = vec![];
+        write_csv(&rejections, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("frame,mean,median,deviation\n3,40.000,40.000,-"));
+        assert_eq!(
+            vec![3, 7],
+            parse_rejected(&csv)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_rejected() {
+        assert_eq!(
+            vec![2, 5],
+            parse_rejected("frame,mark\n2,bad\n3,good\n\n5,bad\n")
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+        assert!(parse_rejected("index\n1\n").is_err());
+        assert!(parse_rejected("frame\nfirst\n").is_err());
+        assert_eq!(
+            Path::new("/data/jupiter_rejected.csv"),
+            rejection_path(Some(Path::new("/data/jupiter.ser")))
+        );
+    }
+}
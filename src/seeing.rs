@@ -0,0 +1,229 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Seeing measurement for star captures. The full width at half maximum (FWHM) of the
+//! brightest star is measured in each frame, so that the steadiest parts of a capture can be
+//! chosen for stacking and the seeing conditions of a session can be recorded.
+
+use std::f64::consts::PI;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::quality::brightness;
+use crate::sidecar::sidecar_path;
+use crate::video_format::{check_uncompressed, Video};
+
+/// Largest distance from the peak, in measured pixels, that the star is followed out to, so
+/// that a bright nebula or the glow of the moon is not taken for a very large star
+const MAX_RADIUS: usize = 50;
+
+/// How much brighter than the background the star's peak must be, as a fraction of full
+/// scale, for the frame to be measured
+const MIN_CONTRAST: f32 = 0.02;
+
+/// FWHM of the brightest star in a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Seeing {
+    pub index: usize,
+    /// Seconds since the first frame was captured, if the frames have timestamps
+    pub seconds: Option<f64>,
+    /// FWHM in pixels, or `None` if no star stands out from the background
+    pub fwhm: Option<f64>,
+}
+
+/// Measure the FWHM of the brightest star in every frame of a video in parallel, returning
+/// the measurements in frame order
+pub fn measure_seeing(video: &dyn Video) -> Result<Vec<Seeing>> {
+    check_uncompressed(video)?;
+    let start = video.frame_timestamp(0);
+    video.par_map_frames(|index, frame| Seeing {
+        index,
+        seconds: start
+            .zip(video.frame_timestamp(index))
+            .and_then(|(start, time)| time.duration_since(start).ok())
+            .map(|elapsed| elapsed.as_secs_f64()),
+        fwhm: frame_fwhm(video, frame.bytes),
+    })
+}
+
+/// FWHM of the brightest star in a frame, in pixels of the original frame. Bayer frames are
+/// measured at half resolution, where each pixel is a quad of the color filter pattern.
+fn frame_fwhm(video: &dyn Video, bytes: &[u8]) -> Option<f64> {
+    let (width, height, values) = brightness(video, bytes);
+    let scale = video.image_width() as f64 / width.max(1) as f64;
+    star_fwhm(width, height, &values).map(|fwhm| fwhm * scale)
+}
+
+/// FWHM of the brightest star in an image, found from the area of the connected pixels
+/// around the peak that are brighter than halfway between the background and the peak. The
+/// peak is found after averaging each pixel with its neighbours so that a hot pixel is not
+/// taken for a star.
+pub(crate) fn star_fwhm(width: usize, height: usize, values: &[f32]) -> Option<f64> {
+    if width < 3 || height < 3 {
+        return None;
+    }
+    let mut peak = None;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let mut sum = 0.0;
+            for row in y - 1..=y + 1 {
+                sum += values[row * width + x - 1..=row * width + x + 1]
+                    .iter()
+                    .sum::<f32>();
+            }
+            if peak.is_none_or(|(_, _, best)| sum > best) {
+                peak = Some((x, y, sum));
+            }
+        }
+    }
+    let (x, y, _) = peak?;
+    let peak = (y - 1..=y + 1)
+        .flat_map(|row| values[row * width + x - 1..=row * width + x + 1].iter())
+        .copied()
+        .fold(f32::MIN, f32::max);
+    let background = background(values);
+    if peak - background < MIN_CONTRAST {
+        return None;
+    }
+    let half = background + (peak - background) / 2.0;
+
+    // flood fill from the peak, staying within MAX_RADIUS of it
+    let mut inside = vec![false; values.len()];
+    let mut pending = vec![(x, y)];
+    let mut area = 0_usize;
+    while let Some((px, py)) = pending.pop() {
+        let i = py * width + px;
+        if inside[i] || values[i] <= half {
+            continue;
+        }
+        inside[i] = true;
+        area += 1;
+        let neighbours = [
+            (px.wrapping_sub(1), py),
+            (px + 1, py),
+            (px, py.wrapping_sub(1)),
+            (px, py + 1),
+        ];
+        for (nx, ny) in neighbours {
+            if nx < width
+                && ny < height
+                && nx.max(x) - nx.min(x) <= MAX_RADIUS
+                && ny.max(y) - ny.min(y) <= MAX_RADIUS
+            {
+                pending.push((nx, ny));
+            }
+        }
+    }
+    // the diameter of a disc with the same area
+    Some(2.0 * (area as f64 / PI).sqrt())
+}
+
+/// Median brightness, which is the sky background for star fields where the stars cover
+/// only a small part of the frame
+fn background(values: &[f32]) -> f32 {
+    let mut values = values.to_vec();
+    let middle = values.len() / 2;
+    let (_, median, _) = values.select_nth_unstable_by(middle, |a, b| {
+        a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    *median
+}
+
+/// Median FWHM of the frames in which a star was measured
+pub fn median_fwhm(seeing: &[Seeing]) -> Option<f64> {
+    let mut fwhm = seeing.iter().filter_map(|s| s.fwhm).collect::<Vec<_>>();
+    if fwhm.is_empty() {
+        return None;
+    }
+    fwhm.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    Some(fwhm[fwhm.len() / 2])
+}
+
+/// File that the seeing measurements of a video are exported to, which is named after the
+/// video and written next to it
+pub fn seeing_path(video_path: Option<&Path>) -> PathBuf {
+    sidecar_path(video_path, "_seeing.csv")
+}
+
+/// Write measurements as CSV with `frame`, `seconds` and `fwhm` columns, in frame order. The
+/// seconds and FWHM are left empty when they are not known.
+pub fn write_seeing_csv(seeing: &[Seeing], out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "frame,seconds,fwhm")?;
+    for seeing in seeing {
+        let seconds = seeing
+            .seconds
+            .map(|seconds| format!("{:.3}", seconds))
+            .unwrap_or_default();
+        let fwhm = seeing
+            .fwhm
+            .map(|fwhm| format!("{:.2}", fwhm))
+            .unwrap_or_default();
+        writeln!(out, "{},{},{}", seeing.index, seconds, fwhm)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{ser_format, ser_video};
+    use crate::video_format::Bayer;
+
+    /// A 32x32 frame with a Gaussian star of the given FWHM at (12, 20) on a dim background
+    fn star_frame(fwhm: f64) -> Vec<u8> {
+        let sigma = fwhm / (8.0 * 2_f64.ln()).sqrt();
+        (0..32 * 32)
+            .map(|i| {
+                let (x, y) = ((i % 32) as f64 - 12.0, (i / 32) as f64 - 20.0);
+                let star = 200.0 * (-(x * x + y * y) / (2.0 * sigma * sigma)).exp();
+                (20.0 + star).round() as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_measure_seeing() {
+        let mut frames = vec![star_frame(3.0), star_frame(6.0), vec![20; 32 * 32]];
+        // a hot pixel is not taken for the star
+        frames[1][5 * 32 + 5] = 255;
+        let video = ser_video(&ser_format(32, 32, Bayer::Mono, 8), &frames);
+        let seeing = measure_seeing(&video).unwrap();
+        assert_eq!(3, seeing.len());
+        let fwhm = seeing.iter().map(|s| s.fwhm).collect::<Vec<_>>();
+        assert!((fwhm[0].unwrap() - 3.0).abs() < 0.5, "{:?}", fwhm);
+        assert!((fwhm[1].unwrap() - 6.0).abs() < 0.5, "{:?}", fwhm);
+        assert_eq!(None, fwhm[2]);
+        assert_eq!(fwhm[1], median_fwhm(&seeing));
+
+        let mut csv = vec![];
+        write_seeing_csv(&seeing, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("frame,seconds,fwhm\n0,,"));
+        assert!(csv.ends_with("\n2,,\n"));
+        assert_eq!(
+            Path::new("/data/m13_seeing.csv"),
+            seeing_path(Some(Path::new("/data/m13.ser")))
+        );
+    }
+}
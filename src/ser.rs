@@ -0,0 +1,681 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reading SER files from memory and writing SER files. Files on disk are read with
+//! `SerVideo`, which memory-maps the file.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+use crate::error::{PlayerError, Result};
+use crate::video_format::{
+    check_uncompressed, format_utc, top_down_frame, Bayer, Endianness, OpenMode, Video,
+};
+
+/// Size of the fixed SER header
+pub(crate) const HEADER_SIZE: usize = 178;
+
+/// Offset of the frame count within the header
+pub(crate) const FRAME_COUNT_OFFSET: u64 = 38;
+
+/// Offset of the local and UTC capture start times at the end of the header
+const DATE_TIME_OFFSET: u64 = HEADER_SIZE as u64 - 16;
+
+/// Timestamps count 100 ns intervals since the start of year 1
+const TIMESTAMP_TICKS_PER_SECOND: f64 = 10_000_000.0;
+
+/// Number of timestamp ticks between the start of year 1 and the Unix epoch
+const UNIX_EPOCH_TICKS: u64 = 621_355_968_000_000_000;
+
+/// Image format of the frames in a SER file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerFormat {
+    pub width: u32,
+    pub height: u32,
+    pub bayer: Bayer,
+    pub pixel_depth_bits: u32,
+    pub endianness: Endianness,
+}
+
+impl SerFormat {
+    /// Use the same format as an existing video
+    pub fn from_video(video: &dyn Video) -> Self {
+        Self {
+            width: video.image_width(),
+            height: video.image_height(),
+            bayer: *video.bayer(),
+            pixel_depth_bits: video.pixel_depth_bits(),
+            endianness: *video.endianness(),
+        }
+    }
+
+    /// Number of bytes in each frame. Panics if the size cannot be represented, which
+    /// `SerVideo::new` checks for files that are opened.
+    pub fn frame_size(&self) -> usize {
+        self.checked_frame_size()
+            .expect("SER frame size overflows usize")
+    }
+
+    /// Number of bytes in each frame, or `None` if the size cannot be represented
+    pub(crate) fn checked_frame_size(&self) -> Option<usize> {
+        let bytes_per_sample = if self.pixel_depth_bits > 8 { 2 } else { 1 };
+        (self.width as usize)
+            .checked_mul(self.height as usize)?
+            .checked_mul(self.bayer.planes() * bytes_per_sample)
+    }
+}
+
+/// The values in a SER header, after checking them against the size of the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerHeader {
+    pub format: SerFormat,
+    pub frame_count: usize,
+    /// Color ID as recorded, which is also available as `format.bayer`
+    pub color_id: u32,
+    /// Camera series ID, which is usually zero
+    pub lu_id: u32,
+    pub observer: String,
+    pub instrument: String,
+    pub telescope: String,
+    /// Capture start in local time and in UTC, as timestamps
+    pub date_time: u64,
+    pub date_time_utc: u64,
+}
+
+impl SerHeader {
+    /// Parse the header from the start of a file that is `file_len` bytes long. Frames that
+    /// extend past the end of the file are always an error. In strict mode, any data after the
+    /// frames other than a timestamp trailer is also an error, as are unknown color IDs and
+    /// bit depths above 16.
+    pub fn parse(bytes: &[u8], file_len: u64, mode: OpenMode) -> Result<Self> {
+        if bytes.len() < HEADER_SIZE || !bytes[0..14].eq_ignore_ascii_case(b"LUCAM-RECORDER") {
+            return Err(PlayerError::Format("Not a SER file".to_string()));
+        }
+        let field = |offset: usize| LittleEndian::read_u32(&bytes[offset..offset + 4]);
+        let format = SerFormat {
+            bayer: Bayer::from_color_id(field(18)),
+            // ser-io reads zero as little endian
+            endianness: match field(22) {
+                0 => Endianness::LittleEndian,
+                _ => Endianness::BigEndian,
+            },
+            width: field(26),
+            height: field(30),
+            pixel_depth_bits: field(34),
+        };
+        let frame_count = field(38) as usize;
+        if format.width == 0 || format.height == 0 {
+            return Err(PlayerError::Format(format!(
+                "Invalid SER image size {}x{}",
+                format.width, format.height
+            )));
+        }
+        let frame_size = format.checked_frame_size().ok_or_else(|| {
+            PlayerError::Format(format!(
+                "Invalid SER image size {}x{}",
+                format.width, format.height
+            ))
+        })?;
+        let available = (file_len.saturating_sub(HEADER_SIZE as u64) / frame_size as u64) as usize;
+        if available < frame_count {
+            return Err(PlayerError::Format(format!(
+                "SER header declares {} frames but the file only contains {}",
+                frame_count, available
+            )));
+        }
+        // samples are at most 16 bits, so deeper frames cannot be decoded even leniently
+        if format.pixel_depth_bits > 16
+            || (mode == OpenMode::Strict && format.pixel_depth_bits == 0)
+        {
+            return Err(PlayerError::Format(format!(
+                "Invalid SER bit depth {}",
+                format.pixel_depth_bits
+            )));
+        }
+        if mode == OpenMode::Strict {
+            if let Bayer::Unknown(id) = format.bayer {
+                return Err(PlayerError::Format(format!("Unknown SER color ID {}", id)));
+            }
+            let trailer = file_len - HEADER_SIZE as u64 - frame_size as u64 * frame_count as u64;
+            if trailer != 0 && trailer != 8 * frame_count as u64 {
+                return Err(PlayerError::Format(format!(
+                    "SER file has {} unexpected bytes after the last frame",
+                    trailer
+                )));
+            }
+        }
+        let text = |offset: usize| {
+            String::from_utf8_lossy(&bytes[offset..offset + 40])
+                .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string()
+        };
+        let date_time = |offset: u64| LittleEndian::read_u64(&bytes[offset as usize..]);
+        Ok(Self {
+            format,
+            frame_count,
+            color_id: field(18),
+            lu_id: field(14),
+            observer: text(42),
+            instrument: text(82),
+            telescope: text(122),
+            date_time: date_time(DATE_TIME_OFFSET),
+            date_time_utc: date_time(DATE_TIME_OFFSET + 8),
+        })
+    }
+
+    /// Header values that are not part of the image format, as `(name, value)` pairs for
+    /// `Video::header_fields`. Empty strings and unset times are left out.
+    pub fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = vec![
+            ("SER color ID".to_string(), self.color_id.to_string()),
+            ("SER LuID".to_string(), self.lu_id.to_string()),
+        ];
+        for (name, value) in [
+            ("Observer", &self.observer),
+            ("Instrument", &self.instrument),
+            ("Telescope", &self.telescope),
+        ] {
+            if !value.is_empty() {
+                fields.push((name.to_string(), value.clone()));
+            }
+        }
+        for (name, ticks) in [
+            ("Capture start (local)", self.date_time),
+            ("Capture start (UTC)", self.date_time_utc),
+        ] {
+            if let Some(time) = timestamp_to_time(ticks).filter(|_| ticks != 0) {
+                // the local time is formatted as if it were UTC
+                let time = format_utc(time).trim_end_matches(" UTC").to_string();
+                fields.push((name.to_string(), time));
+            }
+        }
+        fields
+    }
+
+    /// Offset of the optional timestamp trailer, which follows the last frame
+    pub fn trailer_offset(&self) -> u64 {
+        HEADER_SIZE as u64 + self.format.frame_size() as u64 * self.frame_count as u64
+    }
+}
+
+/// Parse the timestamp trailer, returning one timestamp per frame, or an empty list if the
+/// file has no trailer
+pub fn parse_timestamps(trailer: &[u8], frame_count: usize) -> Vec<u64> {
+    if frame_count == 0 || trailer.len() < 8 * frame_count {
+        return vec![];
+    }
+    trailer
+        .chunks_exact(8)
+        .take(frame_count)
+        .map(LittleEndian::read_u64)
+        .collect()
+}
+
+/// Average frame rate of a capture, based on the timestamps of the first and last frames
+pub fn frame_rate(timestamps: &[u64]) -> Option<f64> {
+    match (timestamps.first(), timestamps.last()) {
+        (Some(first), Some(last)) if last > first => {
+            Some((timestamps.len() - 1) as f64 * TIMESTAMP_TICKS_PER_SECOND / (last - first) as f64)
+        }
+        _ => None,
+    }
+}
+
+/// Convert a timestamp to a time. Timestamps before 1970 are treated as missing, because
+/// capture software writes zero when the time is not known.
+pub fn timestamp_to_time(ticks: u64) -> Option<SystemTime> {
+    let ticks = ticks.checked_sub(UNIX_EPOCH_TICKS)?;
+    Some(UNIX_EPOCH + Duration::from_nanos(ticks.checked_mul(100)?))
+}
+
+/// Convert a time to a timestamp, or `None` for times before 1970
+pub fn time_to_timestamp(time: SystemTime) -> Option<u64> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    let ticks = u64::try_from(since_epoch.as_nanos() / 100).ok()?;
+    UNIX_EPOCH_TICKS.checked_add(ticks)
+}
+
+/// Writes frames to a SER file. The frame count in the header is updated by `finish`, which
+/// also writes the timestamp trailer if every frame was written with a timestamp.
+pub struct SerWriter<W: Write + Seek> {
+    out: W,
+    frame_size: usize,
+    frame_count: u32,
+    timestamps: Vec<u64>,
+}
+
+impl SerWriter<BufWriter<File>> {
+    pub fn create(path: &Path, format: &SerFormat) -> Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+impl<W: Write + Seek> SerWriter<W> {
+    pub fn new(mut out: W, format: &SerFormat) -> Result<Self> {
+        let color_id = match format.bayer {
+            Bayer::Unknown(id) => {
+                return Err(PlayerError::Unsupported(format!(
+                    "Cannot write SER file with unknown color ID {}",
+                    id
+                )))
+            }
+            bayer => bayer.color_id(),
+        };
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(b"LUCAM-RECORDER");
+        header.write_i32::<LittleEndian>(0)?; // LuID
+        header.write_u32::<LittleEndian>(color_id)?;
+        // ser-io reads zero as little endian
+        header.write_i32::<LittleEndian>(match format.endianness {
+            Endianness::LittleEndian => 0,
+            Endianness::BigEndian => 1,
+        })?;
+        header.write_u32::<LittleEndian>(format.width)?;
+        header.write_u32::<LittleEndian>(format.height)?;
+        header.write_u32::<LittleEndian>(format.pixel_depth_bits)?;
+        header.write_u32::<LittleEndian>(0)?; // frame count
+        header.resize(HEADER_SIZE - 16, 0); // observer, instrument, telescope
+        header.write_i64::<LittleEndian>(0)?; // local date time
+        header.write_i64::<LittleEndian>(0)?; // UTC date time
+        debug_assert_eq!(HEADER_SIZE, header.len());
+        out.write_all(&header)?;
+        Ok(Self {
+            out,
+            frame_size: format.frame_size(),
+            frame_count: 0,
+            timestamps: vec![],
+        })
+    }
+
+    /// Append a raw frame along with the time that it was captured
+    pub fn write_frame_at(&mut self, bytes: &[u8], time: SystemTime) -> Result<()> {
+        let timestamp = time_to_timestamp(time).ok_or_else(|| {
+            PlayerError::InvalidArgument("Cannot write a timestamp before 1970".to_string())
+        })?;
+        self.write_frame(bytes)?;
+        self.timestamps.push(timestamp);
+        Ok(())
+    }
+
+    /// Append a raw frame, which must be in the format that the writer was created with
+    pub fn write_frame(&mut self, bytes: &[u8]) -> Result<()> {
+        if bytes.len() != self.frame_size {
+            return Err(PlayerError::Format(format!(
+                "Frame is {} bytes but expected {} bytes",
+                bytes.len(),
+                self.frame_size
+            )));
+        }
+        self.out.write_all(bytes)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Write the frame count to the header, along with the timestamp trailer and capture
+    /// start time if every frame has a timestamp, and flush the output
+    pub fn finish(mut self) -> Result<W> {
+        let timestamped =
+            self.frame_count > 0 && self.timestamps.len() == self.frame_count as usize;
+        if timestamped {
+            for timestamp in &self.timestamps {
+                self.out.write_u64::<LittleEndian>(*timestamp)?;
+            }
+        }
+        self.out.seek(SeekFrom::Start(FRAME_COUNT_OFFSET))?;
+        self.out.write_u32::<LittleEndian>(self.frame_count)?;
+        if timestamped {
+            // the time zone of the capture is not known, so both times are UTC
+            self.out.seek(SeekFrom::Start(DATE_TIME_OFFSET))?;
+            self.out.write_u64::<LittleEndian>(self.timestamps[0])?;
+            self.out.write_u64::<LittleEndian>(self.timestamps[0])?;
+        }
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Copy frames of a video to a new SER file in their raw format, keeping their timestamps.
+/// This is used to save trimmed or edited clips for stacking software, and to convert AVI
+/// captures, which most stacking software handles far better as SER. Bottom-up frames are
+/// flipped because SER frames are always stored top-down.
+pub fn write_clip(video: &dyn Video, indices: &[usize], path: &Path) -> Result<()> {
+    check_uncompressed(video)?;
+    let mut writer = SerWriter::create(path, &SerFormat::from_video(video))?;
+    for &index in indices {
+        let frame = video.read_frame(index)?;
+        // any bytes after the end of the image are not part of the SER frame
+        let frame = &frame[..frame.len().min(writer.frame_size)];
+        let bytes = top_down_frame(video, frame);
+        match video.frame_timestamp(index) {
+            Some(time) => writer.write_frame_at(&bytes, time)?,
+            None => writer.write_frame(&bytes)?,
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// A SER file that has been read into memory, for platforms such as the browser where files
+/// cannot be memory-mapped
+pub struct SerBytes {
+    format: SerFormat,
+    frame_count: usize,
+    header_fields: Vec<(String, String)>,
+    timestamps: Vec<u64>,
+    bytes: Vec<u8>,
+}
+
+impl SerBytes {
+    pub fn new(bytes: Vec<u8>, mode: OpenMode) -> Result<Self> {
+        let header = SerHeader::parse(&bytes, bytes.len() as u64, mode)?;
+        let trailer = &bytes[header.trailer_offset() as usize..];
+        Ok(Self {
+            timestamps: parse_timestamps(trailer, header.frame_count),
+            header_fields: header.fields(),
+            format: header.format,
+            frame_count: header.frame_count,
+            bytes,
+        })
+    }
+}
+
+impl Video for SerBytes {
+    fn image_width(&self) -> u32 {
+        self.format.width
+    }
+
+    fn image_height(&self) -> u32 {
+        self.format.height
+    }
+
+    fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        if self.format.pixel_depth_bits > 8 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.format.pixel_depth_bits
+    }
+
+    fn bayer(&self) -> &Bayer {
+        &self.format.bayer
+    }
+
+    fn endianness(&self) -> &Endianness {
+        &self.format.endianness
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        frame_rate(&self.timestamps)
+    }
+
+    fn frame_timestamp(&self, index: usize) -> Option<SystemTime> {
+        self.timestamps
+            .get(index)
+            .and_then(|ticks| timestamp_to_time(*ticks))
+    }
+
+    fn get_frame(&self, index: usize) -> Result<&[u8]> {
+        if index >= self.frame_count {
+            return Err(PlayerError::Decode(format!(
+                "Invalid frame index {}",
+                index
+            )));
+        }
+        let size = self.format.frame_size();
+        let offset = HEADER_SIZE + index * size;
+        Ok(&self.bytes[offset..offset + size])
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.header_fields.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::avi::AviFile;
+    use crate::testutil::{avi_stream_bytes, ser_bytes, ser_format, AviStream, TempFile};
+    use crate::video_format::{AviVideo, FormatOverrides, OverrideVideo};
+    use std::sync::Arc;
+
+    /// A SER file where every byte of frame `i` is `i`
+    fn build_ser(format: &SerFormat, frame_count: usize) -> Vec<u8> {
+        let frames = (0..frame_count)
+            .map(|i| vec![i as u8; format.frame_size()])
+            .collect::<Vec<_>>();
+        ser_bytes(format, &frames)
+    }
+
+    #[test]
+    fn test_write_header() {
+        let format = ser_format(4, 2, Bayer::RGGB, 16);
+        let mut writer = SerWriter::new(Cursor::new(vec![]), &format).unwrap();
+        writer.write_frame(&[0; 16]).unwrap();
+        writer.write_frame(&[1; 16]).unwrap();
+        assert!(writer.write_frame(&[1; 8]).is_err());
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(HEADER_SIZE + 32, bytes.len());
+        assert_eq!(b"LUCAM-RECORDER", &bytes[0..14]);
+        assert_eq!(8, bytes[18]);
+        assert_eq!(4, bytes[26]);
+        assert_eq!(2, bytes[30]);
+        assert_eq!(16, bytes[34]);
+        assert_eq!(2, bytes[38]);
+        assert_eq!(1, bytes[HEADER_SIZE + 16]);
+
+        let video = SerBytes::new(bytes, OpenMode::Strict).unwrap();
+        assert_eq!(format, SerFormat::from_video(&video));
+        assert_eq!(2, video.frame_count());
+        assert_eq!(&[1; 16], &*video.read_frame(1).unwrap());
+        assert!(video.read_frame(2).is_err());
+    }
+
+    #[test]
+    fn test_write_timestamps() {
+        let format = ser_format(2, 2, Bayer::Mono, 8);
+        let start = timestamp_to_time(637_677_049_815_000_000).unwrap();
+        let mut writer = SerWriter::new(Cursor::new(vec![]), &format).unwrap();
+        writer.write_frame_at(&[0; 4], start).unwrap();
+        writer
+            .write_frame_at(&[1; 4], start + Duration::from_millis(40))
+            .unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+        assert_eq!(HEADER_SIZE + 8 + 16, bytes.len());
+        assert_eq!(
+            637_677_049_815_000_000,
+            LittleEndian::read_u64(&bytes[HEADER_SIZE - 8..])
+        );
+
+        let video = SerBytes::new(bytes, OpenMode::Strict).unwrap();
+        assert_eq!(Some(start), video.frame_timestamp(0));
+        assert_eq!(25, video.frame_rate().unwrap().round() as i32);
+
+        // the trailer is left out unless every frame has a timestamp
+        let mut writer = SerWriter::new(Cursor::new(vec![]), &format).unwrap();
+        writer.write_frame_at(&[0; 4], start).unwrap();
+        writer.write_frame(&[1; 4]).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+        assert_eq!(HEADER_SIZE + 8, bytes.len());
+        assert_eq!(0, LittleEndian::read_u64(&bytes[HEADER_SIZE - 8..]));
+    }
+
+    #[test]
+    fn test_convert_avi() {
+        // raw 8-bit frames with rows of 6 samples, which are padded to 8 bytes in the AVI
+        let frames = (0..2)
+            .map(|i| {
+                (0..32)
+                    .map(|b| if b % 8 < 6 { i * 32 + b } else { 255 })
+                    .collect::<Vec<u8>>()
+            })
+            .collect::<Vec<_>>();
+        let avi = TempFile::new(
+            ".avi",
+            &avi_stream_bytes(6, 4, 40_000, &AviStream::RAW8, &frames),
+        );
+        let video = AviVideo {
+            avi: AviFile::open(avi.path_str()).unwrap(),
+        };
+        // every reader sees the frames without padding
+        assert_eq!(6 * 4, video.read_frame(0).unwrap().len());
+        // AVI files do not record the Bayer pattern, so it is chosen when converting
+        let overrides = FormatOverrides {
+            bayer: Some(Bayer::RGGB),
+            ..FormatOverrides::default()
+        };
+        let video = OverrideVideo::new(Arc::new(video), overrides);
+        let ser = TempFile::new(".ser", &[]);
+        write_clip(&video, &[1], ser.path()).unwrap();
+
+        let converted =
+            SerBytes::new(std::fs::read(ser.path()).unwrap(), OpenMode::Strict).unwrap();
+        assert_eq!(Bayer::RGGB, *converted.bayer());
+        assert_eq!(
+            (6, 4, 1),
+            (
+                converted.image_width(),
+                converted.image_height(),
+                converted.frame_count()
+            )
+        );
+        let expected = (0..32)
+            .filter(|b| b % 8 < 6)
+            .map(|b| 32 + b)
+            .collect::<Vec<u8>>();
+        assert_eq!(expected, *converted.read_frame(0).unwrap());
+    }
+
+    #[test]
+    fn test_header_fields() {
+        let format = ser_format(2, 2, Bayer::Mono, 8);
+        let mut bytes = build_ser(&format, 1);
+        bytes[82..88].copy_from_slice(b"ASI224");
+        bytes[122..128].copy_from_slice(b"C11   ");
+        let header = SerHeader::parse(&bytes, bytes.len() as u64, OpenMode::Strict).unwrap();
+        assert_eq!("", header.observer);
+        assert_eq!("ASI224", header.instrument);
+        assert_eq!("C11", header.telescope);
+
+        let video = SerBytes::new(bytes, OpenMode::Strict).unwrap();
+        let fields = video.header_fields();
+        assert_eq!(("SER color ID".to_string(), "0".to_string()), fields[0]);
+        assert!(fields.contains(&("Instrument".to_string(), "ASI224".to_string())));
+        assert!(!fields.iter().any(|(name, _)| name == "Observer"));
+    }
+
+    #[test]
+    fn test_strict_trailer() {
+        let format = ser_format(3, 2, Bayer::Mono, 8);
+        let mut bytes = build_ser(&format, 2);
+        // timestamps
+        bytes.extend_from_slice(&10_000_000_u64.to_le_bytes());
+        bytes.extend_from_slice(&10_500_000_u64.to_le_bytes());
+        let video = SerBytes::new(bytes.clone(), OpenMode::Strict).unwrap();
+        assert_eq!(Some(20.0), video.frame_rate());
+        // the timestamps are before 1970
+        assert_eq!(None, video.frame_timestamp(0));
+        bytes.push(0);
+        assert!(SerBytes::new(bytes.clone(), OpenMode::Strict).is_err());
+        assert!(SerBytes::new(bytes, OpenMode::Lenient).is_ok());
+    }
+
+    #[test]
+    fn test_timestamp_to_time() {
+        assert_eq!(Some(UNIX_EPOCH), timestamp_to_time(UNIX_EPOCH_TICKS));
+        // 2021-09-20 03:23:01.5 UTC
+        let time = timestamp_to_time(637_677_049_815_000_000).unwrap();
+        assert_eq!(
+            Duration::from_millis(1_632_108_181_500),
+            time.duration_since(UNIX_EPOCH).unwrap()
+        );
+        assert_eq!(None, timestamp_to_time(0));
+    }
+
+    /// Dimensions whose frame size overflows, or is far larger than the file, must be rejected
+    /// before anything is allocated for them
+    #[test]
+    fn test_huge_dimensions() {
+        for (width, height, frame_count) in [(u32::MAX, u32::MAX, 0), (65536, 65536, 1)] {
+            let format = ser_format(1, 1, Bayer::RGB, 16);
+            let mut bytes = build_ser(&format, 1);
+            bytes[26..30].copy_from_slice(&width.to_le_bytes());
+            bytes[30..34].copy_from_slice(&height.to_le_bytes());
+            bytes[38..42].copy_from_slice(&(frame_count as u32).to_le_bytes());
+            for mode in [OpenMode::Lenient, OpenMode::Strict] {
+                let err = SerBytes::new(bytes.clone(), mode).err().unwrap();
+                assert!(err.to_string().contains("SER"), "{}", err);
+            }
+        }
+    }
+
+    /// Parsing arbitrary corruptions of a valid header must never panic
+    #[test]
+    fn test_corrupt_files() {
+        let format = SerFormat {
+            width: 3,
+            height: 2,
+            bayer: Bayer::RGGB,
+            pixel_depth_bits: 12,
+            endianness: Endianness::BigEndian,
+        };
+        let valid = build_ser(&format, 4);
+        let mut rng = StdRng::seed_from_u64(1230);
+        for _ in 0..2000 {
+            let mut bytes = valid.clone();
+            if rng.gen_bool(0.2) {
+                bytes.truncate(rng.gen_range(0..valid.len()));
+            } else {
+                // corrupt the color ID, endianness, size, depth or frame count
+                let i = rng.gen_range(18..42);
+                bytes[i] = rng.gen();
+            }
+            for mode in [OpenMode::Lenient, OpenMode::Strict] {
+                if let Ok(video) = SerBytes::new(bytes.clone(), mode) {
+                    for index in 0..video.frame_count() {
+                        video.read_frame(index).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Bilinear debayer matching `BilinearDebayerCodec`, see `gpu.rs`. Each invocation writes one
+// BGRA output pixel, estimating the missing colors from the nearest pixels of each color in
+// the surrounding 3x3 neighborhood.
+
+[[block]]
+struct Params {
+    width: u32;
+    height: u32;
+    scale: u32;
+    out_width: u32;
+    out_height: u32;
+    pixel_depth_bits: u32;
+    // channel (0 red, 1 green, 2 blue) of each pixel in a 2x2 quad, one per byte
+    colors: u32;
+};
+
+// 16-bit samples, two per word
+[[block]]
+struct Samples {
+    data: [[stride(4)]] array<u32>;
+};
+
+// BGRA pixels, one per word
+[[block]]
+struct Pixels {
+    data: [[stride(4)]] array<u32>;
+};
+
+[[group(0), binding(0)]] var<uniform> params: Params;
+[[group(0), binding(1)]] var<storage> samples: [[access(read)]] Samples;
+[[group(0), binding(2)]] var<storage> pixels: [[access(read_write)]] Pixels;
+
+[[builtin(global_invocation_id)]] var global_id: vec3<u32>;
+
+fn sample(x: u32, y: u32) -> u32 {
+    const index: u32 = y * params.width + x;
+    return (samples.data[index / 2u] >> ((index % 2u) * 16u)) & 65535u;
+}
+
+fn channel(x: u32, y: u32) -> u32 {
+    return (params.colors >> (((y % 2u) * 2u + x % 2u) * 8u)) & 255u;
+}
+
+fn to_u8(value: u32) -> u32 {
+    const max_value: u32 = (1u << params.pixel_depth_bits) - 1u;
+    return min((min(value, max_value) * 255u) >> params.pixel_depth_bits, 255u);
+}
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn main() {
+    if (global_id.x >= params.out_width || global_id.y >= params.out_height) {
+        return;
+    }
+    const x: u32 = global_id.x * params.scale;
+    const y: u32 = global_id.y * params.scale;
+
+    var left: u32 = 0u;
+    if (x > 0u) {
+        left = x - 1u;
+    }
+    var top: u32 = 0u;
+    if (y > 0u) {
+        top = y - 1u;
+    }
+    const right: u32 = min(x + 2u, params.width);
+    const bottom: u32 = min(y + 2u, params.height);
+
+    var red: u32 = 0u;
+    var green: u32 = 0u;
+    var blue: u32 = 0u;
+    var red_count: u32 = 0u;
+    var green_count: u32 = 0u;
+    var blue_count: u32 = 0u;
+    var ny: u32 = top;
+    loop {
+        if (ny >= bottom) {
+            break;
+        }
+        var nx: u32 = left;
+        loop {
+            if (nx >= right) {
+                break;
+            }
+            const color: u32 = channel(nx, ny);
+            const value: u32 = sample(nx, ny);
+            if (color == 0u) {
+                red = red + value;
+                red_count = red_count + 1u;
+            } else {
+                if (color == 1u) {
+                    green = green + value;
+                    green_count = green_count + 1u;
+                } else {
+                    blue = blue + value;
+                    blue_count = blue_count + 1u;
+                }
+            }
+            nx = nx + 1u;
+        }
+        ny = ny + 1u;
+    }
+    red = red / max(red_count, 1u);
+    green = green / max(green_count, 1u);
+    blue = blue / max(blue_count, 1u);
+
+    const own: u32 = channel(x, y);
+    if (own == 0u) {
+        red = sample(x, y);
+    } else {
+        if (own == 1u) {
+            green = sample(x, y);
+        } else {
+            blue = sample(x, y);
+        }
+    }
+
+    pixels.data[global_id.y * params.out_width + global_id.x] =
+        to_u8(blue) | (to_u8(green) << 8u) | (to_u8(red) << 16u) | (255u << 24u);
+}
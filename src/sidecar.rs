@@ -0,0 +1,66 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Names of the files that are written next to a video, such as exported frames, reports and
+//! saved player state
+
+use std::path::{Path, PathBuf};
+
+/// File named after a video with `suffix` in place of its extension, in the same directory
+/// as the video, such as `jupiter_marks.csv` for `jupiter.ser` and a suffix of `_marks.csv`.
+/// Files for a video without a path, such as a live capture, are named `video` followed by
+/// the suffix in the current directory.
+pub fn sidecar_path(video_path: Option<&Path>, suffix: &str) -> PathBuf {
+    let stem = video_path
+        .and_then(|path| path.file_stem())
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "video".to_string());
+    let name = format!("{}{}", stem, suffix);
+    match video_path.and_then(|path| path.parent()) {
+        Some(directory) => directory.join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(
+            Path::new("/data/2021-09-14").join("jupiter_marks.csv"),
+            sidecar_path(
+                Some(Path::new("/data/2021-09-14/jupiter.ser")),
+                "_marks.csv"
+            )
+        );
+        assert_eq!(
+            PathBuf::from("jupiter.avpstate.json"),
+            sidecar_path(Some(Path::new("jupiter.ser")), ".avpstate.json")
+        );
+        assert_eq!(
+            PathBuf::from("video_stack.png"),
+            sidecar_path(None, "_stack.png")
+        );
+    }
+}
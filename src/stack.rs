@@ -0,0 +1,505 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Combining multiple frames into a single image
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::Path;
+
+use rayon::prelude::*;
+use tracing::instrument;
+
+use crate::align::{
+    frame_shifts, pattern_period, shift_samples, source_position, source_rows, Shift,
+};
+use crate::codec::ImageCodec;
+use crate::error::{PlayerError, Result};
+use crate::export::{export_frame, save_png, ExportFormat};
+use crate::processing::DecodedFrame;
+use crate::quality::{rank, score_selected, sharpest};
+use crate::video_format::{
+    check_uncompressed, samples, top_down_frame, write_samples, Compression, FrameView, Video,
+};
+
+/// Memory used to hold the samples of every frame for a band of the image while median
+/// combining, which limits the memory used for long captures
+const MEDIAN_BAND_BYTES: usize = 256 << 20;
+
+/// How the samples of the stacked frames are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combine {
+    Mean,
+    /// The median rejects outliers such as satellite trails and hot pixels, but is slower
+    Median,
+}
+
+/// Names of the combine methods, as accepted by `parse_combine`
+pub const COMBINE_NAMES: &[&str] = &["mean", "median"];
+
+pub fn parse_combine(name: &str) -> Result<Combine> {
+    match name.to_ascii_lowercase().as_str() {
+        "mean" | "average" => Ok(Combine::Mean),
+        "median" => Ok(Combine::Median),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown combine method '{}'",
+            name
+        ))),
+    }
+}
+
+/// Choose frames to stack for lucky imaging: the sharpest `fraction` of the given frames, in
+/// frame order, along with the shifts that align them with the sharpest frame when `align`
+/// is set. No frames are scored if all of them are stacked without aligning them.
+pub fn lucky_frames(
+    video: &dyn Video,
+    indices: &[usize],
+    fraction: f64,
+    align: bool,
+) -> Result<(Vec<usize>, Vec<Shift>)> {
+    if fraction >= 1.0 && !align {
+        return Ok((indices.to_vec(), vec![]));
+    }
+    let ranked = rank(&score_selected(video, indices)?);
+    let selected = if fraction >= 1.0 {
+        indices.to_vec()
+    } else {
+        sharpest(&ranked, fraction)
+    };
+    let shifts = match ranked.first() {
+        Some(best) if align => frame_shifts(video, &selected, best.index)?,
+        _ => vec![],
+    };
+    Ok((selected, shifts))
+}
+
+/// Combine the raw samples of the frames with the given indices in full precision. The
+/// result is a raw frame in the same format as the frames of the video, so it can be
+/// debayered and exported like any other frame.
+///
+/// Frames are translated by the corresponding `shifts` before they are combined, which can
+/// be found with `align::frame_shifts`. No frames are translated if `shifts` is empty.
+#[instrument(skip(video, indices, shifts), fields(frames = indices.len()))]
+pub fn stack_raw(
+    video: &dyn Video,
+    indices: &[usize],
+    shifts: &[Shift],
+    combine: Combine,
+) -> Result<Vec<u8>> {
+    check_uncompressed(video)?;
+    if indices.is_empty() {
+        return Err(PlayerError::Format("No frames to stack".to_string()));
+    }
+    if !shifts.is_empty() && shifts.len() != indices.len() {
+        return Err(PlayerError::InvalidArgument(format!(
+            "{} shifts were given for {} frames",
+            shifts.len(),
+            indices.len()
+        )));
+    }
+    let bytes_per_sample = video.bytes_per_pixel().max(1) as usize;
+    let sample_count = video.read_frame(indices[0])?.len() / bytes_per_sample;
+    let too_small = |index: usize| {
+        PlayerError::Decode(format!(
+            "Frame {} is smaller than frame {}",
+            index, indices[0]
+        ))
+    };
+    let (width, height) = (video.image_width() as usize, video.image_height() as usize);
+    let channels = video.bayer().planes();
+    if !shifts.is_empty() && sample_count < width * height * channels {
+        return Err(PlayerError::Decode(format!(
+            "Frame {} is too small for a {}x{} image",
+            indices[0], width, height
+        )));
+    }
+    let period = pattern_period(video);
+    let stacked = match combine {
+        Combine::Mean => {
+            // the frames are read with their rows in top-down order, which is the order that
+            // the shifts are measured in
+            let mut sum = vec![0_u64; sample_count];
+            for (i, &index) in indices.iter().enumerate() {
+                let mut values = video.get_frame_u16(index)?;
+                if values.len() < sample_count {
+                    return Err(too_small(index));
+                }
+                if let Some(shift) = shifts.get(i).filter(|shift| **shift != Shift::default()) {
+                    values = Cow::Owned(shift_samples(
+                        &values, width, height, channels, period, *shift,
+                    ));
+                }
+                for (total, value) in sum.iter_mut().zip(values.iter()) {
+                    *total += *value as u64;
+                }
+            }
+            let count = indices.len() as u64;
+            sum.into_iter()
+                .map(|total| ((total + count / 2) / count) as u16)
+                .collect::<Vec<_>>()
+        }
+        Combine::Median => {
+            let frames = indices.len();
+            let band = (MEDIAN_BAND_BYTES / 2 / frames).max(1).min(sample_count);
+            let mut stacked = Vec::with_capacity(sample_count);
+            // the samples of each frame for one position are next to each other
+            let mut values = vec![0_u16; band * frames];
+            for start in (0..sample_count).step_by(band.max(1)) {
+                let len = band.min(sample_count - start);
+                for (i, &index) in indices.iter().enumerate() {
+                    let bytes;
+                    let band_values: Box<dyn Iterator<Item = u16>> = match shifts.get(i) {
+                        // only the band is translated, rather than the whole frame each time
+                        Some(shift) if *shift != Shift::default() => {
+                            let band = ShiftedBand {
+                                index,
+                                shift: *shift,
+                                width,
+                                height,
+                                channels,
+                            };
+                            Box::new(band.read(video, start..start + len)?.into_iter())
+                        }
+                        _ => {
+                            // only the band is read, for frames that are read from disk
+                            let range = start * bytes_per_sample..(start + len) * bytes_per_sample;
+                            bytes = video.read_frame_range(index, range)?;
+                            samples(video, &bytes)
+                        }
+                    };
+                    for (j, value) in band_values.enumerate() {
+                        values[j * frames + i] = value;
+                    }
+                }
+                stacked.par_extend(values[..len * frames].par_chunks_mut(frames).map(median));
+            }
+            stacked
+        }
+    };
+    let mut bytes = vec![];
+    write_samples(video, &stacked, &mut bytes);
+    match combine {
+        // flip the rows back to the order that the video stores them in
+        Combine::Mean => Ok(top_down_frame(video, &bytes).into_owned()),
+        Combine::Median => Ok(bytes),
+    }
+}
+
+/// Part of a frame after translating it by `shift`, see `align::shift_samples`
+struct ShiftedBand {
+    index: usize,
+    shift: Shift,
+    width: usize,
+    height: usize,
+    channels: usize,
+}
+
+impl ShiftedBand {
+    /// Samples `range` of the translated frame. Only the rows that they are translated from
+    /// are read, and any samples after the image are not translated.
+    fn read(&self, video: &dyn Video, range: Range<usize>) -> Result<Vec<u16>> {
+        let bytes_per_sample = video.bytes_per_pixel().max(1) as usize;
+        let period = pattern_period(video);
+        // shifts are measured on top-down images, so frames that are stored bottom-up move
+        // the other way in the stored rows that are read here
+        let shift = match video.bottom_up() {
+            true => Shift {
+                dx: self.shift.dx,
+                dy: -self.shift.dy,
+            },
+            false => self.shift,
+        };
+        let row = self.width * self.channels;
+        let image_end = range.end.min(row * self.height);
+        let mut band = Vec::with_capacity(range.len());
+        if range.start < image_end {
+            let rows = range.start / row..(image_end - 1) / row + 1;
+            let rows = source_rows(rows, self.height, period, shift);
+            let bytes = video.read_frame_range(
+                self.index,
+                rows.start * row * bytes_per_sample..rows.end * row * bytes_per_sample,
+            )?;
+            let source = samples(video, &bytes).collect::<Vec<_>>();
+            for i in range.start..image_end {
+                let (y, x) = ((i / row) as i64, (i % row / self.channels) as i64);
+                let source_y = source_position(y - shift.dy as i64, self.height, period);
+                let source_x = source_position(x - shift.dx as i64, self.width, period);
+                let j =
+                    (source_y - rows.start) * row + source_x * self.channels + i % self.channels;
+                band.push(source[j]);
+            }
+        }
+        let rest = range.start.max(image_end)..range.end;
+        if !rest.is_empty() {
+            let bytes = video.read_frame_range(
+                self.index,
+                rest.start * bytes_per_sample..rest.end * bytes_per_sample,
+            )?;
+            band.extend(samples(video, &bytes));
+        }
+        Ok(band)
+    }
+}
+
+/// Median of some values, averaging the middle two if there are an even number of them
+fn median(values: &mut [u16]) -> u16 {
+    let even = values.len().is_multiple_of(2);
+    let (lower, upper, _) = values.select_nth_unstable(values.len() / 2);
+    match lower.iter().max() {
+        Some(below) if even => (*below as u32 + *upper as u32).div_ceil(2) as u16,
+        _ => *upper,
+    }
+}
+
+/// Stack frames with `stack_raw` and save the result in the given format. PNG images are
+/// debayered with the codec, while TIFF and FITS images keep the stacked raw samples.
+/// Compressed frames have no raw samples, so they can only be averaged without aligning them
+/// after decoding them, and saved as PNG.
+pub fn export_stack(
+    video: &dyn Video,
+    codec: &dyn ImageCodec,
+    indices: &[usize],
+    shifts: &[Shift],
+    combine: Combine,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<()> {
+    if video.compression() != Compression::None
+        && shifts.is_empty()
+        && combine == Combine::Mean
+        && format == ExportFormat::Png
+    {
+        return save_png(&mean(video, codec, indices)?, path);
+    }
+    let bytes = stack_raw(video, indices, shifts, combine)?;
+    export_frame(&FrameView::new(video, &bytes), codec, 0, format, path)
+}
+
+/// Decode the frames with the given indices and average them
+#[instrument(skip(video, codec, indices), fields(frames = indices.len()))]
+pub fn mean(video: &dyn Video, codec: &dyn ImageCodec, indices: &[usize]) -> Result<DecodedFrame> {
+    let mut sum: Vec<u32> = vec![];
+    let (mut width, mut height) = (0, 0);
+    let mut pixels = vec![];
+    for &index in indices {
+        let (w, h) = codec.decode_into(video, index, &mut pixels)?;
+        if sum.is_empty() {
+            width = w;
+            height = h;
+            sum = vec![0; pixels.len()];
+        }
+        for (total, value) in sum.iter_mut().zip(&pixels) {
+            *total += *value as u32;
+        }
+    }
+    if indices.is_empty() {
+        return Err(PlayerError::Format("No frames to stack".to_string()));
+    }
+    let count = indices.len() as u32;
+    Ok(DecodedFrame::new(
+        width,
+        height,
+        sum.into_iter().map(|total| (total / count) as u8).collect(),
+    ))
+}
+
+/// Running mean of the raw samples of frames that are added one at a time, such as the
+/// frames shown during playback. Each frame is only added once, so frames are not counted
+/// twice when playback loops.
+#[derive(Debug, Default)]
+pub struct RunningStack {
+    sum: Vec<u64>,
+    added: HashSet<usize>,
+}
+
+impl RunningStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the samples of a frame, returning false if it was already added
+    pub fn add(&mut self, video: &dyn Video, index: usize) -> Result<bool> {
+        check_uncompressed(video)?;
+        if self.added.contains(&index) {
+            return Ok(false);
+        }
+        let bytes = video.read_frame(index)?;
+        let sample_count = bytes.len() / video.bytes_per_pixel().max(1) as usize;
+        if self.sum.is_empty() {
+            self.sum = vec![0; sample_count];
+        } else if sample_count < self.sum.len() {
+            return Err(PlayerError::Decode(format!(
+                "Frame {} is smaller than the stacked frames",
+                index
+            )));
+        }
+        for (total, value) in self.sum.iter_mut().zip(samples(video, &bytes)) {
+            *total += value as u64;
+        }
+        self.added.insert(index);
+        Ok(true)
+    }
+
+    /// Number of frames that have been added
+    pub fn frame_count(&self) -> usize {
+        self.added.len()
+    }
+
+    /// The mean of the frames that have been added, as a raw frame in the format of the
+    /// frames of the video
+    pub fn mean(&self, video: &dyn Video) -> Result<Vec<u8>> {
+        let count = self.added.len() as u64;
+        if count == 0 {
+            return Err(PlayerError::Format("No frames to stack".to_string()));
+        }
+        let mean = self
+            .sum
+            .iter()
+            .map(|total| ((total + count / 2) / count) as u16)
+            .collect::<Vec<_>>();
+        let mut bytes = vec![];
+        write_samples(video, &mean, &mut bytes);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::codec_for;
+    use crate::testutil::{ser_format, ser_video, TempFile};
+    use crate::video_format::{Bayer, FitsVideo, OpenMode};
+
+    fn frame(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_stack_raw() {
+        let frames = [[100, 1000], [200, 1001], [60000, 1003], [300, 1002]]
+            .iter()
+            .map(|values| frame(values))
+            .collect::<Vec<_>>();
+        let video = ser_video(&ser_format(2, 1, Bayer::Mono, 16), &frames);
+
+        let mean = stack_raw(&video, &[0, 1, 2, 3], &[], Combine::Mean).unwrap();
+        assert_eq!(frame(&[15150, 1002]), mean);
+        // the outlier is rejected and the middle two values are averaged
+        let median = stack_raw(&video, &[0, 1, 2, 3], &[], Combine::Median).unwrap();
+        assert_eq!(frame(&[250, 1002]), median);
+        let median = stack_raw(&video, &[0, 1, 2], &[], Combine::Median).unwrap();
+        assert_eq!(frame(&[200, 1001]), median);
+
+        assert!(stack_raw(&video, &[], &[], Combine::Mean).is_err());
+        assert_eq!(Combine::Median, parse_combine("Median").unwrap());
+        assert!(parse_combine("sigma").is_err());
+
+        // the running mean matches the mean of the same frames, each counted once
+        let mut running = RunningStack::new();
+        assert!(running.mean(&video).is_err());
+        for index in [0, 1, 2, 1, 3] {
+            running.add(&video, index).unwrap();
+        }
+        assert!(!running.add(&video, 3).unwrap());
+        assert_eq!(4, running.frame_count());
+        assert_eq!(mean, running.mean(&video).unwrap());
+    }
+
+    #[test]
+    fn test_shifted_band() {
+        let format = ser_format(6, 4, Bayer::RGGB, 16);
+        let values = (0..24).map(|i| i * 7).collect::<Vec<u16>>();
+        let video = ser_video(&format, &[frame(&values)]);
+        for &(dx, dy) in &[(2, -2), (-4, 2), (0, 4), (6, 0)] {
+            let shift = Shift { dx, dy };
+            let expected = shift_samples(&values, 6, 4, 1, 2, shift);
+            let band = ShiftedBand {
+                index: 0,
+                shift,
+                width: 6,
+                height: 4,
+                channels: 1,
+            };
+            for start in (0..24).step_by(5) {
+                let end = (start + 5).min(24);
+                let actual = band.read(&video, start..end).unwrap();
+                assert_eq!(&expected[start..end], &actual[..], "{:?} {}", shift, start);
+            }
+        }
+    }
+
+    #[test]
+    fn test_export_stack() {
+        let frames = vec![frame(&[0, 100, 200, 300]), frame(&[100, 300, 400, 500])];
+        let video = ser_video(&ser_format(2, 2, Bayer::RGGB, 16), &frames);
+        let codec = codec_for(&video).unwrap();
+        let file = TempFile::new(".fits", &[]);
+        export_stack(
+            &video,
+            codec.as_ref(),
+            &[0, 1],
+            &[],
+            Combine::Mean,
+            ExportFormat::Fits,
+            file.path(),
+        )
+        .unwrap();
+        let stacked = FitsVideo::open(file.path_str(), OpenMode::Strict).unwrap();
+        assert_eq!(Bayer::RGGB, *stacked.bayer());
+        let values = stacked
+            .read_frame(0)
+            .unwrap()
+            .chunks_exact(2)
+            .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![50, 200, 300, 400], values);
+    }
+
+    #[test]
+    fn test_lucky_stack() {
+        // a sharp bright quad that moves between frames, and a blank frame
+        let quad = |x: usize| -> Vec<u8> {
+            let mut values = vec![10_u16; 16 * 8];
+            for y in 2..4 {
+                values[y * 16 + x] = 1000;
+                values[y * 16 + x + 1] = 1000;
+            }
+            frame(&values)
+        };
+        let frames = vec![quad(4), frame(&[10; 16 * 8]), quad(8), quad(6)];
+        let video = ser_video(&ser_format(16, 8, Bayer::Mono, 16), &frames);
+
+        let (indices, shifts) = lucky_frames(&video, &[0, 1, 2, 3], 0.75, true).unwrap();
+        assert_eq!(vec![0, 2, 3], indices);
+        assert_eq!(
+            vec![0, -4, -2],
+            shifts.iter().map(|s| s.dx).collect::<Vec<_>>()
+        );
+        let stacked = stack_raw(&video, &indices, &shifts, Combine::Mean).unwrap();
+        assert_eq!(frames[0], stacked);
+
+        // nothing is scored when every frame is stacked without aligning them
+        let (indices, shifts) = lucky_frames(&video, &[1, 3], 1.0, false).unwrap();
+        assert_eq!((vec![1, 3], vec![]), (indices, shifts));
+    }
+}
@@ -0,0 +1,204 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! On-demand frame access for captures that are larger than available memory.
+//!
+//! `SerVideo` and `AviVideo` memory-map their files, so frames are only paged in when they
+//! are read, but the mapped pages still count towards resident memory until the OS evicts
+//! them. `FrameReader` instead reads each frame from its offset on disk into a buffer owned
+//! by the caller, so memory use is bounded by a single frame regardless of the file size.
+//!
+//! SER files opened with `ReadStrategy::Read` use a `FrameReader` for `Video::read_frame`.
+//! `benches/streaming.rs` compares the memory used by both strategies when every frame of
+//! a large file is decoded.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Mutex;
+
+use ser_io::SerFile;
+use tracing::{instrument, trace};
+
+use crate::avi::AviFile;
+use crate::codec::ImageCodec;
+use crate::error::{lock, PlayerError, Result};
+use crate::video_format::{FrameView, Video};
+
+/// Size of the SER file header, in bytes
+const SER_HEADER_SIZE: u64 = 178;
+
+/// Location of a single frame within a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameExtent {
+    pub offset: u64,
+    pub size: usize,
+}
+
+/// Reads frames from disk on demand using seek and read
+pub struct FrameReader {
+    file: Mutex<File>,
+    frames: Vec<FrameExtent>,
+}
+
+impl FrameReader {
+    pub fn open(filename: &str, frames: Vec<FrameExtent>) -> Result<Self> {
+        Ok(Self {
+            file: Mutex::new(File::open(filename)?),
+            frames,
+        })
+    }
+
+    /// Create a reader for the frames of a SER file that has already been opened
+    pub fn for_ser(filename: &str, ser: &SerFile) -> Result<Self> {
+        let size = ser.image_frame_size as usize;
+        let frames = (0..ser.frame_count)
+            .map(|i| FrameExtent {
+                offset: SER_HEADER_SIZE + (i * size) as u64,
+                size,
+            })
+            .collect();
+        Self::open(filename, frames)
+    }
+
+    /// Create a reader for the frames of an AVI file that has already been opened
+    pub fn for_avi(filename: &str, avi: &AviFile) -> Result<Self> {
+        let frames = avi
+            .frames()
+            .iter()
+            .map(|chunk| FrameExtent {
+                offset: chunk.data_offset as u64,
+                size: chunk.data_size,
+            })
+            .collect();
+        Self::open(filename, frames)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Read the raw bytes of a frame into `buf`, reusing its allocation
+    #[instrument(level = "trace", skip(self, buf))]
+    pub fn read_frame_into(&self, index: usize, buf: &mut Vec<u8>) -> Result<()> {
+        let size = self.extent(index)?.size;
+        self.read_range_into(index, 0..size, buf)
+    }
+
+    /// Read bytes `range` of a frame into `buf`, reusing its allocation
+    #[instrument(level = "trace", skip(self, buf))]
+    pub fn read_range_into(
+        &self,
+        index: usize,
+        range: Range<usize>,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        let extent = self.extent(index)?;
+        if range.start > range.end || range.end > extent.size {
+            return Err(PlayerError::Decode(format!(
+                "Bytes {:?} are outside frame {}, which has {} bytes",
+                range, index, extent.size
+            )));
+        }
+        let offset = extent.offset + range.start as u64;
+        trace!(offset, size = range.len(), "reading frame");
+        buf.resize(range.len(), 0);
+        let mut file = lock(&self.file);
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
+
+    fn extent(&self, index: usize) -> Result<&FrameExtent> {
+        self.frames
+            .get(index)
+            .ok_or_else(|| PlayerError::Decode(format!("Invalid frame index {}", index)))
+    }
+}
+
+/// Decodes frames one at a time from a `FrameReader`, using the wrapped video only for its
+/// metadata. A single frame buffer is reused for every decode.
+pub struct StreamingDecoder {
+    video: Box<dyn Video>,
+    reader: FrameReader,
+    buf: Vec<u8>,
+}
+
+impl StreamingDecoder {
+    pub fn new(video: Box<dyn Video>, reader: FrameReader) -> Self {
+        Self {
+            video,
+            reader,
+            buf: vec![],
+        }
+    }
+
+    pub fn video(&self) -> &dyn Video {
+        self.video.as_ref()
+    }
+
+    /// Read a frame from disk and decode it into BGRA pixels
+    pub fn decode(&mut self, codec: &dyn ImageCodec, index: usize) -> Result<(u32, u32, Vec<u8>)> {
+        self.reader.read_frame_into(index, &mut self.buf)?;
+        let frame = FrameView::new(self.video.as_ref(), &self.buf);
+        codec.decode(&frame, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_read_frame_into() {
+        let path = std::env::temp_dir().join("astro-video-player-frame-reader.bin");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&(0..=255_u8).collect::<Vec<_>>()).unwrap();
+        drop(file);
+
+        let frames = vec![
+            FrameExtent {
+                offset: 10,
+                size: 4,
+            },
+            FrameExtent {
+                offset: 200,
+                size: 2,
+            },
+        ];
+        let reader = FrameReader::open(path.to_str().unwrap(), frames).unwrap();
+        assert_eq!(2, reader.frame_count());
+
+        let mut buf = vec![];
+        reader.read_frame_into(1, &mut buf).unwrap();
+        assert_eq!(vec![200, 201], buf);
+        reader.read_frame_into(0, &mut buf).unwrap();
+        assert_eq!(vec![10, 11, 12, 13], buf);
+        assert!(reader.read_frame_into(2, &mut buf).is_err());
+        reader.read_range_into(0, 1..3, &mut buf).unwrap();
+        assert_eq!(vec![11, 12], buf);
+        assert!(reader.read_range_into(0, 2..5, &mut buf).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Synthetic SER and AVI files for tests, so that the decoders can be tested without sample
+//! captures.
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::ser::{SerBytes, SerFormat, SerWriter};
+use crate::video_format::{Bayer, Endianness, OpenMode};
+
+/// Little endian format with the given image size, color encoding and bit depth
+pub(crate) fn ser_format(
+    width: u32,
+    height: u32,
+    bayer: Bayer,
+    pixel_depth_bits: u32,
+) -> SerFormat {
+    SerFormat {
+        width,
+        height,
+        bayer,
+        pixel_depth_bits,
+        endianness: Endianness::LittleEndian,
+    }
+}
+
+/// Contents of a SER file containing the given frames
+pub(crate) fn ser_bytes(format: &SerFormat, frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut writer = SerWriter::new(Cursor::new(vec![]), format).unwrap();
+    for frame in frames {
+        writer.write_frame(frame).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+/// In-memory SER video containing the given frames
+pub(crate) fn ser_video(format: &SerFormat, frames: &[Vec<u8>]) -> SerBytes {
+    SerBytes::new(ser_bytes(format, frames), OpenMode::Strict).unwrap()
+}
+
+/// A frame where every sample is different, so that decoding errors that move or swap
+/// samples are detected. Samples are encoded using the bit depth and endianness of `format`.
+pub(crate) fn gradient_frame(format: &SerFormat, seed: u32) -> Vec<u8> {
+    let max = (1_u32 << format.pixel_depth_bits.min(16)) - 1;
+    let samples = format.frame_size() / if format.pixel_depth_bits > 8 { 2 } else { 1 };
+    let mut bytes = Vec::with_capacity(format.frame_size());
+    for i in 0..samples as u32 {
+        let value = (i.wrapping_mul(7919) + seed * 31) % (max + 1);
+        if format.pixel_depth_bits > 8 {
+            match format.endianness {
+                Endianness::LittleEndian => bytes.write_u16::<LittleEndian>(value as u16),
+                Endianness::BigEndian => bytes.write_u16::<byteorder::BigEndian>(value as u16),
+            }
+            .unwrap();
+        } else {
+            bytes.push(value as u8);
+        }
+    }
+    bytes
+}
+
+/// Contents of an AVI file containing uncompressed 24-bit BGR frames, as written by capture
+/// software such as SharpCap and FireCapture
+pub(crate) fn avi_bytes(
+    width: u32,
+    height: u32,
+    micro_sec_per_frame: u32,
+    frames: &[Vec<u8>],
+) -> Vec<u8> {
+    avi_stream_bytes(width, height, micro_sec_per_frame, &AviStream::BGR, frames)
+}
+
+/// How the frames of a test AVI file are encoded
+pub(crate) struct AviStream {
+    pub handler: &'static [u8; 4],
+    pub bit_count: u16,
+    pub compression: &'static [u8; 4],
+    pub chunk_id: &'static [u8; 4],
+    /// Whether rows are stored from the bottom of the image up, which is the default for DIBs
+    pub bottom_up: bool,
+    /// Red, green and blue masks, written after the header when not empty
+    pub bit_masks: &'static [u32],
+}
+
+impl AviStream {
+    /// Uncompressed 24-bit BGR
+    pub(crate) const BGR: AviStream = AviStream {
+        handler: b"DIB ",
+        bit_count: 24,
+        compression: &[0; 4],
+        chunk_id: b"00db",
+        bottom_up: true,
+        bit_masks: &[],
+    };
+    /// Raw 8-bit samples with a grayscale palette
+    pub(crate) const RAW8: AviStream = AviStream {
+        handler: b"DIB ",
+        bit_count: 8,
+        compression: &[0; 4],
+        chunk_id: b"00db",
+        bottom_up: false,
+        bit_masks: &[],
+    };
+    /// Raw 16-bit samples
+    pub(crate) const RAW16: AviStream = AviStream {
+        handler: b"DIB ",
+        bit_count: 16,
+        compression: b"Y16 ",
+        chunk_id: b"00db",
+        bottom_up: false,
+        bit_masks: &[],
+    };
+    /// JPEG compressed frames
+    pub(crate) const MJPEG: AviStream = AviStream {
+        handler: b"MJPG",
+        bit_count: 24,
+        compression: b"MJPG",
+        chunk_id: b"00dc",
+        bottom_up: true,
+        bit_masks: &[],
+    };
+}
+
+/// Contents of an AVI file with the given stream encoding
+pub(crate) fn avi_stream_bytes(
+    width: u32,
+    height: u32,
+    micro_sec_per_frame: u32,
+    stream: &AviStream,
+    frames: &[Vec<u8>],
+) -> Vec<u8> {
+    // truncated like a writer that overflows, so that tests can use huge dimensions
+    let frame_size = (width as u64 * height as u64 * stream.bit_count as u64 / 8) as u32;
+    let mut avih = u32s(&[
+        micro_sec_per_frame,
+        0,
+        0,
+        0,
+        frames.len() as u32,
+        0,
+        1,
+        frame_size,
+        width,
+        height,
+    ]);
+    avih.resize(56, 0);
+    let mut strh = b"vids".to_vec();
+    strh.extend_from_slice(stream.handler);
+    strh.resize(56, 0);
+    // a negative height marks a top-down DIB
+    let dib_height = if stream.bottom_up {
+        height as i32
+    } else {
+        -(height as i32)
+    };
+    let mut strf = u32s(&[40, width, dib_height as u32]);
+    strf.write_u16::<LittleEndian>(1).unwrap();
+    strf.write_u16::<LittleEndian>(stream.bit_count).unwrap();
+    strf.extend_from_slice(stream.compression);
+    strf.extend(u32s(&[frame_size]));
+    strf.resize(40, 0);
+    strf.extend(u32s(stream.bit_masks));
+    if stream.bit_count == 8 {
+        strf.extend((0..=255).flat_map(|i| [i, i, i, 0]));
+    }
+    strf.resize(strf.len().max(48), 0);
+    let frames = frames
+        .iter()
+        .map(|frame| riff_chunk(stream.chunk_id, frame))
+        .collect::<Vec<_>>();
+    let hdrl = riff_list(
+        b"hdrl",
+        &[
+            riff_chunk(b"avih", &avih),
+            riff_list(
+                b"strl",
+                &[
+                    riff_chunk(b"strh", &strh),
+                    riff_chunk(b"strf", &strf),
+                    riff_chunk(b"indx", &[0; 24]),
+                ],
+            ),
+        ],
+    );
+    let mut data = b"AVI ".to_vec();
+    data.extend(hdrl);
+    data.extend(riff_list(b"movi", &frames));
+    riff_chunk(b"RIFF", &data)
+}
+
+/// Contents of a FITS file with the given BITPIX and axis sizes. `cards` are extra header
+/// records such as `BZERO   =                32768`, and `data` is padded to a whole block.
+pub(crate) fn fits_bytes(bitpix: i32, axes: &[u32], cards: &[&str], data: &[u8]) -> Vec<u8> {
+    let mut header = vec![
+        "SIMPLE  =                    T".to_string(),
+        format!("BITPIX  = {:>20}", bitpix),
+        format!("NAXIS   = {:>20}", axes.len()),
+    ];
+    for (i, size) in axes.iter().enumerate() {
+        header.push(format!("NAXIS{:<3}= {:>20}", i + 1, size));
+    }
+    header.extend(cards.iter().map(|card| card.to_string()));
+    header.push("END".to_string());
+    let mut bytes = header
+        .iter()
+        .flat_map(|card| format!("{:80}", card).into_bytes())
+        .collect::<Vec<_>>();
+    bytes.resize(bytes.len().div_ceil(2880) * 2880, b' ');
+    bytes.extend_from_slice(data);
+    bytes.resize(bytes.len().div_ceil(2880) * 2880, 0);
+    bytes
+}
+
+/// A RIFF chunk, padded to an even number of bytes
+pub(crate) fn riff_chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut bytes = id.to_vec();
+    bytes.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+    bytes.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    bytes
+}
+
+pub(crate) fn riff_list(list_type: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = list_type.to_vec();
+    children.iter().for_each(|c| data.extend_from_slice(c));
+    riff_chunk(b"LIST", &data)
+}
+
+fn u32s(values: &[u32]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for value in values {
+        bytes.write_u32::<LittleEndian>(*value).unwrap();
+    }
+    bytes
+}
+
+/// A file in the temporary directory that is deleted when it is dropped
+pub(crate) struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Write a file with a unique name ending in `suffix`, such as `.ser`
+    pub(crate) fn new(suffix: &str, bytes: &[u8]) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "avp-test-{}-{}{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst),
+            suffix
+        );
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        Self { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn path_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,335 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Color themes for the player UI: a dark theme for night use, a light theme and a red-light
+//! astronomy mode that tints all of the UI chrome deep red to preserve dark adaptation at the
+//! telescope. Frames and charts are drawn with their own colors and are not themed.
+
+use iced::{Background, Color, Vector};
+use iced_style::{
+    button, checkbox, container, menu, pane_grid, pick_list, scrollable, slider, text_input,
+};
+
+use crate::error::{PlayerError, Result};
+
+/// Names of the themes offered in the player and accepted in the configuration file
+pub const THEME_NAMES: &[&str] = &["dark", "light", "astronomy"];
+
+/// Color theme of the player UI
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+    /// Deep red on black, for use at the telescope
+    Astronomy,
+}
+
+impl Theme {
+    /// Name of the theme, from `THEME_NAMES`
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+            Theme::Astronomy => "astronomy",
+        }
+    }
+
+    fn palette(self) -> Palette {
+        match self {
+            Theme::Dark => Palette {
+                background: Color::from_rgb(0.11, 0.11, 0.12),
+                surface: Color::from_rgb(0.19, 0.19, 0.21),
+                hovered: Color::from_rgb(0.26, 0.26, 0.29),
+                accent: Color::from_rgb(0.30, 0.45, 0.75),
+                text: Color::from_rgb(0.88, 0.88, 0.88),
+                border: Color::from_rgb(0.35, 0.35, 0.38),
+            },
+            Theme::Light => Palette {
+                background: Color::from_rgb(0.97, 0.97, 0.97),
+                surface: Color::from_rgb(0.87, 0.87, 0.87),
+                hovered: Color::from_rgb(0.80, 0.80, 0.84),
+                accent: Color::from_rgb(0.40, 0.40, 1.0),
+                text: Color::BLACK,
+                border: Color::from_rgb(0.70, 0.70, 0.70),
+            },
+            Theme::Astronomy => Palette {
+                background: Color::from_rgb(0.04, 0.0, 0.0),
+                surface: Color::from_rgb(0.14, 0.01, 0.01),
+                hovered: Color::from_rgb(0.24, 0.02, 0.02),
+                accent: Color::from_rgb(0.50, 0.04, 0.04),
+                text: Color::from_rgb(0.80, 0.10, 0.10),
+                border: Color::from_rgb(0.36, 0.03, 0.03),
+            },
+        }
+    }
+}
+
+/// Parse a theme name from `THEME_NAMES`, ignoring case
+pub fn parse_theme(name: &str) -> Result<Theme> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "dark" => Ok(Theme::Dark),
+        "light" => Ok(Theme::Light),
+        "astronomy" => Ok(Theme::Astronomy),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown theme '{}', expected one of {}",
+            name,
+            THEME_NAMES.join(", ")
+        ))),
+    }
+}
+
+/// Colors that the widget styles of a theme are built from
+struct Palette {
+    /// Window background
+    background: Color,
+    /// Background of buttons, lists and other controls
+    surface: Color,
+    /// Background of controls under the mouse
+    hovered: Color,
+    /// Selections, checked boxes and the filled part of sliders
+    accent: Color,
+    text: Color,
+    border: Color,
+}
+
+const BORDER_RADIUS: f32 = 3.0;
+
+impl container::StyleSheet for Theme {
+    fn style(&self) -> container::Style {
+        let palette = self.palette();
+        container::Style {
+            text_color: Some(palette.text),
+            background: Some(Background::Color(palette.background)),
+            ..container::Style::default()
+        }
+    }
+}
+
+impl button::StyleSheet for Theme {
+    fn active(&self) -> button::Style {
+        let palette = self.palette();
+        button::Style {
+            shadow_offset: Vector::new(0.0, 0.0),
+            background: Some(Background::Color(palette.surface)),
+            border_radius: BORDER_RADIUS,
+            border_width: 1.0,
+            border_color: palette.border,
+            text_color: palette.text,
+        }
+    }
+
+    fn hovered(&self) -> button::Style {
+        button::Style {
+            background: Some(Background::Color(self.palette().hovered)),
+            ..self.active()
+        }
+    }
+
+    fn pressed(&self) -> button::Style {
+        button::Style {
+            background: Some(Background::Color(self.palette().accent)),
+            ..self.active()
+        }
+    }
+}
+
+impl checkbox::StyleSheet for Theme {
+    fn active(&self, _is_checked: bool) -> checkbox::Style {
+        let palette = self.palette();
+        checkbox::Style {
+            background: Background::Color(palette.surface),
+            checkmark_color: palette.text,
+            border_radius: BORDER_RADIUS,
+            border_width: 1.0,
+            border_color: palette.border,
+        }
+    }
+
+    fn hovered(&self, is_checked: bool) -> checkbox::Style {
+        checkbox::Style {
+            background: Background::Color(self.palette().hovered),
+            ..self.active(is_checked)
+        }
+    }
+}
+
+impl pick_list::StyleSheet for Theme {
+    fn menu(&self) -> menu::Style {
+        let palette = self.palette();
+        menu::Style {
+            text_color: palette.text,
+            background: Background::Color(palette.surface),
+            border_width: 1.0,
+            border_color: palette.border,
+            selected_text_color: palette.text,
+            selected_background: Background::Color(palette.accent),
+        }
+    }
+
+    fn active(&self) -> pick_list::Style {
+        let palette = self.palette();
+        pick_list::Style {
+            text_color: palette.text,
+            background: Background::Color(palette.surface),
+            border_radius: BORDER_RADIUS,
+            border_color: palette.border,
+            ..pick_list::Style::default()
+        }
+    }
+
+    fn hovered(&self) -> pick_list::Style {
+        pick_list::Style {
+            background: Background::Color(self.palette().hovered),
+            ..self.active()
+        }
+    }
+}
+
+impl slider::StyleSheet for Theme {
+    fn active(&self) -> slider::Style {
+        let palette = self.palette();
+        slider::Style {
+            rail_colors: (palette.accent, palette.border),
+            handle: slider::Handle {
+                shape: slider::HandleShape::Rectangle {
+                    width: 8,
+                    border_radius: 4.0,
+                },
+                color: palette.surface,
+                border_width: 1.0,
+                border_color: palette.border,
+            },
+        }
+    }
+
+    fn hovered(&self) -> slider::Style {
+        let mut style = self.active();
+        style.handle.color = self.palette().hovered;
+        style
+    }
+
+    fn dragging(&self) -> slider::Style {
+        let mut style = self.active();
+        style.handle.color = self.palette().accent;
+        style
+    }
+}
+
+impl text_input::StyleSheet for Theme {
+    fn active(&self) -> text_input::Style {
+        let palette = self.palette();
+        text_input::Style {
+            background: Background::Color(palette.surface),
+            border_radius: BORDER_RADIUS,
+            border_width: 1.0,
+            border_color: palette.border,
+        }
+    }
+
+    fn focused(&self) -> text_input::Style {
+        text_input::Style {
+            border_color: self.palette().accent,
+            ..self.active()
+        }
+    }
+
+    fn placeholder_color(&self) -> Color {
+        self.palette().border
+    }
+
+    fn value_color(&self) -> Color {
+        self.palette().text
+    }
+
+    fn selection_color(&self) -> Color {
+        self.palette().accent
+    }
+}
+
+impl scrollable::StyleSheet for Theme {
+    fn active(&self) -> scrollable::Scrollbar {
+        let palette = self.palette();
+        scrollable::Scrollbar {
+            background: Some(Background::Color(palette.background)),
+            border_radius: BORDER_RADIUS,
+            border_width: 0.0,
+            border_color: Color::TRANSPARENT,
+            scroller: scrollable::Scroller {
+                color: palette.surface,
+                border_radius: BORDER_RADIUS,
+                border_width: 1.0,
+                border_color: palette.border,
+            },
+        }
+    }
+
+    fn hovered(&self) -> scrollable::Scrollbar {
+        let mut style = self.active();
+        style.scroller.color = self.palette().hovered;
+        style
+    }
+}
+
+impl pane_grid::StyleSheet for Theme {
+    fn picked_split(&self) -> Option<pane_grid::Line> {
+        Some(pane_grid::Line {
+            color: self.palette().accent,
+            width: 2.0,
+        })
+    }
+
+    fn hovered_split(&self) -> Option<pane_grid::Line> {
+        Some(pane_grid::Line {
+            color: self.palette().border,
+            width: 2.0,
+        })
+    }
+}
+
+/// Style of the title bar of a detached panel
+pub struct TitleBar(pub Theme);
+
+impl container::StyleSheet for TitleBar {
+    fn style(&self) -> container::Style {
+        let palette = self.0.palette();
+        container::Style {
+            text_color: Some(palette.text),
+            background: Some(Background::Color(palette.surface)),
+            ..container::Style::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_theme() {
+        for name in THEME_NAMES {
+            assert_eq!(*name, parse_theme(name).unwrap().name());
+        }
+        assert_eq!(Theme::Astronomy, parse_theme("Astronomy").unwrap());
+        assert!(parse_theme("blue").is_err());
+    }
+}
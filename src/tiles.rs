@@ -0,0 +1,248 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Progressive decoding of very large frames, such as full-frame 4144x2822 captures. The
+//! full resolution frame is decoded in bands of rows, and each band is shown over the
+//! coarse preview as soon as it is ready so that the image refines from the top down.
+
+use std::sync::Arc;
+
+use crate::codec::{ImageCodec, RegionCodec};
+use crate::error::Result;
+use crate::video_format::{check_uncompressed, Region, Video};
+
+/// Frames with more pixels than this are decoded in bands
+const TILED_MIN_PIXELS: u64 = 4_000_000;
+
+/// Number of bands that large frames are decoded in
+const BANDS: u32 = 4;
+
+/// Extra rows decoded above and below each band so that interpolating debayers see the same
+/// neighbours as they would in a full frame decode, leaving no seams between the bands. This
+/// is a whole number of repeats of every color filter pattern, and of 2x2 superpixels.
+const MARGIN: u32 = 4;
+
+/// Number of rows in each band when the frames of a video are decoded in bands, or `None`
+/// if they are small enough to decode in one go. Compressed frames are always decoded in
+/// one go, because the whole frame has to be decompressed to get at any of its rows.
+pub fn band_rows(video: &dyn Video) -> Option<u32> {
+    let (width, height) = (video.image_width(), video.image_height());
+    if check_uncompressed(video).is_err() || width as u64 * height as u64 <= TILED_MIN_PIXELS {
+        return None;
+    }
+    let rows = height.div_ceil(BANDS);
+    Some(rows.div_ceil(MARGIN) * MARGIN)
+}
+
+/// A band of decoded BGRA rows, along with the size of the whole decoded frame
+#[derive(Debug, Clone)]
+pub struct Band {
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Decode `rows` rows of a frame starting at row `start`, which must be a multiple of the
+/// value returned by `band_rows`. Codecs that shrink the frame, such as 2x2 superpixels,
+/// return correspondingly fewer rows.
+pub fn decode_band(
+    codec: &Arc<dyn ImageCodec>,
+    video: &dyn Video,
+    index: usize,
+    start: u32,
+    rows: u32,
+) -> Result<Band> {
+    let (width, height) = (video.image_width(), video.image_height());
+    let top = start.saturating_sub(MARGIN);
+    let bottom = (start + rows + MARGIN).min(height);
+    let region = Region {
+        x: 0,
+        y: top,
+        width,
+        height: bottom - top,
+    };
+    let (band_width, band_height, mut pixels) =
+        RegionCodec::new(codec.clone(), region).decode(video, index)?;
+    // every codec shrinks the frame by a whole factor, if at all
+    let factor = (width / band_width.max(1)).max(1);
+    let skip = (start - top) / factor;
+    let keep = if start + rows >= height {
+        band_height.saturating_sub(skip)
+    } else {
+        rows / factor
+    };
+    let row_size = band_width as usize * 4;
+    pixels.truncate((skip + keep) as usize * row_size);
+    pixels.drain(..skip as usize * row_size);
+    Ok(Band {
+        frame_width: band_width,
+        frame_height: height / factor,
+        pixels,
+    })
+}
+
+/// A frame that is being decoded in bands. Rows that have not been decoded yet show the
+/// coarse preview, scaled up to the size of the frame.
+#[derive(Debug)]
+pub struct TiledFrame {
+    /// Number of rows of the video frame in each band, from `band_rows`
+    band_rows: u32,
+    /// Row of the video frame that the next band starts at
+    next_row: u32,
+    width: u32,
+    height: u32,
+    /// BGRA pixels, which are empty until the first band is decoded and the size of the
+    /// decoded frame is known
+    pixels: Vec<u8>,
+    /// Number of rows from the top of `pixels` that are decoded at full resolution
+    rows: u32,
+    /// Coarse preview that arrived before the first band, as width, height and BGRA pixels
+    preview: Option<(u32, u32, Vec<u8>)>,
+}
+
+impl TiledFrame {
+    pub fn new(band_rows: u32) -> Self {
+        Self {
+            band_rows,
+            next_row: 0,
+            width: 0,
+            height: 0,
+            pixels: vec![],
+            rows: 0,
+            preview: None,
+        }
+    }
+
+    /// Rows of the video frame in the next band to decode, as the first row and the number of
+    /// rows
+    pub fn next_band(&self) -> (u32, u32) {
+        (self.next_row, self.band_rows)
+    }
+
+    /// Whether any bands have been decoded yet
+    pub fn is_started(&self) -> bool {
+        self.next_row > 0
+    }
+
+    /// Show a coarse preview of the frame in the rows that have not been decoded yet
+    pub fn set_preview(&mut self, width: u32, height: u32, pixels: &[u8]) {
+        if self.pixels.is_empty() {
+            self.preview = Some((width, height, pixels.to_vec()));
+        } else {
+            self.fill(width, height, pixels);
+        }
+    }
+
+    /// Add the next band of decoded rows, from `decode_band`
+    pub fn add_band(&mut self, band: Band) {
+        if self.pixels.is_empty() {
+            self.width = band.frame_width;
+            self.height = band.frame_height;
+            self.pixels = vec![0; self.width as usize * self.height as usize * 4];
+            if let Some((width, height, pixels)) = self.preview.take() {
+                self.fill(width, height, &pixels);
+            }
+        }
+        let start = (self.rows as usize * self.width as usize * 4).min(self.pixels.len());
+        let len = band.pixels.len().min(self.pixels.len() - start);
+        self.pixels[start..start + len].copy_from_slice(&band.pixels[..len]);
+        self.rows = (start + len) as u32 / (self.width.max(1) * 4);
+        self.next_row += self.band_rows;
+    }
+
+    /// Size and BGRA pixels of the frame as it is so far
+    pub fn image(&self) -> (u32, u32, &[u8]) {
+        (self.width, self.height, &self.pixels)
+    }
+
+    /// Size and BGRA pixels of the frame once every band has been decoded
+    pub fn into_image(self) -> (u32, u32, Vec<u8>) {
+        (self.width, self.height, self.pixels)
+    }
+
+    /// Scale a preview up to the size of the frame, with nearest neighbour sampling, into the
+    /// rows that have not been decoded yet
+    fn fill(&mut self, width: u32, height: u32, pixels: &[u8]) {
+        if width == 0 || height == 0 || pixels.len() < width as usize * height as usize * 4 {
+            return;
+        }
+        for y in self.rows..self.height {
+            let source_y = (y as u64 * height as u64 / self.height as u64) as usize;
+            for x in 0..self.width {
+                let source_x = (x as u64 * width as u64 / self.width as u64) as usize;
+                let from = (source_y * width as usize + source_x) * 4;
+                let to = (y as usize * self.width as usize + x as usize) * 4;
+                self.pixels[to..to + 4].copy_from_slice(&pixels[from..from + 4]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{BilinearDebayerCodec, DebayerCodec};
+    use crate::testutil::ser_format;
+    use crate::video_format::{Bayer, MemoryVideo};
+
+    fn mosaic(width: u32, height: u32) -> MemoryVideo {
+        let pixels = (0..width * height).map(|i| (i * 7 % 251) as u8).collect();
+        MemoryVideo::from_frames(ser_format(width, height, Bayer::RGGB, 8), vec![pixels]).unwrap()
+    }
+
+    #[test]
+    fn test_band_rows() {
+        assert_eq!(None, band_rows(&mosaic(64, 48)));
+        assert_eq!(Some(708), band_rows(&mosaic(4144, 2822)));
+    }
+
+    #[test]
+    fn test_bands_match_full_decode() {
+        let video = mosaic(32, 30);
+        let codecs: Vec<Arc<dyn ImageCodec>> =
+            vec![Arc::new(BilinearDebayerCodec {}), Arc::new(DebayerCodec {})];
+        for codec in codecs {
+            let (width, height, expected) = codec.decode(&video, 0).unwrap();
+            let mut tiled = TiledFrame::new(8);
+            tiled.set_preview(1, 1, &[1, 2, 3, 255]);
+            while tiled.next_band().0 < video.image_height() {
+                let (start, rows) = tiled.next_band();
+                tiled.add_band(decode_band(&codec, &video, 0, start, rows).unwrap());
+            }
+            assert_eq!((width, height, expected.as_slice()), tiled.image());
+        }
+    }
+
+    #[test]
+    fn test_preview_fills_remaining_rows() {
+        let video = mosaic(8, 8);
+        let codec: Arc<dyn ImageCodec> = Arc::new(BilinearDebayerCodec {});
+        let mut tiled = TiledFrame::new(4);
+        tiled.add_band(decode_band(&codec, &video, 0, 0, 4).unwrap());
+        assert!(tiled.is_started());
+        tiled.set_preview(1, 1, &[1, 2, 3, 255]);
+        let (width, height, pixels) = tiled.image();
+        assert_eq!((8, 8), (width, height));
+        assert_eq!([1, 2, 3, 255], pixels[8 * 4 * 4..8 * 4 * 4 + 4]);
+        assert_eq!([1, 2, 3, 255], pixels[pixels.len() - 4..]);
+    }
+}
@@ -20,42 +20,840 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::mem::{self, Discriminant};
+use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use iced::canvas::{self, Canvas, Cursor, Frame, Geometry, Path, Stroke};
 use iced::image::Handle;
+use iced::keyboard::{self, KeyCode};
 use iced::{
-    button, Align, Application, Button, Clipboard, Column, Container, Element, Image, Length, Row,
-    Text,
+    button, mouse, pane_grid, pick_list, scrollable, slider, text_input, Align, Application,
+    Button, Checkbox, Clipboard, Color, Column, Container, Element, Image, Length, PaneGrid,
+    PickList, Point, Rectangle, Row, Scrollable, Size, Slider, Subscription, Text, TextInput,
+    Vector,
 };
 use iced::{executor, Command};
+use iced_native::image;
+use iced_native::{event, window, Event};
+use tracing::{debug, error, warn};
+
+use crate::align::{centering_shift, shift_bgra, Shift};
+use crate::analysis::Histogram;
+use crate::animation::{
+    animation_path, export_animation, parse_animation_format, AnimationFormat, AnimationOptions,
+    ANIMATION_FORMAT_NAMES,
+};
+use crate::annotations::{annotations_path, load_annotations, save_annotations, Annotation};
+use crate::blink::{parse_blink_rate, Blink, BlinkTarget, BLINK_RATE_NAMES, DEFAULT_BLINK_RATE};
+use crate::cache::{FrameCache, Prefetcher};
+use crate::codec::{
+    codec_named, parse_binning, BinningCodec, CodecRegistry, DebayerCodec, ImageCodec, RegionCodec,
+    BINNING_NAMES, CODEC_NAMES,
+};
+use crate::config::Config;
+use crate::error::lock;
+use crate::export::{
+    clip_path, export_annotated_png, export_frame, export_region, frame_path, parse_export_format,
+    stack_path, ExportFormat, EXPORT_FORMAT_NAMES,
+};
+use crate::file_dialog::choose_video;
+use crate::filmstrip::{thumbnail_frames, thumbnail_scale};
+use crate::gaps::{find_gaps, Gap};
+use crate::history::History;
+use crate::inspect::{line_profile, read_pixel, LineProfile};
+use crate::locale::{languages, locales_dir, Locale};
+use crate::marks::{marks_path, toggle_mark, write_marks_csv, Mark};
+use crate::orientation::{parse_pixel_scale, Orientation};
+use crate::playback::{
+    parse_playback_mode, parse_speed, real_time_interval, Playback, PLAYBACK_MODE_NAMES,
+    SPEED_NAMES,
+};
+use crate::playlist::{save_state, FileState, Playlist};
+use crate::processing::{
+    parse_color_map, split_bgra, Adjustments, ColorMap, Stretch, WhiteBalance, WhiteBalanceMethod,
+    COLOR_MAP_NAMES,
+};
+use crate::quality::{rank, ranking_path, score_frames, sharpest, write_csv, FrameScore};
+use crate::seeing::{measure_seeing, median_fwhm, seeing_path, write_seeing_csv, Seeing};
+use crate::ser::write_clip;
+use crate::stack::{
+    export_stack, lucky_frames, parse_combine, Combine, RunningStack, COMBINE_NAMES,
+};
+use crate::theme::{parse_theme, Theme, TitleBar as TitleBarStyle, THEME_NAMES};
+use crate::tiles::{band_rows, decode_band, Band, TiledFrame};
+use crate::video_format::{
+    format_utc, localized_video_info, open_video_with, parse_bayer, parse_endianness_override,
+    Bayer, Compression, Endianness, EndiannessOverride, FormatOverrides, FrameView, OpenMode,
+    OpenOptions, OverrideVideo, ReadStrategy, Region, Video,
+};
+use crate::viewport::{Callout, ImageView, ScaleOverlay, Viewport};
+
+/// Scale factor used for the coarse preview that is shown while the full resolution frame
+/// is decoded in the background
+const PREVIEW_SCALE: u32 = 4;
+
+/// Most frames to decode ahead of the current frame in the background, which is further
+/// limited to half of the frame cache
+const PREFETCH_FRAMES: usize = 16;
+
+/// Number of threads that decode frames ahead of the current frame
+const PREFETCH_THREADS: usize = 2;
+
+/// Shortest interval between showing new frames of a growing video in follow mode, so that
+/// fast cameras do not flood the UI with redraws
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(40);
+
+/// Longest time to wait for new frames of a growing video before checking whether it is
+/// still open
+const WATCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Size of the histogram panel
+const HISTOGRAM_WIDTH: u16 = 256;
+const HISTOGRAM_HEIGHT: u16 = 160;
+
+/// Width of the display stretch sliders
+const STRETCH_SLIDER_WIDTH: u16 = 150;
+
+/// Color of the error message
+const ERROR_COLOR: Color = Color::from_rgb(0.8, 0.1, 0.1);
+
+/// Height of the strip above the seek bar that shows marked frames
+const MARK_STRIP_HEIGHT: u16 = 6;
+
+/// Number of capture gaps listed in the file information panel
+const MAX_LISTED_GAPS: usize = 20;
+
+/// Number of thumbnails in the filmstrip and their height
+const FILMSTRIP_THUMBNAILS: usize = 10;
+const FILMSTRIP_HEIGHT: u16 = 64;
+
+/// Width of the line profile plot
+const PROFILE_WIDTH: u16 = 320;
+
+/// Height of the line showing the raw values of the pixel under the cursor
+const READOUT_HEIGHT: u16 = 20;
+
+/// Width of the file information panel
+const INFO_PANEL_WIDTH: u16 = 320;
+
+/// Space between the panes of the window while the analysis panels are detached
+const PANE_SPACING: u16 = 5;
+
+/// Fraction of the width of the window taken by the image when the panels are first detached
+const MAIN_PANE_RATIO: f32 = 0.7;
+
+/// Width of the list of files, which is shown when more than one file is given
+const PLAYLIST_WIDTH: u16 = 220;
+
+/// Maximum number of edits that can be undone
+const UNDO_LIMIT: usize = 100;
+
+/// Choices for overriding the header values of the video, where `header` uses the value in
+/// the header, or the detected pattern for raw AVI files. The byte order can also be `auto`,
+/// which judges it from the samples.
+const BAYER_CHOICES: &[&str] = &[HEADER, "mono", "rggb", "grbg", "gbrg", "bggr", "rgb", "bgr"];
+const BIT_DEPTH_CHOICES: &[&str] = &[HEADER, "8", "10", "12", "14", "16"];
+const ENDIANNESS_CHOICES: &[&str] = &[HEADER, "auto", "little", "big"];
+const HEADER: &str = "header";
+
+/// Playback frame rates, where `auto` uses the rate recorded in the file and `real-time`
+/// replays frames at the intervals they were captured at
+const FPS_CHOICES: &[&str] = &[
+    "auto", REAL_TIME, "1", "5", "10", "15", "25", "30", "50", "100",
+];
+const REAL_TIME: &str = "real-time";
+
+/// Orders for stepping through and playing frames once they have been scored for sharpness,
+/// where the `sharpest` choices skip the other frames
+const ORDER_CHOICES: &[&str] = &[
+    "file order",
+    "sharpest first",
+    "sharpest 50%",
+    "sharpest 25%",
+    "sharpest 10%",
+];
+
+/// Ways of choosing the white balance automatically
+const WHITE_BALANCE_CHOICES: &[&str] = &["gray world", "max RGB"];
+
+/// Range of the white balance gain sliders
+const MIN_GAIN: f32 = 0.25;
+const MAX_GAIN: f32 = 4.0;
 
-use crate::codec::ImageCodec;
-use crate::video_format::Video;
+/// Playback frame rate for files that do not record one
+const DEFAULT_FPS: f64 = 25.0;
+
+/// Limits on the playback frame rate, since some files record nonsensical rates
+const MIN_FPS: f64 = 0.1;
+const MAX_FPS: f64 = 1000.0;
 
 pub struct VideoPlayerArgs {
     pub video: Option<Box<dyn Video>>,
-    pub codec: Option<Box<dyn ImageCodec>>,
+    /// File that the video was opened from, used to name exported frames
+    pub path: Option<PathBuf>,
+    /// Files to switch between, including `path`
+    pub playlist: Vec<PathBuf>,
+    /// Header values to replace when decoding the video
+    pub overrides: FormatOverrides,
+    /// Name of the codec to decode the video with, see `codec_named`
+    pub codec_name: String,
+    /// Validation applied to files that are opened from the player
+    pub mode: OpenMode,
+    /// How frames are read from SER files that are opened from the player
+    pub read: ReadStrategy,
+    /// Whether SER files that are opened from the player are followed while they are captured
+    pub follow: bool,
+    /// Error to display instead of a video, such as a file that could not be opened
+    pub error: Option<String>,
+    /// Playback frame rate, defaulting to the rate recorded in the file
+    pub fps: Option<f64>,
+    /// Video to blink each frame against, see `Message::BlinkWith`
+    pub blink: Option<PathBuf>,
+    pub config: Config,
+    /// Configuration file that the player settings are saved to when the window is closed
+    pub config_path: Option<PathBuf>,
 }
 
 impl Default for VideoPlayerArgs {
     fn default() -> Self {
         Self {
             video: None,
-            codec: None,
+            path: None,
+            playlist: vec![],
+            overrides: FormatOverrides::default(),
+            codec_name: "auto".to_string(),
+            mode: OpenMode::default(),
+            read: ReadStrategy::default(),
+            follow: false,
+            error: None,
+            fps: None,
+            blink: None,
+            config: Config::default(),
+            config_path: None,
+        }
+    }
+}
+
+/// The video that is currently open along with the codec used to decode it
+#[derive(Clone)]
+struct OpenVideo {
+    /// The video as described by its header
+    original: Arc<dyn Video>,
+    /// The video with any format overrides applied
+    video: Arc<dyn Video>,
+    /// Codec for decoding the video, or the reason that no codec could be chosen
+    codec: std::result::Result<Arc<dyn ImageCodec>, String>,
+    /// Gaps in the capture, found from the frame timestamps
+    gaps: Vec<Gap>,
+}
+
+impl OpenVideo {
+    fn new(
+        original: Arc<dyn Video>,
+        overrides: &FormatOverrides,
+        codecs: &CodecRegistry,
+        codec_name: &str,
+    ) -> Self {
+        let video: Arc<dyn Video> = if overrides.is_empty() {
+            original.clone()
+        } else {
+            Arc::new(OverrideVideo::new(original.clone(), overrides.clone()))
+        };
+        let codec = codecs
+            .create(codec_name, video.as_ref())
+            .map(Arc::from)
+            .map_err(|e| e.to_string());
+        let gaps = find_gaps(original.as_ref());
+        Self {
+            original,
+            video,
+            codec,
+            gaps,
+        }
+    }
+
+    /// Everything known about the file as it was opened, without the overrides, in the
+    /// language of `locale`
+    fn info(&self, locale: &Locale) -> Vec<(String, String)> {
+        let mut info = localized_video_info(self.original.as_ref(), locale);
+        if self.original.frame_timestamp(0).is_some() {
+            let dropped: usize = self.gaps.iter().map(|gap| gap.dropped).sum();
+            info.push((
+                locale.text("info-capture-gaps"),
+                locale.format(
+                    "info-gaps",
+                    &[("count", &self.gaps.len()), ("dropped", &dropped)],
+                ),
+            ));
+            for gap in self.gaps.iter().take(MAX_LISTED_GAPS) {
+                info.push((
+                    locale.format("info-gap-after", &[("frame", &(gap.after + 1))]),
+                    locale.format(
+                        "info-gap",
+                        &[
+                            ("seconds", &format!("{:.3}", gap.duration.as_secs_f64())),
+                            ("dropped", &gap.dropped),
+                        ],
+                    ),
+                ));
+            }
+        }
+        info
+    }
+}
+
+/// The blink comparator along with the reference frame that it alternates with
+struct BlinkState {
+    blink: Blink,
+    /// Video that the reference frame is taken from, or `None` for the open video
+    source: Option<OpenVideo>,
+    /// File that `source` was opened from
+    path: Option<PathBuf>,
+    /// Epoch and index of the reference frame that was last decoded or is being decoded
+    key: Option<(u64, usize)>,
+    /// The reference frame as decoded
+    decoded: Option<Handle>,
+    /// `decoded` with the offset, the white balance and the display stretch applied
+    image: Option<Handle>,
+}
+
+/// Second codec that the current frame is decoded with, which is shown to the right of the
+/// divider to compare demosaic algorithms
+struct CompareState {
+    codec: std::result::Result<Arc<dyn ImageCodec>, String>,
+    /// Epoch and index of the frame that was last decoded or is being decoded
+    key: Option<(u64, usize)>,
+    /// The frame decoded with `codec`, with its epoch and index
+    decoded: Option<((u64, usize), Handle)>,
+}
+
+/// Running mean of the frames that have been shown, which is shown instead of the current
+/// frame to preview the stacked result
+struct LiveStack {
+    stack: Arc<Mutex<RunningStack>>,
+    /// Epoch that the frames were stacked in, because the stack depends on the format overrides
+    epoch: u64,
+    /// Number of times the stack has been reset in this epoch, to ignore stale results
+    resets: u64,
+    /// The latest mean and the number of frames in it
+    decoded: Option<(Handle, usize)>,
+    /// Whether a frame is being stacked in the background. Frames that are shown meanwhile
+    /// are skipped, so that stacking never holds up playback.
+    busy: bool,
+}
+
+impl LiveStack {
+    fn new(epoch: u64) -> Self {
+        Self {
+            stack: Arc::new(Mutex::new(RunningStack::new())),
+            epoch,
+            resets: 0,
+            decoded: None,
+            busy: false,
         }
     }
+
+    /// Start again from an empty stack
+    fn reset(&mut self, epoch: u64) {
+        self.resets = if epoch == self.epoch {
+            self.resets + 1
+        } else {
+            0
+        };
+        self.epoch = epoch;
+        *lock(&self.stack) = RunningStack::new();
+        self.decoded = None;
+    }
+
+    fn key(&self) -> (u64, u64) {
+        (self.epoch, self.resets)
+    }
+}
+
+/// Settings changed by the user that can be undone and redone
+#[derive(Debug, Clone, PartialEq)]
+struct Edits {
+    adjustments: Adjustments,
+    stretch: Stretch,
+    white_balance: WhiteBalance,
+    region: Option<Region>,
+}
+
+/// The panes of the window while the analysis panels are detached
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Panel {
+    /// The image and all of the controls
+    Main,
+    /// The histogram, line profile, seeing chart and file information
+    Analysis,
+}
+
+/// Thumbnails of frames spread through the video, which are decoded one at a time in the
+/// background so that they do not hold up the current frame
+struct Filmstrip {
+    /// Epoch that the thumbnails are decoded in
+    epoch: u64,
+    frames: Vec<usize>,
+    thumbnails: Vec<Option<Handle>>,
+    buttons: Vec<button::State>,
 }
 
 pub struct VideoPlayer {
-    video: Box<dyn Video>,
-    codec: Box<dyn ImageCodec>,
+    source: Option<OpenVideo>,
+    path: Option<PathBuf>,
     value: u32,
+    /// Most recently decoded image for the current frame (either the preview or full frame)
+    decoded: Option<Handle>,
+    /// Whether `decoded` is the full resolution frame, so that a preview that finishes
+    /// decoding later does not replace it
+    decoded_full: bool,
+    /// Full resolution frame that is being decoded in bands, for frames that are large
+    /// enough to refine progressively over the coarse preview
+    tiled: Option<TiledFrame>,
+    /// Generation of the preview that is being decoded for dragging the seek bar. Seeking
+    /// starts no other preview until it finishes, so that drag events do not queue up decodes.
+    seek_preview: Option<u64>,
+    /// Whether the seek bar moved to another frame while `seek_preview` was being decoded
+    seek_pending: bool,
+    /// `decoded` with the white balance and display stretch applied
+    image: Option<Handle>,
+    /// Color channel gains, which are applied to decoded frames before the display stretch
+    white_balance: WhiteBalance,
+    /// Method used by the auto white balance button, from `WHITE_BALANCE_CHOICES`
+    white_balance_method: &'static str,
+    /// Display stretch, which is applied to decoded frames before they are shown
+    stretch: Stretch,
+    /// Colors used to show the stretched brightness
+    color_map: ColorMap,
+    /// Whether decoded frames are translated to keep the target in the center
+    center_target: bool,
+    /// Region of interest, which is the only part of each frame that is decoded and shown
+    region: Option<Region>,
+    /// Whether dragging on the image selects a region of interest rather than panning
+    selecting_region: bool,
+    /// Whether a scale bar and compass are drawn over the image
+    show_scale: bool,
+    /// Image scale in arcseconds per pixel as entered, see `parse_pixel_scale`
+    pixel_scale: String,
+    /// Which way the sky is turned in the frames
+    orientation: Orientation,
+    /// Image scales in arcseconds per pixel keyed by instrument, from the configuration and
+    /// the scales entered for the files that have been opened
+    plate_scales: HashMap<String, f64>,
+    /// Whether saved frames only contain the region of interest
+    crop_exports: bool,
+    /// Position of the cursor over the image, as a fraction of the image size
+    hovered: Option<Vector>,
+    /// Raw values of the pixel under the cursor, or why they cannot be read
+    readout: Option<String>,
+    /// Whether dragging on the image draws the line for a line profile rather than panning
+    drawing_profile: bool,
+    /// Ends of the line profile, as fractions of the size of the image that is shown
+    profile_line: Option<(Vector, Vector)>,
+    /// Raw values along `profile_line` in the current frame, or why they cannot be read
+    profile: Option<std::result::Result<LineProfile, String>>,
+    /// Whether dragging on the image draws an arrow and clicking places a label rather than
+    /// panning
+    annotating: bool,
+    /// Text of the next label or arrow that is placed
+    annotation_text: String,
+    /// Whether annotations are drawn into saved PNG frames
+    burn_annotations: bool,
+    /// Zoom and position of the image view
+    viewport: Viewport,
+    /// Whether the histogram panel is shown
+    show_histogram: bool,
+    /// Whether the file information panel is shown
+    show_info: bool,
+    /// Whether the analysis panels are shown in a pane of their own, which can be resized
+    /// and moved to any side so that a window spanning two monitors can show the image on
+    /// one and the panels on the other
+    panels_detached: bool,
+    panes: pane_grid::State<Panel>,
+    /// Color theme of the UI, saved to the configuration file
+    theme: Theme,
+    /// Translations of the UI messages, shared with `view` without copying the catalogs
+    locale: Arc<Locale>,
+    /// Languages with a catalog, which can be switched between while the player runs
+    languages: Vec<String>,
+    /// Histogram of `decoded`, which is only calculated while the panel is shown
+    histogram: Option<Histogram>,
+    /// Error to show above the controls until it is dismissed or the frame changes
+    error: Option<String>,
+    /// Result of the last action, such as exporting a frame
+    status: Option<String>,
+    /// Incremented every time the current frame changes so that results from background
+    /// decodes of frames that are no longer current can be discarded. It is shared with the
+    /// background decodes, which are skipped if the frame changed before they started.
+    generation: Arc<AtomicU64>,
+    /// Incremented every time the video is reopened with different settings, so that frames
+    /// decoded with the old settings are not taken from the cache
+    epoch: u64,
+    /// Decodes upcoming frames into the frame cache
+    prefetcher: Prefetcher<Handle>,
+    /// Adjustments made by the user, such as excluded frames
+    adjustments: Adjustments,
+    history: History<Edits>,
+    /// Settings from before the most recent message that edits them, along with which kind
+    /// of message it was. These are recorded in `history` once the edit is finished.
+    pending_edit: Option<(Discriminant<Message>, Edits)>,
+    overrides: FormatOverrides,
+    codec_name: String,
+    /// Codecs that `codec_name` is looked up in, where `auto` debayers at the configured
+    /// quality
+    codecs: CodecRegistry,
+    /// Binning factor of the previews shown while seeking and playing, or 1 to sample every
+    /// `PREVIEW_SCALE`th pixel instead. Playback then only decodes the binned previews, and
+    /// the full resolution frame is decoded when playback stops.
+    binning: u32,
+    mode: OpenMode,
+    read: ReadStrategy,
+    /// Whether SER files are followed while they are captured, see `FollowedSerVideo`
+    follow_files: bool,
+    /// Contents of the file name field
+    path_input: String,
+    playlist: Playlist,
+    /// Whether frames are being advanced automatically
+    playing: bool,
+    /// Whether the newest frame is shown as frames are added to a growing video
+    follow: bool,
+    /// Identifies the video that frames are being watched for, so that notifications for a
+    /// video that has since been closed are ignored
+    watch_id: u64,
+    /// Running mean of the shown frames, if the live stack is on
+    live_stack: Option<LiveStack>,
+    /// Playback frame rate chosen by the user, or `None` to use the rate from the file
+    fps: Option<f64>,
+    /// Whether frames are played at the intervals between their timestamps instead of at
+    /// `fps`
+    real_time: bool,
+    /// Multiple of the capture speed that real-time playback runs at
+    speed: f64,
+    /// What happens at the end of playback, and the A-B loop region
+    playback: Playback,
+    export_format: ExportFormat,
+    animation_format: AnimationFormat,
+    /// Whether an animation is being saved in the background
+    animating: bool,
+    /// Sharpness of each frame from the sharpest to the least sharp, once scored
+    scores: Option<Vec<FrameScore>>,
+    /// Whether the frames are being scored in the background
+    scoring: bool,
+    /// FWHM of the brightest star in each frame, once measured
+    seeing: Option<Vec<Seeing>>,
+    /// Whether the seeing is being measured in the background
+    measuring_seeing: bool,
+    /// Order to step through frames in, from `ORDER_CHOICES`
+    order: &'static str,
+    /// How frames are combined when stacking, from `COMBINE_NAMES`
+    combine: &'static str,
+    /// Whether frames are aligned with the sharpest frame before they are stacked
+    align: bool,
+    /// Whether frames are being stacked in the background
+    stacking: bool,
+    /// Blink comparator, while blinking
+    blink: Option<BlinkState>,
+    /// Thumbnail filmstrip below the frame, while it is shown
+    filmstrip: Option<Filmstrip>,
+    /// Images shown per second by the blink comparator
+    blink_rate: f64,
+    /// Side-by-side comparison with a second codec, while comparing
+    compare: Option<CompareState>,
+    /// Name of the codec to compare with, from `CODEC_NAMES`
+    compare_codec: &'static str,
+    /// Position of the divider between the two codecs, as a percentage of the frame width
+    divider: u32,
+    dismiss_button: button::State,
+    path_input_state: text_input::State,
+    open_button: button::State,
+    choose_file_button: button::State,
+    previous_file_button: button::State,
+    next_file_button: button::State,
+    /// One button for each file in the playlist
+    playlist_buttons: Vec<button::State>,
+    playlist_scroll: scrollable::State,
+    play_button: button::State,
+    save_button: button::State,
+    clip_button: button::State,
+    stack_button: button::State,
+    histogram_button: button::State,
+    filmstrip_button: button::State,
+    info_button: button::State,
+    detach_button: button::State,
+    theme_list: pick_list::State<&'static str>,
+    language_list: pick_list::State<String>,
+    profile_button: button::State,
+    clear_profile_button: button::State,
+    info_scroll: scrollable::State,
+    fit_button: button::State,
+    actual_size_button: button::State,
+    region_button: button::State,
+    full_frame_button: button::State,
+    auto_stretch_button: button::State,
+    reset_stretch_button: button::State,
+    black_slider: slider::State,
+    white_slider: slider::State,
+    gamma_slider: slider::State,
+    red_gain_slider: slider::State,
+    green_gain_slider: slider::State,
+    blue_gain_slider: slider::State,
+    auto_white_balance_button: button::State,
+    reset_white_balance_button: button::State,
+    white_balance_list: pick_list::State<&'static str>,
+    color_map_list: pick_list::State<&'static str>,
+    pixel_scale_state: text_input::State,
+    rotation_slider: slider::State,
+    annotate_button: button::State,
+    annotation_text_state: text_input::State,
+    clear_annotations_button: button::State,
+    seek_slider: slider::State,
     increment_button: button::State,
     decrement_button: button::State,
+    /// Numbers of frames to jump by, from the configuration
+    frame_steps: Vec<u32>,
+    /// Messages sent by keys without modifiers, from the configuration
+    key_bindings: HashMap<KeyCode, Message>,
+    /// Configuration file to save the settings to when the window is closed
+    config_path: Option<PathBuf>,
+    /// Settings that the player started with, including command line overrides, so that only
+    /// the settings changed in the player are saved
+    startup_config: Config,
+    /// Whether the window has been closed and the settings saved
+    exiting: bool,
+    /// Backward and forward buttons for each of `frame_steps`
+    step_buttons: Vec<(button::State, button::State)>,
+    exclude_button: button::State,
+    undo_button: button::State,
+    redo_button: button::State,
+    score_button: button::State,
+    export_ranking_button: button::State,
+    export_marks_button: button::State,
+    seeing_button: button::State,
+    export_seeing_button: button::State,
+    clear_seeing_button: button::State,
+    bayer_list: pick_list::State<&'static str>,
+    bit_depth_list: pick_list::State<&'static str>,
+    endianness_list: pick_list::State<&'static str>,
+    codec_list: pick_list::State<&'static str>,
+    binning_list: pick_list::State<&'static str>,
+    fps_list: pick_list::State<&'static str>,
+    speed_list: pick_list::State<&'static str>,
+    playback_mode_list: pick_list::State<&'static str>,
+    marker_a_button: button::State,
+    marker_b_button: button::State,
+    clear_markers_button: button::State,
+    reset_stack_button: button::State,
+    export_list: pick_list::State<&'static str>,
+    animation_button: button::State,
+    animation_list: pick_list::State<&'static str>,
+    order_list: pick_list::State<&'static str>,
+    combine_list: pick_list::State<&'static str>,
+    blink_frame_button: button::State,
+    blink_file_button: button::State,
+    blink_rate_list: pick_list::State<&'static str>,
+    blink_left_button: button::State,
+    blink_right_button: button::State,
+    blink_up_button: button::State,
+    blink_down_button: button::State,
+    blink_reset_button: button::State,
+    stop_blink_button: button::State,
+    compare_codec_list: pick_list::State<&'static str>,
+    divider_slider: slider::State,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Message {
     NextFrame,
     PrevFrame,
+    /// Jump forward by a number of frames in the chosen order, or backward if it is negative
+    StepFrames(i64),
+    /// Start playback, or pause it if it is already playing
+    TogglePlaying,
+    /// Advance to the next frame during playback
+    Tick(Instant),
+    /// Show the newest frame as frames are added to a growing video
+    FollowToggled(bool),
+    /// The growing video with the given watch ID has the given number of frames
+    FramesAdded(u64, usize),
+    /// Show the running mean of the frames that are shown instead of the current frame
+    LiveStackToggled(bool),
+    ResetLiveStack,
+    /// A frame was added to the live stack with the given key, giving the new mean and the
+    /// number of frames in it
+    FrameStacked((u64, u64), std::result::Result<(Handle, usize), String>),
+    PlaybackModeSelected(&'static str),
+    /// Mark the current frame as the start of the A-B loop region
+    SetMarkerA,
+    /// Mark the current frame as the end of the A-B loop region
+    SetMarkerB,
+    ClearMarkers,
+    FpsSelected(&'static str),
+    /// Speed of real-time playback, from `SPEED_NAMES`
+    SpeedSelected(&'static str),
+    /// The seek slider was moved to a frame, which is previewed while dragging
+    Seek(u32),
+    /// The seek slider was released, so the current frame can be decoded in full
+    SeekReleased,
+    /// A coarse preview decode finished for the given generation
+    FramePreviewed(u64, Result<Handle, String>),
+    /// A full resolution decode finished for the given generation
+    FrameDecoded(u64, Result<Handle, String>),
+    /// The next band of rows of a large frame was decoded for the given generation
+    BandDecoded(u64, Result<Band, String>),
+    /// Hide the error message
+    DismissError,
+    PathInputChanged(String),
+    /// Replace the current video with the file at the given path
+    OpenFile(PathBuf),
+    /// Show the native file dialog to choose the video to open
+    ChooseFile,
+    /// The file dialog was closed, with the chosen file unless it was cancelled
+    FileChosen(Result<Option<PathBuf>, String>),
+    PreviousFile,
+    NextFile,
+    /// Exclude the current frame, or include it again if it was already excluded
+    ToggleExcluded,
+    /// Mark the current frame, or remove the mark if it already has the same one
+    MarkFrame(Mark),
+    /// Show or hide the histogram panel
+    ToggleHistogram,
+    /// Show or hide the file information panel
+    ToggleInfo,
+    /// Move the analysis panels into a pane of their own, or back beside the image
+    DetachPanels,
+    PaneDragged(pane_grid::DragEvent),
+    PaneResized(pane_grid::ResizeEvent),
+    ThemeSelected(&'static str),
+    LanguageSelected(String),
+    /// Show or hide the thumbnail filmstrip
+    ToggleFilmstrip,
+    /// The thumbnail at a position in the filmstrip was decoded in the given epoch
+    ThumbnailDecoded(u64, usize, Result<Handle, String>),
+    /// Show a frame chosen from the filmstrip
+    JumpToFrame(usize),
+    /// Zoom out to show the whole frame
+    ZoomToFit,
+    /// Zoom to one screen pixel per frame pixel
+    ZoomToActualSize,
+    CenterTargetToggled(bool),
+    /// Start or cancel selecting a region of interest on the image
+    SelectRegion,
+    /// A region of the image that is shown was selected, as fractions of its size
+    RegionSelected(Rectangle),
+    /// Stop cropping to the region of interest
+    ShowFullFrame,
+    /// The cursor moved over the image, to the given fraction of its size, or left it
+    CursorOverImage(Option<Vector>),
+    /// Start or cancel drawing the line for a line profile on the image
+    DrawProfileLine,
+    /// A line was drawn on the image, from and to fractions of its size
+    ProfileLineDrawn(Vector, Vector),
+    ClearProfile,
+    /// Start or stop placing annotations on the image
+    Annotate,
+    AnnotationTextChanged(String),
+    /// An arrow was drawn on the image, from and to fractions of its size
+    ArrowDrawn(Vector, Vector),
+    /// The image was clicked to place a label, at a fraction of its size
+    LabelPlaced(Vector),
+    /// Remove the annotations on the current frame
+    ClearFrameAnnotations,
+    BurnAnnotationsToggled(bool),
+    AnnotationsSaved(Result<PathBuf, String>),
+    CropExportsToggled(bool),
+    BlackPointChanged(u8),
+    WhitePointChanged(u8),
+    GammaChanged(f32),
+    RedGainChanged(f32),
+    GreenGainChanged(f32),
+    BlueGainChanged(f32),
+    WhiteBalanceMethodSelected(&'static str),
+    ColorMapSelected(&'static str),
+    /// Show or hide the scale bar and compass
+    ScaleToggled(bool),
+    PixelScaleChanged(String),
+    /// Angle of north from the top of the frame, in degrees counterclockwise
+    RotationChanged(f64),
+    MirroredToggled(bool),
+    /// Choose the channel gains from the current frame using the selected method
+    AutoWhiteBalance,
+    ResetWhiteBalance,
+    /// Choose the black and white points from the histogram of the current frame
+    AutoStretch,
+    ResetStretch,
+    Undo,
+    Redo,
+    BayerSelected(&'static str),
+    BitDepthSelected(&'static str),
+    EndiannessSelected(&'static str),
+    CodecSelected(&'static str),
+    BinningSelected(&'static str),
+    /// Show the current frame decoded with a second codec to the right of a divider
+    CompareToggled(bool),
+    CompareCodecSelected(&'static str),
+    DividerChanged(u32),
+    /// The frame with the given epoch and index was decoded with the comparison codec
+    ComparisonDecoded((u64, usize), Result<Handle, String>),
+    ExportFormatSelected(&'static str),
+    /// Save the current frame next to the video in the selected format
+    ExportFrame,
+    FrameExported(Result<PathBuf, String>),
+    /// Save the frames that are not excluded or skipped by the chosen order as a SER file
+    SaveClip,
+    ClipSaved(Result<(PathBuf, usize), String>),
+    AnimationFormatSelected(&'static str),
+    /// Save the frames that are not excluded or skipped by the chosen order as an animation,
+    /// shown as they are in the player
+    SaveAnimation,
+    AnimationSaved(Result<(PathBuf, usize), String>),
+    CombineSelected(&'static str),
+    AlignToggled(bool),
+    /// Stack the frames that are not excluded or skipped by the chosen order and save the
+    /// result next to the video in the selected format
+    StackFrames,
+    FramesStacked(Result<(PathBuf, usize), String>),
+    /// Score the sharpness of every frame in the background
+    ScoreFrames,
+    /// Scoring finished for the given epoch, with the scores in frame order
+    FramesScored(u64, Result<Vec<FrameScore>, String>),
+    OrderSelected(&'static str),
+    /// Save the sharpness ranking next to the video as CSV
+    ExportRanking,
+    RankingExported(Result<PathBuf, String>),
+    /// Blink between the frame that is shown now and whichever frame is shown later
+    BlinkFrame,
+    /// Blink between each frame and the same frame of the video at the given path
+    BlinkWith(PathBuf),
+    StopBlink,
+    /// Switch between the current frame and the reference frame while blinking
+    BlinkTick(Instant),
+    BlinkRateSelected(&'static str),
+    /// Move the reference frame by the given number of pixels
+    NudgeBlink(i32, i32),
+    ResetBlinkOffset,
+    /// The reference frame with the given epoch and index was decoded
+    ReferenceDecoded((u64, usize), Result<Handle, String>),
+    /// Save the list of marked frames next to the video as CSV
+    ExportMarks,
+    MarksExported(Result<PathBuf, String>),
+    /// Measure the FWHM of the brightest star in every frame in the background
+    MeasureSeeing,
+    /// The seeing was measured in the given epoch, with the measurements in frame order
+    SeeingMeasured(u64, Result<Vec<Seeing>, String>),
+    /// Save the seeing measurements next to the video as CSV
+    ExportSeeing,
+    SeeingExported(Result<PathBuf, String>),
+    /// Close the seeing chart
+    ClearSeeing,
+    /// A key was pressed without modifiers, which is looked up in the key bindings
+    KeyPressed(KeyCode),
+    /// The window was closed, so save the settings and exit
+    CloseRequested,
 }
 
 impl Application for VideoPlayer {
@@ -64,31 +862,912 @@ impl Application for VideoPlayer {
     type Flags = VideoPlayerArgs;
 
     fn new(flags: Self::Flags) -> (Self, Command<Message>) {
-        let app = Self {
-            video: flags.video.unwrap(),
-            codec: flags.codec.unwrap(),
+        let (overrides, codec_name) = (flags.overrides, flags.codec_name);
+        let codecs = CodecRegistry::with_quality(flags.config.codec_quality);
+        let source = flags
+            .video
+            .map(|video| OpenVideo::new(Arc::from(video), &overrides, &codecs, &codec_name));
+        let error = flags.error;
+        let blink = flags.blink;
+        let cache = FrameCache::new(flags.config.cache_size_mb * 1024 * 1024);
+        let frame_steps = flags.config.frame_steps.clone();
+        let cache = Arc::new(Mutex::new(cache));
+        let prefetcher = Prefetcher::new(cache, PREFETCH_THREADS, |width, height, pixels| {
+            // the image handle owns its pixels, so the buffer cannot be reused
+            let pixels = mem::take(pixels);
+            let size = pixels.len();
+            (Handle::from_pixels(width, height, pixels), size)
+        });
+        let last_directory = flags.config.last_directory.as_ref();
+        let path_input = flags
+            .path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .or_else(|| {
+                let dir = last_directory?;
+                Some(format!("{}{}", dir.display(), MAIN_SEPARATOR))
+            })
+            .unwrap_or_default();
+        let theme = parse_theme(&flags.config.theme).unwrap_or_else(|e| {
+            warn!("{}", e);
+            Theme::default()
+        });
+        let language = &flags.config.language;
+        let locales = flags.config_path.as_deref().and_then(locales_dir);
+        let locale = locales
+            .as_ref()
+            .map(|dir| Locale::load(language, dir))
+            .unwrap_or_else(|| Ok(Locale::english()))
+            .unwrap_or_else(|e| {
+                warn!("Could not load language {}: {}", language, e);
+                Locale::english()
+            });
+        let languages = languages(locales.as_deref());
+        let mut playlist = Playlist::new(flags.playlist);
+        let state = flags.path.as_ref().map(|path| playlist.select(path));
+        let mut app = Self {
+            source,
+            path: flags.path,
             value: 0,
+            decoded: None,
+            decoded_full: false,
+            tiled: None,
+            seek_preview: None,
+            seek_pending: false,
+            image: None,
+            white_balance: WhiteBalance::default(),
+            white_balance_method: WHITE_BALANCE_CHOICES[0],
+            stretch: flags.config.stretch,
+            color_map: ColorMap::default(),
+            center_target: false,
+            region: None,
+            selecting_region: false,
+            show_scale: false,
+            pixel_scale: String::new(),
+            orientation: Orientation::default(),
+            plate_scales: flags.config.plate_scale.clone(),
+            crop_exports: true,
+            hovered: None,
+            readout: None,
+            drawing_profile: false,
+            annotating: false,
+            annotation_text: String::new(),
+            burn_annotations: false,
+            profile_line: None,
+            profile: None,
+            viewport: Viewport::default(),
+            show_histogram: false,
+            show_info: false,
+            panels_detached: false,
+            panes: pane_grid::State::with_configuration(pane_grid::Configuration::Split {
+                axis: pane_grid::Axis::Vertical,
+                ratio: MAIN_PANE_RATIO,
+                a: Box::new(pane_grid::Configuration::Pane(Panel::Main)),
+                b: Box::new(pane_grid::Configuration::Pane(Panel::Analysis)),
+            }),
+            theme,
+            locale: Arc::new(locale),
+            languages,
+            histogram: None,
+            error,
+            status: None,
+            generation: Arc::new(AtomicU64::new(0)),
+            epoch: 0,
+            prefetcher,
+            adjustments: Adjustments::default(),
+            history: History::new(UNDO_LIMIT),
+            pending_edit: None,
+            overrides,
+            codec_name,
+            codecs,
+            binning: 1,
+            mode: flags.mode,
+            read: flags.read,
+            follow_files: flags.follow,
+            path_input,
+            playlist,
+            playing: false,
+            follow: false,
+            watch_id: 0,
+            live_stack: None,
+            fps: flags.fps,
+            real_time: false,
+            speed: 1.0,
+            playback: Playback::default(),
+            export_format: ExportFormat::Png,
+            animation_format: AnimationFormat::Gif,
+            animating: false,
+            scores: None,
+            scoring: false,
+            seeing: None,
+            measuring_seeing: false,
+            order: ORDER_CHOICES[0],
+            combine: COMBINE_NAMES[0],
+            align: false,
+            stacking: false,
+            blink: None,
+            filmstrip: None,
+            blink_rate: DEFAULT_BLINK_RATE,
+            compare: None,
+            compare_codec: "bilinear",
+            divider: 50,
+            dismiss_button: button::State::default(),
+            path_input_state: text_input::State::default(),
+            open_button: button::State::default(),
+            choose_file_button: button::State::default(),
+            previous_file_button: button::State::default(),
+            next_file_button: button::State::default(),
+            playlist_buttons: vec![],
+            playlist_scroll: scrollable::State::default(),
+            play_button: button::State::default(),
+            save_button: button::State::default(),
+            clip_button: button::State::default(),
+            stack_button: button::State::default(),
+            histogram_button: button::State::default(),
+            filmstrip_button: button::State::default(),
+            info_button: button::State::default(),
+            detach_button: button::State::default(),
+            theme_list: pick_list::State::default(),
+            language_list: pick_list::State::default(),
+            profile_button: button::State::default(),
+            clear_profile_button: button::State::default(),
+            info_scroll: scrollable::State::default(),
+            fit_button: button::State::default(),
+            actual_size_button: button::State::default(),
+            region_button: button::State::default(),
+            full_frame_button: button::State::default(),
+            auto_stretch_button: button::State::default(),
+            reset_stretch_button: button::State::default(),
+            black_slider: slider::State::default(),
+            white_slider: slider::State::default(),
+            gamma_slider: slider::State::default(),
+            red_gain_slider: slider::State::default(),
+            green_gain_slider: slider::State::default(),
+            blue_gain_slider: slider::State::default(),
+            auto_white_balance_button: button::State::default(),
+            reset_white_balance_button: button::State::default(),
+            white_balance_list: pick_list::State::default(),
+            color_map_list: pick_list::State::default(),
+            pixel_scale_state: text_input::State::default(),
+            rotation_slider: slider::State::default(),
+            annotate_button: button::State::default(),
+            annotation_text_state: text_input::State::default(),
+            clear_annotations_button: button::State::default(),
+            seek_slider: slider::State::default(),
             increment_button: button::State::default(),
             decrement_button: button::State::default(),
+            step_buttons: frame_steps.iter().map(|_| Default::default()).collect(),
+            frame_steps,
+            key_bindings: key_bindings(&flags.config.keys),
+            config_path: flags.config_path,
+            startup_config: flags.config.clone(),
+            exiting: false,
+            exclude_button: button::State::default(),
+            undo_button: button::State::default(),
+            redo_button: button::State::default(),
+            score_button: button::State::default(),
+            export_ranking_button: button::State::default(),
+            export_marks_button: button::State::default(),
+            seeing_button: button::State::default(),
+            export_seeing_button: button::State::default(),
+            clear_seeing_button: button::State::default(),
+            bayer_list: pick_list::State::default(),
+            bit_depth_list: pick_list::State::default(),
+            endianness_list: pick_list::State::default(),
+            codec_list: pick_list::State::default(),
+            binning_list: pick_list::State::default(),
+            fps_list: pick_list::State::default(),
+            speed_list: pick_list::State::default(),
+            playback_mode_list: pick_list::State::default(),
+            marker_a_button: button::State::default(),
+            marker_b_button: button::State::default(),
+            clear_markers_button: button::State::default(),
+            reset_stack_button: button::State::default(),
+            export_list: pick_list::State::default(),
+            animation_button: button::State::default(),
+            animation_list: pick_list::State::default(),
+            order_list: pick_list::State::default(),
+            combine_list: pick_list::State::default(),
+            blink_frame_button: button::State::default(),
+            blink_file_button: button::State::default(),
+            blink_rate_list: pick_list::State::default(),
+            blink_left_button: button::State::default(),
+            blink_right_button: button::State::default(),
+            blink_up_button: button::State::default(),
+            blink_down_button: button::State::default(),
+            blink_reset_button: button::State::default(),
+            stop_blink_button: button::State::default(),
+            compare_codec_list: pick_list::State::default(),
+            divider_slider: slider::State::default(),
+        };
+        // growing videos follow their newest frame from the start
+        app.follow = app.is_growing();
+        if let Some(state) = state {
+            app.restore(state);
+        }
+        app.pixel_scale = app.configured_pixel_scale().unwrap_or_default();
+        app.load_annotations();
+        let command = Command::batch(vec![app.show_frame(), app.watch_frames()]);
+        let command = match blink {
+            Some(path) => Command::batch(vec![command, app.blink_with(path)]),
+            None => command,
         };
 
-        (app, Command::none())
+        (app, command)
     }
 
     fn title(&self) -> String {
         String::from("Astro Video Player")
     }
 
+    fn should_exit(&self) -> bool {
+        self.exiting
+    }
+
     fn update(&mut self, message: Message, _clipboard: &mut Clipboard) -> Command<Message> {
+        self.record_edit(&message);
         match message {
+            Message::KeyPressed(key_code) => {
+                if let Some(message) = self.key_bindings.get(&key_code).cloned() {
+                    return self.update(message, _clipboard);
+                }
+            }
+            Message::CloseRequested => {
+                self.save_settings();
+                self.remember_file();
+                self.exiting = true;
+            }
             Message::NextFrame => {
-                if self.value + 1 < self.video.frame_count() as u32 {
-                    self.value += 1;
+                if let Some(next) = self.next_frame() {
+                    self.value = next as u32;
+                    return self.show_frame();
                 }
             }
             Message::PrevFrame => {
-                if self.value > 0 {
-                    self.value -= 1;
+                if let Some(previous) = self.previous_frame() {
+                    self.value = previous as u32;
+                    return self.show_frame();
+                }
+            }
+            Message::StepFrames(steps) => {
+                if let Some(frame) = self.step_frame(steps) {
+                    if frame != self.value as usize {
+                        self.value = frame as u32;
+                        return self.show_frame();
+                    }
+                }
+            }
+            Message::TogglePlaying => {
+                let was_playing = self.playing;
+                self.playing = !self.playing && self.source.is_some();
+                // start again from the beginning after reaching the end, or from the start of
+                // the A-B region when outside it
+                if self.playing {
+                    let order = self.frame_order();
+                    if let Some(first) = self.playback.start(&order, self.value as usize) {
+                        self.value = first as u32;
+                        return self.show_frame();
+                    }
+                } else if was_playing && self.binning > 1 {
+                    // only binned previews were decoded during playback
+                    return self.show_frame();
+                }
+            }
+            Message::Tick(_) => {
+                if self.playing {
+                    let order = self.frame_order();
+                    if let Some(next) = self.playback.advance(&order, self.value as usize) {
+                        self.value = next as u32;
+                        return self.show_frame();
+                    }
+                    self.playing = false;
+                    if self.binning > 1 {
+                        return self.show_frame();
+                    }
+                }
+            }
+            Message::FollowToggled(follow) => {
+                self.follow = follow;
+                if follow {
+                    return self.show_newest();
+                }
+            }
+            Message::FramesAdded(watch_id, _) => {
+                if watch_id == self.watch_id {
+                    let command = if self.follow && !self.playing {
+                        self.show_newest()
+                    } else {
+                        Command::none()
+                    };
+                    return Command::batch(vec![command, self.watch_frames()]);
+                }
+            }
+            Message::LiveStackToggled(stack) => {
+                if stack {
+                    self.live_stack = Some(LiveStack::new(self.epoch));
+                    return self.stack_frame();
+                }
+                self.live_stack = None;
+                self.apply_display();
+            }
+            Message::ResetLiveStack => {
+                let epoch = self.epoch;
+                if let Some(state) = &mut self.live_stack {
+                    state.reset(epoch);
+                    self.apply_display();
+                    return self.stack_frame();
+                }
+            }
+            Message::FrameStacked(key, result) => {
+                let state = match &mut self.live_stack {
+                    Some(state) => state,
+                    None => return Command::none(),
+                };
+                state.busy = false;
+                if state.key() == key {
+                    match result {
+                        Ok((handle, frames)) => {
+                            if self.show_histogram {
+                                self.histogram = histogram(&handle);
+                            }
+                            state.decoded = Some((handle, frames));
+                            self.apply_display();
+                        }
+                        Err(e) => {
+                            self.status =
+                                Some(self.locale.format("stack-frame-failed", &[("error", &e)]))
+                        }
+                    }
+                }
+            }
+            Message::PlaybackModeSelected(choice) => {
+                self.playback.mode = parse_playback_mode(choice).unwrap_or_default();
+            }
+            Message::SetMarkerA => {
+                if self.source.is_some() {
+                    self.playback.marker_a = Some(self.value as usize);
+                }
+            }
+            Message::SetMarkerB => {
+                if self.source.is_some() {
+                    self.playback.marker_b = Some(self.value as usize);
+                }
+            }
+            Message::ClearMarkers => self.playback.clear_markers(),
+            Message::FpsSelected(choice) => {
+                self.real_time = choice == REAL_TIME;
+                self.fps = choice.parse().ok();
+            }
+            Message::SpeedSelected(choice) => {
+                self.speed = parse_speed(choice).unwrap_or(1.0);
+            }
+            Message::Seek(value) => {
+                if value != self.value {
+                    // like scrolling back in a terminal, seeking stops following new frames
+                    self.follow = false;
+                    self.value = value;
+                    if self.show_cached() {
+                        return self.preview_seek();
+                    }
+                }
+            }
+            Message::SeekReleased => return self.show_frame(),
+            Message::FramePreviewed(generation, result) => {
+                let next_preview = self.finish_seek_preview(generation);
+                if generation == self.generation() && !self.decoded_full {
+                    match result {
+                        Ok(handle) => {
+                            // bands of a large frame that are already decoded stay on screen
+                            let handle = match &mut self.tiled {
+                                Some(tiled) => {
+                                    if let image::Data::Pixels {
+                                        width,
+                                        height,
+                                        pixels,
+                                    } = handle.data()
+                                    {
+                                        tiled.set_preview(*width, *height, pixels);
+                                    }
+                                    if tiled.is_started() {
+                                        let (width, height, pixels) = tiled.image();
+                                        Handle::from_pixels(width, height, pixels.to_vec())
+                                    } else {
+                                        handle
+                                    }
+                                }
+                                None => handle,
+                            };
+                            self.set_image(handle);
+                        }
+                        Err(e) => self.show_error(e),
+                    }
+                }
+                return next_preview;
+            }
+            Message::FrameDecoded(generation, result) => {
+                if generation == self.generation() {
+                    match result {
+                        Ok(handle) => {
+                            self.decoded_full = true;
+                            self.set_image(handle);
+                        }
+                        Err(e) => self.show_error(e),
+                    }
+                }
+            }
+            Message::BandDecoded(generation, result) => {
+                if generation == self.generation() {
+                    match result {
+                        Ok(band) => return self.add_band(band),
+                        Err(e) => self.show_error(e),
+                    }
+                }
+            }
+            Message::DismissError => self.error = None,
+            Message::PathInputChanged(path) => self.path_input = path,
+            Message::OpenFile(path) => return self.open_file(path),
+            Message::ChooseFile => return self.choose_file(),
+            Message::FileChosen(result) => match result {
+                Ok(Some(path)) => return self.open_file(path),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Could not show the file dialog: {}", e);
+                    self.error = Some(e);
+                }
+            },
+            Message::PreviousFile => {
+                if let Some(path) = self.playlist.previous() {
+                    let path = path.to_path_buf();
+                    return self.open_file(path);
+                }
+            }
+            Message::NextFile => {
+                if let Some(path) = self.playlist.next() {
+                    let path = path.to_path_buf();
+                    return self.open_file(path);
+                }
+            }
+            Message::ToggleExcluded => {
+                if self.source.is_some() {
+                    let index = self.value as usize;
+                    let excluded = &mut self.adjustments.excluded;
+                    if !excluded.remove(&index) {
+                        excluded.insert(index);
+                    }
+                }
+            }
+            Message::MarkFrame(mark) => {
+                if self.source.is_some() {
+                    let index = self.value as usize;
+                    toggle_mark(&mut self.adjustments.marks, index, mark);
+                }
+            }
+            Message::ToggleHistogram => {
+                self.show_histogram = !self.show_histogram;
+                self.histogram = None;
+                if let Some(handle) = self.decoded.clone() {
+                    self.set_image(handle);
+                }
+            }
+            Message::ToggleInfo => self.show_info = !self.show_info,
+            Message::DetachPanels => self.panels_detached = !self.panels_detached,
+            Message::PaneDragged(pane_grid::DragEvent::Dropped { pane, target }) => {
+                self.panes.swap(&pane, &target);
+            }
+            Message::PaneDragged(_) => {}
+            Message::PaneResized(pane_grid::ResizeEvent { split, ratio }) => {
+                self.panes.resize(&split, ratio);
+            }
+            Message::ThemeSelected(choice) => {
+                self.theme = parse_theme(choice).unwrap_or_default();
+            }
+            Message::LanguageSelected(language) => {
+                let dir = self
+                    .config_path
+                    .as_deref()
+                    .and_then(locales_dir)
+                    .unwrap_or_default();
+                match Locale::load(&language, &dir) {
+                    Ok(locale) => self.locale = Arc::new(locale),
+                    Err(e) => {
+                        self.status =
+                            Some(self.locale.format(
+                                "language-failed",
+                                &[("language", &language), ("error", &e)],
+                            ))
+                    }
+                }
+            }
+            Message::ToggleFilmstrip => {
+                if self.filmstrip.take().is_none() {
+                    return self.start_filmstrip();
+                }
+            }
+            Message::ThumbnailDecoded(epoch, position, result) => {
+                if let Some(filmstrip) = &mut self.filmstrip {
+                    if filmstrip.epoch == epoch && position < filmstrip.thumbnails.len() {
+                        filmstrip.thumbnails[position] = result.ok();
+                        return self.decode_thumbnail(position + 1);
+                    }
+                }
+            }
+            Message::JumpToFrame(index) => {
+                if index < self.frame_count() {
+                    self.value = index as u32;
+                    return self.show_frame();
+                }
+            }
+            Message::ZoomToFit => self.viewport.fit(),
+            Message::ZoomToActualSize => self.viewport.actual_size(),
+            Message::CenterTargetToggled(center) => {
+                self.center_target = center;
+                self.apply_display();
+            }
+            Message::SelectRegion => {
+                self.selecting_region = !self.selecting_region && self.source.is_some();
+                self.drawing_profile = false;
+                self.annotating = false;
+            }
+            Message::RegionSelected(selected) => return self.select_region(selected),
+            Message::CursorOverImage(position) => {
+                self.hovered = position;
+                self.update_readout();
+            }
+            Message::DrawProfileLine => {
+                self.drawing_profile = !self.drawing_profile && self.source.is_some();
+                self.selecting_region = false;
+                self.annotating = false;
+            }
+            Message::ProfileLineDrawn(start, end) => {
+                self.drawing_profile = false;
+                self.profile_line = Some((start, end));
+                self.update_profile();
+            }
+            Message::ClearProfile => self.clear_profile(),
+            Message::Annotate => {
+                self.annotating = !self.annotating && self.source.is_some();
+                self.selecting_region = false;
+                self.drawing_profile = false;
+            }
+            Message::AnnotationTextChanged(text) => self.annotation_text = text,
+            Message::ArrowDrawn(start, end) => {
+                let to = self.frame_position(end);
+                return self.annotate(start, to);
+            }
+            Message::LabelPlaced(position) => {
+                if !self.annotation_text.trim().is_empty() {
+                    return self.annotate(position, None);
+                }
+            }
+            Message::ClearFrameAnnotations => {
+                let index = self.index();
+                self.adjustments
+                    .annotations
+                    .retain(|annotation| annotation.frame != index);
+                return self.save_annotations();
+            }
+            Message::BurnAnnotationsToggled(burn) => self.burn_annotations = burn,
+            Message::AnnotationsSaved(result) => {
+                if let Err(e) = result {
+                    self.status = Some(
+                        self.locale
+                            .format("save-annotations-failed", &[("error", &e)]),
+                    );
+                }
+            }
+            Message::ShowFullFrame => {
+                self.region = None;
+                self.selecting_region = false;
+                self.clear_profile();
+                self.viewport.fit();
+                return self.redecode();
+            }
+            Message::CropExportsToggled(crop) => self.crop_exports = crop,
+            Message::BlackPointChanged(black) => {
+                self.stretch.set_black(black);
+                self.apply_display();
+            }
+            Message::WhitePointChanged(white) => {
+                self.stretch.set_white(white);
+                self.apply_display();
+            }
+            Message::GammaChanged(gamma) => {
+                self.stretch.gamma = gamma;
+                self.apply_display();
+            }
+            Message::RedGainChanged(gain) => {
+                self.white_balance.red = gain;
+                self.apply_display();
+            }
+            Message::GreenGainChanged(gain) => {
+                self.white_balance.green = gain;
+                self.apply_display();
+            }
+            Message::BlueGainChanged(gain) => {
+                self.white_balance.blue = gain;
+                self.apply_display();
+            }
+            Message::WhiteBalanceMethodSelected(choice) => self.white_balance_method = choice,
+            Message::AutoWhiteBalance => {
+                let method = match self.white_balance_method {
+                    "max RGB" => WhiteBalanceMethod::MaxRgb,
+                    _ => WhiteBalanceMethod::GrayWorld,
+                };
+                if let Some(histogram) = self.decoded.as_ref().and_then(histogram) {
+                    let auto = WhiteBalance::auto(&histogram, method);
+                    self.white_balance = WhiteBalance {
+                        red: auto.red.clamp(MIN_GAIN, MAX_GAIN),
+                        green: auto.green,
+                        blue: auto.blue.clamp(MIN_GAIN, MAX_GAIN),
+                    };
+                    self.apply_display();
+                }
+            }
+            Message::ResetWhiteBalance => {
+                self.white_balance = WhiteBalance::default();
+                self.apply_display();
+            }
+            Message::AutoStretch => {
+                // the stretch is applied to white balanced frames
+                let histogram = self
+                    .decoded
+                    .as_ref()
+                    .and_then(|handle| match handle.data() {
+                        image::Data::Pixels { pixels, .. } => {
+                            let mut pixels = pixels.clone();
+                            self.white_balance.apply(&mut pixels);
+                            Some(Histogram::from_bgra(&pixels))
+                        }
+                        _ => None,
+                    });
+                if let Some(histogram) = histogram {
+                    self.stretch = self.stretch.auto(&histogram);
+                    self.apply_display();
+                }
+            }
+            Message::ResetStretch => {
+                self.stretch = Stretch::default();
+                self.apply_display();
+            }
+            Message::ColorMapSelected(name) => {
+                if let Ok(color_map) = parse_color_map(name) {
+                    self.color_map = color_map;
+                    self.apply_display();
+                }
+            }
+            Message::ScaleToggled(show) => self.show_scale = show,
+            Message::PixelScaleChanged(text) => {
+                if let (Some(instrument), Some(scale)) =
+                    (self.instrument(), parse_pixel_scale(&text))
+                {
+                    self.plate_scales.insert(instrument, scale);
+                }
+                self.pixel_scale = text;
+            }
+            Message::RotationChanged(rotation) => self.orientation.rotation = rotation,
+            Message::MirroredToggled(mirrored) => self.orientation.mirrored = mirrored,
+            Message::Undo => {
+                if let Some(previous) = self.history.undo(self.edits()) {
+                    return self.set_edits(previous);
+                }
+            }
+            Message::Redo => {
+                if let Some(next) = self.history.redo(self.edits()) {
+                    return self.set_edits(next);
+                }
+            }
+            Message::BayerSelected(choice) => {
+                self.overrides.bayer = parse_bayer(choice).ok();
+                return self.reopen();
+            }
+            Message::BitDepthSelected(choice) => {
+                self.overrides.pixel_depth_bits = choice.parse().ok();
+                return self.reopen();
+            }
+            Message::EndiannessSelected(choice) => {
+                self.overrides.endianness = parse_endianness_override(choice).ok();
+                return self.reopen();
+            }
+            Message::CodecSelected(choice) => {
+                self.codec_name = choice.to_string();
+                return self.reopen();
+            }
+            Message::BinningSelected(choice) => {
+                self.binning = parse_binning(choice).unwrap_or(1);
+            }
+            Message::CompareToggled(compare) => {
+                self.compare = if compare { self.compare_state() } else { None };
+                self.apply_display();
+                return self.decode_comparison();
+            }
+            Message::CompareCodecSelected(choice) => {
+                self.compare_codec = choice;
+                if self.compare.is_some() {
+                    self.compare = self.compare_state();
+                    self.apply_display();
+                    return self.decode_comparison();
+                }
+            }
+            Message::DividerChanged(divider) => {
+                self.divider = divider;
+                self.apply_display();
+            }
+            Message::ComparisonDecoded(key, result) => {
+                if let Some(state) = &mut self.compare {
+                    if state.key == Some(key) {
+                        match result {
+                            Ok(handle) => {
+                                state.decoded = Some((key, handle));
+                                self.apply_display();
+                            }
+                            Err(e) => {
+                                self.status = Some(self.locale.format(
+                                    "decode-with-failed",
+                                    &[("codec", &self.compare_codec), ("error", &e)],
+                                ))
+                            }
+                        }
+                    }
+                }
+            }
+            Message::ExportFormatSelected(choice) => {
+                self.export_format = parse_export_format(choice).unwrap_or(ExportFormat::Png);
+            }
+            Message::ExportFrame => return self.export_frame(),
+            Message::FrameExported(result) => {
+                self.status = Some(match result {
+                    Ok(path) => self.locale.format("saved", &[("path", &path.display())]),
+                    Err(e) => self.locale.format("save-frame-failed", &[("error", &e)]),
+                });
+            }
+            Message::SaveClip => return self.save_clip(),
+            Message::ClipSaved(result) => {
+                self.status = Some(match result {
+                    Ok((path, count)) => self.locale.format(
+                        "saved-frames",
+                        &[("count", &count), ("path", &path.display())],
+                    ),
+                    Err(e) => self.locale.format("save-clip-failed", &[("error", &e)]),
+                });
+            }
+            Message::AnimationFormatSelected(choice) => {
+                self.animation_format =
+                    parse_animation_format(choice).unwrap_or(AnimationFormat::Gif);
+            }
+            Message::SaveAnimation => return self.save_animation(),
+            Message::AnimationSaved(result) => {
+                self.animating = false;
+                self.status = Some(match result {
+                    Ok((path, count)) => self.locale.format(
+                        "saved-frames",
+                        &[("count", &count), ("path", &path.display())],
+                    ),
+                    Err(e) => self
+                        .locale
+                        .format("save-animation-failed", &[("error", &e)]),
+                });
+            }
+            Message::CombineSelected(choice) => self.combine = choice,
+            Message::AlignToggled(align) => self.align = align,
+            Message::StackFrames => return self.stack_frames(),
+            Message::FramesStacked(result) => {
+                self.stacking = false;
+                self.status = Some(match result {
+                    Ok((path, count)) => self.locale.format(
+                        "stacked-frames",
+                        &[("count", &count), ("path", &path.display())],
+                    ),
+                    Err(e) => self.locale.format("stack-failed", &[("error", &e)]),
+                });
+            }
+            Message::ScoreFrames => return self.score_frames(),
+            Message::FramesScored(epoch, result) => {
+                if epoch == self.epoch {
+                    self.scoring = false;
+                    match result {
+                        Ok(scores) => {
+                            self.status = Some(
+                                self.locale
+                                    .format("scored-frames", &[("count", &scores.len())]),
+                            );
+                            self.scores = Some(rank(&scores));
+                        }
+                        Err(e) => {
+                            self.status = Some(self.locale.format("score-failed", &[("error", &e)]))
+                        }
+                    }
+                }
+            }
+            Message::OrderSelected(choice) => self.order = choice,
+            Message::ExportRanking => return self.export_ranking(),
+            Message::RankingExported(result) => {
+                self.status = Some(match result {
+                    Ok(path) => self.locale.format("saved", &[("path", &path.display())]),
+                    Err(e) => self.locale.format("save-ranking-failed", &[("error", &e)]),
+                });
+            }
+            Message::ExportMarks => return self.export_marks(),
+            Message::MarksExported(result) => {
+                self.status = Some(match result {
+                    Ok(path) => self.locale.format("saved", &[("path", &path.display())]),
+                    Err(e) => self.locale.format("save-marks-failed", &[("error", &e)]),
+                });
+            }
+            Message::MeasureSeeing => return self.measure_seeing(),
+            Message::SeeingMeasured(epoch, result) => {
+                if epoch == self.epoch {
+                    self.measuring_seeing = false;
+                    match result {
+                        Ok(seeing) => {
+                            let measured = seeing.iter().filter(|s| s.fwhm.is_some()).count();
+                            self.status = Some(self.locale.format(
+                                "seeing-measured",
+                                &[("measured", &measured), ("count", &seeing.len())],
+                            ));
+                            self.seeing = Some(seeing);
+                        }
+                        Err(e) => {
+                            self.status =
+                                Some(self.locale.format("seeing-failed", &[("error", &e)]))
+                        }
+                    }
+                }
+            }
+            Message::ExportSeeing => return self.export_seeing(),
+            Message::SeeingExported(result) => {
+                self.status = Some(match result {
+                    Ok(path) => self.locale.format("saved", &[("path", &path.display())]),
+                    Err(e) => self.locale.format("save-seeing-failed", &[("error", &e)]),
+                });
+            }
+            Message::ClearSeeing => self.seeing = None,
+            Message::BlinkFrame => {
+                if self.source.is_some() {
+                    let target = BlinkTarget::Frame(self.index());
+                    return self.start_blink(target, None, None);
+                }
+            }
+            Message::BlinkWith(path) => return self.blink_with(path),
+            Message::StopBlink => self.blink = None,
+            Message::BlinkTick(_) => {
+                if let Some(state) = &mut self.blink {
+                    state.blink.toggle();
+                }
+            }
+            Message::BlinkRateSelected(choice) => {
+                self.blink_rate = parse_blink_rate(choice).unwrap_or(DEFAULT_BLINK_RATE);
+                if let Some(state) = &mut self.blink {
+                    state.blink.rate = self.blink_rate;
+                }
+            }
+            Message::NudgeBlink(dx, dy) => {
+                if let Some(state) = &mut self.blink {
+                    state.blink.nudge(dx, dy);
+                    self.apply_blink_display();
+                }
+            }
+            Message::ResetBlinkOffset => {
+                if let Some(state) = &mut self.blink {
+                    state.blink.offset = Shift::default();
+                    self.apply_blink_display();
+                }
+            }
+            Message::ReferenceDecoded(key, result) => {
+                if let Some(state) = &mut self.blink {
+                    if state.key == Some(key) {
+                        match result {
+                            Ok(handle) => {
+                                state.decoded = Some(handle);
+                                self.apply_blink_display();
+                            }
+                            Err(e) => {
+                                self.status = Some(
+                                    self.locale
+                                        .format("reference-decode-failed", &[("error", &e)]),
+                                )
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -96,50 +1775,3331 @@ impl Application for VideoPlayer {
         Command::none()
     }
 
-    fn view(&mut self) -> Element<Message> {
-        let index = if (self.value as usize) < self.video.frame_count() {
-            self.value as usize
+    fn subscription(&self) -> Subscription<Message> {
+        let mut subscriptions = vec![iced_native::subscription::events_with(input_event)];
+        if self.playing {
+            // a new timer starts whenever the interval changes, so in real-time playback each
+            // frame is shown for its own interval
+            let interval = self.playback_interval();
+            subscriptions.push(iced::time::every(interval).map(Message::Tick));
+        }
+        if let Some(state) = &self.blink {
+            let interval = Duration::from_secs_f64(1.0 / state.blink.rate);
+            subscriptions.push(iced::time::every(interval).map(Message::BlinkTick));
+        }
+        Subscription::batch(subscriptions)
+    }
+
+    fn view(&mut self) -> Element<'_, Message> {
+        let theme = self.theme;
+        let locale = Arc::clone(&self.locale);
+        let frame_count = self.frame_count();
+        let growing = self.is_growing();
+        let index = self.index();
+        let scale_overlay = self.scale_overlay();
+        let callouts = self.callouts();
+        let edited = self.is_edited();
+        let stacked_frames = self.live_stack.as_ref().map(|state| {
+            state
+                .decoded
+                .as_ref()
+                .map(|(_, frames)| *frames)
+                .unwrap_or(0)
+        });
+        let open_path = PathBuf::from(self.path_input.trim());
+        let excluded = self.adjustments.excluded.contains(&(self.value as usize));
+        let mark = self.adjustments.marks.get(&(self.value as usize)).copied();
+        let fps = 1.0 / self.playback_interval().as_secs_f64();
+        let speed = SPEED_NAMES
+            .iter()
+            .find(|name| {
+                parse_speed(name).is_ok_and(|speed| (speed - self.speed).abs() < f64::EPSILON)
+            })
+            .copied();
+        let timestamp = self
+            .source
+            .as_ref()
+            .and_then(|source| source.video.frame_timestamp(self.value as usize));
+        let sharpness_rank = self.scores.as_ref().and_then(|scores| {
+            scores
+                .iter()
+                .position(|score| score.index == self.value as usize)
+                .map(|rank| {
+                    locale.format(
+                        "sharpness-rank",
+                        &[("rank", &(rank + 1)), ("count", &scores.len())],
+                    )
+                })
+        });
+        // while blinking, the reference frame is shown in place of the current frame
+        let shown = match &self.blink {
+            Some(BlinkState {
+                blink,
+                image: Some(reference),
+                ..
+            }) if blink.showing_reference => Some(reference),
+            _ => self.image.as_ref(),
+        };
+        let image: Element<Message> = match shown {
+            Some(handle) if self.selecting_region => {
+                ImageView::new(&mut self.viewport, handle.clone())
+                    .on_select(Message::RegionSelected)
+                    .on_hover(Message::CursorOverImage)
+                    .into()
+            }
+            Some(handle) if self.drawing_profile => {
+                ImageView::new(&mut self.viewport, handle.clone())
+                    .on_line(Message::ProfileLineDrawn)
+                    .on_hover(Message::CursorOverImage)
+                    .into()
+            }
+            Some(handle) if self.annotating => ImageView::new(&mut self.viewport, handle.clone())
+                .on_line(Message::ArrowDrawn)
+                .on_point(Message::LabelPlaced)
+                .on_hover(Message::CursorOverImage)
+                .overlay(scale_overlay)
+                .callouts(callouts)
+                .into(),
+            Some(handle) => ImageView::new(&mut self.viewport, handle.clone())
+                .on_hover(Message::CursorOverImage)
+                .line(self.profile_line)
+                .overlay(scale_overlay)
+                .callouts(callouts)
+                .into(),
+            None if self.source.is_none() => Text::new(locale.text("open-prompt")).into(),
+            None if self.error.is_some() => Text::new(locale.text("no-frame")).into(),
+            None => Text::new(locale.text("decoding")).into(),
+        };
+        let mut image_row = Row::new().spacing(20).align_items(Align::Center).push(
+            Container::new(image)
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .center_x()
+                .center_y(),
+        );
+        // the analysis panels are shown beside the image, or in a pane of their own that can
+        // be moved and resized when they are detached
+        let detached = self.panels_detached;
+        let panel_width = |width| {
+            if detached {
+                Length::Fill
+            } else {
+                Length::Units(width)
+            }
+        };
+        let mut panels: Vec<Element<Message>> = vec![];
+        if let (true, Some(histogram)) = (self.show_histogram, &self.histogram) {
+            let [red, green, blue] = histogram.clipped();
+            panels.push(
+                Column::new()
+                    .spacing(5)
+                    .push(
+                        Canvas::new(HistogramChart(histogram.clone()))
+                            .width(panel_width(HISTOGRAM_WIDTH))
+                            .height(Length::Units(HISTOGRAM_HEIGHT)),
+                    )
+                    .push(Text::new(locale.format(
+                        "clipped",
+                        &[
+                            ("red", &format!("{:.2}", red * 100.0)),
+                            ("green", &format!("{:.2}", green * 100.0)),
+                            ("blue", &format!("{:.2}", blue * 100.0)),
+                        ],
+                    )))
+                    .into(),
+            );
+        }
+        if let Some(profile) = &self.profile {
+            let chart: Element<Message> = match profile {
+                Ok(profile) => Canvas::new(ProfileChart(profile.clone()))
+                    .width(panel_width(PROFILE_WIDTH))
+                    .height(Length::Units(HISTOGRAM_HEIGHT))
+                    .into(),
+                Err(e) => Text::new(e.clone())
+                    .size(16)
+                    .width(panel_width(PROFILE_WIDTH))
+                    .into(),
+            };
+            let title = match profile {
+                Ok(profile) => locale.format("profile-length", &[("length", &profile.len())]),
+                Err(_) => locale.text("profile-title"),
+            };
+            panels.push(
+                Column::new()
+                    .spacing(5)
+                    .push(Text::new(title))
+                    .push(chart)
+                    .push(
+                        Button::new(
+                            &mut self.clear_profile_button,
+                            Text::new(locale.text("clear")),
+                        )
+                        .style(theme)
+                        .on_press(Message::ClearProfile),
+                    )
+                    .into(),
+            );
+        }
+        if let Some(seeing) = &self.seeing {
+            let current = seeing
+                .iter()
+                .find(|s| s.index == index)
+                .and_then(|s| s.fwhm);
+            let title = match (median_fwhm(seeing), current) {
+                (Some(median), Some(current)) => locale.format(
+                    "seeing-fwhm",
+                    &[
+                        ("fwhm", &format!("{:.2}", current)),
+                        ("median", &format!("{:.2}", median)),
+                    ],
+                ),
+                (Some(median), None) => {
+                    locale.format("seeing-median", &[("median", &format!("{:.2}", median))])
+                }
+                (None, _) => locale.text("no-stars"),
+            };
+            panels.push(
+                Column::new()
+                    .spacing(5)
+                    .push(Text::new(title))
+                    .push(
+                        Canvas::new(SeeingChart {
+                            seeing: seeing.clone(),
+                            current: index,
+                        })
+                        .width(panel_width(PROFILE_WIDTH))
+                        .height(Length::Units(HISTOGRAM_HEIGHT)),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .push(
+                                Button::new(
+                                    &mut self.export_seeing_button,
+                                    Text::new(locale.text("export")),
+                                )
+                                .style(theme)
+                                .on_press(Message::ExportSeeing),
+                            )
+                            .push(
+                                Button::new(
+                                    &mut self.clear_seeing_button,
+                                    Text::new(locale.text("clear")),
+                                )
+                                .style(theme)
+                                .on_press(Message::ClearSeeing),
+                            ),
+                    )
+                    .into(),
+            );
+        }
+        if let (true, Some(source)) = (self.show_info, &self.source) {
+            let mut info = Scrollable::new(&mut self.info_scroll)
+                .style(theme)
+                .spacing(2)
+                .width(panel_width(INFO_PANEL_WIDTH))
+                .height(Length::Fill);
+            if let Some(path) = &self.path {
+                info = info.push(
+                    Text::new(locale.format("info-file", &[("path", &path.display())])).size(16),
+                );
+            }
+            for (name, value) in source.info(&locale) {
+                info = info.push(Text::new(format!("{}: {}", name, value)).size(16));
+            }
+            panels.push(info.into());
+        }
+        let analysis = if detached {
+            let column = Column::new().padding(20).spacing(20);
+            Some(panels.into_iter().fold(column, Column::push))
         } else {
-            self.video.frame_count() - 1
+            image_row = panels.into_iter().fold(image_row, Row::push);
+            None
         };
 
-        let (w, h, pixels) = self.codec.decode(self.video.as_ref(), index);
-
-        let handle = Handle::from_pixels(w, h, pixels);
-
-        let image = Image::new(handle).width(Length::Fill).height(Length::Fill);
+        let seek = Slider::new(
+            &mut self.seek_slider,
+            0..=(frame_count.max(1) - 1) as u32,
+            self.value,
+            Message::Seek,
+        )
+        .style(theme)
+        .on_release(Message::SeekReleased)
+        .width(Length::Fill);
+        let filmstrip = self.filmstrip.as_mut().map(|filmstrip| {
+            filmstrip
+                .buttons
+                .iter_mut()
+                .zip(&filmstrip.frames)
+                .zip(&filmstrip.thumbnails)
+                .fold(
+                    Row::new().spacing(5).align_items(Align::Center),
+                    |row, ((state, &frame), thumbnail)| {
+                        let content: Element<Message> = match thumbnail {
+                            Some(handle) => Image::new(handle.clone())
+                                .height(Length::Units(FILMSTRIP_HEIGHT))
+                                .into(),
+                            None => Container::new(Text::new((frame + 1).to_string()).size(16))
+                                .height(Length::Units(FILMSTRIP_HEIGHT))
+                                .center_y()
+                                .into(),
+                        };
+                        row.push(
+                            Button::new(state, content)
+                                .style(theme)
+                                .padding(2)
+                                .on_press(Message::JumpToFrame(frame)),
+                        )
+                    },
+                )
+        });
+        let mark_strip = Canvas::new(MarkStrip {
+            frame_count,
+            marks: self.adjustments.marks.clone(),
+            gaps: self
+                .source
+                .as_ref()
+                .map(|source| source.gaps.clone())
+                .unwrap_or_default(),
+        })
+        .width(Length::Fill)
+        .height(Length::Units(MARK_STRIP_HEIGHT));
 
         let controls = Row::new()
             .padding(20)
+            .spacing(10)
             .align_items(Align::Center)
             .push(
-                Button::new(&mut self.decrement_button, Text::new("<<"))
-                    .on_press(Message::PrevFrame),
+                Button::new(
+                    &mut self.play_button,
+                    Text::new(if self.playing {
+                        locale.text("pause")
+                    } else {
+                        locale.text("play")
+                    }),
+                )
+                .style(theme)
+                .on_press(Message::TogglePlaying),
+            );
+        let controls = if growing {
+            controls.push(
+                Checkbox::new(
+                    self.follow,
+                    locale.text("follow-newest"),
+                    Message::FollowToggled,
+                )
+                .style(theme),
             )
-            .push(
-                Text::new(format!(
-                    "Frame {} of {}",
-                    self.value + 1,
-                    self.video.frame_count()
+        } else {
+            controls
+        };
+        let controls = controls.push(
+            Checkbox::new(
+                stacked_frames.is_some(),
+                locale.text("live-stack"),
+                Message::LiveStackToggled,
+            )
+            .style(theme),
+        );
+        let controls = match stacked_frames {
+            Some(frames) => controls
+                .push(Text::new(
+                    locale.format("stacked-count", &[("count", &frames)]),
                 ))
-                .size(22),
+                .push(
+                    Button::new(
+                        &mut self.reset_stack_button,
+                        Text::new(locale.text("reset")),
+                    )
+                    .style(theme)
+                    .on_press(Message::ResetLiveStack),
+                ),
+            None => controls,
+        };
+        let controls = controls.push(
+            PickList::new(
+                &mut self.fps_list,
+                FPS_CHOICES,
+                if self.real_time {
+                    Some(REAL_TIME)
+                } else {
+                    choice(FPS_CHOICES, self.fps.map(|fps| fps.to_string()))
+                },
+                Message::FpsSelected,
+            )
+            .style(theme),
+        );
+        let controls = if self.real_time {
+            controls.push(
+                PickList::new(
+                    &mut self.speed_list,
+                    SPEED_NAMES,
+                    speed,
+                    Message::SpeedSelected,
+                )
+                .style(theme),
+            )
+        } else {
+            controls
+        };
+        let controls = controls
+            .push(Text::new(
+                locale.format("fps", &[("fps", &format!("{:.1}", fps))]),
+            ))
+            .push(
+                PickList::new(
+                    &mut self.playback_mode_list,
+                    PLAYBACK_MODE_NAMES,
+                    Some(self.playback.mode.name()),
+                    Message::PlaybackModeSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                Button::new(
+                    &mut self.marker_a_button,
+                    Text::new(locale.text("marker-a")),
+                )
+                .style(theme)
+                .on_press(Message::SetMarkerA),
+            )
+            .push(
+                Button::new(
+                    &mut self.marker_b_button,
+                    Text::new(locale.text("marker-b")),
+                )
+                .style(theme)
+                .on_press(Message::SetMarkerB),
+            );
+        let marker = |marker: Option<usize>| {
+            marker
+                .map(|index| (index + 1).to_string())
+                .unwrap_or_else(|| "-".to_string())
+        };
+        let controls = if self.playback.marker_a.is_some() || self.playback.marker_b.is_some() {
+            controls
+                .push(Text::new(locale.format(
+                    "marker-range",
+                    &[
+                        ("from", &marker(self.playback.marker_a)),
+                        ("to", &marker(self.playback.marker_b)),
+                    ],
+                )))
+                .push(
+                    Button::new(
+                        &mut self.clear_markers_button,
+                        Text::new(locale.text("clear-markers")),
+                    )
+                    .style(theme)
+                    .on_press(Message::ClearMarkers),
+                )
+        } else {
+            controls
+        };
+        let mut frame_position = locale.format(
+            "frame-position",
+            &[("frame", &(self.value + 1)), ("count", &frame_count)],
+        );
+        if excluded {
+            frame_position = format!("{} ({})", frame_position, locale.text("excluded"));
+        }
+        if let Some(mark) = mark {
+            let name = locale.text(&format!("mark-{}", mark.name()));
+            frame_position = format!("{} ({})", frame_position, name);
+        }
+        // the largest steps are furthest from the frame number
+        let (backward, forward): (Vec<_>, Vec<_>) = self
+            .step_buttons
+            .iter_mut()
+            .map(|(backward, forward)| (backward, forward))
+            .unzip();
+        let steps = &self.frame_steps;
+        let controls = backward
+            .into_iter()
+            .zip(steps)
+            .rev()
+            .fold(controls, |controls, (state, &step)| {
+                controls.push(
+                    Button::new(state, Text::new(format!("-{}", step)))
+                        .style(theme)
+                        .on_press(Message::StepFrames(-(step as i64))),
+                )
+            })
+            .push(
+                Button::new(&mut self.decrement_button, Text::new("<<"))
+                    .style(theme)
+                    .on_press(Message::PrevFrame),
             )
+            .push(Text::new(frame_position).size(22))
             .push(
                 Button::new(&mut self.increment_button, Text::new(">>"))
+                    .style(theme)
                     .on_press(Message::NextFrame),
             );
-
-        Column::new()
-            .padding(20)
-            .align_items(Align::Center)
+        let controls = forward
+            .into_iter()
+            .zip(steps)
+            .fold(controls, |controls, (state, &step)| {
+                controls.push(
+                    Button::new(state, Text::new(format!("+{}", step)))
+                        .style(theme)
+                        .on_press(Message::StepFrames(step as i64)),
+                )
+            })
+            .push(Text::new(timestamp.map(format_utc).unwrap_or_default()))
+            .push(Text::new(sharpness_rank.unwrap_or_default()))
+            .push(
+                Button::new(
+                    &mut self.exclude_button,
+                    Text::new(if excluded {
+                        locale.text("include")
+                    } else {
+                        locale.text("exclude")
+                    }),
+                )
+                .style(theme)
+                .on_press(Message::ToggleExcluded),
+            );
+        let mut undo =
+            Button::new(&mut self.undo_button, Text::new(locale.text("undo"))).style(theme);
+        if edited || self.history.can_undo() {
+            undo = undo.on_press(Message::Undo);
+        }
+        let mut redo =
+            Button::new(&mut self.redo_button, Text::new(locale.text("redo"))).style(theme);
+        if !edited && self.history.can_redo() {
+            redo = redo.on_press(Message::Redo);
+        }
+        let controls = controls
+            .push(undo)
+            .push(redo)
             .push(
-                Container::new(image)
-                    .width(Length::Fill)
-                    .height(Length::Fill)
-                    .center_x()
-                    .center_y(),
+                Button::new(&mut self.save_button, Text::new(locale.text("save-frame")))
+                    .style(theme)
+                    .on_press(Message::ExportFrame),
             )
-            .push(controls)
-            .into()
+            .push(
+                PickList::new(
+                    &mut self.export_list,
+                    EXPORT_FORMAT_NAMES,
+                    Some(self.export_format.extension()),
+                    Message::ExportFormatSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                Button::new(&mut self.clip_button, Text::new(locale.text("save-clip")))
+                    .style(theme)
+                    .on_press(Message::SaveClip),
+            )
+            .push(if self.animating || self.source.is_none() {
+                Button::new(
+                    &mut self.animation_button,
+                    Text::new(locale.text("save-animation")),
+                )
+                .style(theme)
+            } else {
+                Button::new(
+                    &mut self.animation_button,
+                    Text::new(locale.text("save-animation")),
+                )
+                .style(theme)
+                .on_press(Message::SaveAnimation)
+            })
+            .push(
+                PickList::new(
+                    &mut self.animation_list,
+                    ANIMATION_FORMAT_NAMES,
+                    Some(self.animation_format.extension()),
+                    Message::AnimationFormatSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                Button::new(
+                    &mut self.histogram_button,
+                    Text::new(locale.text("histogram")),
+                )
+                .style(theme)
+                .on_press(Message::ToggleHistogram),
+            )
+            .push(
+                Button::new(
+                    &mut self.filmstrip_button,
+                    Text::new(locale.text("filmstrip")),
+                )
+                .style(theme)
+                .on_press(Message::ToggleFilmstrip),
+            )
+            .push(
+                Button::new(&mut self.info_button, Text::new(locale.text("info")))
+                    .style(theme)
+                    .on_press(Message::ToggleInfo),
+            )
+            .push(
+                Button::new(
+                    &mut self.detach_button,
+                    Text::new(if self.panels_detached {
+                        locale.text("attach-panels")
+                    } else {
+                        locale.text("detach-panels")
+                    }),
+                )
+                .style(theme)
+                .on_press(Message::DetachPanels),
+            )
+            .push(
+                PickList::new(
+                    &mut self.theme_list,
+                    THEME_NAMES,
+                    Some(self.theme.name()),
+                    Message::ThemeSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                PickList::new(
+                    &mut self.language_list,
+                    &self.languages[..],
+                    Some(locale.language().to_string()),
+                    Message::LanguageSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                Button::new(
+                    &mut self.profile_button,
+                    Text::new(if self.drawing_profile {
+                        locale.text("cancel-line")
+                    } else {
+                        locale.text("line-profile")
+                    }),
+                )
+                .style(theme)
+                .on_press(Message::DrawProfileLine),
+            )
+            .push(
+                Button::new(&mut self.fit_button, Text::new(locale.text("fit")))
+                    .style(theme)
+                    .on_press(Message::ZoomToFit),
+            )
+            .push(
+                Button::new(
+                    &mut self.actual_size_button,
+                    Text::new(locale.text("actual-size")),
+                )
+                .style(theme)
+                .on_press(Message::ZoomToActualSize),
+            )
+            .push(
+                Checkbox::new(
+                    self.center_target,
+                    locale.text("center"),
+                    Message::CenterTargetToggled,
+                )
+                .style(theme),
+            );
+
+        let bayer = self
+            .overrides
+            .bayer
+            .as_ref()
+            .map_or_else(|| HEADER.to_string(), |bayer| format!("{:?}", bayer));
+        let bit_depth = self
+            .overrides
+            .pixel_depth_bits
+            .map_or_else(|| HEADER.to_string(), |bits| bits.to_string());
+        let endianness = match self.overrides.endianness {
+            None => HEADER,
+            Some(EndiannessOverride::Auto) => "auto",
+            Some(EndiannessOverride::Fixed(Endianness::LittleEndian)) => "little",
+            Some(EndiannessOverride::Fixed(Endianness::BigEndian)) => "big",
+        };
+        let binning = self.binning;
+        let binning = BINNING_NAMES
+            .iter()
+            .copied()
+            .find(|name| parse_binning(name).ok() == Some(binning));
+        let format_controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(Text::new(locale.text("bayer")))
+            .push(
+                PickList::new(
+                    &mut self.bayer_list,
+                    BAYER_CHOICES,
+                    choice(BAYER_CHOICES, Some(bayer)),
+                    Message::BayerSelected,
+                )
+                .style(theme),
+            )
+            .push(Text::new(locale.text("bit-depth")))
+            .push(
+                PickList::new(
+                    &mut self.bit_depth_list,
+                    BIT_DEPTH_CHOICES,
+                    choice(BIT_DEPTH_CHOICES, Some(bit_depth)),
+                    Message::BitDepthSelected,
+                )
+                .style(theme),
+            )
+            .push(Text::new(locale.text("endianness")))
+            .push(
+                PickList::new(
+                    &mut self.endianness_list,
+                    ENDIANNESS_CHOICES,
+                    Some(endianness),
+                    Message::EndiannessSelected,
+                )
+                .style(theme),
+            )
+            .push(Text::new(locale.text("codec")))
+            .push(
+                PickList::new(
+                    &mut self.codec_list,
+                    CODEC_NAMES,
+                    choice(CODEC_NAMES, Some(self.codec_name.clone())),
+                    Message::CodecSelected,
+                )
+                .style(theme),
+            )
+            .push(Text::new(locale.text("preview")))
+            .push(
+                PickList::new(
+                    &mut self.binning_list,
+                    BINNING_NAMES,
+                    binning,
+                    Message::BinningSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                Checkbox::new(
+                    self.compare.is_some(),
+                    locale.text("compare-with"),
+                    Message::CompareToggled,
+                )
+                .style(theme),
+            )
+            .push(
+                PickList::new(
+                    &mut self.compare_codec_list,
+                    CODEC_NAMES,
+                    Some(self.compare_codec),
+                    Message::CompareCodecSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                Button::new(
+                    &mut self.region_button,
+                    Text::new(if self.selecting_region {
+                        locale.text("cancel-selection")
+                    } else {
+                        locale.text("select-region")
+                    }),
+                )
+                .style(theme)
+                .on_press(Message::SelectRegion),
+            )
+            .push(if self.region.is_some() {
+                Button::new(
+                    &mut self.full_frame_button,
+                    Text::new(locale.text("full-frame")),
+                )
+                .style(theme)
+                .on_press(Message::ShowFullFrame)
+            } else {
+                Button::new(
+                    &mut self.full_frame_button,
+                    Text::new(locale.text("full-frame")),
+                )
+                .style(theme)
+            })
+            .push(
+                Checkbox::new(
+                    self.crop_exports,
+                    locale.text("crop-exports"),
+                    Message::CropExportsToggled,
+                )
+                .style(theme),
+            );
+
+        let mut score = Button::new(
+            &mut self.score_button,
+            Text::new(if self.scoring {
+                locale.text("scoring")
+            } else {
+                locale.text("score-frames")
+            }),
+        )
+        .style(theme);
+        if !self.scoring && self.source.is_some() {
+            score = score.on_press(Message::ScoreFrames);
+        }
+        let mut export_ranking = Button::new(
+            &mut self.export_ranking_button,
+            Text::new(locale.text("export-ranking")),
+        )
+        .style(theme);
+        if self.scores.is_some() {
+            export_ranking = export_ranking.on_press(Message::ExportRanking);
+        }
+        let mut seeing = Button::new(
+            &mut self.seeing_button,
+            Text::new(if self.measuring_seeing {
+                locale.text("measuring")
+            } else {
+                locale.text("measure-seeing")
+            }),
+        )
+        .style(theme);
+        if !self.measuring_seeing && self.source.is_some() {
+            seeing = seeing.on_press(Message::MeasureSeeing);
+        }
+        let mut export_marks = Button::new(
+            &mut self.export_marks_button,
+            Text::new(locale.text("export-marks")),
+        )
+        .style(theme);
+        if !self.adjustments.marks.is_empty() {
+            export_marks = export_marks.on_press(Message::ExportMarks);
+        }
+        let mut stack = Button::new(
+            &mut self.stack_button,
+            Text::new(if self.stacking {
+                locale.text("stacking")
+            } else {
+                locale.text("stack")
+            }),
+        )
+        .style(theme);
+        if !self.stacking && self.source.is_some() {
+            stack = stack.on_press(Message::StackFrames);
+        }
+        let quality_controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(score)
+            .push(Text::new(locale.text("order")))
+            .push(
+                PickList::new(
+                    &mut self.order_list,
+                    ORDER_CHOICES,
+                    Some(self.order),
+                    Message::OrderSelected,
+                )
+                .style(theme),
+            )
+            .push(export_ranking)
+            .push(seeing)
+            .push(export_marks)
+            .push(
+                PickList::new(
+                    &mut self.combine_list,
+                    COMBINE_NAMES,
+                    Some(self.combine),
+                    Message::CombineSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                Checkbox::new(self.align, locale.text("align"), Message::AlignToggled).style(theme),
+            )
+            .push(stack);
+
+        let format_controls = if self.compare.is_some() {
+            format_controls.push(
+                Slider::new(
+                    &mut self.divider_slider,
+                    0..=100,
+                    self.divider,
+                    Message::DividerChanged,
+                )
+                .style(theme)
+                .width(Length::Units(STRETCH_SLIDER_WIDTH)),
+            )
+        } else {
+            format_controls
+        };
+
+        let mut blink_frame = Button::new(
+            &mut self.blink_frame_button,
+            Text::new(locale.text("blink-frame")),
+        )
+        .style(theme);
+        if self.source.is_some() {
+            blink_frame = blink_frame.on_press(Message::BlinkFrame);
+        }
+        let mut blink_controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(blink_frame)
+            .push(
+                Button::new(
+                    &mut self.blink_file_button,
+                    Text::new(locale.text("blink-with-file")),
+                )
+                .style(theme)
+                .on_press(Message::BlinkWith(open_path.clone())),
+            )
+            .push(Text::new(locale.text("blink-rate")))
+            .push(
+                PickList::new(
+                    &mut self.blink_rate_list,
+                    BLINK_RATE_NAMES,
+                    choice(BLINK_RATE_NAMES, Some(self.blink_rate.to_string())),
+                    Message::BlinkRateSelected,
+                )
+                .style(theme),
+            )
+            .push(Text::new(locale.text("per-second")));
+        if let Some(state) = &self.blink {
+            let reference = match (state.key, &state.path) {
+                (Some((_, index)), Some(path)) => locale.format(
+                    "blink-reference-file",
+                    &[
+                        ("frame", &(index + 1)),
+                        (
+                            "file",
+                            &path.file_name().unwrap_or_default().to_string_lossy(),
+                        ),
+                    ],
+                ),
+                (Some((_, index)), None) => {
+                    locale.format("blink-reference", &[("frame", &(index + 1))])
+                }
+                (None, _) => locale.text("no-reference"),
+            };
+            let offset = state.blink.offset;
+            blink_controls = blink_controls
+                .push(Text::new(if state.blink.showing_reference {
+                    reference
+                } else {
+                    locale.format("blink-current", &[("frame", &(self.value + 1))])
+                }))
+                .push(Text::new(locale.format(
+                    "blink-offset",
+                    &[("dx", &offset.dx), ("dy", &offset.dy)],
+                )))
+                .push(
+                    Button::new(&mut self.blink_left_button, Text::new("X-"))
+                        .style(theme)
+                        .on_press(Message::NudgeBlink(-1, 0)),
+                )
+                .push(
+                    Button::new(&mut self.blink_right_button, Text::new("X+"))
+                        .style(theme)
+                        .on_press(Message::NudgeBlink(1, 0)),
+                )
+                .push(
+                    Button::new(&mut self.blink_up_button, Text::new("Y-"))
+                        .style(theme)
+                        .on_press(Message::NudgeBlink(0, -1)),
+                )
+                .push(
+                    Button::new(&mut self.blink_down_button, Text::new("Y+"))
+                        .style(theme)
+                        .on_press(Message::NudgeBlink(0, 1)),
+                )
+                .push(
+                    Button::new(
+                        &mut self.blink_reset_button,
+                        Text::new(locale.text("reset-offset")),
+                    )
+                    .style(theme)
+                    .on_press(Message::ResetBlinkOffset),
+                )
+                .push(
+                    Button::new(
+                        &mut self.stop_blink_button,
+                        Text::new(locale.text("stop-blink")),
+                    )
+                    .style(theme)
+                    .on_press(Message::StopBlink),
+                );
+        }
+
+        let stretch_controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(Text::new(locale.text("black")))
+            .push(
+                Slider::new(
+                    &mut self.black_slider,
+                    0..=254,
+                    self.stretch.black,
+                    Message::BlackPointChanged,
+                )
+                .style(theme)
+                .width(Length::Units(STRETCH_SLIDER_WIDTH)),
+            )
+            .push(Text::new(locale.text("white")))
+            .push(
+                Slider::new(
+                    &mut self.white_slider,
+                    1..=255,
+                    self.stretch.white,
+                    Message::WhitePointChanged,
+                )
+                .style(theme)
+                .width(Length::Units(STRETCH_SLIDER_WIDTH)),
+            )
+            .push(Text::new(locale.format(
+                "gamma",
+                &[("gamma", &format!("{:.2}", self.stretch.gamma))],
+            )))
+            .push(
+                Slider::new(
+                    &mut self.gamma_slider,
+                    0.2..=5.0,
+                    self.stretch.gamma,
+                    Message::GammaChanged,
+                )
+                .style(theme)
+                .step(0.05)
+                .width(Length::Units(STRETCH_SLIDER_WIDTH)),
+            )
+            .push(
+                Button::new(
+                    &mut self.auto_stretch_button,
+                    Text::new(locale.text("auto-stretch")),
+                )
+                .style(theme)
+                .on_press(Message::AutoStretch),
+            )
+            .push(
+                Button::new(
+                    &mut self.reset_stretch_button,
+                    Text::new(locale.text("reset")),
+                )
+                .style(theme)
+                .on_press(Message::ResetStretch),
+            )
+            .push(Text::new(locale.text("colors")))
+            .push(
+                PickList::new(
+                    &mut self.color_map_list,
+                    COLOR_MAP_NAMES,
+                    Some(self.color_map.name()),
+                    Message::ColorMapSelected,
+                )
+                .style(theme),
+            );
+
+        let gain_slider = |state, gain, on_change: fn(f32) -> Message| {
+            Slider::new(state, MIN_GAIN..=MAX_GAIN, gain, on_change)
+                .style(theme)
+                .step(0.01)
+                .width(Length::Units(STRETCH_SLIDER_WIDTH))
+        };
+        let white_balance_controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(Text::new(locale.format(
+                "red-gain",
+                &[("gain", &format!("{:.2}", self.white_balance.red))],
+            )))
+            .push(gain_slider(
+                &mut self.red_gain_slider,
+                self.white_balance.red,
+                Message::RedGainChanged,
+            ))
+            .push(Text::new(locale.format(
+                "green-gain",
+                &[("gain", &format!("{:.2}", self.white_balance.green))],
+            )))
+            .push(gain_slider(
+                &mut self.green_gain_slider,
+                self.white_balance.green,
+                Message::GreenGainChanged,
+            ))
+            .push(Text::new(locale.format(
+                "blue-gain",
+                &[("gain", &format!("{:.2}", self.white_balance.blue))],
+            )))
+            .push(gain_slider(
+                &mut self.blue_gain_slider,
+                self.white_balance.blue,
+                Message::BlueGainChanged,
+            ))
+            .push(
+                PickList::new(
+                    &mut self.white_balance_list,
+                    WHITE_BALANCE_CHOICES,
+                    Some(self.white_balance_method),
+                    Message::WhiteBalanceMethodSelected,
+                )
+                .style(theme),
+            )
+            .push(
+                Button::new(
+                    &mut self.auto_white_balance_button,
+                    Text::new(locale.text("auto-white-balance")),
+                )
+                .style(theme)
+                .on_press(Message::AutoWhiteBalance),
+            )
+            .push(
+                Button::new(
+                    &mut self.reset_white_balance_button,
+                    Text::new(locale.text("reset")),
+                )
+                .style(theme)
+                .on_press(Message::ResetWhiteBalance),
+            );
+
+        let overlay_controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(
+                Checkbox::new(
+                    self.show_scale,
+                    locale.text("scale-bar"),
+                    Message::ScaleToggled,
+                )
+                .style(theme),
+            )
+            .push(Text::new(locale.text("pixel-scale")))
+            .push(
+                TextInput::new(
+                    &mut self.pixel_scale_state,
+                    "0.38",
+                    &self.pixel_scale,
+                    Message::PixelScaleChanged,
+                )
+                .style(theme)
+                .padding(5)
+                .width(Length::Units(80)),
+            )
+            .push(Text::new(locale.format(
+                "rotation",
+                &[("degrees", &format!("{:.0}", self.orientation.rotation))],
+            )))
+            .push(
+                Slider::new(
+                    &mut self.rotation_slider,
+                    -180.0..=180.0,
+                    self.orientation.rotation,
+                    Message::RotationChanged,
+                )
+                .style(theme)
+                .width(Length::Units(STRETCH_SLIDER_WIDTH)),
+            )
+            .push(
+                Checkbox::new(
+                    self.orientation.mirrored,
+                    locale.text("mirrored"),
+                    Message::MirroredToggled,
+                )
+                .style(theme),
+            )
+            .push(
+                Button::new(
+                    &mut self.annotate_button,
+                    Text::new(if self.annotating {
+                        locale.text("done")
+                    } else {
+                        locale.text("annotate")
+                    }),
+                )
+                .style(theme)
+                .on_press(Message::Annotate),
+            )
+            .push(
+                TextInput::new(
+                    &mut self.annotation_text_state,
+                    &locale.text("label-placeholder"),
+                    &self.annotation_text,
+                    Message::AnnotationTextChanged,
+                )
+                .style(theme)
+                .padding(5)
+                .width(Length::Units(150)),
+            )
+            .push(
+                Button::new(
+                    &mut self.clear_annotations_button,
+                    Text::new(locale.text("clear-frame")),
+                )
+                .style(theme)
+                .on_press(Message::ClearFrameAnnotations),
+            )
+            .push(
+                Checkbox::new(
+                    self.burn_annotations,
+                    locale.text("burn-annotations"),
+                    Message::BurnAnnotationsToggled,
+                )
+                .style(theme),
+            );
+
+        let file_controls = Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(
+                Button::new(
+                    &mut self.choose_file_button,
+                    Text::new(locale.text("choose-file")),
+                )
+                .style(theme)
+                .on_press(Message::ChooseFile),
+            )
+            .push(Text::new(locale.text("file")))
+            .push(
+                TextInput::new(
+                    &mut self.path_input_state,
+                    &locale.text("file-path-placeholder"),
+                    &self.path_input,
+                    Message::PathInputChanged,
+                )
+                .style(theme)
+                .padding(5)
+                .on_submit(Message::OpenFile(open_path.clone())),
+            )
+            .push(
+                Button::new(&mut self.open_button, Text::new(locale.text("open")))
+                    .style(theme)
+                    .on_press(Message::OpenFile(open_path)),
+            );
+
+        let mut content = Column::new()
+            .padding(20)
+            .align_items(Align::Center)
+            .push(file_controls);
+        if let Some(error) = &self.error {
+            content = content.push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Align::Center)
+                    .push(
+                        Text::new(locale.format("error", &[("error", error)]))
+                            .size(22)
+                            .color(ERROR_COLOR),
+                    )
+                    .push(
+                        Button::new(&mut self.dismiss_button, Text::new(locale.text("dismiss")))
+                            .style(theme)
+                            .on_press(Message::DismissError),
+                    ),
+            );
+        }
+        let mut content = content.push(image_row);
+        if self.source.is_some() {
+            // the line is kept when the cursor leaves the image so that the image does not
+            // change size
+            content = content.push(
+                Text::new(self.readout.clone().unwrap_or_default())
+                    .size(16)
+                    .height(Length::Units(READOUT_HEIGHT)),
+            );
+        }
+        if let Some(filmstrip) = filmstrip {
+            content = content.push(filmstrip);
+        }
+        let content = content
+            .push(mark_strip)
+            .push(seek)
+            .push(controls)
+            .push(format_controls)
+            .push(stretch_controls)
+            .push(white_balance_controls)
+            .push(overlay_controls)
+            .push(quality_controls)
+            .push(blink_controls)
+            .push(Text::new(self.status.clone().unwrap_or_default()));
+
+        let content: Element<Message> = match analysis {
+            Some(analysis) => {
+                // each pane takes its content once
+                let main = Cell::new(Some(Element::from(content)));
+                let analysis = Cell::new(Some(Element::from(analysis)));
+                PaneGrid::new(&mut self.panes, |_, panel| {
+                    let content = match panel {
+                        Panel::Main => &main,
+                        Panel::Analysis => &analysis,
+                    };
+                    let content = pane_grid::Content::new(
+                        content.take().unwrap_or_else(|| Column::new().into()),
+                    );
+                    match panel {
+                        Panel::Main => content,
+                        Panel::Analysis => content.title_bar(
+                            pane_grid::TitleBar::new(
+                                Text::new(locale.text("analysis-title")).size(16),
+                            )
+                            .padding(5)
+                            .style(TitleBarStyle(theme)),
+                        ),
+                    }
+                })
+                .spacing(PANE_SPACING)
+                .style(theme)
+                .on_drag(Message::PaneDragged)
+                .on_resize(PANE_SPACING * 2, Message::PaneResized)
+                .into()
+            }
+            None => content.into(),
+        };
+
+        // the playlist sidebar is only shown for more than one file
+        let root: Element<Message> = if self.playlist.len() < 2 {
+            content
+        } else {
+            self.playlist_buttons
+                .resize_with(self.playlist.len(), button::State::default);
+            let current = self.playlist.current();
+            let mut files = Scrollable::new(&mut self.playlist_scroll)
+                .style(theme)
+                .spacing(5)
+                .height(Length::Fill);
+            for (index, (path, state)) in self
+                .playlist
+                .files()
+                .iter()
+                .zip(&mut self.playlist_buttons)
+                .enumerate()
+            {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.display().to_string());
+                let marker = if current == Some(index) { "> " } else { "" };
+                files = files.push(
+                    Button::new(state, Text::new(format!("{}{}", marker, name)))
+                        .style(theme)
+                        .width(Length::Fill)
+                        .on_press(Message::OpenFile(path.clone())),
+                );
+            }
+            let mut previous = Button::new(
+                &mut self.previous_file_button,
+                Text::new(locale.text("previous-file")),
+            )
+            .style(theme);
+            if self.playlist.previous().is_some() {
+                previous = previous.on_press(Message::PreviousFile);
+            }
+            let mut next = Button::new(
+                &mut self.next_file_button,
+                Text::new(locale.text("next-file")),
+            )
+            .style(theme);
+            if self.playlist.next().is_some() {
+                next = next.on_press(Message::NextFile);
+            }
+            let sidebar = Column::new()
+                .padding(20)
+                .spacing(10)
+                .width(Length::Units(PLAYLIST_WIDTH))
+                .push(Text::new(
+                    locale.format("files", &[("count", &self.playlist.len())]),
+                ))
+                .push(files)
+                .push(Row::new().spacing(10).push(previous).push(next));
+            Row::new().push(sidebar).push(content).into()
+        };
+        Container::new(root)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .style(theme)
+            .into()
+    }
+}
+
+impl VideoPlayer {
+    fn frame_count(&self) -> usize {
+        self.source
+            .as_ref()
+            .map(|source| source.video.frame_count())
+            .unwrap_or(0)
+    }
+
+    /// Whether frames are still being added to the open video, whose newest frame is then
+    /// followed from the start
+    fn is_growing(&self) -> bool {
+        self.source
+            .as_ref()
+            .map(|source| source.video.frame_watch().is_some())
+            .unwrap_or(false)
+    }
+
+    /// Wait in the background for frames to be added to the open video, if it is growing
+    fn watch_frames(&self) -> Command<Message> {
+        let watch = match self
+            .source
+            .as_ref()
+            .and_then(|source| source.video.frame_watch())
+        {
+            Some(watch) => watch,
+            None => return Command::none(),
+        };
+        let watch_id = self.watch_id;
+        let frame_count = self.frame_count();
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    std::thread::sleep(FOLLOW_INTERVAL);
+                    watch.wait(frame_count, WATCH_TIMEOUT)
+                })
+                .await
+                .unwrap_or(frame_count)
+            },
+            move |frame_count| Message::FramesAdded(watch_id, frame_count),
+        )
+    }
+
+    /// Show the newest frame of the open video
+    fn show_newest(&mut self) -> Command<Message> {
+        let newest = self.frame_count().saturating_sub(1) as u32;
+        if newest == self.value {
+            return Command::none();
+        }
+        self.value = newest;
+        self.show_frame()
+    }
+
+    /// Frame rate to play the video at, which is the rate chosen by the user or else the rate
+    /// recorded in the file
+    fn playback_fps(&self) -> f64 {
+        let recorded = self
+            .source
+            .as_ref()
+            .and_then(|source| source.video.frame_rate());
+        self.fps
+            .or(recorded)
+            .unwrap_or(DEFAULT_FPS)
+            .clamp(MIN_FPS, MAX_FPS)
+    }
+
+    /// Save the codec, display stretch, playback frame rate, theme and language if they were
+    /// changed in the player, along with the directory of the current file and plate scales,
+    /// to the configuration file. Values given on the command line, such as `--codec`, are
+    /// not saved unless they were changed in the player, and a configuration file that cannot
+    /// be read is left alone.
+    fn save_settings(&self) {
+        let path = match &self.config_path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut config = match Config::load_from(path) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Not saving settings to {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let startup = &self.startup_config;
+        if self.codec_name != startup.codec {
+            config.codec = self.codec_name.clone();
+        }
+        if self.stretch != startup.stretch {
+            config.stretch = self.stretch;
+        }
+        if self.fps != startup.fps {
+            config.fps = self.fps;
+        }
+        if self.theme.name() != startup.theme {
+            config.theme = self.theme.name().to_string();
+        }
+        if self.locale.language() != startup.language {
+            config.language = self.locale.language().to_string();
+        }
+        config.plate_scale.extend(self.plate_scales.clone());
+        if let Some(dir) = self
+            .path
+            .as_ref()
+            .and_then(|path| path.canonicalize().ok())
+            .and_then(|path| path.parent().map(PathBuf::from))
+        {
+            config.last_directory = Some(dir);
+        }
+        if let Err(e) = config.save_to(path) {
+            warn!("Could not save settings to {}: {}", path.display(), e);
+        }
+    }
+
+    /// How long to show the current frame for during playback: the interval between its
+    /// timestamp and the next one in real-time playback, or else one frame at the playback
+    /// frame rate
+    fn playback_interval(&self) -> Duration {
+        let fixed = Duration::from_secs_f64(1.0 / self.playback_fps());
+        let video = match &self.source {
+            Some(source) if self.real_time => &source.video,
+            _ => return fixed,
+        };
+        // the last frame is shown for as long as the one before it
+        let next = (self.index() + 1).min(video.frame_count().saturating_sub(1));
+        let this = next.saturating_sub(1);
+        match (video.frame_timestamp(this), video.frame_timestamp(next)) {
+            (Some(this_time), Some(next_time)) if this != next => {
+                real_time_interval(this_time, next_time, self.speed)
+            }
+            _ => fixed,
+        }
+    }
+
+    fn edits(&self) -> Edits {
+        Edits {
+            adjustments: self.adjustments.clone(),
+            stretch: self.stretch,
+            white_balance: self.white_balance,
+            region: self.region,
+        }
+    }
+
+    /// Record the settings from before each message that edits them, so that the edit can be
+    /// undone. Consecutive changes to the same slider are one edit, and an edit that did not
+    /// change anything is not recorded.
+    fn record_edit(&mut self, message: &Message) {
+        let kind = mem::discriminant(message);
+        let continues = is_gradual_edit(message)
+            && matches!(&self.pending_edit, Some((pending, _)) if *pending == kind);
+        let finishes = is_edit(message) || matches!(message, Message::Undo | Message::Redo);
+        if continues || !finishes {
+            return;
+        }
+        if let Some((_, before)) = self.pending_edit.take() {
+            if before != self.edits() {
+                self.history.record(before);
+            }
+        }
+        if is_edit(message) {
+            self.pending_edit = Some((kind, self.edits()));
+        }
+    }
+
+    /// Whether the most recent edit changed the settings and has not been recorded yet
+    fn is_edited(&self) -> bool {
+        matches!(&self.pending_edit, Some((_, before)) if *before != self.edits())
+    }
+
+    /// Restore settings from the undo history, updating everything that depends on them
+    fn set_edits(&mut self, edits: Edits) -> Command<Message> {
+        let previous = self.edits();
+        self.adjustments = edits.adjustments;
+        self.stretch = edits.stretch;
+        self.white_balance = edits.white_balance;
+        self.region = edits.region;
+        let mut commands = vec![];
+        if self.adjustments.annotations != previous.adjustments.annotations {
+            commands.push(self.save_annotations());
+        }
+        if self.region != previous.region {
+            self.selecting_region = false;
+            self.clear_profile();
+            self.viewport.fit();
+            commands.push(self.redecode());
+        } else if self.stretch != previous.stretch || self.white_balance != previous.white_balance {
+            self.apply_display();
+        }
+        Command::batch(commands)
+    }
+
+    /// Show the native file dialog in the directory of the open file. The dialog blocks until
+    /// it is closed, so it runs on a blocking thread while the player keeps drawing.
+    fn choose_file(&self) -> Command<Message> {
+        let directory = self
+            .path
+            .as_ref()
+            .and_then(|path| path.parent())
+            .filter(|directory| !directory.as_os_str().is_empty())
+            .map(std::path::Path::to_path_buf);
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || choose_video(directory.as_deref()))
+                    .await
+                    .map_err(|e| e.to_string())
+            },
+            Message::FileChosen,
+        )
+    }
+
+    /// Replace the current video with a file chosen by the user, showing the frame that was
+    /// last viewed in it. The adjustments, scores and cached frames of the previous video are
+    /// discarded, but the format overrides are kept.
+    fn open_file(&mut self, path: PathBuf) -> Command<Message> {
+        self.remember_file();
+        let options = OpenOptions {
+            mode: self.mode,
+            read: self.read,
+            follow: self.follow_files,
+            ..OpenOptions::default()
+        };
+        let video = match open_video_with(&path.to_string_lossy(), &options) {
+            Ok(video) => video,
+            Err(e) => {
+                error!("Could not open {}: {}", path.display(), e);
+                self.error = Some(
+                    self.locale
+                        .format("open-failed", &[("path", &path.display()), ("error", &e)]),
+                );
+                return Command::none();
+            }
+        };
+        self.source = Some(OpenVideo::new(
+            Arc::from(video),
+            &self.overrides,
+            &self.codecs,
+            &self.codec_name,
+        ));
+        self.path_input = path.display().to_string();
+        let state = self.playlist.select(&path);
+        self.restore(state);
+        // keep the scale that was entered unless one is known for the new instrument
+        if let Some(scale) = self.configured_pixel_scale() {
+            self.pixel_scale = scale;
+        }
+        self.path = Some(path);
+        self.playing = false;
+        self.follow = self.is_growing();
+        self.watch_id += 1;
+        // a pinned reference frame belongs to the previous video
+        if matches!(&self.blink, Some(BlinkState { source: None, .. })) {
+            self.blink = None;
+        }
+        self.decoded = None;
+        self.image = None;
+        self.histogram = None;
+        self.status = None;
+        self.adjustments = Adjustments::default();
+        self.history = History::new(UNDO_LIMIT);
+        self.pending_edit = None;
+        self.load_annotations();
+        self.annotating = false;
+        self.viewport = Viewport::default();
+        self.selecting_region = false;
+        self.clear_profile();
+        Command::batch(vec![self.reopen(), self.watch_frames()])
+    }
+
+    /// Remember the state of the open file, and save it next to the file so that it is
+    /// restored when the file is opened in a later session
+    fn remember_file(&mut self) {
+        let path = match (&self.source, &self.path) {
+            (Some(_), Some(path)) => path.clone(),
+            _ => return,
+        };
+        let state = FileState {
+            frame: self.value,
+            white_balance: self.white_balance,
+            stretch: Some(self.stretch),
+            region: self.region,
+            marker_a: self.playback.marker_a,
+            marker_b: self.playback.marker_b,
+        };
+        self.playlist.remember(state);
+        if let Err(e) = save_state(&path, &state) {
+            warn!("Could not save the state of {}: {}", path.display(), e);
+        }
+    }
+
+    /// Restore the state of the file that was just opened. Growing files start at their
+    /// newest frame, and a region or markers that no longer fit the video are dropped.
+    fn restore(&mut self, state: FileState) {
+        let frame_count = self.frame_count();
+        let last_frame = frame_count.saturating_sub(1) as u32;
+        self.value = if self.is_growing() {
+            last_frame
+        } else {
+            state.frame.min(last_frame)
+        };
+        self.white_balance = state.white_balance;
+        if let Some(stretch) = state.stretch {
+            self.stretch = stretch;
+        }
+        self.region = match (&self.source, state.region) {
+            (Some(source), Some(region)) => region.fit_to(source.video.as_ref()).ok(),
+            _ => None,
+        };
+        self.playback.marker_a = state.marker_a.filter(|&marker| marker < frame_count);
+        self.playback.marker_b = state.marker_b.filter(|&marker| marker < frame_count);
+    }
+
+    /// Apply the current format overrides and codec choice to the open video and show the
+    /// current frame again
+    fn reopen(&mut self) -> Command<Message> {
+        if let Some(source) = &mut self.source {
+            *source = OpenVideo::new(
+                source.original.clone(),
+                &self.overrides,
+                &self.codecs,
+                &self.codec_name,
+            );
+        }
+        if let Some(BlinkState {
+            source: Some(source),
+            ..
+        }) = &mut self.blink
+        {
+            *source = OpenVideo::new(
+                source.original.clone(),
+                &self.overrides,
+                &self.codecs,
+                &self.codec_name,
+            );
+        }
+        if self.compare.is_some() {
+            self.compare = self.compare_state();
+        }
+        // scores and seeing depend on how the samples are interpreted
+        self.scores = None;
+        self.scoring = false;
+        self.seeing = None;
+        self.measuring_seeing = false;
+        self.redecode()
+    }
+
+    /// Discard the decoded frames and show the current frame again
+    fn redecode(&mut self) -> Command<Message> {
+        self.epoch += 1;
+        lock(self.prefetcher.cache()).clear();
+        let command = self.show_frame();
+        if self.filmstrip.is_some() {
+            Command::batch(vec![command, self.start_filmstrip()])
+        } else {
+            command
+        }
+    }
+
+    /// Show the filmstrip for the open video and start decoding its thumbnails
+    fn start_filmstrip(&mut self) -> Command<Message> {
+        let frames = thumbnail_frames(self.frame_count(), FILMSTRIP_THUMBNAILS);
+        self.filmstrip = Some(Filmstrip {
+            epoch: self.epoch,
+            thumbnails: vec![None; frames.len()],
+            buttons: frames.iter().map(|_| button::State::default()).collect(),
+            frames,
+        });
+        self.decode_thumbnail(0)
+    }
+
+    /// Decode the thumbnail at a position in the filmstrip in the background. Each thumbnail
+    /// starts decoding the next one when it is done.
+    fn decode_thumbnail(&self, position: usize) -> Command<Message> {
+        let (video, codec) = match &self.source {
+            Some(OpenVideo {
+                video,
+                codec: Ok(codec),
+                ..
+            }) => (video.clone(), self.display_codec(codec)),
+            _ => return Command::none(),
+        };
+        let (epoch, index) = match &self.filmstrip {
+            Some(filmstrip) if position < filmstrip.frames.len() => {
+                (filmstrip.epoch, filmstrip.frames[position])
+            }
+            _ => return Command::none(),
+        };
+        let height = self
+            .region
+            .map_or(video.image_height(), |region| region.height);
+        let scale = thumbnail_scale(height, FILMSTRIP_HEIGHT as u32);
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    codec
+                        .decode_scaled(video.as_ref(), index, scale)
+                        .map(|(width, height, pixels)| Handle::from_pixels(width, height, pixels))
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            move |result| Message::ThumbnailDecoded(epoch, position, result),
+        )
+    }
+
+    /// Read the raw values of the pixel under the cursor in the current frame. Nothing is
+    /// read while the image is centered or blinking, because the pixel that is shown is then
+    /// not the pixel at the same position in the current frame.
+    fn update_readout(&mut self) {
+        self.readout = match (&self.source, self.hovered) {
+            (Some(source), Some(position)) if !self.center_target && self.blink.is_none() => {
+                let video = source.video.as_ref();
+                let (x, y) = self.shown_pixel(video, position);
+                Some(match read_pixel(video, self.index(), x, y) {
+                    Ok(readout) => readout.to_string(),
+                    Err(e) => format!("x {}, y {}: {}", x, y, e),
+                })
+            }
+            _ => None,
+        };
+    }
+
+    /// Read the raw values along the line profile in the current frame
+    fn update_profile(&mut self) {
+        if let (Some(source), Some((start, end))) = (&self.source, self.profile_line) {
+            let video = source.video.as_ref();
+            let (start, end) = (self.shown_pixel(video, start), self.shown_pixel(video, end));
+            self.profile =
+                Some(line_profile(video, self.index(), start, end).map_err(|e| e.to_string()));
+        }
+    }
+
+    fn clear_profile(&mut self) {
+        self.drawing_profile = false;
+        self.profile_line = None;
+        self.profile = None;
+    }
+
+    /// Instrument that captured the open video, as recorded in its header
+    fn instrument(&self) -> Option<String> {
+        let source = self.source.as_ref()?;
+        source
+            .original
+            .header_fields()
+            .into_iter()
+            .find(|(name, value)| name == "Instrument" && !value.trim().is_empty())
+            .map(|(_, value)| value.trim().to_string())
+    }
+
+    /// Plate scale of the instrument of the open video, if one is known
+    fn configured_pixel_scale(&self) -> Option<String> {
+        let scale = self.plate_scales.get(&self.instrument()?)?;
+        Some(scale.to_string())
+    }
+
+    /// Scale bar and compass to draw over the image, if they are shown
+    fn scale_overlay(&self) -> Option<ScaleOverlay> {
+        let video = &self.source.as_ref()?.video;
+        if !self.show_scale {
+            return None;
+        }
+        let frame_width = self
+            .region
+            .map(|region| region.width)
+            .unwrap_or_else(|| video.image_width());
+        Some(ScaleOverlay {
+            arcsec_per_pixel: parse_pixel_scale(&self.pixel_scale),
+            orientation: self.orientation,
+            frame_width: frame_width as f32,
+        })
+    }
+
+    /// Part of the frame that the image shows
+    fn shown_region(&self, video: &dyn Video) -> Region {
+        self.region.unwrap_or(Region {
+            x: 0,
+            y: 0,
+            width: video.image_width(),
+            height: video.image_height(),
+        })
+    }
+
+    /// Position in the frame, in pixels, of a position in the image that is shown, given as
+    /// fractions of its size
+    fn frame_position(&self, position: Vector) -> Option<(f32, f32)> {
+        let shown = self.shown_region(self.source.as_ref()?.video.as_ref());
+        Some((
+            shown.x as f32 + position.x.clamp(0.0, 1.0) * shown.width as f32,
+            shown.y as f32 + position.y.clamp(0.0, 1.0) * shown.height as f32,
+        ))
+    }
+
+    /// Arrows and labels on the current frame, positioned on the image that is shown
+    fn callouts(&self) -> Vec<Callout> {
+        let video = match &self.source {
+            Some(source) => source.video.as_ref(),
+            None => return vec![],
+        };
+        let shown = self.shown_region(video);
+        let position = |(x, y): (f32, f32)| {
+            Vector::new(
+                (x - shown.x as f32) / shown.width.max(1) as f32,
+                (y - shown.y as f32) / shown.height.max(1) as f32,
+            )
+        };
+        let index = self.index();
+        self.adjustments
+            .annotations
+            .iter()
+            .filter(|annotation| annotation.frame == index)
+            .map(|annotation| Callout {
+                at: position((annotation.x, annotation.y)),
+                to: annotation.to.map(position),
+                text: annotation.text.clone(),
+            })
+            .collect()
+    }
+
+    /// Add an annotation to the current frame at a position in the image that is shown,
+    /// labelled with the text that has been entered, and save the annotations
+    fn annotate(&mut self, position: Vector, to: Option<(f32, f32)>) -> Command<Message> {
+        let (x, y) = match self.frame_position(position) {
+            Some(position) => position,
+            None => return Command::none(),
+        };
+        let annotation = Annotation {
+            frame: self.index(),
+            x,
+            y,
+            to,
+            text: self.annotation_text.trim().to_string(),
+        };
+        self.adjustments.annotations.push(annotation);
+        self.save_annotations()
+    }
+
+    /// Load the annotations of the open video from the file next to it
+    fn load_annotations(&mut self) {
+        let path = match &self.path {
+            Some(path) => annotations_path(Some(path)),
+            None => return,
+        };
+        match load_annotations(&path) {
+            Ok(annotations) => self.adjustments.annotations = annotations,
+            Err(e) => {
+                warn!("Could not load annotations: {}", e);
+                self.status = Some(
+                    self.locale
+                        .format("load-annotations-failed", &[("error", &e)]),
+                );
+            }
+        }
+    }
+
+    /// Save the annotations to the file next to the video in the background
+    fn save_annotations(&self) -> Command<Message> {
+        let path = match &self.path {
+            Some(path) => annotations_path(Some(path)),
+            None => return Command::none(),
+        };
+        let annotations = self.adjustments.annotations.clone();
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    save_annotations(&path, &annotations)
+                        .map(|_| path)
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::AnnotationsSaved,
+        )
+    }
+
+    /// Pixel of the frame at a position in the image that is shown, given as fractions of
+    /// its size, which differs from the frame when it is cropped to a region of interest
+    fn shown_pixel(&self, video: &dyn Video, position: Vector) -> (u32, u32) {
+        let shown = self.shown_region(video);
+        let pixel = |fraction: f32, size: u32| {
+            ((fraction.max(0.0) * size as f32) as u32).min(size.saturating_sub(1))
+        };
+        (
+            shown.x + pixel(position.x, shown.width),
+            shown.y + pixel(position.y, shown.height),
+        )
+    }
+
+    /// Crop to a region selected on the image that is shown, which may itself be a region
+    /// of interest
+    fn select_region(&mut self, selected: Rectangle) -> Command<Message> {
+        let video = match &self.source {
+            Some(source) => source.video.clone(),
+            None => return Command::none(),
+        };
+        let shown = self.shown_region(video.as_ref());
+        let scale = |fraction: f32, size: u32| (fraction * size as f32).round() as u32;
+        let region = Region {
+            x: shown.x + scale(selected.x, shown.width),
+            y: shown.y + scale(selected.y, shown.height),
+            width: scale(selected.width, shown.width).max(1),
+            height: scale(selected.height, shown.height).max(1),
+        };
+        self.selecting_region = false;
+        match region.fit_to(video.as_ref()) {
+            Ok(region) => {
+                self.region = Some(region);
+                self.clear_profile();
+                self.viewport.fit();
+                self.redecode()
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                Command::none()
+            }
+        }
+    }
+
+    /// Codec for the frames that are shown, which only decodes the region of interest if
+    /// there is one
+    fn display_codec(&self, codec: &Arc<dyn ImageCodec>) -> Arc<dyn ImageCodec> {
+        match self.region {
+            Some(region) => Arc::new(RegionCodec::new(codec.clone(), region)),
+            None => codec.clone(),
+        }
+    }
+
+    /// Show the current frame from the cache, or else start decoding a coarse preview and the
+    /// full resolution frame in the background. The previous frame stays on screen until the
+    /// preview is ready, so the UI never waits for a decode. While playing with binning on,
+    /// only the preview is decoded.
+    fn show_frame(&mut self) -> Command<Message> {
+        self.update_readout();
+        self.update_profile();
+        let mut commands = vec![
+            self.decode_reference(),
+            self.decode_comparison(),
+            self.stack_frame(),
+        ];
+        if self.show_cached() {
+            commands.push(self.decode_frame(PREVIEW_SCALE));
+            if !(self.playing && self.binning > 1) {
+                commands.push(self.decode_full());
+            }
+        }
+        self.prefetch();
+        Command::batch(commands)
+    }
+
+    /// Show the current frame if it is in the cache. Returns true if it still needs to be
+    /// decoded.
+    fn show_cached(&mut self) -> bool {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.decoded_full = false;
+        self.tiled = None;
+        self.seek_pending = false;
+        let source = match &self.source {
+            Some(source) => source,
+            None => return false,
+        };
+        if let Err(e) = &source.codec {
+            let e = e.clone();
+            self.show_error(e);
+            return false;
+        }
+        self.error = None;
+        let index = self.index();
+
+        let cached = {
+            let mut cache = lock(self.prefetcher.cache());
+            cache.set_current(self.epoch, index);
+            cache.get(&(self.epoch, index))
+        };
+        match cached {
+            Some(handle) => {
+                self.decoded_full = true;
+                self.set_image(handle);
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Show an error in place of the current frame, which may be stale
+    fn show_error(&mut self, error: String) {
+        self.error = Some(error);
+        self.image = None;
+        self.histogram = None;
+    }
+
+    /// Show a decoded image, updating the histogram if it is shown
+    fn set_image(&mut self, handle: Handle) {
+        // while the live stack is shown, the histogram is of the stack
+        if self.show_histogram && self.live_stack.is_none() {
+            self.histogram = histogram(&handle);
+        }
+        self.decoded = Some(handle);
+        self.apply_display();
+    }
+
+    /// Update the image that is shown from the decoded image, the white balance, the display
+    /// stretch and centering
+    fn apply_display(&mut self) {
+        self.image = match (&self.live_stack, &self.decoded) {
+            (
+                Some(LiveStack {
+                    decoded: Some((stacked, _)),
+                    ..
+                }),
+                _,
+            ) => Some(self.display_image(stacked, Shift::default())),
+            (_, Some(handle)) => {
+                Some(self.display_image(&self.split_view(handle), Shift::default()))
+            }
+            _ => None,
+        };
+        self.apply_blink_display();
+    }
+
+    /// Add the current frame to the live stack in the background and decode the new mean,
+    /// unless the previous frame is still being stacked
+    fn stack_frame(&mut self) -> Command<Message> {
+        let (video, codec) = match (&self.source, &self.live_stack) {
+            (
+                Some(OpenVideo {
+                    video,
+                    codec: Ok(codec),
+                    ..
+                }),
+                Some(_),
+            ) => (video.clone(), self.display_codec(codec)),
+            _ => return Command::none(),
+        };
+        let (epoch, index) = (self.epoch, self.index());
+        let state = self.live_stack.as_mut().unwrap();
+        if state.epoch != epoch {
+            state.reset(epoch);
+        }
+        if state.busy {
+            return Command::none();
+        }
+        state.busy = true;
+        let key = state.key();
+        let stack = state.stack.clone();
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let mut stack = lock(&stack);
+                    stack
+                        .add(video.as_ref(), index)
+                        .and_then(|_| stack.mean(video.as_ref()))
+                        .and_then(|mean| codec.decode(&FrameView::new(video.as_ref(), &mean), 0))
+                        .map(|(width, height, pixels)| {
+                            (
+                                Handle::from_pixels(width, height, pixels),
+                                stack.frame_count(),
+                            )
+                        })
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            move |result| Message::FrameStacked(key, result),
+        )
+    }
+
+    /// A decoded image with the part to the right of the divider replaced by the same frame
+    /// decoded with the comparison codec, once that has been decoded at the same size
+    fn split_view(&self, handle: &Handle) -> Handle {
+        let other = match &self.compare {
+            Some(CompareState {
+                decoded: Some((key, other)),
+                ..
+            }) if *key == (self.epoch, self.index()) => other,
+            _ => return handle.clone(),
+        };
+        match (handle.data(), other.data()) {
+            (
+                image::Data::Pixels {
+                    width,
+                    height,
+                    pixels,
+                },
+                image::Data::Pixels {
+                    width: other_width,
+                    height: other_height,
+                    pixels: other_pixels,
+                },
+            ) if width == other_width && height == other_height => {
+                let divider = (*width as u64 * self.divider as u64 / 100) as u32;
+                let pixels = split_bgra(*width, pixels, other_pixels, divider);
+                Handle::from_pixels(*width, *height, pixels)
+            }
+            _ => handle.clone(),
+        }
+    }
+
+    /// Comparison state for the open video with the chosen comparison codec
+    fn compare_state(&self) -> Option<CompareState> {
+        let source = self.source.as_ref()?;
+        Some(CompareState {
+            codec: codec_named(self.compare_codec, source.video.as_ref())
+                .map(Arc::from)
+                .map_err(|e| e.to_string()),
+            key: None,
+            decoded: None,
+        })
+    }
+
+    /// Decode the current frame with the comparison codec in the background, unless it has
+    /// already been decoded
+    fn decode_comparison(&mut self) -> Command<Message> {
+        let video = match (&self.source, &self.compare) {
+            (Some(source), Some(_)) => source.video.clone(),
+            _ => return Command::none(),
+        };
+        let key = (self.epoch, self.index());
+        let state = self.compare.as_mut().unwrap();
+        let codec = match &state.codec {
+            Ok(codec) => codec.clone(),
+            Err(e) => {
+                self.status = Some(self.locale.format("compare-failed", &[("error", &e)]));
+                return Command::none();
+            }
+        };
+        if state.key == Some(key) {
+            return Command::none();
+        }
+        state.key = Some(key);
+        let codec = self.display_codec(&codec);
+        let index = key.1;
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    codec
+                        .decode(video.as_ref(), index)
+                        .map(|(width, height, pixels)| Handle::from_pixels(width, height, pixels))
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            move |result| Message::ComparisonDecoded(key, result),
+        )
+    }
+
+    /// Update the reference frame that is shown while blinking, which is adjusted like the
+    /// current frame and then moved by the blink offset
+    fn apply_blink_display(&mut self) {
+        let image = match &self.blink {
+            Some(state) => state
+                .decoded
+                .as_ref()
+                .map(|handle| self.display_image(handle, state.blink.offset)),
+            None => return,
+        };
+        if let Some(state) = &mut self.blink {
+            state.image = image;
+        }
+    }
+
+    /// A decoded image with the white balance, display stretch and centering applied, then
+    /// translated by `offset`
+    fn display_image(&self, handle: &Handle, offset: Shift) -> Handle {
+        let identity = self.white_balance.is_identity()
+            && self.stretch.is_identity()
+            && self.color_map.is_identity()
+            && !self.center_target
+            && offset == Shift::default();
+        match handle.data() {
+            image::Data::Pixels {
+                width,
+                height,
+                pixels,
+            } if !identity => {
+                let mut pixels = pixels.clone();
+                self.white_balance.apply(&mut pixels);
+                self.stretch.apply(&mut pixels);
+                self.color_map.apply(&mut pixels);
+                if self.center_target {
+                    let shift = centering_shift(*width, *height, &pixels);
+                    pixels = shift_bgra(*width, *height, &pixels, shift);
+                }
+                if offset != Shift::default() {
+                    pixels = shift_bgra(*width, *height, &pixels, offset);
+                }
+                Handle::from_pixels(*width, *height, pixels)
+            }
+            _ => handle.clone(),
+        }
+    }
+
+    /// Start blinking between the current frame and a reference frame from `source`, or from
+    /// the open video if it is `None`
+    fn start_blink(
+        &mut self,
+        target: BlinkTarget,
+        source: Option<OpenVideo>,
+        path: Option<PathBuf>,
+    ) -> Command<Message> {
+        self.blink = Some(BlinkState {
+            blink: Blink::new(target, self.blink_rate),
+            source,
+            path,
+            key: None,
+            decoded: None,
+            image: None,
+        });
+        self.decode_reference()
+    }
+
+    /// Blink between each frame and the same frame of another video, which is opened and
+    /// decoded like the open video
+    fn blink_with(&mut self, path: PathBuf) -> Command<Message> {
+        let options = OpenOptions {
+            mode: self.mode,
+            read: self.read,
+            ..OpenOptions::default()
+        };
+        match open_video_with(&path.to_string_lossy(), &options) {
+            Ok(video) => {
+                let source = OpenVideo::new(
+                    Arc::from(video),
+                    &self.overrides,
+                    &self.codecs,
+                    &self.codec_name,
+                );
+                self.start_blink(BlinkTarget::SameIndex, Some(source), Some(path))
+            }
+            Err(e) => {
+                error!("Could not open {}: {}", path.display(), e);
+                self.error = Some(
+                    self.locale
+                        .format("open-failed", &[("path", &path.display()), ("error", &e)]),
+                );
+                Command::none()
+            }
+        }
+    }
+
+    /// Decode the reference frame for the current frame in the background, unless it has
+    /// already been decoded
+    fn decode_reference(&mut self) -> Command<Message> {
+        let source = match &self.blink {
+            Some(state) => state.source.as_ref().or(self.source.as_ref()),
+            None => return Command::none(),
+        };
+        let (video, codec) = match source {
+            Some(OpenVideo {
+                video,
+                codec: Ok(codec),
+                ..
+            }) => (video.clone(), self.display_codec(codec)),
+            _ => return Command::none(),
+        };
+        let state = self.blink.as_mut().unwrap();
+        let index = match state
+            .blink
+            .target
+            .index(self.value as usize, video.frame_count())
+        {
+            Some(index) => index,
+            None => return Command::none(),
+        };
+        let key = (self.epoch, index);
+        if state.key == Some(key) {
+            return Command::none();
+        }
+        state.key = Some(key);
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    codec
+                        .decode(video.as_ref(), index)
+                        .map(|(width, height, pixels)| Handle::from_pixels(width, height, pixels))
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            move |result| Message::ReferenceDecoded(key, result),
+        )
+    }
+
+    /// Start decoding the frames that playback shows after the current frame in the
+    /// background, following the frame order, the loop region and the direction of ping-pong
+    /// playback. This is limited to the number of frames that fit comfortably in the cache.
+    fn prefetch(&self) {
+        let (video, codec) = match &self.source {
+            Some(OpenVideo {
+                video,
+                codec: Ok(codec),
+                ..
+            }) => (video.clone(), self.display_codec(codec)),
+            _ => return,
+        };
+        let frame_size = (video.image_width() as usize * video.image_height() as usize * 4).max(1);
+        let capacity = lock(self.prefetcher.cache()).capacity();
+        let count = PREFETCH_FRAMES.min(capacity / frame_size / 2);
+        let indices = self
+            .playback
+            .upcoming(&self.frame_order(), self.index(), count);
+        self.prefetcher.prefetch(self.epoch, video, codec, indices);
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Codec and scale for a coarse preview of the current frame. Previews of uncompressed
+    /// videos are binned when binning is on, and Bayer mosaics are otherwise previewed with
+    /// 2x2 superpixels, which is much faster than sampling the output of an interpolating
+    /// debayer.
+    fn preview_codec(
+        &self,
+        video: &dyn Video,
+        codec: &Arc<dyn ImageCodec>,
+        scale: u32,
+    ) -> (Arc<dyn ImageCodec>, u32) {
+        if video.compression() != Compression::None {
+            return (self.display_codec(codec), scale);
+        }
+        let (preview, scale): (Arc<dyn ImageCodec>, u32) = match video.bayer() {
+            _ if self.binning > 1 => (Arc::new(BinningCodec::new(self.binning)), 1),
+            Bayer::Mono | Bayer::RGB | Bayer::BGR => (codec.clone(), scale),
+            // superpixels are already half the size of the frame in each direction
+            _ => (Arc::new(DebayerCodec {}), (scale / 2).max(1)),
+        };
+        (self.display_codec(&preview), scale)
+    }
+
+    /// Decode the current frame in the background, sampling every `scale`th pixel. Full
+    /// resolution frames (a scale of 1) are added to the cache and reported with
+    /// `FrameDecoded`, while coarse previews from `preview_codec` are reported with
+    /// `FramePreviewed`. The decode is skipped if the frame changes before it starts.
+    fn decode_frame(&self, scale: u32) -> Command<Message> {
+        let generation = self.generation();
+        let current = self.generation.clone();
+        let full = scale == 1;
+        let (video, codec, scale) = match &self.source {
+            Some(OpenVideo {
+                video,
+                codec: Ok(codec),
+                ..
+            }) if self.error.is_none() => {
+                let (codec, scale) = if full {
+                    (self.display_codec(codec), scale)
+                } else {
+                    self.preview_codec(video.as_ref(), codec, scale)
+                };
+                (video.clone(), codec, scale)
+            }
+            _ => return Command::none(),
+        };
+        let index = self.index();
+        let key = (self.epoch, index);
+        let cache = self.prefetcher.cache().clone();
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    if current.load(Ordering::SeqCst) != generation {
+                        // the result would be discarded
+                        return Err("superseded".to_string());
+                    }
+                    let start = Instant::now();
+                    let result = codec.decode_scaled(video.as_ref(), index, scale);
+                    match &result {
+                        Ok(_) => {
+                            debug!(frame = index, scale, elapsed = ?start.elapsed(), "decoded frame")
+                        }
+                        Err(e) => error!(frame = index, scale, "failed to decode frame: {}", e),
+                    }
+                    result
+                        .map(|(w, h, pixels)| {
+                            let size = pixels.len();
+                            let handle = Handle::from_pixels(w, h, pixels);
+                            if full {
+                                lock(&cache).insert(key, handle.clone(), size);
+                            }
+                            handle
+                        })
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            move |result| {
+                if full {
+                    Message::FrameDecoded(generation, result)
+                } else {
+                    Message::FramePreviewed(generation, result)
+                }
+            },
+        )
+    }
+
+    /// Decode a preview of the frame the seek bar was dragged to, or wait for the preview that
+    /// is being decoded to finish and then preview the latest frame
+    fn preview_seek(&mut self) -> Command<Message> {
+        if self.seek_preview.is_some() {
+            self.seek_pending = true;
+            return Command::none();
+        }
+        self.seek_preview = Some(self.generation());
+        self.decode_frame(PREVIEW_SCALE)
+    }
+
+    /// Preview the latest frame the seek bar was dragged to once the seek preview with the
+    /// given generation finishes
+    fn finish_seek_preview(&mut self, generation: u64) -> Command<Message> {
+        if self.seek_preview != Some(generation) {
+            return Command::none();
+        }
+        self.seek_preview = None;
+        if mem::take(&mut self.seek_pending) && !self.decoded_full {
+            self.preview_seek()
+        } else {
+            Command::none()
+        }
+    }
+
+    /// Decode the current frame at full resolution in the background. Large uncompressed
+    /// frames are decoded in bands that are shown as they finish, see `tiles`.
+    fn decode_full(&mut self) -> Command<Message> {
+        let band_rows = match &self.source {
+            Some(source) if self.region.is_none() => band_rows(source.video.as_ref()),
+            _ => None,
+        };
+        match band_rows {
+            Some(rows) => {
+                self.tiled = Some(TiledFrame::new(rows));
+                self.decode_band()
+            }
+            None => self.decode_frame(1),
+        }
+    }
+
+    /// Decode the next band of rows of the current frame in the background, which is skipped
+    /// if the frame changes before it starts
+    fn decode_band(&self) -> Command<Message> {
+        let generation = self.generation();
+        let current = self.generation.clone();
+        let (video, codec) = match &self.source {
+            Some(OpenVideo {
+                video,
+                codec: Ok(codec),
+                ..
+            }) if self.error.is_none() => (video.clone(), codec.clone()),
+            _ => return Command::none(),
+        };
+        let (start, rows) = match &self.tiled {
+            Some(tiled) => tiled.next_band(),
+            None => return Command::none(),
+        };
+        let index = self.index();
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    if current.load(Ordering::SeqCst) != generation {
+                        // the result would be discarded
+                        return Err("superseded".to_string());
+                    }
+                    let started = Instant::now();
+                    let result = decode_band(&codec, video.as_ref(), index, start, rows);
+                    match &result {
+                        Ok(_) => {
+                            debug!(frame = index, row = start, elapsed = ?started.elapsed(), "decoded band")
+                        }
+                        Err(e) => error!(frame = index, row = start, "failed to decode band: {}", e),
+                    }
+                    result.map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            move |result| Message::BandDecoded(generation, result),
+        )
+    }
+
+    /// Show the current frame with the next band of rows decoded, then decode the band after
+    /// it, or add the frame to the cache once every band is decoded
+    fn add_band(&mut self, band: Band) -> Command<Message> {
+        let height = match &self.source {
+            Some(source) => source.video.image_height(),
+            None => return Command::none(),
+        };
+        let mut tiled = match self.tiled.take() {
+            Some(tiled) => tiled,
+            None => return Command::none(),
+        };
+        tiled.add_band(band);
+        if tiled.next_band().0 < height {
+            let (width, height, pixels) = tiled.image();
+            let handle = Handle::from_pixels(width, height, pixels.to_vec());
+            self.tiled = Some(tiled);
+            self.set_image(handle);
+            return self.decode_band();
+        }
+        let (width, height, pixels) = tiled.into_image();
+        let size = pixels.len();
+        let handle = Handle::from_pixels(width, height, pixels);
+        let key = (self.epoch, self.index());
+        lock(self.prefetcher.cache()).insert(key, handle.clone(), size);
+        self.decoded_full = true;
+        self.set_image(handle);
+        Command::none()
+    }
+
+    /// Save the current frame in the background
+    fn export_frame(&self) -> Command<Message> {
+        let (video, codec) = match &self.source {
+            Some(OpenVideo {
+                video,
+                codec: Ok(codec),
+                ..
+            }) => (video.clone(), codec.clone()),
+            _ => return Command::none(),
+        };
+        let index = self.index();
+        let format = self.export_format;
+        let path = frame_path(self.path.as_deref(), index, format);
+        let region = self.region.filter(|_| self.crop_exports);
+        let annotations: Vec<Annotation> = self
+            .adjustments
+            .annotations
+            .iter()
+            .filter(|annotation| annotation.frame == index)
+            .cloned()
+            .collect();
+        let burn = self.burn_annotations && format == ExportFormat::Png && !annotations.is_empty();
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    match region {
+                        Some(region) if burn => export_annotated_png(
+                            video.as_ref(),
+                            &RegionCodec::new(codec, region),
+                            index,
+                            &annotations,
+                            (region.x, region.y),
+                            &path,
+                            &mut vec![],
+                        ),
+                        None if burn => export_annotated_png(
+                            video.as_ref(),
+                            codec.as_ref(),
+                            index,
+                            &annotations,
+                            (0, 0),
+                            &path,
+                            &mut vec![],
+                        ),
+                        Some(region) => {
+                            export_region(video.as_ref(), codec, index, region, format, &path)
+                        }
+                        None => export_frame(video.as_ref(), codec.as_ref(), index, format, &path),
+                    }
+                    .map(|_| path)
+                    .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::FrameExported,
+        )
+    }
+
+    /// Frames to step through, in order. This is every frame in file order until the frames
+    /// have been scored.
+    fn frame_order(&self) -> Vec<usize> {
+        match (&self.scores, self.order) {
+            (Some(scores), "sharpest first") => scores.iter().map(|score| score.index).collect(),
+            (Some(scores), "sharpest 50%") => sharpest(scores, 0.5),
+            (Some(scores), "sharpest 25%") => sharpest(scores, 0.25),
+            (Some(scores), "sharpest 10%") => sharpest(scores, 0.1),
+            _ => (0..self.frame_count()).collect(),
+        }
+    }
+
+    /// Frame after the current frame in the chosen order. If the current frame was skipped
+    /// by the order, this is the next frame in file order that is not skipped.
+    fn next_frame(&self) -> Option<usize> {
+        let order = self.frame_order();
+        let current = self.value as usize;
+        match order.iter().position(|&index| index == current) {
+            Some(position) => order.get(position + 1).copied(),
+            None => order.into_iter().find(|&index| index > current),
+        }
+    }
+
+    /// Frame `steps` frames after the current frame in the chosen order, or before it if
+    /// `steps` is negative, stopping at the first or last frame. A current frame that was
+    /// skipped by the order counts as being just before the next frame that is not skipped.
+    fn step_frame(&self, steps: i64) -> Option<usize> {
+        let order = self.frame_order();
+        let current = self.value as usize;
+        let position = match order.iter().position(|&index| index == current) {
+            Some(position) => position as i64,
+            None => {
+                let next = order
+                    .iter()
+                    .position(|&index| index > current)
+                    .unwrap_or(order.len()) as i64;
+                if steps > 0 {
+                    next - 1
+                } else {
+                    next
+                }
+            }
+        };
+        let target = (position + steps).max(0).min(order.len() as i64 - 1);
+        order.get(target as usize).copied()
+    }
+
+    /// Frame before the current frame in the chosen order
+    fn previous_frame(&self) -> Option<usize> {
+        let order = self.frame_order();
+        let current = self.value as usize;
+        match order.iter().position(|&index| index == current) {
+            Some(position) => position.checked_sub(1).map(|position| order[position]),
+            None => order.into_iter().rev().find(|&index| index < current),
+        }
+    }
+
+    /// Save the frames that are not excluded or skipped by the chosen order, in file order, as
+    /// a SER file in the background. The raw frames are copied without the format overrides.
+    fn save_clip(&self) -> Command<Message> {
+        let video = match &self.source {
+            Some(source) => source.original.clone(),
+            None => return Command::none(),
+        };
+        let indices = self.selected_frames();
+        let path = clip_path(self.path.as_deref());
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    write_clip(video.as_ref(), &indices, &path)
+                        .map(|_| (path, indices.len()))
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::ClipSaved,
+        )
+    }
+
+    /// Save the selected frames as an animation in the background, rendered with the region
+    /// of interest, white balance and stretch that are shown
+    fn save_animation(&mut self) -> Command<Message> {
+        let (video, codec) = match &self.source {
+            Some(OpenVideo {
+                video,
+                codec: Ok(codec),
+                ..
+            }) => (video.clone(), self.display_codec(codec)),
+            _ => return Command::none(),
+        };
+        let indices = self.selected_frames();
+        let format = self.animation_format;
+        let path = animation_path(self.path.as_deref(), format);
+        let options = AnimationOptions {
+            fps: self.playback_fps(),
+            white_balance: self.white_balance,
+            stretch: self.stretch,
+            color_map: self.color_map,
+            ..AnimationOptions::default()
+        };
+        self.animating = true;
+        self.status = Some(
+            self.locale
+                .format("saving-frames", &[("count", &indices.len())]),
+        );
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    export_animation(
+                        video.as_ref(),
+                        codec.as_ref(),
+                        &indices,
+                        format,
+                        &options,
+                        &path,
+                    )
+                    .map(|_| (path, indices.len()))
+                    .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::AnimationSaved,
+        )
+    }
+
+    /// Stack the selected frames in the background
+    fn stack_frames(&mut self) -> Command<Message> {
+        let (video, codec) = match &self.source {
+            Some(OpenVideo {
+                video,
+                codec: Ok(codec),
+                ..
+            }) => (video.clone(), codec.clone()),
+            _ => return Command::none(),
+        };
+        let indices = self.selected_frames();
+        let combine = parse_combine(self.combine).unwrap_or(Combine::Mean);
+        let format = self.export_format;
+        let path = stack_path(self.path.as_deref(), format);
+        let align = self.align;
+        self.stacking = true;
+        self.status = Some(
+            self.locale
+                .format("stacking-frames", &[("count", &indices.len())]),
+        );
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let stack = || -> crate::Result<usize> {
+                        let (indices, shifts) = lucky_frames(video.as_ref(), &indices, 1.0, align)?;
+                        export_stack(
+                            video.as_ref(),
+                            codec.as_ref(),
+                            &indices,
+                            &shifts,
+                            combine,
+                            format,
+                            &path,
+                        )?;
+                        Ok(indices.len())
+                    };
+                    stack()
+                        .map(|count| (path.clone(), count))
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::FramesStacked,
+        )
+    }
+
+    /// Frames that are not excluded or skipped by the chosen order, in file order
+    fn selected_frames(&self) -> Vec<usize> {
+        let mut indices = self.frame_order();
+        indices.sort_unstable();
+        indices.retain(|index| !self.adjustments.excluded.contains(index));
+        indices
+    }
+
+    /// Score the sharpness of every frame in the background
+    fn score_frames(&mut self) -> Command<Message> {
+        let video = match &self.source {
+            Some(source) => source.video.clone(),
+            None => return Command::none(),
+        };
+        self.scoring = true;
+        self.status = Some(self.locale.text("scoring-frames"));
+        let epoch = self.epoch;
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let start = Instant::now();
+                    let result = score_frames(video.as_ref()).map_err(|e| e.to_string());
+                    debug!(elapsed = ?start.elapsed(), "scored frames");
+                    result
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            move |result| Message::FramesScored(epoch, result),
+        )
+    }
+
+    /// Save the sharpness ranking in the background
+    fn export_ranking(&self) -> Command<Message> {
+        let scores = match &self.scores {
+            Some(scores) => scores.clone(),
+            None => return Command::none(),
+        };
+        let path = ranking_path(self.path.as_deref());
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    std::fs::File::create(&path)
+                        .map_err(|e| e.into())
+                        .and_then(|file| write_csv(&scores, &mut std::io::BufWriter::new(file)))
+                        .map(|_| path)
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::RankingExported,
+        )
+    }
+
+    /// Measure the seeing in every frame in the background
+    fn measure_seeing(&mut self) -> Command<Message> {
+        let video = match &self.source {
+            Some(source) => source.video.clone(),
+            None => return Command::none(),
+        };
+        self.measuring_seeing = true;
+        self.status = Some(self.locale.text("measuring-seeing"));
+        let epoch = self.epoch;
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let start = Instant::now();
+                    let result = measure_seeing(video.as_ref()).map_err(|e| e.to_string());
+                    debug!(elapsed = ?start.elapsed(), "measured seeing");
+                    result
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            move |result| Message::SeeingMeasured(epoch, result),
+        )
+    }
+
+    /// Save the seeing measurements in the background
+    fn export_seeing(&self) -> Command<Message> {
+        let seeing = match &self.seeing {
+            Some(seeing) => seeing.clone(),
+            None => return Command::none(),
+        };
+        let path = seeing_path(self.path.as_deref());
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    std::fs::File::create(&path)
+                        .map_err(|e| e.into())
+                        .and_then(|file| {
+                            write_seeing_csv(&seeing, &mut std::io::BufWriter::new(file))
+                        })
+                        .map(|_| path)
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::SeeingExported,
+        )
+    }
+
+    /// Save the marked frames in the background
+    fn export_marks(&self) -> Command<Message> {
+        let marks = self.adjustments.marks.clone();
+        let path = marks_path(self.path.as_deref());
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    std::fs::File::create(&path)
+                        .map_err(|e| e.into())
+                        .and_then(|file| {
+                            write_marks_csv(&marks, &mut std::io::BufWriter::new(file))
+                        })
+                        .map(|_| path)
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::MarksExported,
+        )
+    }
+
+    /// Index of the current frame, limited to the frames in the video
+    fn index(&self) -> usize {
+        (self.value as usize).min(self.frame_count().saturating_sub(1))
+    }
+}
+
+/// Histogram of a decoded image
+fn histogram(handle: &Handle) -> Option<Histogram> {
+    match handle.data() {
+        image::Data::Pixels { pixels, .. } => Some(Histogram::from_bgra(pixels)),
+        _ => None,
+    }
+}
+
+/// Ticks at the position of each marked frame, drawn above the seek bar: green for good frames
+/// and red for bad ones, with capture gaps in orange
+struct MarkStrip {
+    frame_count: usize,
+    marks: BTreeMap<usize, Mark>,
+    gaps: Vec<Gap>,
+}
+
+impl canvas::Program<Message> for MarkStrip {
+    fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
+        let mut frame = Frame::new(bounds.size());
+        let last = self.frame_count.saturating_sub(1).max(1) as f32;
+        let gaps = self
+            .gaps
+            .iter()
+            .map(|gap| (gap.after, Color::from_rgb8(255, 150, 0)));
+        let marks = self.marks.iter().map(|(&index, mark)| {
+            let color = match mark {
+                Mark::Good => Color::from_rgb8(60, 200, 60),
+                Mark::Bad => Color::from_rgb8(230, 60, 60),
+            };
+            (index, color)
+        });
+        for (index, color) in gaps.chain(marks) {
+            let x = (index as f32 / last).min(1.0) * (bounds.width - 2.0).max(0.0);
+            frame.fill_rectangle(Point::new(x, 0.0), Size::new(2.0, bounds.height), color);
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Line chart of the red, green, blue and luminance histograms on a log scale, so that the
+/// background level of astronomical images does not flatten the rest of the chart
+struct HistogramChart(Histogram);
+
+impl canvas::Program<Message> for HistogramChart {
+    fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
+        let mut frame = Frame::new(bounds.size());
+        frame.fill_rectangle(Point::ORIGIN, bounds.size(), Color::from_rgb8(32, 32, 32));
+        let histogram = &self.0;
+        let channels = [
+            (&histogram.red, Color::from_rgb8(230, 60, 60)),
+            (&histogram.green, Color::from_rgb8(60, 200, 60)),
+            (&histogram.blue, Color::from_rgb8(80, 120, 255)),
+            (&histogram.luminance, Color::WHITE),
+        ];
+        let max = channels
+            .iter()
+            .flat_map(|(counts, _)| counts.iter())
+            .max()
+            .copied()
+            .unwrap_or(0);
+        let scale = |count: u32| (count as f32).ln_1p() / (max as f32).ln_1p().max(1.0);
+        for (counts, color) in channels {
+            let path = Path::new(|builder| {
+                for (level, count) in counts.iter().enumerate() {
+                    let point = Point::new(
+                        level as f32 / 255.0 * bounds.width,
+                        (1.0 - scale(*count)) * bounds.height,
+                    );
+                    if level == 0 {
+                        builder.move_to(point);
+                    } else {
+                        builder.line_to(point);
+                    }
+                }
+            });
+            frame.stroke(&path, Stroke::default().with_color(color).with_width(1.0));
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Plot of the values of each channel along a line profile
+struct ProfileChart(LineProfile);
+
+impl canvas::Program<Message> for ProfileChart {
+    fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
+        let mut frame = Frame::new(bounds.size());
+        frame.fill_rectangle(Point::ORIGIN, bounds.size(), Color::from_rgb8(32, 32, 32));
+        let profile = &self.0;
+        let last = profile.len().saturating_sub(1).max(1) as f32;
+        let max = profile.max_value.max(1) as f32;
+        for (name, values) in &profile.channels {
+            let color = match *name {
+                "R" => Color::from_rgb8(230, 60, 60),
+                "G" => Color::from_rgb8(60, 200, 60),
+                "B" => Color::from_rgb8(80, 120, 255),
+                _ => Color::WHITE,
+            };
+            let path = Path::new(|builder| {
+                for (i, value) in values.iter().enumerate() {
+                    let point = Point::new(
+                        i as f32 / last * bounds.width,
+                        (1.0 - value / max) * bounds.height,
+                    );
+                    if i == 0 {
+                        builder.move_to(point);
+                    } else {
+                        builder.line_to(point);
+                    }
+                }
+            });
+            frame.stroke(&path, Stroke::default().with_color(color).with_width(1.0));
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Plot of the FWHM of each frame against its capture time, or its index if the frames have
+/// no timestamps, with the median as a dim line and the current frame marked. Clicking the
+/// plot shows the nearest frame.
+struct SeeingChart {
+    seeing: Vec<Seeing>,
+    current: usize,
+}
+
+impl SeeingChart {
+    /// Position of a frame along the horizontal axis
+    fn position(&self, seeing: &Seeing) -> f32 {
+        if self.seeing.iter().all(|s| s.seconds.is_some()) {
+            seeing.seconds.unwrap_or(0.0) as f32
+        } else {
+            seeing.index as f32
+        }
+    }
+
+    /// Horizontal scale from positions to the chart
+    fn scale(&self, bounds: Rectangle) -> f32 {
+        let last = self.seeing.last().map(|s| self.position(s)).unwrap_or(0.0);
+        bounds.width / last.max(1.0)
+    }
+}
+
+impl canvas::Program<Message> for SeeingChart {
+    fn update(
+        &mut self,
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> (event::Status, Option<Message>) {
+        match (event, cursor.position_in(&bounds)) {
+            (
+                canvas::Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)),
+                Some(point),
+            ) => {
+                let target = point.x / self.scale(bounds);
+                let nearest = self.seeing.iter().min_by(|a, b| {
+                    (self.position(a) - target)
+                        .abs()
+                        .partial_cmp(&(self.position(b) - target).abs())
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+                (
+                    event::Status::Captured,
+                    nearest.map(|s| Message::JumpToFrame(s.index)),
+                )
+            }
+            _ => (event::Status::Ignored, None),
+        }
+    }
+
+    fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
+        let mut frame = Frame::new(bounds.size());
+        frame.fill_rectangle(Point::ORIGIN, bounds.size(), Color::from_rgb8(32, 32, 32));
+        let scale = self.scale(bounds);
+        let max = self
+            .seeing
+            .iter()
+            .filter_map(|s| s.fwhm)
+            .fold(0.0, f64::max)
+            .max(1.0) as f32
+            * 1.1;
+        let y = |fwhm: f64| (1.0 - fwhm as f32 / max) * bounds.height;
+        if let Some(median) = median_fwhm(&self.seeing) {
+            let path = Path::line(
+                Point::new(0.0, y(median)),
+                Point::new(bounds.width, y(median)),
+            );
+            frame.stroke(
+                &path,
+                Stroke::default()
+                    .with_color(Color::from_rgb8(100, 100, 100))
+                    .with_width(1.0),
+            );
+        }
+        if let Some(current) = self.seeing.iter().find(|s| s.index == self.current) {
+            let x = self.position(current) * scale;
+            let path = Path::line(Point::new(x, 0.0), Point::new(x, bounds.height));
+            frame.stroke(
+                &path,
+                Stroke::default()
+                    .with_color(Color::from_rgb8(255, 150, 0))
+                    .with_width(1.0),
+            );
+        }
+        // frames without a star break the line
+        let path = Path::new(|builder| {
+            let mut drawing = false;
+            for seeing in &self.seeing {
+                match seeing.fwhm {
+                    Some(fwhm) => {
+                        let point = Point::new(self.position(seeing) * scale, y(fwhm));
+                        if drawing {
+                            builder.line_to(point);
+                        } else {
+                            builder.move_to(point);
+                        }
+                        drawing = true;
+                    }
+                    None => drawing = false,
+                }
+            }
+        });
+        frame.stroke(
+            &path,
+            Stroke::default().with_color(Color::WHITE).with_width(1.0),
+        );
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Find the choice matching a header override, or `auto` when there is no override
+fn choice(choices: &[&'static str], value: Option<String>) -> Option<&'static str> {
+    let value = value.unwrap_or_else(|| "auto".to_string());
+    choices
+        .iter()
+        .find(|choice| choice.eq_ignore_ascii_case(&value))
+        .copied()
+}
+
+/// Actions that can be bound to keys in the `keys` table of the configuration file, with
+/// their default keys: X to exclude the current frame, G or B to mark it as good or bad, H to
+/// show or hide the histogram, I to show or hide the file information, T to show or hide the
+/// filmstrip, F to fit the frame to
+/// the view, 1 to show it at actual size, Space to start or pause playback and Page Up or Page
+/// Down to switch to the previous or next file
+fn key_actions() -> Vec<(&'static str, KeyCode, Message)> {
+    vec![
+        ("exclude", KeyCode::X, Message::ToggleExcluded),
+        ("mark_good", KeyCode::G, Message::MarkFrame(Mark::Good)),
+        ("mark_bad", KeyCode::B, Message::MarkFrame(Mark::Bad)),
+        ("histogram", KeyCode::H, Message::ToggleHistogram),
+        ("info", KeyCode::I, Message::ToggleInfo),
+        ("filmstrip", KeyCode::T, Message::ToggleFilmstrip),
+        ("fit", KeyCode::F, Message::ZoomToFit),
+        ("actual_size", KeyCode::Key1, Message::ZoomToActualSize),
+        ("play", KeyCode::Space, Message::TogglePlaying),
+        ("previous_file", KeyCode::PageUp, Message::PreviousFile),
+        ("next_file", KeyCode::PageDown, Message::NextFile),
+    ]
+}
+
+/// Keys that can be bound to actions, which are named as in `KeyCode`, except that digits
+/// can also be given without the `Key` prefix
+const BINDABLE_KEYS: &[KeyCode] = &[
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Space,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Escape,
+    KeyCode::Delete,
+    KeyCode::Insert,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Minus,
+    KeyCode::Equals,
+];
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let name = name.trim();
+    BINDABLE_KEYS.iter().copied().find(|key| {
+        let key_name = format!("{:?}", key);
+        key_name.eq_ignore_ascii_case(name)
+            || key_name
+                .strip_prefix("Key")
+                .is_some_and(|digit| digit == name)
+    })
+}
+
+/// Whether a message edits settings that can be undone
+fn is_edit(message: &Message) -> bool {
+    is_gradual_edit(message)
+        || matches!(
+            message,
+            Message::ToggleExcluded
+                | Message::MarkFrame(_)
+                | Message::ArrowDrawn(..)
+                | Message::LabelPlaced(_)
+                | Message::ClearFrameAnnotations
+                | Message::AutoStretch
+                | Message::ResetStretch
+                | Message::AutoWhiteBalance
+                | Message::ResetWhiteBalance
+                | Message::RegionSelected(_)
+                | Message::ShowFullFrame
+        )
+}
+
+/// Whether a message is one of a run of messages that make a single edit, such as the
+/// values of a slider while it is dragged
+fn is_gradual_edit(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::BlackPointChanged(_)
+            | Message::WhitePointChanged(_)
+            | Message::GammaChanged(_)
+            | Message::RedGainChanged(_)
+            | Message::GreenGainChanged(_)
+            | Message::BlueGainChanged(_)
+    )
+}
+
+/// Map keys to messages, using the default key for any action that is not in the
+/// configuration
+fn key_bindings(keys: &BTreeMap<String, String>) -> HashMap<KeyCode, Message> {
+    let actions = key_actions();
+    for name in keys.keys() {
+        if !actions.iter().any(|(action, _, _)| action == name) {
+            warn!("Ignoring key binding for unknown action {}", name);
+        }
+    }
+    actions
+        .into_iter()
+        .map(|(action, default_key, message)| {
+            let key = match keys.get(action) {
+                Some(name) => parse_key(name).unwrap_or_else(|| {
+                    warn!("Ignoring unknown key {} for {}", name, action);
+                    default_key
+                }),
+                None => default_key,
+            };
+            (key, message)
+        })
+        .collect()
+}
+
+/// Map key presses to messages: Ctrl+Z to undo and Ctrl+Shift+Z or Ctrl+Y to redo, with Cmd
+/// instead of Ctrl on macOS. Keys without modifiers are looked up in the key bindings, see
+/// `key_actions`. Files dropped on the window are opened.
+fn input_event(event: Event, status: event::Status) -> Option<Message> {
+    if let Event::Window(window::Event::CloseRequested) = event {
+        return Some(Message::CloseRequested);
+    }
+    if status == event::Status::Captured {
+        return None;
+    }
+    match event {
+        Event::Window(window::Event::FileDropped(path)) => Some(Message::OpenFile(path)),
+        Event::Keyboard(keyboard::Event::KeyPressed {
+            key_code,
+            modifiers,
+        }) => {
+            let command = modifiers.is_command_pressed();
+            match key_code {
+                KeyCode::Z if command && modifiers.shift => Some(Message::Redo),
+                KeyCode::Z if command => Some(Message::Undo),
+                KeyCode::Y if command => Some(Message::Redo),
+                _ if !command => Some(Message::KeyPressed(key_code)),
+                _ => None,
+            }
+        }
+        _ => None,
     }
 }
@@ -0,0 +1,603 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Live preview of UVC and other Video4Linux cameras, so that the player can be used for
+//! focusing and framing with the same stretch, zoom and histogram tools as for captures.
+//!
+//! The device is opened with `V4l2Video::open`, which negotiates a pixel format and streams
+//! frames from memory-mapped driver buffers on a background thread into a `LiveVideo`.
+//! Raw Bayer and grayscale formats are preferred so that the samples are not processed by
+//! the camera, falling back to YUYV, which is converted to RGB.
+
+use std::borrow::Cow;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::thread;
+use std::time::SystemTime;
+
+use libc::{c_int, c_ulong, c_void};
+use tracing::{debug, info, warn};
+
+use crate::error::{PlayerError, Result};
+use crate::live::{live_video, FrameWatch, LiveFeed, LiveVideo, DEFAULT_LIVE_MEMORY};
+use crate::ser::SerFormat;
+use crate::video_format::{Bayer, Endianness, Video};
+
+/// Number of driver buffers to stream into
+const BUFFER_COUNT: u32 = 4;
+
+/// How long the capture thread waits for a frame before checking whether the video has
+/// been closed, in milliseconds
+const POLL_TIMEOUT_MS: c_int = 200;
+
+// constants and structures from linux/videodev2.h
+const BUF_TYPE_VIDEO_CAPTURE: u32 = 1;
+const MEMORY_MMAP: u32 = 1;
+const FIELD_NONE: u32 = 1;
+const CAP_VIDEO_CAPTURE: u32 = 0x0000_0001;
+const CAP_STREAMING: u32 = 0x0400_0000;
+const CAP_DEVICE_CAPS: u32 = 0x8000_0000;
+
+#[repr(C)]
+struct Capability {
+    driver: [u8; 16],
+    card: [u8; 32],
+    bus_info: [u8; 32],
+    version: u32,
+    capabilities: u32,
+    device_caps: u32,
+    reserved: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PixFormat {
+    width: u32,
+    height: u32,
+    pixelformat: u32,
+    field: u32,
+    bytesperline: u32,
+    sizeimage: u32,
+    colorspace: u32,
+    private: u32,
+    flags: u32,
+    ycbcr_enc: u32,
+    quantization: u32,
+    xfer_func: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union FormatData {
+    pix: PixFormat,
+    raw_data: [u8; 200],
+    // other members of the union hold pointers, which sets its alignment
+    _align: [usize; 0],
+}
+
+#[repr(C)]
+struct Format {
+    buffer_type: u32,
+    fmt: FormatData,
+}
+
+#[repr(C)]
+struct RequestBuffers {
+    count: u32,
+    buffer_type: u32,
+    memory: u32,
+    capabilities: u32,
+    flags: u8,
+    reserved: [u8; 3],
+}
+
+#[repr(C)]
+struct Timecode {
+    timecode_type: u32,
+    flags: u32,
+    frames: u8,
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    userbits: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union BufferLocation {
+    offset: u32,
+    userptr: c_ulong,
+    fd: i32,
+}
+
+#[repr(C)]
+struct Buffer {
+    index: u32,
+    buffer_type: u32,
+    bytesused: u32,
+    flags: u32,
+    field: u32,
+    timestamp: libc::timeval,
+    timecode: Timecode,
+    sequence: u32,
+    memory: u32,
+    m: BufferLocation,
+    length: u32,
+    reserved2: u32,
+    request_fd: i32,
+}
+
+/// Request number of an ioctl, like the `_IOC` macro
+const fn ioc(read: bool, write: bool, number: u32, size: usize) -> c_ulong {
+    let direction = (read as u32) << 1 | write as u32;
+    (direction << 30 | (size as u32) << 16 | (b'V' as u32) << 8 | number) as c_ulong
+}
+
+const VIDIOC_QUERYCAP: c_ulong = ioc(true, false, 0, mem::size_of::<Capability>());
+const VIDIOC_S_FMT: c_ulong = ioc(true, true, 5, mem::size_of::<Format>());
+const VIDIOC_G_FMT: c_ulong = ioc(true, true, 4, mem::size_of::<Format>());
+const VIDIOC_REQBUFS: c_ulong = ioc(true, true, 8, mem::size_of::<RequestBuffers>());
+const VIDIOC_QUERYBUF: c_ulong = ioc(true, true, 9, mem::size_of::<Buffer>());
+const VIDIOC_QBUF: c_ulong = ioc(true, true, 15, mem::size_of::<Buffer>());
+const VIDIOC_DQBUF: c_ulong = ioc(true, true, 17, mem::size_of::<Buffer>());
+const VIDIOC_STREAMON: c_ulong = ioc(false, true, 18, mem::size_of::<c_int>());
+const VIDIOC_STREAMOFF: c_ulong = ioc(false, true, 19, mem::size_of::<c_int>());
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    code[0] as u32 | (code[1] as u32) << 8 | (code[2] as u32) << 16 | (code[3] as u32) << 24
+}
+
+/// How the samples of a pixel format are stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    /// One sample per pixel of the given Bayer pattern or mono, 1 or 2 bytes each
+    Raw(Bayer, u32),
+    /// Packed 4:2:2 YUV, which is converted to 8-bit RGB
+    Yuyv,
+}
+
+/// Pixel formats in order of preference: raw samples at the highest depth first, so that
+/// the camera does not process them
+const PIXEL_FORMATS: &[(&[u8; 4], Layout)] = &[
+    (b"RG16", Layout::Raw(Bayer::RGGB, 16)),
+    (b"BYR2", Layout::Raw(Bayer::BGGR, 16)),
+    (b"GB16", Layout::Raw(Bayer::GBRG, 16)),
+    (b"GR16", Layout::Raw(Bayer::GRBG, 16)),
+    (b"Y16 ", Layout::Raw(Bayer::Mono, 16)),
+    (b"RGGB", Layout::Raw(Bayer::RGGB, 8)),
+    (b"BA81", Layout::Raw(Bayer::BGGR, 8)),
+    (b"GBRG", Layout::Raw(Bayer::GBRG, 8)),
+    (b"GRBG", Layout::Raw(Bayer::GRBG, 8)),
+    (b"GREY", Layout::Raw(Bayer::Mono, 8)),
+    (b"YUYV", Layout::Yuyv),
+];
+
+/// A Video4Linux device that is streaming into memory-mapped buffers. The buffers are
+/// unmapped and the device is closed when it is dropped.
+struct Device {
+    fd: c_int,
+    buffers: Vec<(*mut c_void, usize)>,
+    streaming: bool,
+}
+
+// the mapped buffers are only accessed by the thread that owns the device
+unsafe impl Send for Device {}
+
+impl Device {
+    fn open(path: &str) -> Result<Self> {
+        let name = CString::new(path)
+            .map_err(|_| PlayerError::InvalidArgument(format!("Invalid device {}", path)))?;
+        let fd = unsafe { libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            fd,
+            buffers: vec![],
+            streaming: false,
+        })
+    }
+
+    fn ioctl<T>(&self, request: c_ulong, arg: &mut T) -> io::Result<()> {
+        loop {
+            if unsafe { libc::ioctl(self.fd, request as _, arg as *mut T) } != -1 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    fn query_capabilities(&self, path: &str) -> Result<Capability> {
+        let mut capability: Capability = unsafe { mem::zeroed() };
+        self.ioctl(VIDIOC_QUERYCAP, &mut capability).map_err(|e| {
+            PlayerError::Unsupported(format!("{} is not a Video4Linux device: {}", path, e))
+        })?;
+        let caps = if capability.capabilities & CAP_DEVICE_CAPS != 0 {
+            capability.device_caps
+        } else {
+            capability.capabilities
+        };
+        if caps & CAP_VIDEO_CAPTURE == 0 || caps & CAP_STREAMING == 0 {
+            return Err(PlayerError::Unsupported(format!(
+                "{} cannot stream video captures",
+                path
+            )));
+        }
+        Ok(capability)
+    }
+
+    /// Choose the first pixel format of `PIXEL_FORMATS` that the device accepts, keeping the
+    /// current frame size
+    fn negotiate_format(&self) -> Result<(PixFormat, Layout)> {
+        let mut format: Format = unsafe { mem::zeroed() };
+        format.buffer_type = BUF_TYPE_VIDEO_CAPTURE;
+        self.ioctl(VIDIOC_G_FMT, &mut format)?;
+        let current = unsafe { format.fmt.pix };
+        for (code, layout) in PIXEL_FORMATS {
+            let mut format: Format = unsafe { mem::zeroed() };
+            format.buffer_type = BUF_TYPE_VIDEO_CAPTURE;
+            format.fmt.pix = PixFormat {
+                pixelformat: fourcc(code),
+                field: FIELD_NONE,
+                ..current
+            };
+            // drivers replace pixel formats that they do not support with one that they do
+            if self.ioctl(VIDIOC_S_FMT, &mut format).is_ok() {
+                let pix = unsafe { format.fmt.pix };
+                if pix.pixelformat == fourcc(code) {
+                    return Ok((pix, *layout));
+                }
+            }
+        }
+        Err(PlayerError::Unsupported(format!(
+            "The camera does not offer a raw, grayscale or YUYV format (it offers {})",
+            fourcc_name(current.pixelformat)
+        )))
+    }
+
+    /// Map the driver's buffers, queue them and start streaming
+    fn start(&mut self) -> Result<()> {
+        let mut request = RequestBuffers {
+            count: BUFFER_COUNT,
+            buffer_type: BUF_TYPE_VIDEO_CAPTURE,
+            memory: MEMORY_MMAP,
+            capabilities: 0,
+            flags: 0,
+            reserved: [0; 3],
+        };
+        self.ioctl(VIDIOC_REQBUFS, &mut request)?;
+        for index in 0..request.count {
+            let mut buffer = new_buffer(index);
+            self.ioctl(VIDIOC_QUERYBUF, &mut buffer)?;
+            let length = buffer.length as usize;
+            let address = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    length,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    self.fd,
+                    buffer.m.offset as libc::off_t,
+                )
+            };
+            if address == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error().into());
+            }
+            self.buffers.push((address, length));
+            self.ioctl(VIDIOC_QBUF, &mut buffer)?;
+        }
+        let mut buffer_type = BUF_TYPE_VIDEO_CAPTURE as c_int;
+        self.ioctl(VIDIOC_STREAMON, &mut buffer_type)?;
+        self.streaming = true;
+        Ok(())
+    }
+
+    /// Wait for the next frame and call `f` with its bytes, returning `Ok(false)` if no frame
+    /// arrived before the timeout
+    fn next_frame(&self, f: impl FnOnce(&[u8])) -> io::Result<bool> {
+        let mut poll = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut poll, 1, POLL_TIMEOUT_MS) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                return match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(e),
+                };
+            }
+            0 => return Ok(false),
+            _ => {}
+        }
+        let mut buffer = new_buffer(0);
+        match self.ioctl(VIDIOC_DQBUF, &mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if let Some(&(address, length)) = self.buffers.get(buffer.index as usize) {
+            let used = (buffer.bytesused as usize).min(length);
+            f(unsafe { std::slice::from_raw_parts(address as *const u8, used) });
+        }
+        self.ioctl(VIDIOC_QBUF, &mut buffer)?;
+        Ok(true)
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        if self.streaming {
+            let mut buffer_type = BUF_TYPE_VIDEO_CAPTURE as c_int;
+            let _ = self.ioctl(VIDIOC_STREAMOFF, &mut buffer_type);
+        }
+        for &(address, length) in &self.buffers {
+            unsafe { libc::munmap(address, length) };
+        }
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn new_buffer(index: u32) -> Buffer {
+    let mut buffer: Buffer = unsafe { mem::zeroed() };
+    buffer.index = index;
+    buffer.buffer_type = BUF_TYPE_VIDEO_CAPTURE;
+    buffer.memory = MEMORY_MMAP;
+    buffer
+}
+
+fn fourcc_name(code: u32) -> String {
+    code.to_le_bytes()
+        .iter()
+        .map(|&byte| byte as char)
+        .collect()
+}
+
+/// NUL-terminated string from a capability field
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Copy the rows of a frame, which may be padded to `bytes_per_line`, into the layout of
+/// `Video` frames
+fn convert_frame(bytes: &[u8], pix: &PixFormat, layout: Layout) -> Vec<u8> {
+    let (width, height) = (pix.width as usize, pix.height as usize);
+    let stride = pix.bytesperline as usize;
+    let rows = bytes.chunks(stride.max(1)).take(height);
+    match layout {
+        Layout::Raw(_, bits) => {
+            let row_size = width * if bits > 8 { 2 } else { 1 };
+            let mut frame = Vec::with_capacity(row_size * height);
+            for row in rows {
+                frame.extend_from_slice(&row[..row_size.min(row.len())]);
+            }
+            frame.resize(row_size * height, 0);
+            frame
+        }
+        Layout::Yuyv => {
+            let mut frame = Vec::with_capacity(width * height * 3);
+            for row in rows {
+                for pair in row[..(width * 2).min(row.len())].chunks_exact(4) {
+                    frame.extend_from_slice(&yuv_to_rgb(pair[0], pair[1], pair[3]));
+                    frame.extend_from_slice(&yuv_to_rgb(pair[2], pair[1], pair[3]));
+                }
+            }
+            frame.resize(width * height * 3, 0);
+            frame
+        }
+    }
+}
+
+/// Convert a BT.601 limited range YUV sample to RGB
+fn yuv_to_rgb(luma: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let luma = (luma as i32 - 16) * 298;
+    let cb = cb as i32 - 128;
+    let cr = cr as i32 - 128;
+    let clamp = |value: i32| ((value + 128) >> 8).clamp(0, 255) as u8;
+    [
+        clamp(luma + 409 * cr),
+        clamp(luma - 100 * cb - 208 * cr),
+        clamp(luma + 516 * cb),
+    ]
+}
+
+/// Live stream from a Video4Linux camera, such as `/dev/video0`. Frames are captured on a
+/// background thread until the video is dropped, and only the most recent frames are kept.
+pub struct V4l2Video {
+    live: LiveVideo,
+}
+
+impl V4l2Video {
+    /// Open a camera and start streaming from it
+    pub fn open(path: &str) -> Result<Self> {
+        let mut device = Device::open(path)?;
+        let capability = device.query_capabilities(path)?;
+        let (pix, layout) = device.negotiate_format()?;
+        let format = SerFormat {
+            width: pix.width,
+            height: pix.height,
+            bayer: match layout {
+                Layout::Raw(bayer, _) => bayer,
+                Layout::Yuyv => Bayer::RGB,
+            },
+            pixel_depth_bits: match layout {
+                Layout::Raw(_, bits) => bits,
+                Layout::Yuyv => 8,
+            },
+            // 16-bit Video4Linux formats are little endian
+            endianness: Endianness::LittleEndian,
+        };
+        device.start()?;
+        let card = c_string(&capability.card);
+        info!(
+            device = path,
+            camera = card.as_str(),
+            width = pix.width,
+            height = pix.height,
+            format = fourcc_name(pix.pixelformat).as_str(),
+            "streaming from camera"
+        );
+        let (live, feed) = live_video(format, DEFAULT_LIVE_MEMORY);
+        let live = live.with_header_fields(vec![
+            ("Camera".to_string(), card),
+            ("Driver".to_string(), c_string(&capability.driver)),
+            ("Bus".to_string(), c_string(&capability.bus_info)),
+            ("Pixel format".to_string(), fourcc_name(pix.pixelformat)),
+        ]);
+        thread::Builder::new()
+            .name("v4l2".to_string())
+            .spawn(move || capture(device, pix, layout, feed))?;
+        Ok(Self { live })
+    }
+}
+
+/// Capture frames into `feed` until the video is closed or the device fails
+fn capture(device: Device, pix: PixFormat, layout: Layout, feed: LiveFeed) {
+    while !feed.is_closed() {
+        let mut result = Ok(());
+        let received = device.next_frame(|bytes| {
+            let frame = convert_frame(bytes, &pix, layout);
+            result = feed.push_frame(frame, Some(SystemTime::now()));
+        });
+        match received {
+            Ok(_) => {
+                if let Err(e) = result {
+                    warn!("dropped a frame from the camera: {}", e);
+                }
+            }
+            Err(e) => {
+                warn!("stopped streaming from the camera: {}", e);
+                return;
+            }
+        }
+    }
+    debug!("closed the camera");
+}
+
+impl Video for V4l2Video {
+    fn image_width(&self) -> u32 {
+        self.live.image_width()
+    }
+
+    fn image_height(&self) -> u32 {
+        self.live.image_height()
+    }
+
+    fn frame_count(&self) -> usize {
+        self.live.frame_count()
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        self.live.bytes_per_pixel()
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.live.pixel_depth_bits()
+    }
+
+    fn bayer(&self) -> &Bayer {
+        self.live.bayer()
+    }
+
+    fn endianness(&self) -> &Endianness {
+        self.live.endianness()
+    }
+
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        self.live.read_frame(index)
+    }
+
+    fn frame_timestamp(&self, index: usize) -> Option<SystemTime> {
+        self.live.frame_timestamp(index)
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.live.header_fields()
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn frame_watch(&self) -> Option<FrameWatch> {
+        self.live.frame_watch()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_struct_sizes() {
+        // sizes from linux/videodev2.h, which are also encoded in the ioctl numbers
+        assert_eq!(104, mem::size_of::<Capability>());
+        assert_eq!(208, mem::size_of::<Format>());
+        assert_eq!(20, mem::size_of::<RequestBuffers>());
+        assert_eq!(88, mem::size_of::<Buffer>());
+        assert_eq!(0xc0d0_5605, VIDIOC_S_FMT);
+        assert_eq!(0xc058_5611, VIDIOC_DQBUF);
+        assert_eq!(0x4004_5612, VIDIOC_STREAMON);
+    }
+
+    #[test]
+    fn test_convert_frame() {
+        let pix = PixFormat {
+            width: 2,
+            height: 2,
+            bytesperline: 6,
+            ..unsafe { mem::zeroed() }
+        };
+        let padded = [1, 2, 3, 4, 0, 0, 5, 6, 7, 8, 0, 0];
+        assert_eq!(
+            vec![1, 2, 5, 6],
+            convert_frame(&padded, &pix, Layout::Raw(Bayer::RGGB, 8))
+        );
+        assert_eq!(
+            vec![1, 2, 3, 4, 5, 6, 7, 8],
+            convert_frame(&padded, &pix, Layout::Raw(Bayer::Mono, 16))
+        );
+        // white and black pixels in YUYV
+        let yuyv = [235, 128, 16, 128, 235, 128, 16, 128];
+        let rgb = convert_frame(
+            &yuyv,
+            &PixFormat {
+                bytesperline: 4,
+                ..pix
+            },
+            Layout::Yuyv,
+        );
+        assert_eq!(vec![255, 255, 255, 0, 0, 0, 255, 255, 255, 0, 0, 0], rgb);
+        assert_eq!("YUYV", fourcc_name(fourcc(b"YUYV")));
+    }
+}
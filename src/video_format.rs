@@ -20,26 +20,1190 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::io::Result;
+use std::borrow::Cow;
+#[cfg(not(target_arch = "wasm32"))]
+use std::convert::TryFrom;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::avi::AviFile;
-use ser_io::{Bayer, Endianness, SerFile};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+#[cfg(not(target_arch = "wasm32"))]
+use tracing::{info, instrument};
 
-pub trait Video {
+#[cfg(not(target_arch = "wasm32"))]
+use crate::analysis::detect_bayer;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::avi::{AviFile, ColorCoding};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::directory::DirectoryVideo;
+use crate::error::{PlayerError, Result};
+use crate::fits::FitsHeader;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::follow::FollowedSerVideo;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::live;
+use crate::live::FrameWatch;
+use crate::locale::Locale;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::probe::{format_from_extension, probe, Probe, SIGNATURE_SIZE};
+use crate::ser::SerFormat;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::ser::{self, SerHeader, HEADER_SIZE};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::stream::{FrameExtent, FrameReader};
+#[cfg(not(target_arch = "wasm32"))]
+use ser_io::SerFile;
+
+/// Color filter pattern of raw frames, or the channel order of frames that are already in
+/// color. The values match the SER color IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bayer {
+    Mono,
+    RGGB,
+    GRBG,
+    GBRG,
+    BGGR,
+    CYYM,
+    YCMY,
+    YMCY,
+    MYYC,
+    RGB,
+    BGR,
+    Unknown(u32),
+}
+
+impl Bayer {
+    pub fn from_color_id(color_id: u32) -> Self {
+        match color_id {
+            0 => Bayer::Mono,
+            8 => Bayer::RGGB,
+            9 => Bayer::GRBG,
+            10 => Bayer::GBRG,
+            11 => Bayer::BGGR,
+            16 => Bayer::CYYM,
+            17 => Bayer::YCMY,
+            18 => Bayer::YMCY,
+            19 => Bayer::MYYC,
+            100 => Bayer::RGB,
+            101 => Bayer::BGR,
+            other => Bayer::Unknown(other),
+        }
+    }
+
+    /// Number of samples in each pixel, which is 3 for frames that are already in color and
+    /// 1 for mono frames and color mosaics
+    pub fn planes(&self) -> usize {
+        match self {
+            Bayer::RGB | Bayer::BGR => 3,
+            _ => 1,
+        }
+    }
+
+    pub fn color_id(&self) -> u32 {
+        match self {
+            Bayer::Mono => 0,
+            Bayer::RGGB => 8,
+            Bayer::GRBG => 9,
+            Bayer::GBRG => 10,
+            Bayer::BGGR => 11,
+            Bayer::CYYM => 16,
+            Bayer::YCMY => 17,
+            Bayer::YMCY => 18,
+            Bayer::MYYC => 19,
+            Bayer::RGB => 100,
+            Bayer::BGR => 101,
+            Bayer::Unknown(id) => *id,
+        }
+    }
+}
+
+/// Byte order of samples that are wider than 8 bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    LittleEndian,
+    BigEndian,
+}
+
+/// Byte order to read samples with instead of the one in the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndiannessOverride {
+    /// Judge the byte order from the samples, see `detect_endianness`
+    Auto,
+    Fixed(Endianness),
+}
+
+/// How the frames of a video are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Raw samples
+    None,
+    /// Each frame is a JPEG image
+    Mjpeg,
+}
+
+/// How much validation to apply to the structure of a file when it is opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenMode {
+    /// Read as much as possible from damaged files, such as captures that were interrupted
+    #[default]
+    Lenient,
+    /// Reject files with out-of-bounds offsets, overlapping chunks, truncated frames or
+    /// unexpected trailing data
+    Strict,
+}
+
+/// How the frames of a SER file are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadStrategy {
+    /// Memory-map the file and borrow frames from the mapping
+    #[default]
+    Mmap,
+    /// Seek to each frame and read it into a new buffer, so that memory use does not grow
+    /// with the number of frames that have been viewed. See `stream.rs`.
+    Read,
+}
+
+/// Names of the read strategies, as accepted by `parse_read_strategy`
+pub const READ_STRATEGY_NAMES: &[&str] = &["mmap", "read"];
+
+pub fn parse_read_strategy(name: &str) -> Result<ReadStrategy> {
+    match name.to_ascii_lowercase().as_str() {
+        "mmap" => Ok(ReadStrategy::Mmap),
+        "read" => Ok(ReadStrategy::Read),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown read strategy '{}', expected one of {}",
+            name,
+            READ_STRATEGY_NAMES.join(", ")
+        ))),
+    }
+}
+
+/// Options for opening a video
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    pub overrides: FormatOverrides,
+    pub mode: OpenMode,
+    pub read: ReadStrategy,
+    /// Follow SER files that are still being captured, see `FollowedSerVideo`. Other formats
+    /// are opened as they are.
+    pub follow: bool,
+}
+
+/// Container formats that can be opened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoFormat {
+    Ser,
+    Avi,
+    Fits,
+    /// A still TIFF or PNG image, which is opened as a sequence of one frame
+    Image,
+}
+
+/// Determine the format of a file from the signature at the start of the file, so that files
+/// open whatever their extension is. The extension is only used for files with a signature
+/// that is not recognized.
+#[instrument]
+#[cfg(not(target_arch = "wasm32"))]
+pub fn detect_format(filename: &str) -> Result<VideoFormat> {
+    let mut header = Vec::with_capacity(SIGNATURE_SIZE);
+    File::open(filename)?
+        .take(SIGNATURE_SIZE as u64)
+        .read_to_end(&mut header)?;
+    let probe = probe(&header);
+    debug!(?probe, "detected file format");
+    match probe {
+        Probe::Supported(format) => Ok(format),
+        Probe::Unsupported { name, advice } => Err(PlayerError::Unsupported(format!(
+            "{} is {}, which is an unsupported container. {}",
+            filename, name, advice
+        ))),
+        Probe::Unknown => match format_from_extension(std::path::Path::new(filename)) {
+            Some(format) => {
+                debug!(
+                    ?format,
+                    "unrecognized file signature, using the file extension"
+                );
+                Ok(format)
+            }
+            None => Err(PlayerError::Unsupported(format!(
+                "{} is not a SER, AVI, FITS, TIFF or PNG file (unrecognized file signature)",
+                filename
+            ))),
+        },
+    }
+}
+
+/// Open a SER, AVI or FITS file, a TIFF or PNG image, or a directory of images, choosing the
+/// format based on the file signature rather than the file extension
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_video(filename: &str) -> Result<Box<dyn Video>> {
+    open_video_with(filename, &OpenOptions::default())
+}
+
+/// Open a video, validating its structure according to `options.mode`, reading SER frames
+/// according to `options.read` and replacing header values with any that are given in
+/// `options.overrides`. A directory is opened as an image sequence, see `DirectoryVideo`, and
+/// a camera such as `/dev/video0` or `indi://host/device` as a live video, see `V4l2Video` and
+/// `IndiVideo`.
+#[instrument(skip(options))]
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_video_with(filename: &str, options: &OpenOptions) -> Result<Box<dyn Video>> {
+    if live::is_camera_device(filename) {
+        return open_camera(filename, options);
+    }
+    let format = if std::path::Path::new(filename).is_dir() {
+        None
+    } else {
+        Some(detect_format(filename)?)
+    };
+    let video: Box<dyn Video> = match format {
+        None => Box::new(DirectoryVideo::open(filename)?),
+        Some(VideoFormat::Ser) if options.follow => {
+            Box::new(FollowedSerVideo::open(std::path::Path::new(filename))?)
+        }
+        Some(VideoFormat::Ser) => {
+            let video = SerVideo::open(filename, options.mode)?;
+            match options.read {
+                ReadStrategy::Mmap => Box::new(video),
+                ReadStrategy::Read => {
+                    let reader = FrameReader::for_ser(filename, &video.ser)?;
+                    Box::new(video.with_reader(reader))
+                }
+            }
+        }
+        Some(VideoFormat::Avi) => Box::new(AviVideo {
+            avi: AviFile::open_with_mode(filename, options.mode)?,
+        }),
+        Some(VideoFormat::Fits) => Box::new(FitsVideo::open(filename, options.mode)?),
+        Some(VideoFormat::Image) => Box::new(DirectoryVideo::from_files(vec![filename.into()])?),
+    };
+    // use the most likely Bayer pattern for frames that do not record one, unless the
+    // pattern is overridden anyway
+    let detected = if video.declares_bayer() || options.overrides.bayer.is_some() {
+        None
+    } else {
+        detect_bayer(video.as_ref()).unwrap_or(None)
+    };
+    let video = match detected {
+        Some(bayer) => {
+            info!(
+                "{} does not record its Bayer pattern, using the detected {:?}",
+                filename, bayer
+            );
+            let overrides = FormatOverrides {
+                bayer: Some(bayer),
+                ..FormatOverrides::default()
+            };
+            Box::new(OverrideVideo::new(Arc::from(video), overrides))
+        }
+        _ => video,
+    };
+    if options.overrides.is_empty() {
+        Ok(video)
+    } else {
+        Ok(Box::new(OverrideVideo::new(
+            Arc::from(video),
+            options.overrides.clone(),
+        )))
+    }
+}
+
+/// Start streaming from a camera. Frames from cameras always record their color encoding, so
+/// only the header overrides are applied.
+#[cfg(not(target_arch = "wasm32"))]
+fn open_camera(path: &str, options: &OpenOptions) -> Result<Box<dyn Video>> {
+    let video = if live::is_indi_url(path) {
+        open_indi(path)?
+    } else {
+        open_v4l2(path)?
+    };
+    if options.overrides.is_empty() {
+        Ok(video)
+    } else {
+        Ok(Box::new(OverrideVideo::new(
+            Arc::from(video),
+            options.overrides.clone(),
+        )))
+    }
+}
+
+#[cfg(all(feature = "live", target_os = "linux"))]
+fn open_v4l2(path: &str) -> Result<Box<dyn Video>> {
+    Ok(Box::new(crate::v4l2::V4l2Video::open(path)?))
+}
+
+#[cfg(all(
+    not(all(feature = "live", target_os = "linux")),
+    not(target_arch = "wasm32")
+))]
+fn open_v4l2(path: &str) -> Result<Box<dyn Video>> {
+    crate::capabilities::Capability::LiveCamera.require()?;
+    Err(PlayerError::Unsupported(format!(
+        "{} cannot be opened",
+        path
+    )))
+}
+
+#[cfg(all(feature = "indi", not(target_arch = "wasm32")))]
+fn open_indi(url: &str) -> Result<Box<dyn Video>> {
+    Ok(Box::new(crate::indi::IndiVideo::open(url)?))
+}
+
+#[cfg(all(not(feature = "indi"), not(target_arch = "wasm32")))]
+fn open_indi(url: &str) -> Result<Box<dyn Video>> {
+    crate::capabilities::Capability::IndiCamera.require()?;
+    Err(PlayerError::Unsupported(format!(
+        "{} cannot be opened",
+        url
+    )))
+}
+
+/// A sequence of raw image frames along with the metadata needed to decode them. Frames are
+/// decoded into images with an `ImageCodec`.
+pub trait Video: Send + Sync {
+    /// Width of each frame in pixels
     fn image_width(&self) -> u32;
+    /// Height of each frame in pixels
     fn image_height(&self) -> u32;
+    /// Number of frames, which grows while the video is shown for videos that return a
+    /// `frame_watch`
     fn frame_count(&self) -> usize;
+    /// Number of bytes used to store each sample, which is 1 or 2
     fn bytes_per_pixel(&self) -> u8;
+    /// Number of significant bits in each sample, such as 12 for a 12-bit camera that stores
+    /// samples in 2 bytes
     fn pixel_depth_bits(&self) -> u32;
+    /// Color filter pattern of raw frames, or the channel order of color frames
     fn bayer(&self) -> &Bayer;
+    /// Byte order of 2-byte samples
     fn endianness(&self) -> &Endianness;
-    fn get_frame(&self, index: usize) -> Result<&[u8]>;
+    /// Raw bytes of a frame, without any decoding, borrowed from a file that is memory-mapped
+    /// or held in memory. Videos that read or assemble frames on demand, such as SER files
+    /// opened with `ReadStrategy::Read` and live cameras, return an error, so use
+    /// `read_frame` for frames of any video.
+    fn get_frame(&self, index: usize) -> Result<&[u8]> {
+        Err(PlayerError::Unsupported(format!(
+            "Frame {} is not held in memory and must be read with read_frame",
+            index
+        )))
+    }
+
+    /// Raw bytes of a frame, without any decoding. Frames are borrowed with `get_frame` when
+    /// the video holds them in memory, and are otherwise read into a new buffer. Videos must
+    /// implement either this or `get_frame`.
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        self.get_frame(index).map(Cow::Borrowed)
+    }
+
+    /// Bytes `range` of a frame, for processing a frame in bands without reading all of it
+    /// each time. Videos that read frames from disk only read the requested bytes.
+    fn read_frame_range(&self, index: usize, range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+        let frame = self.read_frame(index)?;
+        if range.start > range.end || range.end > frame.len() {
+            return Err(PlayerError::Decode(format!(
+                "Bytes {:?} are outside frame {}, which has {} bytes",
+                range,
+                index,
+                frame.len()
+            )));
+        }
+        Ok(match frame {
+            Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[range]),
+            Cow::Owned(bytes) => Cow::Owned(bytes[range].to_vec()),
+        })
+    }
+
+    /// Sample values of a frame in native precision, taking the bytes per sample and the
+    /// endianness into account, for analysis such as stacking and photometry. Rows are in
+    /// top-down order, even for videos that store frames bottom-up. Values are not scaled, so
+    /// a 12-bit camera may produce values up to 4095. Only uncompressed frames have raw
+    /// samples.
+    fn get_frame_u16(&self, index: usize) -> Result<Cow<'_, [u16]>> {
+        check_uncompressed(self)?;
+        let frame = self.read_frame(index)?;
+        Ok(Cow::Owned(
+            samples(self, &top_down_frame(self, &frame)).collect(),
+        ))
+    }
+
+    /// Frame rate of the capture in frames per second, if it is recorded in the file
+    fn frame_rate(&self) -> Option<f64> {
+        None
+    }
+
+    /// UTC time at which a frame was captured, if it is recorded in the file
+    fn frame_timestamp(&self, _index: usize) -> Option<SystemTime> {
+        None
+    }
+
+    /// How frames are stored. Raw samples can only be read from uncompressed frames; other
+    /// frames must be decoded by a codec for their compression.
+    fn compression(&self) -> Compression {
+        Compression::None
+    }
+
+    /// Whether the rows of each frame are stored from the bottom of the image to the top, as
+    /// in bottom-up DIBs. Codecs flip such frames so that they are shown the right way up,
+    /// and the Bayer pattern describes the flipped image.
+    fn bottom_up(&self) -> bool {
+        false
+    }
+
+    /// Values from the file header that are specific to the container format, such as the
+    /// observer recorded in a SER file, as `(name, value)` pairs
+    fn header_fields(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+
+    /// Whether the file records the color encoding returned by `bayer`. Raw AVI frames do not,
+    /// so they may be monochrome or use any Bayer pattern.
+    fn declares_bayer(&self) -> bool {
+        true
+    }
+
+    /// Whether the video is the stream from a camera, which only keeps its most recent frames
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    /// Handle for waiting for new frames if frames are still being added to the video, such as
+    /// the stream from a camera or a file that is still being captured, or `None` if all of
+    /// the frames are known when the video is opened
+    fn frame_watch(&self) -> Option<FrameWatch> {
+        None
+    }
+}
+
+/// Everything that is known about a video, as `(name, value)` pairs: the image format
+/// followed by the format-specific `header_fields`
+pub fn video_info(video: &dyn Video) -> Vec<(String, String)> {
+    localized_video_info(video, &Locale::english())
+}
+
+/// `video_info` with the names and values in the language of `locale`
+pub fn localized_video_info(video: &dyn Video, locale: &Locale) -> Vec<(String, String)> {
+    let mut info = vec![
+        (locale.text("info-width"), video.image_width().to_string()),
+        (locale.text("info-height"), video.image_height().to_string()),
+        (locale.text("info-frames"), video.frame_count().to_string()),
+        (
+            locale.text("info-bytes-per-pixel"),
+            video.bytes_per_pixel().to_string(),
+        ),
+        (
+            locale.text("info-pixel-depth"),
+            locale.format("info-bits", &[("bits", &video.pixel_depth_bits())]),
+        ),
+        (locale.text("info-bayer"), format!("{:?}", video.bayer())),
+        (
+            locale.text("info-endianness"),
+            format!("{:?}", video.endianness()),
+        ),
+    ];
+    if video.compression() != Compression::None {
+        info.push((
+            locale.text("info-compression"),
+            format!("{:?}", video.compression()),
+        ));
+    }
+    if let Some(fps) = video.frame_rate() {
+        info.push((
+            locale.text("info-frame-rate"),
+            locale.format("fps", &[("fps", &format!("{:.2}", fps))]),
+        ));
+    }
+    if let Some(time) = video.frame_timestamp(0) {
+        info.push((locale.text("info-capture-start"), format_utc(time)));
+    }
+    info.extend(
+        video
+            .header_fields()
+            .into_iter()
+            .map(|(name, value)| (locale.field_name(&name), value)),
+    );
+    info
+}
+
+/// A raw frame borrowed from a video
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub bytes_per_pixel: u8,
+    pub bytes: &'a [u8],
+}
+
+impl<'v> dyn Video + 'v {
+    /// Call `f` with each frame in order, stopping at the first error. Frames that are read
+    /// from disk are only held in memory while `f` runs.
+    pub fn for_each_frame(&self, mut f: impl FnMut(usize, Frame<'_>) -> Result<()>) -> Result<()> {
+        for index in 0..self.frame_count() {
+            let bytes = self.read_frame(index)?;
+            f(index, self.frame(&bytes))?;
+        }
+        Ok(())
+    }
+
+    /// Call `f` with every frame in parallel, returning the results in frame order
+    pub fn par_map_frames<T, F>(&self, f: F) -> Result<Vec<T>>
+    where
+        T: Send,
+        F: Fn(usize, Frame<'_>) -> T + Send + Sync,
+    {
+        (0..self.frame_count())
+            .into_par_iter()
+            .map(|index| {
+                let bytes = self.read_frame(index)?;
+                Ok(f(index, self.frame(&bytes)))
+            })
+            .collect()
+    }
+
+    fn frame<'a>(&self, bytes: &'a [u8]) -> Frame<'a> {
+        Frame {
+            width: self.image_width(),
+            height: self.image_height(),
+            bytes_per_pixel: self.bytes_per_pixel(),
+            bytes,
+        }
+    }
+}
+
+/// A single frame that has been read into memory, presented as a one-frame video
+pub(crate) struct FrameView<'a> {
+    video: &'a dyn Video,
+    bytes: &'a [u8],
+}
+
+impl<'a> FrameView<'a> {
+    pub(crate) fn new(video: &'a dyn Video, bytes: &'a [u8]) -> Self {
+        Self { video, bytes }
+    }
+}
+
+impl Video for FrameView<'_> {
+    fn image_width(&self) -> u32 {
+        self.video.image_width()
+    }
+
+    fn image_height(&self) -> u32 {
+        self.video.image_height()
+    }
+
+    fn frame_count(&self) -> usize {
+        1
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        self.video.bytes_per_pixel()
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.video.pixel_depth_bits()
+    }
+
+    fn bayer(&self) -> &Bayer {
+        self.video.bayer()
+    }
+
+    fn endianness(&self) -> &Endianness {
+        self.video.endianness()
+    }
+
+    fn get_frame(&self, _index: usize) -> Result<&[u8]> {
+        Ok(self.bytes)
+    }
+
+    fn compression(&self) -> Compression {
+        self.video.compression()
+    }
+
+    fn bottom_up(&self) -> bool {
+        self.video.bottom_up()
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.video.header_fields()
+    }
+}
+
+/// Rectangular region of the image, in pixels from the top left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// Clamp the region to the image of a video. The edges of regions of color mosaics are
+    /// moved outwards to whole repeats of the color filter pattern so that the cropped frame
+    /// has the same pattern as the video.
+    pub fn fit_to(&self, video: &dyn Video) -> Result<Region> {
+        let period = crate::align::pattern_period(video) as u32;
+        let width = video.image_width() / period * period;
+        let height = video.image_height() / period * period;
+        let round_up = |value: u32| value.div_ceil(period) * period;
+        let x = self.x.min(width) / period * period;
+        let y = self.y.min(height) / period * period;
+        let right = round_up(self.x.saturating_add(self.width)).min(width);
+        let bottom = round_up(self.y.saturating_add(self.height)).min(height);
+        if right <= x || bottom <= y {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Region {}x{}+{}+{} is outside the {}x{} image",
+                self.width,
+                self.height,
+                self.x,
+                self.y,
+                video.image_width(),
+                video.image_height()
+            )));
+        }
+        Ok(Region {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+/// A region of a single frame, presented as a one-frame video. Only the samples inside the
+/// region are copied, so decoding a small region of a large frame is much cheaper than
+/// decoding the whole frame.
+pub(crate) struct CroppedFrame<'a> {
+    video: &'a dyn Video,
+    region: Region,
+    /// Raw samples of the region, with rows in top-down order
+    bytes: Vec<u8>,
+}
+
+impl<'a> CroppedFrame<'a> {
+    pub(crate) fn new(video: &'a dyn Video, index: usize, region: Region) -> Result<Self> {
+        check_uncompressed(video)?;
+        let region = region.fit_to(video)?;
+        let frame = video.read_frame(index)?;
+        let planes = video.bayer().planes();
+        let pixel_size = video.bytes_per_pixel() as usize * planes;
+        let row_size = video.image_width() as usize * pixel_size;
+        let start = region.x as usize * pixel_size;
+        let len = region.width as usize * pixel_size;
+        let mut bytes = Vec::with_capacity(len * region.height as usize);
+        for y in region.y..region.y + region.height {
+            let offset = stored_row(video, y) as usize * row_size + start;
+            let row = frame
+                .get(offset..offset + len)
+                .ok_or_else(|| PlayerError::Format(format!("Frame {} is truncated", index)))?;
+            bytes.extend_from_slice(row);
+        }
+        Ok(Self {
+            video,
+            region,
+            bytes,
+        })
+    }
+}
+
+impl Video for CroppedFrame<'_> {
+    fn image_width(&self) -> u32 {
+        self.region.width
+    }
+
+    fn image_height(&self) -> u32 {
+        self.region.height
+    }
+
+    fn frame_count(&self) -> usize {
+        1
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        self.video.bytes_per_pixel()
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.video.pixel_depth_bits()
+    }
+
+    fn bayer(&self) -> &Bayer {
+        self.video.bayer()
+    }
+
+    fn endianness(&self) -> &Endianness {
+        self.video.endianness()
+    }
+
+    fn get_frame(&self, _index: usize) -> Result<&[u8]> {
+        Ok(&self.bytes)
+    }
+
+    fn compression(&self) -> Compression {
+        Compression::None
+    }
+
+    fn bottom_up(&self) -> bool {
+        false
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.video.header_fields()
+    }
+}
+
+/// Header values that replace the ones stored in a file, since capture software frequently
+/// writes incorrect metadata
+#[derive(Debug, Clone, Default)]
+pub struct FormatOverrides {
+    pub bayer: Option<Bayer>,
+    /// Bit depth used to scale sample values. The number of bytes per sample is still
+    /// determined by the file because it defines where each frame starts.
+    pub pixel_depth_bits: Option<u32>,
+    pub endianness: Option<EndiannessOverride>,
+}
+
+impl FormatOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.bayer.is_none() && self.pixel_depth_bits.is_none() && self.endianness.is_none()
+    }
+}
+
+/// A video with some of its header values replaced
+pub struct OverrideVideo {
+    inner: Arc<dyn Video>,
+    overrides: FormatOverrides,
+    /// Byte order from the overrides, which is detected when the video is created if it is
+    /// `EndiannessOverride::Auto`
+    endianness: Option<Endianness>,
+}
+
+impl OverrideVideo {
+    pub fn new(inner: Arc<dyn Video>, overrides: FormatOverrides) -> Self {
+        let endianness = match overrides.endianness {
+            Some(EndiannessOverride::Fixed(endianness)) => Some(endianness),
+            Some(EndiannessOverride::Auto) => {
+                let detected = detect_endianness(inner.as_ref());
+                debug!(?detected, "detected byte order");
+                detected
+            }
+            None => None,
+        };
+        Self {
+            inner,
+            overrides,
+            endianness,
+        }
+    }
+}
+
+impl Video for OverrideVideo {
+    fn image_width(&self) -> u32 {
+        self.inner.image_width()
+    }
+
+    fn image_height(&self) -> u32 {
+        self.inner.image_height()
+    }
+
+    fn frame_count(&self) -> usize {
+        self.inner.frame_count()
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        self.inner.bytes_per_pixel()
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.overrides
+            .pixel_depth_bits
+            .unwrap_or_else(|| self.inner.pixel_depth_bits())
+    }
+
+    fn bayer(&self) -> &Bayer {
+        self.overrides
+            .bayer
+            .as_ref()
+            .unwrap_or_else(|| self.inner.bayer())
+    }
+
+    fn endianness(&self) -> &Endianness {
+        self.endianness
+            .as_ref()
+            .unwrap_or_else(|| self.inner.endianness())
+    }
+
+    fn get_frame(&self, index: usize) -> Result<&[u8]> {
+        self.inner.get_frame(index)
+    }
+
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        self.inner.read_frame(index)
+    }
+
+    fn read_frame_range(&self, index: usize, range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+        self.inner.read_frame_range(index, range)
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        self.inner.frame_rate()
+    }
+
+    fn frame_timestamp(&self, index: usize) -> Option<SystemTime> {
+        self.inner.frame_timestamp(index)
+    }
+
+    fn compression(&self) -> Compression {
+        self.inner.compression()
+    }
+
+    fn bottom_up(&self) -> bool {
+        self.inner.bottom_up()
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.inner.header_fields()
+    }
+
+    fn declares_bayer(&self) -> bool {
+        self.overrides.bayer.is_some() || self.inner.declares_bayer()
+    }
+
+    fn is_live(&self) -> bool {
+        self.inner.is_live()
+    }
+
+    fn frame_watch(&self) -> Option<FrameWatch> {
+        self.inner.frame_watch()
+    }
+}
+
+/// Format a time as UTC with millisecond precision, such as `2021-09-20 03:23:01.500 UTC`
+pub fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(seconds / 86400);
+    let seconds_of_day = seconds % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03} UTC",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of a number of days since 1970-01-01, using the algorithm from
+/// http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Parse a Bayer pattern or color encoding name such as `rggb`, `mono` or `bgr`
+pub fn parse_bayer(name: &str) -> Result<Bayer> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "mono" => Bayer::Mono,
+        "rggb" => Bayer::RGGB,
+        "grbg" => Bayer::GRBG,
+        "gbrg" => Bayer::GBRG,
+        "bggr" => Bayer::BGGR,
+        "cyym" => Bayer::CYYM,
+        "ycmy" => Bayer::YCMY,
+        "ymcy" => Bayer::YMCY,
+        "myyc" => Bayer::MYYC,
+        "rgb" => Bayer::RGB,
+        "bgr" => Bayer::BGR,
+        _ => {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Unknown Bayer pattern '{}'",
+                name
+            )))
+        }
+    })
+}
+
+/// Parse a number of significant bits per sample from 1 to 16
+pub fn parse_bit_depth(text: &str) -> Result<u32> {
+    match text.parse::<u32>() {
+        Ok(bits @ 1..=16) => Ok(bits),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Bit depth '{}' is not a number from 1 to 16",
+            text
+        ))),
+    }
+}
+
+/// Parse `little` or `big`
+pub fn parse_endianness(name: &str) -> Result<Endianness> {
+    match name.to_ascii_lowercase().as_str() {
+        "little" | "le" => Ok(Endianness::LittleEndian),
+        "big" | "be" => Ok(Endianness::BigEndian),
+        _ => Err(PlayerError::InvalidArgument(format!(
+            "Unknown endianness '{}', expected little or big",
+            name
+        ))),
+    }
 }
 
+/// Parse `little`, `big` or `auto`, which judges the byte order from the samples
+pub fn parse_endianness_override(name: &str) -> Result<EndiannessOverride> {
+    if name.eq_ignore_ascii_case("auto") {
+        return Ok(EndiannessOverride::Auto);
+    }
+    parse_endianness(name)
+        .map(EndiannessOverride::Fixed)
+        .map_err(|_| {
+            PlayerError::InvalidArgument(format!(
+                "Unknown endianness '{}', expected little, big or auto",
+                name
+            ))
+        })
+}
+
+/// Byte order of the 16-bit samples of a video judged from the samples themselves, for
+/// capture software that writes the wrong endianness flag. Neighbouring samples of the same
+/// color are close together when they are read in the right order, while swapping the bytes
+/// moves the noisy low bits of each sample to the top. Returns `None` for 8-bit or compressed
+/// videos and when the samples do not decide it, such as for blank frames.
+pub fn detect_endianness(video: &dyn Video) -> Option<Endianness> {
+    if video.bytes_per_pixel() != 2
+        || video.compression() != Compression::None
+        || video.frame_count() == 0
+    {
+        return None;
+    }
+    let frame = video.read_frame(video.frame_count() / 2).ok()?;
+    // distance to the nearest sample of the same color
+    let (planes, step) = match video.bayer() {
+        Bayer::Mono => (1, 1),
+        Bayer::RGB | Bayer::BGR => (3, 3),
+        _ => (1, 2),
+    };
+    let row_len = video.image_width() as usize * planes * 2;
+    let (mut little, mut big) = (0_u64, 0_u64);
+    for row in frame
+        .chunks_exact(row_len.max(1))
+        .take(video.image_height() as usize)
+    {
+        for (a, b) in row.chunks_exact(2).zip(row.chunks_exact(2).skip(step)) {
+            let difference = |read: fn(&[u8]) -> u16| (read(a) as i64 - read(b) as i64).abs();
+            little += difference(LittleEndian::read_u16) as u64;
+            big += difference(BigEndian::read_u16) as u64;
+        }
+    }
+    if little * 2 < big {
+        Some(Endianness::LittleEndian)
+    } else if big * 2 < little {
+        Some(Endianness::BigEndian)
+    } else {
+        None
+    }
+}
+
+/// Return an error if the frames of a video are compressed, since their raw samples cannot
+/// be read without decoding them
+pub(crate) fn check_uncompressed<V: Video + ?Sized>(video: &V) -> Result<()> {
+    match video.compression() {
+        Compression::None => Ok(()),
+        other => Err(PlayerError::Unsupported(format!(
+            "Raw samples cannot be read from {:?} compressed frames",
+            other
+        ))),
+    }
+}
+
+/// Row of the stored frame that holds row `y` of the image, which differs for videos that
+/// store frames bottom-up
+pub(crate) fn stored_row(video: &dyn Video, y: u32) -> u32 {
+    if video.bottom_up() {
+        video.image_height().saturating_sub(y + 1)
+    } else {
+        y
+    }
+}
+
+/// The raw bytes of a frame with its rows in top-down order, which only copies the frame if
+/// the video stores frames bottom-up
+pub(crate) fn top_down_frame<'a, V: Video + ?Sized>(video: &V, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+    if !video.bottom_up() {
+        return Cow::Borrowed(bytes);
+    }
+    let planes = video.bayer().planes();
+    let row_size = video.image_width() as usize * video.bytes_per_pixel() as usize * planes;
+    let rows = (video.image_height() as usize).min(bytes.len() / row_size.max(1));
+    let mut flipped = Vec::with_capacity(bytes.len());
+    for row in bytes[..rows * row_size].chunks_exact(row_size.max(1)).rev() {
+        flipped.extend_from_slice(row);
+    }
+    flipped.extend_from_slice(&bytes[rows * row_size..]);
+    Cow::Owned(flipped)
+}
+
+/// Iterate over the raw sample values in a frame, taking the bit depth and endianness of
+/// the video into account
+pub(crate) fn samples<'a, V: Video + ?Sized>(
+    video: &V,
+    bytes: &'a [u8],
+) -> Box<dyn Iterator<Item = u16> + 'a> {
+    match (video.bytes_per_pixel(), video.endianness()) {
+        (2, Endianness::LittleEndian) => {
+            Box::new(bytes.chunks_exact(2).map(LittleEndian::read_u16))
+        }
+        (2, Endianness::BigEndian) => Box::new(bytes.chunks_exact(2).map(BigEndian::read_u16)),
+        _ => Box::new(bytes.iter().map(|b| *b as u16)),
+    }
+}
+
+/// Encode raw sample values using the bit depth and endianness of the video
+pub(crate) fn write_samples(video: &dyn Video, samples: &[u16], out: &mut Vec<u8>) {
+    out.clear();
+    for &value in samples {
+        match (video.bytes_per_pixel(), video.endianness()) {
+            (2, Endianness::LittleEndian) => out.extend_from_slice(&value.to_le_bytes()),
+            (2, Endianness::BigEndian) => out.extend_from_slice(&value.to_be_bytes()),
+            _ => out.push(value.min(255) as u8),
+        }
+    }
+}
+
+/// A SER file that has been memory-mapped
+#[cfg(not(target_arch = "wasm32"))]
 pub struct SerVideo {
     pub ser: SerFile,
+    bayer: Bayer,
+    endianness: Endianness,
+    /// Timestamp of each frame, or empty if the file has no timestamps
+    timestamps: Vec<u64>,
+    header_fields: Vec<(String, String)>,
+    /// Reads frames from disk instead of from the memory-mapped file
+    reader: Option<FrameReader>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl SerVideo {
+    /// Open a SER file, checking the image size, bit depth and frame count in its header
+    /// against the length of the file before the file is memory-mapped
+    pub fn open(filename: &str, mode: OpenMode) -> Result<Self> {
+        // ser-io indexes the file using header values without checking them
+        let mut file = File::open(filename)?;
+        let file_len = file.metadata()?.len();
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        (&mut file)
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut header)?;
+        let header = SerHeader::parse(&header, file_len, mode)?;
+        // ser-io does not read the timestamps from the correct offsets
+        let mut trailer = vec![];
+        file.seek(SeekFrom::Start(header.trailer_offset()))?;
+        file.take(8 * header.frame_count as u64)
+            .read_to_end(&mut trailer)?;
+        let timestamps = ser::parse_timestamps(&trailer, header.frame_count);
+        Ok(Self::new(SerFile::open(filename)?)?
+            .with_timestamps(timestamps)
+            .with_header(&header))
+    }
+
+    /// Wrap a SER file that was opened with ser-io. ser-io only checks the length of the file
+    /// against the size of one plane of each frame, so files that have not already been
+    /// checked with `SerHeader::parse` should be opened with `open` instead.
+    pub fn new(mut ser: SerFile) -> Result<Self> {
+        let bayer = match ser.bayer {
+            ser_io::Bayer::Mono => Bayer::Mono,
+            ser_io::Bayer::RGGB => Bayer::RGGB,
+            ser_io::Bayer::GRBG => Bayer::GRBG,
+            ser_io::Bayer::GBRG => Bayer::GBRG,
+            ser_io::Bayer::BGGR => Bayer::BGGR,
+            ser_io::Bayer::CYYM => Bayer::CYYM,
+            ser_io::Bayer::YCMY => Bayer::YCMY,
+            ser_io::Bayer::YMCY => Bayer::YMCY,
+            ser_io::Bayer::MYYC => Bayer::MYYC,
+            ser_io::Bayer::RGB => Bayer::RGB,
+            ser_io::Bayer::BGR => Bayer::BGR,
+            ser_io::Bayer::Unknown(id) => Bayer::Unknown(id),
+        };
+        let endianness = match ser.endianness {
+            ser_io::Endianness::LittleEndian => Endianness::LittleEndian,
+            ser_io::Endianness::BigEndian => Endianness::BigEndian,
+        };
+        let format = SerFormat {
+            width: ser.image_width,
+            height: ser.image_height,
+            bayer,
+            pixel_depth_bits: ser.pixel_depth_per_plane,
+            endianness,
+        };
+        // ser-io only counts one plane, so RGB and BGR frames would be read a third at a time
+        ser.image_frame_size = format
+            .checked_frame_size()
+            .and_then(|size| u32::try_from(size).ok())
+            .ok_or_else(|| {
+                PlayerError::Format(format!(
+                    "Invalid SER image size {}x{}",
+                    format.width, format.height
+                ))
+            })?;
+        Ok(Self {
+            ser,
+            bayer,
+            endianness,
+            timestamps: vec![],
+            header_fields: vec![],
+            reader: None,
+        })
+    }
+
+    /// Read frames with seek and read rather than from the memory-mapped file
+    pub fn with_reader(self, reader: FrameReader) -> Self {
+        Self {
+            reader: Some(reader),
+            ..self
+        }
+    }
+
+    /// Use timestamps that were read from the trailer of the file
+    pub fn with_timestamps(self, timestamps: Vec<u64>) -> Self {
+        Self { timestamps, ..self }
+    }
+
+    /// Timestamp of each frame, in 100 ns intervals since the start of year 1
+    pub fn timestamps(&self) -> &[u64] {
+        &self.timestamps
+    }
+
+    /// Use the header values that were checked when the file was opened
+    pub fn with_header(self, header: &SerHeader) -> Self {
+        Self {
+            header_fields: header.fields(),
+            ..self
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 impl Video for SerVideo {
     fn image_width(&self) -> u32 {
         self.ser.image_width
@@ -54,7 +1218,45 @@ impl Video for SerVideo {
     }
 
     fn get_frame(&self, index: usize) -> Result<&[u8]> {
-        self.ser.read_frame(index)
+        match &self.reader {
+            Some(_) => Err(PlayerError::Unsupported(format!(
+                "Frame {} is read from disk and must be read with read_frame",
+                index
+            ))),
+            None => Ok(self.ser.read_frame(index)?),
+        }
+    }
+
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        match &self.reader {
+            Some(reader) => {
+                let mut buf = vec![];
+                reader.read_frame_into(index, &mut buf)?;
+                Ok(Cow::Owned(buf))
+            }
+            None => self.get_frame(index).map(Cow::Borrowed),
+        }
+    }
+
+    fn read_frame_range(&self, index: usize, range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+        match &self.reader {
+            Some(reader) => {
+                let mut buf = vec![];
+                reader.read_range_into(index, range, &mut buf)?;
+                Ok(Cow::Owned(buf))
+            }
+            None => {
+                let frame = self.get_frame(index)?;
+                frame.get(range.clone()).map(Cow::Borrowed).ok_or_else(|| {
+                    PlayerError::Decode(format!(
+                        "Bytes {:?} are outside frame {}, which has {} bytes",
+                        range,
+                        index,
+                        frame.len()
+                    ))
+                })
+            }
+        }
     }
 
     fn bytes_per_pixel(&self) -> u8 {
@@ -65,18 +1267,62 @@ impl Video for SerVideo {
         self.ser.pixel_depth_per_plane
     }
     fn bayer(&self) -> &Bayer {
-        &self.ser.bayer
+        &self.bayer
     }
 
     fn endianness(&self) -> &Endianness {
-        &self.ser.endianness
+        &self.endianness
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        ser::frame_rate(&self.timestamps)
+    }
+
+    fn frame_timestamp(&self, index: usize) -> Option<SystemTime> {
+        self.timestamps
+            .get(index)
+            .and_then(|ticks| ser::timestamp_to_time(*ticks))
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.header_fields.clone()
     }
 }
 
+/// An AVI file that has been memory-mapped
+#[cfg(not(target_arch = "wasm32"))]
 pub struct AviVideo {
     pub avi: AviFile,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl AviVideo {
+    /// The bytes of an uncompressed frame without the padding that DIBs add to make each row
+    /// a multiple of 4 bytes, so that frames have the same layout as in other formats. Rows
+    /// are only unpadded if the frame is large enough to hold padded rows, because some
+    /// capture software does not pad them. The frame is only copied if it has padded rows.
+    fn packed_rows<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        let format = self.avi.stream_format();
+        if let ColorCoding::MJPEG = format.color_coding {
+            return Cow::Borrowed(bytes);
+        }
+        let row_size = self.image_width() as usize * format.header.bit_count as usize / 8;
+        let height = self.image_height() as usize;
+        let padded_row_size = row_size.div_ceil(4) * 4;
+        match padded_row_size.checked_mul(height) {
+            Some(size) if padded_row_size != row_size && bytes.len() >= size => {
+                let mut packed = Vec::with_capacity(row_size * height);
+                for row in bytes.chunks_exact(padded_row_size).take(height) {
+                    packed.extend_from_slice(&row[..row_size]);
+                }
+                Cow::Owned(packed)
+            }
+            _ => Cow::Borrowed(bytes),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 impl Video for AviVideo {
     fn image_width(&self) -> u32 {
         self.avi.main_header().width
@@ -87,32 +1333,632 @@ impl Video for AviVideo {
     }
 
     fn frame_count(&self) -> usize {
-        self.avi.main_header().total_frames as usize
+        // the header value is not trusted because it is wrong for interrupted captures
+        self.avi.frames().len()
     }
 
     fn bytes_per_pixel(&self) -> u8 {
-        //TODO
-        1
+        match self.avi.stream_format().color_coding {
+            ColorCoding::Y16 { .. } => 2,
+            _ => 1,
+        }
     }
 
     fn pixel_depth_bits(&self) -> u32 {
-        //TODO
-        8
-        //self.avi.stream_format().header.bit_count as u32
+        match self.avi.stream_format().color_coding {
+            ColorCoding::Y16 { significant_bits } => significant_bits,
+            _ => 8,
+        }
     }
 
     fn bayer(&self) -> &Bayer {
-        //TODO
-        &Bayer::BGR
+        match self.avi.stream_format().color_coding {
+            // AVI files do not record the Bayer pattern of raw frames, so it must be chosen
+            // with the format overrides
+            ColorCoding::Y8 | ColorCoding::Y16 { .. } => &Bayer::Mono,
+            _ => &Bayer::BGR,
+        }
+    }
+
+    fn declares_bayer(&self) -> bool {
+        !matches!(
+            self.avi.stream_format().color_coding,
+            ColorCoding::Y8 | ColorCoding::Y16 { .. }
+        )
     }
 
     fn endianness(&self) -> &Endianness {
-        //TODO
+        // RIFF files are always little endian
         &Endianness::LittleEndian
     }
 
     fn get_frame(&self, index: usize) -> Result<&[u8]> {
-        let frame_meta = &self.avi.frames()[index];
-        Ok(self.avi.read_bytes(frame_meta))
+        match self.read_frame(index)? {
+            Cow::Borrowed(bytes) => Ok(bytes),
+            Cow::Owned(_) => Err(PlayerError::Unsupported(format!(
+                "Frame {} has padded rows and must be read with read_frame",
+                index
+            ))),
+        }
+    }
+
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        let frame_meta = self
+            .avi
+            .frames()
+            .get(index)
+            .ok_or_else(|| PlayerError::Decode(format!("Invalid frame index {}", index)))?;
+        Ok(self.packed_rows(self.avi.read_bytes(frame_meta)))
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        match self.avi.main_header().micro_sec_per_frame {
+            0 => None,
+            micros => Some(1_000_000.0 / micros as f64),
+        }
+    }
+
+    fn compression(&self) -> Compression {
+        match self.avi.stream_format().color_coding {
+            ColorCoding::MJPEG => Compression::Mjpeg,
+            _ => Compression::None,
+        }
+    }
+
+    fn bottom_up(&self) -> bool {
+        // a positive height means bottom-up for uncompressed DIBs, but compressed formats
+        // always use a positive height
+        self.compression() == Compression::None && self.avi.stream_format().header.height > 0
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.avi.header_fields()
+    }
+}
+
+/// A FITS image or cube of 8 or 16-bit frames. Files that are opened are read from disk a
+/// frame at a time, while files that are given as bytes are held in memory. Samples are
+/// converted to unsigned physical values using `BZERO` and `BSCALE`, and the planes of
+/// color images are interleaved, as frames are read.
+pub struct FitsVideo {
+    header: FitsHeader,
+    bytes: Vec<u8>,
+    /// Reads frames from disk instead of from `bytes`
+    #[cfg(not(target_arch = "wasm32"))]
+    reader: Option<FrameReader>,
+}
+
+impl FitsVideo {
+    /// Open a FITS file, reading only its header until frames are read
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(filename: &str, mode: OpenMode) -> Result<Self> {
+        let mut file = File::open(filename)?;
+        let file_len = file.metadata()?.len();
+        let header = FitsHeader::parse(&crate::fits::read_header(&mut file)?, file_len, mode)?;
+        let size = header.frame_size();
+        let frames = (0..header.frame_count)
+            .map(|i| FrameExtent {
+                offset: (header.data_offset + i * size) as u64,
+                size,
+            })
+            .collect();
+        Ok(Self {
+            header,
+            bytes: vec![],
+            reader: Some(FrameReader::open(filename, frames)?),
+        })
+    }
+
+    pub fn from_bytes(mut bytes: Vec<u8>, mode: OpenMode) -> Result<Self> {
+        let header = FitsHeader::parse(&bytes, bytes.len() as u64, mode)?;
+        let end = header
+            .frame_size()
+            .checked_mul(header.frame_count)
+            .and_then(|size| size.checked_add(header.data_offset))
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| {
+                PlayerError::Format(format!(
+                    "FITS file is {} bytes, which is too short for its header and {} frames",
+                    bytes.len(),
+                    header.frame_count
+                ))
+            })?;
+        bytes[header.data_offset..end]
+            .chunks_exact_mut(header.frame_size())
+            .for_each(|frame| header.decode_frame(frame));
+        Ok(Self {
+            header,
+            bytes,
+            #[cfg(not(target_arch = "wasm32"))]
+            reader: None,
+        })
+    }
+
+    pub fn header(&self) -> &FitsHeader {
+        &self.header
+    }
+}
+
+impl Video for FitsVideo {
+    fn image_width(&self) -> u32 {
+        self.header.width
+    }
+
+    fn image_height(&self) -> u32 {
+        self.header.height
+    }
+
+    fn frame_count(&self) -> usize {
+        self.header.frame_count
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        self.header.bytes_per_sample() as u8
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.header.bitpix as u32
+    }
+
+    fn bayer(&self) -> &Bayer {
+        &self.header.bayer
+    }
+
+    fn endianness(&self) -> &Endianness {
+        &Endianness::BigEndian
+    }
+
+    fn get_frame(&self, index: usize) -> Result<&[u8]> {
+        if index >= self.header.frame_count {
+            return Err(PlayerError::Decode(format!(
+                "Invalid frame index {}",
+                index
+            )));
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.reader.is_some() {
+            return Err(PlayerError::Unsupported(format!(
+                "Frame {} is read from disk and must be read with read_frame",
+                index
+            )));
+        }
+        let size = self.header.frame_size();
+        let offset = self.header.data_offset + index * size;
+        Ok(&self.bytes[offset..offset + size])
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_frame(&self, index: usize) -> Result<Cow<'_, [u8]>> {
+        match &self.reader {
+            Some(reader) => {
+                let mut buf = vec![];
+                reader.read_frame_into(index, &mut buf)?;
+                self.header.decode_frame(&mut buf);
+                Ok(Cow::Owned(buf))
+            }
+            None => self.get_frame(index).map(Cow::Borrowed),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_frame_range(&self, index: usize, range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+        match &self.reader {
+            // samples can only be converted on their own when whole samples of a single
+            // plane are read
+            Some(reader)
+                if self.header.bayer.planes() == 1
+                    && range.start.is_multiple_of(self.header.bytes_per_sample()) =>
+            {
+                let mut buf = vec![];
+                reader.read_range_into(index, range, &mut buf)?;
+                self.header.decode_frame(&mut buf);
+                Ok(Cow::Owned(buf))
+            }
+            _ => {
+                let frame = self.read_frame(index)?;
+                if range.start > range.end || range.end > frame.len() {
+                    return Err(PlayerError::Decode(format!(
+                        "Bytes {:?} are outside frame {}, which has {} bytes",
+                        range,
+                        index,
+                        frame.len()
+                    )));
+                }
+                Ok(match frame {
+                    Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[range]),
+                    Cow::Owned(bytes) => Cow::Owned(bytes[range].to_vec()),
+                })
+            }
+        }
+    }
+
+    fn header_fields(&self) -> Vec<(String, String)> {
+        self.header.cards.clone()
+    }
+}
+
+/// Frames held in memory, for tests and for applications that produce frames themselves,
+/// such as from a live camera, and want to decode them with a codec or show them in the
+/// player. Frames are laid out like SER frames: `format.frame_size()` bytes of top-down rows,
+/// with 2 bytes per sample for depths over 8 bits.
+pub struct MemoryVideo {
+    format: SerFormat,
+    frames: Vec<Vec<u8>>,
+    timestamps: Vec<Option<SystemTime>>,
+    frame_rate: Option<f64>,
+}
+
+impl MemoryVideo {
+    /// An empty video with frames in the given format
+    pub fn new(format: SerFormat) -> Self {
+        Self {
+            format,
+            frames: vec![],
+            timestamps: vec![],
+            frame_rate: None,
+        }
+    }
+
+    /// Video of the given frames, which must all be `format.frame_size()` bytes long
+    pub fn from_frames(format: SerFormat, frames: Vec<Vec<u8>>) -> Result<Self> {
+        let mut video = Self::new(format);
+        for frame in frames {
+            video.push_frame(frame)?;
+        }
+        Ok(video)
+    }
+
+    /// Set the frame rate reported for the video
+    pub fn with_frame_rate(mut self, frame_rate: f64) -> Self {
+        self.frame_rate = Some(frame_rate);
+        self
+    }
+
+    pub fn format(&self) -> &SerFormat {
+        &self.format
+    }
+
+    /// Add a frame to the end of the video
+    pub fn push_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        self.push(frame, None)
+    }
+
+    /// Add a frame that was captured at `time` to the end of the video
+    pub fn push_frame_at(&mut self, frame: Vec<u8>, time: SystemTime) -> Result<()> {
+        self.push(frame, Some(time))
+    }
+
+    fn push(&mut self, frame: Vec<u8>, time: Option<SystemTime>) -> Result<()> {
+        if frame.len() != self.format.frame_size() {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Frame is {} bytes but {}x{} {:?} frames with {}-bit samples are {} bytes",
+                frame.len(),
+                self.format.width,
+                self.format.height,
+                self.format.bayer,
+                self.format.pixel_depth_bits,
+                self.format.frame_size()
+            )));
+        }
+        self.frames.push(frame);
+        self.timestamps.push(time);
+        Ok(())
+    }
+}
+
+impl Video for MemoryVideo {
+    fn image_width(&self) -> u32 {
+        self.format.width
+    }
+
+    fn image_height(&self) -> u32 {
+        self.format.height
+    }
+
+    fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn bytes_per_pixel(&self) -> u8 {
+        if self.format.pixel_depth_bits > 8 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn pixel_depth_bits(&self) -> u32 {
+        self.format.pixel_depth_bits
+    }
+
+    fn bayer(&self) -> &Bayer {
+        &self.format.bayer
+    }
+
+    fn endianness(&self) -> &Endianness {
+        &self.format.endianness
+    }
+
+    fn get_frame(&self, index: usize) -> Result<&[u8]> {
+        match self.frames.get(index) {
+            Some(frame) => Ok(frame),
+            None => Err(PlayerError::Decode(format!(
+                "Invalid frame index {}",
+                index
+            ))),
+        }
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        self.frame_rate
+    }
+
+    fn frame_timestamp(&self, index: usize) -> Option<SystemTime> {
+        self.timestamps.get(index).copied().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fits::write_fits;
+    use crate::testutil::{
+        avi_stream_bytes, fits_bytes, ser_bytes, ser_format, ser_video, AviStream, TempFile,
+    };
+
+    #[test]
+    fn test_memory_video() {
+        let format = ser_format(2, 2, Bayer::RGGB, 12);
+        let frame = vec![1, 0, 2, 0, 3, 0, 255, 15];
+        let mut video = MemoryVideo::from_frames(format, vec![frame.clone()])
+            .unwrap()
+            .with_frame_rate(30.0);
+        assert!(video.push_frame(vec![0; 4]).is_err());
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        video.push_frame_at(frame, time).unwrap();
+        assert_eq!(2, video.frame_count());
+        assert_eq!(2, video.bytes_per_pixel());
+        assert_eq!(&[1, 2, 3, 4095], &*video.get_frame_u16(1).unwrap());
+        assert_eq!(None, video.frame_timestamp(0));
+        assert_eq!(Some(time), video.frame_timestamp(1));
+        assert_eq!(Some(30.0), video.frame_rate());
+        assert!(video.read_frame(2).is_err());
+    }
+
+    /// The header must be checked against the length of the file, because ser-io only checks
+    /// one plane of RGB frames and trusts the bit depth
+    #[test]
+    fn test_open_ser_checks_header() {
+        let format = ser_format(4, 2, Bayer::RGB, 8);
+        let mut bytes = ser_bytes(&format, &[vec![1; 24], vec![2; 24]]);
+        bytes.truncate(HEADER_SIZE + 24 + 16);
+        let file = TempFile::new(".ser", &bytes);
+        let err = SerVideo::open(file.path_str(), OpenMode::Lenient)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("only contains 1"), "{}", err);
+
+        let format = ser_format(4, 2, Bayer::RGB, 16);
+        let mut bytes = ser_bytes(&format, &[vec![1; 48]]);
+        bytes[34..38].copy_from_slice(&32_u32.to_le_bytes());
+        let file = TempFile::new(".ser", &bytes);
+        let err = SerVideo::open(file.path_str(), OpenMode::Lenient)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("bit depth 32"), "{}", err);
+    }
+
+    #[test]
+    fn test_cropped_frame() {
+        let frame = (0..8 * 6).map(|i| i as u8).collect::<Vec<_>>();
+        let video = ser_video(&ser_format(8, 6, Bayer::RGGB, 8), &[frame]);
+        // the region is widened to whole repeats of the color filter pattern
+        let region = Region {
+            x: 3,
+            y: 2,
+            width: 2,
+            height: 2,
+        };
+        let cropped = CroppedFrame::new(&video, 0, region).unwrap();
+        assert_eq!((4, 2), (cropped.image_width(), cropped.image_height()));
+        assert_eq!(
+            &[18, 19, 20, 21, 26, 27, 28, 29],
+            &*cropped.read_frame(0).unwrap()
+        );
+        let outside = Region {
+            x: 8,
+            y: 0,
+            width: 4,
+            height: 4,
+        };
+        assert!(CroppedFrame::new(&video, 0, outside).is_err());
+    }
+
+    #[test]
+    fn test_parse_overrides() {
+        assert!(matches!(parse_bayer("BGGR"), Ok(Bayer::BGGR)));
+        assert!(matches!(parse_bayer("mono"), Ok(Bayer::Mono)));
+        assert!(parse_bayer("rgbg").is_err());
+        assert_eq!(12, parse_bit_depth("12").unwrap());
+        assert!(parse_bit_depth("0").is_err());
+        assert!(parse_bit_depth("32").is_err());
+        assert!(matches!(parse_endianness("big"), Ok(Endianness::BigEndian)));
+        assert!(parse_endianness("middle").is_err());
+        assert_eq!(
+            EndiannessOverride::Auto,
+            parse_endianness_override("AUTO").unwrap()
+        );
+        assert_eq!(
+            EndiannessOverride::Fixed(Endianness::LittleEndian),
+            parse_endianness_override("little").unwrap()
+        );
+        assert!(parse_endianness_override("middle").is_err());
+    }
+
+    #[test]
+    fn test_detect_endianness() {
+        // a noisy 12-bit gradient stored little endian in a file that claims to be big endian
+        let mut format = ser_format(16, 8, Bayer::RGGB, 12);
+        format.endianness = Endianness::BigEndian;
+        let frame = (0..16 * 8_u16)
+            .flat_map(|i| (1000 + i * 3 + (i * 7) % 5).to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let blank = vec![0; frame.len()];
+        let video: Arc<dyn Video> = Arc::new(ser_video(&format, &[frame.clone(), frame]));
+        assert_eq!(
+            Some(Endianness::LittleEndian),
+            detect_endianness(video.as_ref())
+        );
+        let overrides = |endianness| FormatOverrides {
+            endianness: Some(endianness),
+            ..FormatOverrides::default()
+        };
+        let detected = OverrideVideo::new(video.clone(), overrides(EndiannessOverride::Auto));
+        assert_eq!(Endianness::LittleEndian, *detected.endianness());
+        let forced = EndiannessOverride::Fixed(Endianness::BigEndian);
+        assert_eq!(
+            Endianness::BigEndian,
+            *OverrideVideo::new(video, overrides(forced)).endianness()
+        );
+
+        // blank frames keep the byte order from the header
+        let blank: Arc<dyn Video> = Arc::new(ser_video(&format, &[blank]));
+        assert_eq!(None, detect_endianness(blank.as_ref()));
+        let detected = OverrideVideo::new(blank, overrides(EndiannessOverride::Auto));
+        assert_eq!(Endianness::BigEndian, *detected.endianness());
+    }
+
+    #[test]
+    fn test_format_utc() {
+        use std::time::Duration;
+        assert_eq!("1970-01-01 00:00:00.000 UTC", format_utc(UNIX_EPOCH));
+        assert_eq!(
+            "2021-09-20 03:23:01.500 UTC",
+            format_utc(UNIX_EPOCH + Duration::from_millis(1_632_108_181_500))
+        );
+        assert_eq!(
+            "2024-02-29 23:59:59.000 UTC",
+            format_utc(UNIX_EPOCH + Duration::from_secs(1_709_251_199))
+        );
+    }
+
+    #[test]
+    fn test_open_fits() {
+        let data = (0..12_u8).collect::<Vec<_>>();
+        let bytes = fits_bytes(8, &[3, 2, 2], &["BAYERPAT= 'BGGR'"], &data);
+        let file = TempFile::new(".fits", &bytes);
+        let video = open_video(file.path_str()).unwrap();
+        assert_eq!((3, 2), (video.image_width(), video.image_height()));
+        assert_eq!(2, video.frame_count());
+        assert_eq!(8, video.pixel_depth_bits());
+        assert_eq!(&Bayer::BGGR, video.bayer());
+        assert_eq!(&data[6..12], &*video.read_frame(1).unwrap());
+        assert_eq!(&data[8..11], &*video.read_frame_range(1, 2..5).unwrap());
+        assert!(video.read_frame(2).is_err());
+        // frames are read from disk rather than from a copy of the file
+        assert!(video.get_frame(1).is_err());
+
+        // the file ends within the header block, before the data
+        let header_len = 8 * 80;
+        let err = FitsVideo::from_bytes(bytes[..header_len].to_vec(), OpenMode::Lenient)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("too short"), "{}", err);
+    }
+
+    #[test]
+    fn test_fits_color_round_trip() {
+        let values = (0..12).map(|v| v * 1000).collect::<Vec<u16>>();
+        let mut bytes = vec![];
+        write_fits(&mut bytes, 2, 2, &Bayer::RGB, &values).unwrap();
+        let file = TempFile::new(".fits", &bytes);
+        let video = open_video(file.path_str()).unwrap();
+        assert_eq!(1, video.frame_count());
+        assert_eq!(&Bayer::RGB, video.bayer());
+        assert_eq!(values, &*video.get_frame_u16(0).unwrap());
+        let frame = video.read_frame(0).unwrap();
+        assert_eq!(&frame[6..12], &*video.read_frame_range(0, 6..12).unwrap());
+
+        let video = FitsVideo::from_bytes(bytes, OpenMode::Strict).unwrap();
+        assert_eq!(values, &*video.get_frame_u16(0).unwrap());
+    }
+
+    #[test]
+    fn test_read_strategy() {
+        let format = ser_format(4, 2, Bayer::Mono, 8);
+        let frames = vec![vec![1; 8], vec![2; 8], vec![3; 8]];
+        let file = TempFile::new(".ser", &ser_bytes(&format, &frames));
+        for read in [ReadStrategy::Mmap, ReadStrategy::Read].iter() {
+            let options = OpenOptions {
+                read: *read,
+                ..OpenOptions::default()
+            };
+            let video = open_video_with(file.path_str(), &options).unwrap();
+            assert_eq!(3, video.frame_count());
+            let frame = video.read_frame(1).unwrap();
+            assert_eq!(*read == ReadStrategy::Read, matches!(frame, Cow::Owned(_)));
+            assert_eq!(&frames[1], &*frame);
+            assert!(video.read_frame(3).is_err());
+            // only memory-mapped frames can be borrowed
+            assert_eq!(*read == ReadStrategy::Mmap, video.get_frame(1).is_ok());
+            assert_eq!(&[3, 3], &*video.read_frame_range(2, 6..8).unwrap());
+            assert!(video.read_frame_range(2, 6..9).is_err());
+            let sums = video
+                .par_map_frames(|_, frame| frame.bytes.iter().sum::<u8>())
+                .unwrap();
+            assert_eq!(vec![8, 16, 24], sums);
+        }
+        assert_eq!(ReadStrategy::Read, parse_read_strategy("Read").unwrap());
+        assert!(parse_read_strategy("stream").is_err());
+    }
+
+    #[test]
+    fn test_get_frame_u16() {
+        let format = ser_format(2, 1, Bayer::Mono, 16);
+        let file = TempFile::new(".ser", &ser_bytes(&format, &[vec![0x34, 0x12, 0xff, 0x0f]]));
+        let video = open_video(file.path_str()).unwrap();
+        assert_eq!(&[0x1234, 0x0fff], &*video.get_frame_u16(0).unwrap());
+        assert!(video.get_frame_u16(1).is_err());
+
+        let overrides = FormatOverrides {
+            endianness: Some(EndiannessOverride::Fixed(Endianness::BigEndian)),
+            ..FormatOverrides::default()
+        };
+        let video = OverrideVideo::new(Arc::from(video), overrides);
+        assert_eq!(&[0x3412, 0xff0f], &*video.get_frame_u16(0).unwrap());
+
+        let format = ser_format(2, 1, Bayer::Mono, 8);
+        let file = TempFile::new(".ser", &ser_bytes(&format, &[vec![7, 255]]));
+        let video = open_video(file.path_str()).unwrap();
+        assert_eq!(&[7, 255], &*video.get_frame_u16(0).unwrap());
+
+        // bottom-up rows are returned top-down
+        let stream = AviStream {
+            bottom_up: true,
+            ..AviStream::RAW8
+        };
+        let frame = vec![5, 6, 7, 8, 1, 2, 3, 4];
+        let file = TempFile::new(".avi", &avi_stream_bytes(4, 2, 0, &stream, &[frame]));
+        let video = open_video(file.path_str()).unwrap();
+        assert_eq!(&[1, 2, 3, 4, 5, 6, 7, 8], &*video.get_frame_u16(0).unwrap());
+    }
+
+    #[test]
+    fn test_detect_format_ignores_extension() {
+        let format = ser_format(4, 2, Bayer::Mono, 8);
+        let bytes = ser_bytes(&format, &[vec![1; 8]]);
+        for suffix in [".SER", ".Ser", ".avi", ""].iter() {
+            let file = TempFile::new(suffix, &bytes);
+            assert_eq!(VideoFormat::Ser, detect_format(file.path_str()).unwrap());
+            assert_eq!(1, open_video(file.path_str()).unwrap().frame_count());
+        }
+        // the extension is used when the signature is not recognized
+        let file = TempFile::new(".Ser", b"not a capture");
+        assert_eq!(VideoFormat::Ser, detect_format(file.path_str()).unwrap());
+        let file = TempFile::new(".dat", b"not a capture");
+        assert!(matches!(
+            detect_format(file.path_str()),
+            Err(PlayerError::Unsupported(_))
+        ));
     }
 }
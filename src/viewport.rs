@@ -0,0 +1,794 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Image view that can be zoomed with the mouse wheel and panned by dragging, for inspecting
+//! the focus of large frames. Dragging can also select a rectangular region of the image.
+//! A scale bar and compass can be drawn over the image.
+
+use std::hash::Hash;
+
+use iced::rule::{FillMode, Style, StyleSheet};
+use iced_native::event::{self, Event};
+use iced_native::image::{self, viewer};
+use iced_native::layout;
+use iced_native::mouse;
+use iced_native::rule;
+use iced_native::text;
+use iced_native::{
+    Clipboard, Color, Element, Hasher, HorizontalAlignment, Layout, Length, Point, Rectangle, Size,
+    Vector, VerticalAlignment, Widget,
+};
+
+use crate::orientation::{scale_bar, Orientation};
+
+/// Change in zoom for each step of the mouse wheel
+const ZOOM_STEP: f32 = 1.25;
+
+/// Largest zoom, in screen pixels per image pixel
+const MAX_SCALE: f32 = 16.0;
+
+/// Width of the outline of a selected region, in screen pixels
+const SELECTION_WIDTH: u16 = 2;
+
+/// Distance between the dots of a line drawn over the image, in screen pixels
+const LINE_DOT_SPACING: f32 = 4.0;
+
+/// Distance of the scale bar and compass from the edges of the shown image, in screen pixels
+const OVERLAY_MARGIN: f32 = 20.0;
+
+/// Length of the compass arrows, in screen pixels
+const COMPASS_LENGTH: f32 = 40.0;
+
+/// Length of the sides of arrowheads, in screen pixels
+const ARROWHEAD_LENGTH: f32 = 8.0;
+
+/// Distance of the label of an arrow from its tail, in screen pixels
+const CALLOUT_LABEL_DISTANCE: f32 = 14.0;
+
+/// Size of the labels of the scale bar, compass and annotations
+const OVERLAY_TEXT_SIZE: u16 = 16;
+
+/// How the image is scaled to the view
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Zoom {
+    /// Show the whole image
+    Fit,
+    /// One screen pixel per image pixel
+    Actual,
+    /// Multiple of the scale that fits the whole image, which is kept when the size of the
+    /// image changes, such as when a full resolution frame replaces a preview
+    Relative(f32),
+}
+
+/// Zoom level and position of the image view. This is kept by the player so that it is not
+/// reset when stepping through frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Viewport {
+    pub zoom: Zoom,
+    /// Position in the image at the center of the view, as a fraction of the image size
+    center: Vector,
+    /// Cursor position and center when a drag started
+    drag: Option<(Point, Vector)>,
+    /// Corners of a region that is being selected, as fractions of the image size
+    selection: Option<(Vector, Vector)>,
+    /// Whether the cursor was over the image when it last moved
+    hovering: bool,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            zoom: Zoom::Fit,
+            center: Vector::new(0.5, 0.5),
+            drag: None,
+            selection: None,
+            hovering: false,
+        }
+    }
+}
+
+impl Viewport {
+    /// Show the whole image
+    pub fn fit(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Show the image at one screen pixel per image pixel, keeping the same center
+    pub fn actual_size(&mut self) {
+        self.zoom = Zoom::Actual;
+    }
+
+    /// Screen pixels per image pixel
+    fn scale(&self, bounds: Size, image: Size) -> f32 {
+        let fit = (bounds.width / image.width)
+            .min(bounds.height / image.height)
+            .min(1.0);
+        match self.zoom {
+            Zoom::Fit => fit,
+            Zoom::Actual => 1.0,
+            Zoom::Relative(relative) => fit * relative,
+        }
+    }
+
+    /// Offset of the top left of the scaled image from the top left of the view. Images
+    /// that are smaller than the view are centered, and larger images are never moved so
+    /// far that there is empty space at an edge.
+    fn translation(&self, bounds: Size, image_size: Size) -> Vector {
+        let axis = |view: f32, image: f32, center: f32| {
+            if image <= view {
+                (view - image) / 2.0
+            } else {
+                (view / 2.0 - center * image).max(view - image).min(0.0)
+            }
+        };
+        Vector::new(
+            axis(bounds.width, image_size.width, self.center.x),
+            axis(bounds.height, image_size.height, self.center.y),
+        )
+    }
+
+    /// Set the center from a translation, so that later zooming and panning start from the
+    /// position that is shown rather than from a position beyond the edge of the image
+    fn set_translation(&mut self, bounds: Size, image_size: Size, translation: Vector) {
+        self.center = Vector::new(
+            (bounds.width / 2.0 - translation.x) / image_size.width,
+            (bounds.height / 2.0 - translation.y) / image_size.height,
+        );
+        let clamped = self.translation(bounds, image_size);
+        self.center = Vector::new(
+            (bounds.width / 2.0 - clamped.x) / image_size.width,
+            (bounds.height / 2.0 - clamped.y) / image_size.height,
+        );
+    }
+
+    /// Zoom in or out by a number of wheel steps, keeping the image position under the
+    /// cursor in the same place
+    fn zoom_at(&mut self, steps: f32, cursor: Vector, bounds: Size, image: Size) {
+        let scale = self.scale(bounds, image);
+        let translation = self.translation(bounds, scaled(image, scale));
+        let fit = Self::default().scale(bounds, image);
+        let new_scale = (scale * ZOOM_STEP.powf(steps))
+            .max(fit)
+            .min(MAX_SCALE.max(fit));
+        self.zoom = Zoom::Relative(new_scale / fit);
+        let anchor = (cursor - translation) * (new_scale / scale);
+        self.set_translation(bounds, scaled(image, new_scale), cursor - anchor);
+    }
+
+    /// Position in the image under a point in the view, as a fraction of the image size
+    fn image_position(&self, cursor: Vector, bounds: Size, image: Size) -> Vector {
+        let image_size = scaled(image, self.scale(bounds, image));
+        let position = cursor - self.translation(bounds, image_size);
+        Vector::new(
+            (position.x / image_size.width).clamp(0.0, 1.0),
+            (position.y / image_size.height).clamp(0.0, 1.0),
+        )
+    }
+
+    /// Position in the image under a point in the view, as a fraction of the image size, or
+    /// `None` if the point is outside the image
+    fn hovered_position(&self, cursor: Vector, bounds: Size, image: Size) -> Option<Vector> {
+        let image_size = scaled(image, self.scale(bounds, image));
+        let position = cursor - self.translation(bounds, image_size);
+        let position = Vector::new(
+            position.x / image_size.width,
+            position.y / image_size.height,
+        );
+        let inside = |value: f32| (0.0..1.0).contains(&value);
+        if inside(position.x) && inside(position.y) {
+            Some(position)
+        } else {
+            None
+        }
+    }
+
+    /// Region that is being selected, as fractions of the image size
+    fn selected(&self) -> Option<Rectangle> {
+        self.selection.map(|(start, end)| Rectangle {
+            x: start.x.min(end.x),
+            y: start.y.min(end.y),
+            width: (start.x - end.x).abs(),
+            height: (start.y - end.y).abs(),
+        })
+    }
+}
+
+/// Draw a line of square dots, because the renderer can only draw rectangles
+fn draw_line<Renderer: rule::Renderer>(
+    renderer: &mut Renderer,
+    mut output: Renderer::Output,
+    bounds: Rectangle,
+    (start, end): (Point, Point),
+    spacing: f32,
+    style: &Renderer::Style,
+) -> Renderer::Output {
+    let length = start.distance(end);
+    let dots = (length / spacing).ceil().max(1.0) as usize;
+    let size = SELECTION_WIDTH as f32;
+    for dot in 0..=dots {
+        let t = dot as f32 / dots as f32;
+        let center = Point::new(
+            start.x + t * (end.x - start.x),
+            start.y + t * (end.y - start.y),
+        );
+        let rectangle = Rectangle::new(
+            Point::new(center.x - size / 2.0, center.y - size / 2.0),
+            Size::new(size, size),
+        );
+        let overlay = rule::Renderer::draw(renderer, rectangle, style, true);
+        output = renderer.overlay(output, overlay, bounds);
+    }
+    output
+}
+
+/// Draw a line with an arrowhead at its end
+fn draw_arrow<Renderer: rule::Renderer>(
+    renderer: &mut Renderer,
+    mut output: Renderer::Output,
+    bounds: Rectangle,
+    (start, end): (Point, Point),
+    style: &Renderer::Style,
+) -> Renderer::Output {
+    output = draw_line(renderer, output, bounds, (start, end), 1.0, style);
+    let length = start.distance(end).max(f32::EPSILON);
+    let direction = Vector::new((end.x - start.x) / length, (end.y - start.y) / length);
+    for side in [-1.0, 1.0] {
+        let back = Vector::new(
+            -direction.x * 0.8 - side * direction.y * 0.5,
+            -direction.y * 0.8 + side * direction.x * 0.5,
+        );
+        output = draw_line(
+            renderer,
+            output,
+            bounds,
+            (end, end + back * ARROWHEAD_LENGTH),
+            1.0,
+            style,
+        );
+    }
+    output
+}
+
+fn scaled(size: Size, scale: f32) -> Size {
+    Size::new(size.width * scale, size.height * scale)
+}
+
+/// Outline of a region that is being selected
+pub(crate) struct SelectionStyle;
+
+impl StyleSheet for SelectionStyle {
+    fn style(&self) -> Style {
+        Style {
+            color: Color::from_rgb(1.0, 0.8, 0.0),
+            width: SELECTION_WIDTH,
+            radius: 0.0,
+            fill_mode: FillMode::Full,
+        }
+    }
+}
+
+/// Scale bar and compass drawn in white over the image
+pub(crate) struct OverlayStyle;
+
+impl StyleSheet for OverlayStyle {
+    fn style(&self) -> Style {
+        Style {
+            color: Color::WHITE,
+            width: SELECTION_WIDTH,
+            radius: 0.0,
+            fill_mode: FillMode::Full,
+        }
+    }
+}
+
+/// Image scale and orientation to draw over the image as a scale bar and compass
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ScaleOverlay {
+    /// Image scale in arcseconds per frame pixel, if known. Without it only the compass is
+    /// drawn.
+    pub arcsec_per_pixel: Option<f64>,
+    pub orientation: Orientation,
+    /// Width of the part of the frame that the image shows, in frame pixels, since the
+    /// image may be a cropped region or a binned preview
+    pub frame_width: f32,
+}
+
+/// Arrow or text label to draw over the image, with positions as fractions of the image size
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Callout {
+    /// Position of the label and tail of the arrow
+    pub at: Vector,
+    /// Point that the arrow points at, if this is an arrow
+    pub to: Option<Vector>,
+    pub text: String,
+}
+
+/// Widget that shows an image using a `Viewport`
+pub(crate) struct ImageView<'a, Message> {
+    viewport: &'a mut Viewport,
+    handle: image::Handle,
+    /// Called with a region of the image that was dragged out, as fractions of the image
+    /// size. While this is set, dragging selects a region rather than panning.
+    on_select: Option<Box<dyn Fn(Rectangle) -> Message + 'a>>,
+    /// Called with the position in the image under the cursor as a fraction of the image
+    /// size whenever the cursor moves, or `None` when it leaves the image
+    on_hover: Option<Box<dyn Fn(Option<Vector>) -> Message + 'a>>,
+    /// Called with the start and end of a line that was dragged out, as fractions of the
+    /// image size. While this is set, dragging draws a line rather than panning.
+    on_line: Option<Box<dyn Fn(Vector, Vector) -> Message + 'a>>,
+    /// Called with the position of a click that did not drag, as a fraction of the image
+    /// size
+    on_point: Option<Box<dyn Fn(Vector) -> Message + 'a>>,
+    /// Line to draw over the image, as fractions of the image size
+    line: Option<(Vector, Vector)>,
+    /// Scale bar and compass to draw over the image
+    overlay: Option<ScaleOverlay>,
+    /// Arrows and labels to draw over the image
+    callouts: Vec<Callout>,
+}
+
+impl<'a, Message> ImageView<'a, Message> {
+    pub fn new(viewport: &'a mut Viewport, handle: image::Handle) -> Self {
+        Self {
+            viewport,
+            handle,
+            on_select: None,
+            on_hover: None,
+            on_line: None,
+            on_point: None,
+            line: None,
+            overlay: None,
+            callouts: vec![],
+        }
+    }
+
+    /// Select a region by dragging instead of panning
+    pub fn on_select(mut self, on_select: impl Fn(Rectangle) -> Message + 'a) -> Self {
+        self.on_select = Some(Box::new(on_select));
+        self
+    }
+
+    /// Report the position of the cursor in the image
+    pub fn on_hover(mut self, on_hover: impl Fn(Option<Vector>) -> Message + 'a) -> Self {
+        self.on_hover = Some(Box::new(on_hover));
+        self
+    }
+
+    /// Draw a line by dragging instead of panning
+    pub fn on_line(mut self, on_line: impl Fn(Vector, Vector) -> Message + 'a) -> Self {
+        self.on_line = Some(Box::new(on_line));
+        self
+    }
+
+    /// Report clicks on the image that did not drag
+    pub fn on_point(mut self, on_point: impl Fn(Vector) -> Message + 'a) -> Self {
+        self.on_point = Some(Box::new(on_point));
+        self
+    }
+
+    /// Show a line over the image, with its ends as fractions of the image size
+    pub fn line(mut self, line: Option<(Vector, Vector)>) -> Self {
+        self.line = line;
+        self
+    }
+
+    /// Show a scale bar and compass over the image
+    pub fn overlay(mut self, overlay: Option<ScaleOverlay>) -> Self {
+        self.overlay = overlay;
+        self
+    }
+
+    /// Show arrows and labels over the image
+    pub fn callouts(mut self, callouts: Vec<Callout>) -> Self {
+        self.callouts = callouts;
+        self
+    }
+
+    fn image_size<Renderer: image::Renderer>(&self, renderer: &Renderer) -> Size {
+        let (width, height) = renderer.dimensions(&self.handle);
+        Size::new(width.max(1) as f32, height.max(1) as f32)
+    }
+}
+
+impl<'a, Message, Renderer> Widget<Message, Renderer> for ImageView<'a, Message>
+where
+    Renderer: viewer::Renderer + image::Renderer + rule::Renderer + text::Renderer,
+    <Renderer as rule::Renderer>::Style: From<SelectionStyle> + From<OverlayStyle>,
+{
+    fn width(&self) -> Length {
+        Length::Fill
+    }
+
+    fn height(&self) -> Length {
+        Length::Fill
+    }
+
+    fn layout(&self, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.width(Length::Fill).height(Length::Fill).max())
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        messages: &mut Vec<Message>,
+    ) -> event::Status {
+        let bounds = layout.bounds();
+        let is_mouse_over = bounds.contains(cursor_position);
+        let image = self.image_size(renderer);
+        let cursor = cursor_position - bounds.position();
+        if let (Event::Mouse(mouse::Event::CursorMoved { .. }), Some(on_hover)) =
+            (&event, &self.on_hover)
+        {
+            let position = if is_mouse_over {
+                self.viewport.hovered_position(cursor, bounds.size(), image)
+            } else {
+                None
+            };
+            if position.is_some() || self.viewport.hovering {
+                messages.push(on_hover(position));
+            }
+            self.viewport.hovering = position.is_some();
+        }
+        match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) if is_mouse_over => {
+                let steps = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    // a line is roughly 60 pixels on most platforms
+                    mouse::ScrollDelta::Pixels { y, .. } => y / 60.0,
+                };
+                self.viewport.zoom_at(steps, cursor, bounds.size(), image);
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left))
+                if is_mouse_over
+                    && (self.on_select.is_some()
+                        || self.on_line.is_some()
+                        || self.on_point.is_some()) =>
+            {
+                let start = self.viewport.image_position(cursor, bounds.size(), image);
+                self.viewport.selection = Some((start, start));
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
+                if self.viewport.selection.is_some() =>
+            {
+                let selected = self.viewport.selected();
+                let selection = self.viewport.selection.take();
+                if let (Some((start, end)), Some(on_point)) = (selection, &self.on_point) {
+                    if start == end {
+                        messages.push(on_point(start));
+                        return event::Status::Captured;
+                    }
+                }
+                if let (Some((start, end)), Some(on_line)) = (selection, &self.on_line) {
+                    if start != end {
+                        messages.push(on_line(start, end));
+                    }
+                    return event::Status::Captured;
+                }
+                match (selected, &self.on_select) {
+                    (Some(region), Some(on_select))
+                        if region.width > 0.0 && region.height > 0.0 =>
+                    {
+                        messages.push(on_select(region))
+                    }
+                    _ => {}
+                }
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::CursorMoved { .. }) if self.viewport.selection.is_some() => {
+                let end = self.viewport.image_position(cursor, bounds.size(), image);
+                if let Some((_, corner)) = &mut self.viewport.selection {
+                    *corner = end;
+                }
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) if is_mouse_over => {
+                let image_size = scaled(image, self.viewport.scale(bounds.size(), image));
+                let translation = self.viewport.translation(bounds.size(), image_size);
+                self.viewport.drag = Some((cursor_position, translation));
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
+                if self.viewport.drag.is_some() =>
+            {
+                self.viewport.drag = None;
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => match self.viewport.drag {
+                Some((origin, translation)) => {
+                    let image_size = scaled(image, self.viewport.scale(bounds.size(), image));
+                    self.viewport.set_translation(
+                        bounds.size(),
+                        image_size,
+                        translation + (position - origin),
+                    );
+                    event::Status::Captured
+                }
+                None => event::Status::Ignored,
+            },
+            _ => event::Status::Ignored,
+        }
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut Renderer,
+        defaults: &Renderer::Defaults,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _viewport: &Rectangle,
+    ) -> Renderer::Output {
+        let bounds = layout.bounds();
+        let image = self.image_size(renderer);
+        let image_size = scaled(image, self.viewport.scale(bounds.size(), image));
+        let translation = self.viewport.translation(bounds.size(), image_size);
+        let mut output = viewer::Renderer::draw(
+            renderer,
+            &viewer::State::new(),
+            bounds,
+            image_size,
+            translation,
+            self.handle.clone(),
+            bounds.contains(cursor_position),
+        );
+        let origin = bounds.position() + translation;
+        let line = match (self.viewport.selection, &self.on_line) {
+            (Some(selection), Some(_)) => Some(selection),
+            (Some(_), None) => None,
+            (None, _) => self.line,
+        };
+        if let Some((start, end)) = line {
+            // a dotted line, because the renderer can only draw rectangles
+            let point = |position: Vector| {
+                Point::new(
+                    origin.x + position.x * image_size.width,
+                    origin.y + position.y * image_size.height,
+                )
+            };
+            let style = SelectionStyle.into();
+            output = draw_line(
+                renderer,
+                output,
+                bounds,
+                (point(start), point(end)),
+                LINE_DOT_SPACING,
+                &style,
+            );
+        } else if let Some(region) = self.viewport.selected() {
+            // outline the region with a line along each edge
+            let (x, y) = (
+                origin.x + region.x * image_size.width,
+                origin.y + region.y * image_size.height,
+            );
+            let (width, height) = (
+                region.width * image_size.width,
+                region.height * image_size.height,
+            );
+            let line = SELECTION_WIDTH as f32;
+            let edges = [
+                (
+                    Rectangle::new(Point::new(x, y - line), Size::new(width, 2.0 * line)),
+                    true,
+                ),
+                (
+                    Rectangle::new(
+                        Point::new(x, y + height - line),
+                        Size::new(width, 2.0 * line),
+                    ),
+                    true,
+                ),
+                (
+                    Rectangle::new(Point::new(x - line, y), Size::new(2.0 * line, height)),
+                    false,
+                ),
+                (
+                    Rectangle::new(
+                        Point::new(x + width - line, y),
+                        Size::new(2.0 * line, height),
+                    ),
+                    false,
+                ),
+            ];
+            let style = SelectionStyle.into();
+            for (edge, is_horizontal) in edges.iter() {
+                let overlay = rule::Renderer::draw(renderer, *edge, &style, *is_horizontal);
+                output = renderer.overlay(output, overlay, bounds);
+            }
+        }
+        let label = |renderer: &mut Renderer, center: Point, content: &str| {
+            text::Renderer::draw(
+                renderer,
+                defaults,
+                Rectangle::new(
+                    Point::new(center.x - 100.0, center.y - 10.0),
+                    Size::new(200.0, 20.0),
+                ),
+                content,
+                OVERLAY_TEXT_SIZE,
+                Default::default(),
+                Some(Color::WHITE),
+                HorizontalAlignment::Center,
+                VerticalAlignment::Center,
+            )
+        };
+        for callout in &self.callouts {
+            let point = |position: Vector| {
+                Point::new(
+                    origin.x + position.x * image_size.width,
+                    origin.y + position.y * image_size.height,
+                )
+            };
+            let style = OverlayStyle.into();
+            let mut center = point(callout.at);
+            if let Some(to) = callout.to {
+                let (start, end) = (point(callout.at), point(to));
+                output = draw_arrow(renderer, output, bounds, (start, end), &style);
+                // move the label back from the tail so that it does not cover the arrow
+                let length = start.distance(end).max(f32::EPSILON);
+                let back = Vector::new(start.x - end.x, start.y - end.y) * (1.0 / length);
+                center = start + back * CALLOUT_LABEL_DISTANCE;
+            }
+            if !callout.text.is_empty() {
+                let text = label(renderer, center, &callout.text);
+                output = renderer.overlay(output, text, bounds);
+            }
+        }
+        if let Some(overlay) = &self.overlay {
+            // keep the overlay within the part of the image that is in view
+            let left = origin.x.max(bounds.x);
+            let right = (origin.x + image_size.width).min(bounds.x + bounds.width);
+            let bottom = (origin.y + image_size.height).min(bounds.y + bounds.height);
+            let style = OverlayStyle.into();
+
+            let screen_per_frame_pixel = image_size.width / overlay.frame_width.max(1.0);
+            let bar = overlay.arcsec_per_pixel.and_then(|scale| {
+                scale_bar(
+                    scale,
+                    ((right - left) / 4.0 / screen_per_frame_pixel) as f64,
+                )
+            });
+            if let Some(bar) = bar {
+                let length = bar.pixels as f32 * screen_per_frame_pixel;
+                let (x, y) = (left + OVERLAY_MARGIN, bottom - OVERLAY_MARGIN);
+                let line = SELECTION_WIDTH as f32;
+                let rectangle = Rectangle::new(Point::new(x, y - line), Size::new(length, line));
+                let bar_output = rule::Renderer::draw(renderer, rectangle, &style, true);
+                output = renderer.overlay(output, bar_output, bounds);
+                let text = label(renderer, Point::new(x + length / 2.0, y - 14.0), &bar.label);
+                output = renderer.overlay(output, text, bounds);
+            }
+
+            let center = Point::new(
+                right - OVERLAY_MARGIN - COMPASS_LENGTH,
+                bottom - OVERLAY_MARGIN - COMPASS_LENGTH,
+            );
+            let arrows = [
+                ("N", overlay.orientation.north()),
+                ("E", overlay.orientation.east()),
+            ];
+            for (name, (x, y)) in arrows {
+                let direction = Vector::new(x as f32, y as f32);
+                let tip = center + direction * COMPASS_LENGTH;
+                output = draw_arrow(renderer, output, bounds, (center, tip), &style);
+                let text = label(renderer, center + direction * (COMPASS_LENGTH + 12.0), name);
+                output = renderer.overlay(output, text, bounds);
+            }
+        }
+        output
+    }
+
+    fn hash_layout(&self, state: &mut Hasher) {
+        struct Marker;
+        std::any::TypeId::of::<Marker>().hash(state);
+        self.handle.hash(state);
+    }
+}
+
+impl<'a, Message, Renderer> From<ImageView<'a, Message>> for Element<'a, Message, Renderer>
+where
+    Renderer: 'a + viewer::Renderer + image::Renderer + rule::Renderer + text::Renderer,
+    <Renderer as rule::Renderer>::Style: From<SelectionStyle> + From<OverlayStyle>,
+    Message: 'a,
+{
+    fn from(view: ImageView<'a, Message>) -> Element<'a, Message, Renderer> {
+        Element::new(view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zoom_keeps_cursor_position() {
+        let (bounds, image) = (Size::new(400.0, 300.0), Size::new(800.0, 600.0));
+        let mut viewport = Viewport::default();
+        assert!((viewport.scale(bounds, image) - 0.5).abs() < 1e-6);
+
+        // zoom in around the pixel at (600, 150) in the image
+        let cursor = Vector::new(300.0, 75.0);
+        viewport.zoom_at(2.0, cursor, bounds, image);
+        let scale = viewport.scale(bounds, image);
+        assert!((scale - 0.78125).abs() < 1e-6);
+        let translation = viewport.translation(bounds, scaled(image, scale));
+        let under_cursor = (cursor - translation) * (1.0 / scale);
+        assert!((under_cursor.x - 600.0).abs() < 0.01);
+        assert!((under_cursor.y - 150.0).abs() < 0.01);
+
+        // zooming out past the fit scale shows the whole image again
+        viewport.zoom_at(-10.0, cursor, bounds, image);
+        assert!((viewport.scale(bounds, image) - 0.5).abs() < 1e-6);
+        assert_eq!(
+            Vector::new(0.0, 0.0),
+            viewport.translation(bounds, scaled(image, 0.5))
+        );
+    }
+
+    #[test]
+    fn test_selection() {
+        let (bounds, image) = (Size::new(400.0, 300.0), Size::new(800.0, 600.0));
+        let mut viewport = Viewport::default();
+        // dragging up and to the left from the center, past the edge of the image
+        let start = viewport.image_position(Vector::new(200.0, 150.0), bounds, image);
+        let end = viewport.image_position(Vector::new(-50.0, 75.0), bounds, image);
+        viewport.selection = Some((start, end));
+        assert_eq!(
+            Some(Rectangle::new(Point::new(0.0, 0.25), Size::new(0.5, 0.25))),
+            viewport.selected()
+        );
+    }
+
+    #[test]
+    fn test_hovered_position() {
+        // the image is centered vertically, with 50 pixels above and below it
+        let (bounds, image) = (Size::new(400.0, 300.0), Size::new(800.0, 400.0));
+        let viewport = Viewport::default();
+        assert_eq!(
+            Some(Vector::new(0.25, 0.5)),
+            viewport.hovered_position(Vector::new(100.0, 150.0), bounds, image)
+        );
+        assert_eq!(
+            None,
+            viewport.hovered_position(Vector::new(100.0, 25.0), bounds, image)
+        );
+    }
+
+    #[test]
+    fn test_actual_size_is_clamped_to_edges() {
+        let (bounds, image) = (Size::new(400.0, 300.0), Size::new(800.0, 200.0));
+        let mut viewport = Viewport::default();
+        viewport.actual_size();
+        viewport.set_translation(bounds, image, Vector::new(100.0, 0.0));
+        // the image cannot be moved right of the left edge, and is centered vertically
+        assert_eq!(Vector::new(0.0, 50.0), viewport.translation(bounds, image));
+        viewport.set_translation(bounds, image, Vector::new(-1000.0, 0.0));
+        assert_eq!(
+            Vector::new(-400.0, 50.0),
+            viewport.translation(bounds, image)
+        );
+    }
+}
@@ -0,0 +1,107 @@
+// MIT License
+//
+// Copyright (c) 2021 Andy Grove
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Entry points for the browser front-end in `web/`, which lets a SER capture be inspected
+//! by dropping it onto a web page. Only SER files are supported because the AVI reader
+//! memory-maps its file.
+
+use wasm_bindgen::prelude::*;
+
+use crate::codec::{codec_for, ImageCodec};
+use crate::ser::SerBytes;
+use crate::video_format::{OpenMode, Video};
+
+/// A SER file loaded from the browser, along with the most recently decoded frame
+#[wasm_bindgen]
+pub struct WebVideo {
+    video: SerBytes,
+    codec: Box<dyn ImageCodec>,
+    width: u32,
+    height: u32,
+    /// RGBA pixels, in the layout expected by `ImageData`
+    pixels: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl WebVideo {
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: Vec<u8>) -> Result<WebVideo, JsValue> {
+        let video = SerBytes::new(bytes, OpenMode::Lenient).map_err(to_js)?;
+        let codec = codec_for(&video).map_err(to_js)?;
+        Ok(Self {
+            video,
+            codec,
+            width: 0,
+            height: 0,
+            pixels: vec![],
+        })
+    }
+
+    #[wasm_bindgen(js_name = frameCount)]
+    pub fn frame_count(&self) -> usize {
+        self.video.frame_count()
+    }
+
+    /// Summary of the file header for display on the page
+    pub fn describe(&self) -> String {
+        format!(
+            "{}x{}, {} frames, {}-bit {:?}",
+            self.video.image_width(),
+            self.video.image_height(),
+            self.video.frame_count(),
+            self.video.pixel_depth_bits(),
+            self.video.bayer()
+        )
+    }
+
+    /// Decode a frame, making it available from `width`, `height` and `pixels`
+    pub fn decode(&mut self, index: usize) -> Result<(), JsValue> {
+        // the previous frame's buffer is reused
+        let (width, height) = self
+            .codec
+            .decode_into(&self.video, index, &mut self.pixels)
+            .map_err(to_js)?;
+        // codecs produce BGRA
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+fn to_js(e: crate::error::PlayerError) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}